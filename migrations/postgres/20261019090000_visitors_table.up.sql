@@ -0,0 +1,4 @@
+CREATE TABLE visitors(
+    id uuid PRIMARY KEY,
+    first_seen TIMESTAMPTZ NOT NULL
+);
@@ -0,0 +1,18 @@
+CREATE TABLE chat_messages(
+    id uuid PRIMARY KEY,
+    room TEXT NOT NULL,
+    -- session of the author, nil for bots
+    session uuid NOT NULL,
+    author TEXT NOT NULL,
+    body TEXT NOT NULL,
+    sent_at TIMESTAMPTZ NOT NULL,
+    edited_at TIMESTAMPTZ,
+    deleted_at TIMESTAMPTZ
+);
+CREATE INDEX chat_messages_room_sent_at ON chat_messages(room, sent_at);
+CREATE TABLE chat_reactions(
+    message_id uuid NOT NULL REFERENCES chat_messages(id) ON DELETE CASCADE,
+    session uuid NOT NULL,
+    emoji TEXT NOT NULL,
+    PRIMARY KEY (message_id, session, emoji)
+);
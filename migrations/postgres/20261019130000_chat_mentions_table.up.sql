@@ -0,0 +1,6 @@
+-- mentions of names that were offline, handed over when the name is taken again
+CREATE TABLE chat_mentions(
+    message_id uuid NOT NULL REFERENCES chat_messages(id) ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    PRIMARY KEY (name, message_id)
+);
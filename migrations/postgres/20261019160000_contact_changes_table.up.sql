@@ -0,0 +1,13 @@
+-- Who changed which contact when, kept after the contact is deleted
+CREATE TABLE contact_changes(
+    id uuid PRIMARY KEY,
+    contact_id uuid NOT NULL,
+    -- 'update' or 'delete'
+    action TEXT NOT NULL,
+    changed_by TEXT NOT NULL,
+    changed_at TIMESTAMPTZ NOT NULL,
+    -- the contact as JSON before and after, `after` is NULL for deletions
+    before TEXT NOT NULL,
+    after TEXT
+);
+CREATE INDEX contact_changes_contact ON contact_changes(contact_id, changed_at);
@@ -0,0 +1,6 @@
+-- Phones become E.164 text. The old numbers wait in `legacy_phone` until the
+-- app converts them with the default region, the ones that are not phone
+-- numbers end up in `notes`.
+ALTER TABLE contacts RENAME COLUMN phone TO legacy_phone;
+ALTER TABLE contacts ADD COLUMN phone TEXT;
+ALTER TABLE contacts ADD COLUMN notes TEXT;
@@ -0,0 +1,16 @@
+-- Emails waiting for the SMTP server, kept once sent or given up on
+CREATE TABLE email_outbox(
+    id uuid PRIMARY KEY,
+    recipient TEXT NOT NULL,
+    subject TEXT NOT NULL,
+    body TEXT NOT NULL,
+    attempts BIGINT NOT NULL DEFAULT 0,
+    -- pushed back while a worker is sending it and after every failure
+    next_attempt_at TIMESTAMPTZ NOT NULL,
+    last_error TEXT,
+    created_at TIMESTAMPTZ NOT NULL,
+    sent_at TIMESTAMPTZ,
+    -- set once the attempts ran out
+    failed_at TIMESTAMPTZ
+);
+CREATE INDEX email_outbox_due ON email_outbox(next_attempt_at) WHERE sent_at IS NULL AND failed_at IS NULL;
@@ -0,0 +1,11 @@
+-- The numbers from before phones were E.164 are converted by the app ahead
+-- of this migration, by `migrate apply` or the server when it starts. Run any
+-- other way, e.g. `sqlx migrate run`, it refuses while numbers are left in
+-- `legacy_phone` instead of dropping them; apply it with the app then.
+DO $$
+BEGIN
+    IF EXISTS (SELECT 1 FROM contacts WHERE legacy_phone IS NOT NULL) THEN
+        RAISE EXCEPTION 'contacts.legacy_phone has numbers left, convert them with `demcru migrate apply`';
+    END IF;
+END $$;
+ALTER TABLE contacts DROP COLUMN legacy_phone;
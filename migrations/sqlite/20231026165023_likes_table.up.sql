@@ -0,0 +1,4 @@
+CREATE TABLE likes(
+    id uuid PRIMARY KEY,
+    counter BIGINT NOT NULL
+);
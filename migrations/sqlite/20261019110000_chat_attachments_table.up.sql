@@ -0,0 +1,11 @@
+CREATE TABLE chat_attachments(
+    id uuid PRIMARY KEY,
+    -- set once the upload is shared in a room
+    message_id uuid REFERENCES chat_messages(id) ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    mime TEXT NOT NULL,
+    size INTEGER NOT NULL,
+    has_thumbnail BOOLEAN NOT NULL,
+    uploaded_at TIMESTAMP NOT NULL
+);
+CREATE INDEX chat_attachments_message_id ON chat_attachments(message_id);
@@ -0,0 +1,2 @@
+-- transcripts of private rooms are only for their members
+ALTER TABLE chat_messages ADD COLUMN private BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,20 @@
+-- visitors writing to the site owner, one conversation per visitor
+CREATE TABLE inbox_conversations(
+    id uuid PRIMARY KEY,
+    -- visitor cookie of the chat page, missing if the visitor never had one
+    visitor uuid UNIQUE,
+    -- chat session replies go to, the last one the visitor wrote from
+    session uuid NOT NULL,
+    name TEXT NOT NULL,
+    started_at TIMESTAMP NOT NULL,
+    updated_at TIMESTAMP NOT NULL
+);
+CREATE INDEX inbox_conversations_session ON inbox_conversations(session);
+CREATE TABLE inbox_messages(
+    id uuid PRIMARY KEY,
+    conversation_id uuid NOT NULL REFERENCES inbox_conversations(id) ON DELETE CASCADE,
+    from_owner BOOLEAN NOT NULL,
+    body TEXT NOT NULL,
+    sent_at TIMESTAMP NOT NULL
+);
+CREATE INDEX inbox_messages_conversation_sent_at ON inbox_messages(conversation_id, sent_at);
@@ -0,0 +1,11 @@
+CREATE TABLE contacts(
+    id uuid PRIMARY KEY,
+    first_name TEXT NOT NULL,
+    last_name TEXT NOT NULL,
+    -- lower-cased, repeated submissions are matched on it
+    email TEXT NOT NULL,
+    phone INTEGER,
+    message TEXT,
+    created_at TIMESTAMP NOT NULL
+);
+CREATE INDEX contacts_email ON contacts(email, created_at);
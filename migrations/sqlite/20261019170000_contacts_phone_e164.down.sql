@@ -0,0 +1,6 @@
+-- Phones go back to numbers, with their country code. The notes are lost.
+UPDATE contacts SET legacy_phone = CAST(substr(phone, 2) AS INTEGER)
+WHERE legacy_phone IS NULL AND phone IS NOT NULL;
+ALTER TABLE contacts DROP COLUMN notes;
+ALTER TABLE contacts DROP COLUMN phone;
+ALTER TABLE contacts RENAME COLUMN legacy_phone TO phone;
@@ -0,0 +1,13 @@
+-- Submissions of the public forms that scored as spam, for the admin to review
+CREATE TABLE quarantine(
+    id uuid PRIMARY KEY,
+    form TEXT NOT NULL,
+    ip TEXT NOT NULL,
+    -- the submitted fields as JSON
+    payload TEXT NOT NULL,
+    score INTEGER NOT NULL,
+    -- what gave it away, comma separated
+    reasons TEXT NOT NULL,
+    received_at TIMESTAMP NOT NULL
+);
+CREATE INDEX quarantine_received_at ON quarantine(received_at);
@@ -0,0 +1,4 @@
+ALTER TABLE email_outbox DROP COLUMN unsubscribe_url;
+DROP TABLE newsletter_issues;
+DROP TABLE subscription_tokens;
+DROP TABLE subscriptions;
@@ -0,0 +1,27 @@
+-- Newsletter subscribers, `pending` until they follow the link of their confirmation email
+CREATE TABLE subscriptions(
+    id uuid PRIMARY KEY,
+    -- lower-cased
+    email TEXT NOT NULL UNIQUE,
+    status TEXT NOT NULL CHECK (status IN ('pending', 'confirmed', 'unsubscribed')),
+    -- for the unsubscribe link of every email, it never changes
+    unsubscribe_token TEXT NOT NULL UNIQUE,
+    created_at TIMESTAMP NOT NULL,
+    confirmed_at TIMESTAMP,
+    unsubscribed_at TIMESTAMP
+);
+-- Confirmation tokens, deleted when used
+CREATE TABLE subscription_tokens(
+    token TEXT PRIMARY KEY,
+    subscription_id uuid NOT NULL REFERENCES subscriptions(id) ON DELETE CASCADE,
+    created_at TIMESTAMP NOT NULL
+);
+-- Posts sent to the subscribers, each goes out once
+CREATE TABLE newsletter_issues(
+    slug TEXT PRIMARY KEY,
+    title TEXT NOT NULL,
+    recipients INTEGER NOT NULL,
+    sent_at TIMESTAMP NOT NULL
+);
+-- Newsletter emails carry a List-Unsubscribe header
+ALTER TABLE email_outbox ADD COLUMN unsubscribe_url TEXT;
@@ -0,0 +1,2 @@
+-- Empty, the phones are all E.164 by now.
+ALTER TABLE contacts ADD COLUMN legacy_phone INTEGER;
@@ -0,0 +1,10 @@
+-- The numbers from before phones were E.164 are converted by the app ahead
+-- of this migration, by `migrate apply` or the server when it starts. Run any
+-- other way, e.g. `sqlx migrate run`, it refuses while numbers are left in
+-- `legacy_phone` instead of dropping them; apply it with the app then.
+CREATE TEMP TABLE legacy_phones_left (
+    count INTEGER CONSTRAINT convert_them_with_demcru_migrate_apply CHECK (count = 0)
+);
+INSERT INTO legacy_phones_left SELECT COUNT(*) FROM contacts WHERE legacy_phone IS NOT NULL;
+DROP TABLE legacy_phones_left;
+ALTER TABLE contacts DROP COLUMN legacy_phone;
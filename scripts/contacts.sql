@@ -1,50 +0,0 @@
-insert into contacts (id, first_name, last_name, email, phone, created_at) values ('218a7f55-40ce-485a-a1d4-842ed335d0ba', 'Joelie', 'Sayburn', 'jsayburn0@tamu.edu', 59, '11/5/2022');
-insert into contacts (id, first_name, last_name, email, phone, created_at) values ('123aaaac-bfc8-483a-b88b-acf5932aa387', 'Eal', 'Dwane', 'edwane1@diigo.com', 62, '9/16/2022');
-insert into contacts (id, first_name, last_name, email, phone, created_at) values ('32de3641-3136-4f88-97f7-7074a57389d6', 'Cristen', 'Keys', 'ckeys2@sogou.com', 11, '9/4/2022');
-insert into contacts (id, first_name, last_name, email, phone, created_at) values ('ab6eede7-8396-4e2a-89a2-8c35a101b43d', 'Tiff', 'Withull', 'twithull3@discuz.net', 5, '10/3/2022');
-insert into contacts (id, first_name, last_name, email, phone, created_at) values ('f53a8eb1-53b3-4b70-839e-39a62c92ef6f', 'Mickey', 'Whiting', 'mwhiting4@google.com.hk', 45, '12/5/2022');
-insert into contacts (id, first_name, last_name, email, phone, created_at) values ('fe001c88-99e2-4706-a71f-2ed3bc07f6ba', 'Clayton', 'Paslow', 'cpaslow5@angelfire.com', 29, '10/12/2022');
-insert into contacts (id, first_name, last_name, email, phone, created_at) values ('c75e4bc4-ff2c-4d4d-952a-3f5313013d50', 'Arther', 'Bonnett', 'abonnett6@time.com', 100, '10/3/2022');
-insert into contacts (id, first_name, last_name, email, phone, created_at) values ('1acad634-d686-4b78-97ca-85dfecaa54b9', 'Torre', 'Tatton', 'ttatton7@360.cn', 33, '1/7/2023');
-insert into contacts (id, first_name, last_name, email, phone, created_at) values ('1cc71c96-6af3-4d45-8a3b-673334fbcc84', 'Sileas', 'Bonefant', 'sbonefant8@nsw.gov.au', 12, '5/27/2023');
-insert into contacts (id, first_name, last_name, email, phone, created_at) values ('16e92b4e-d558-4766-9884-628d80485184', 'Brandie', 'Cloughton', 'bcloughton9@auda.org.au', 92, '6/21/2023');
-insert into contacts (id, first_name, last_name, email, phone, created_at) values ('42137b91-deca-42a5-a723-2f85040b9175', 'Kincaid', 'Clewer', 'kclewera@wp.com', 39, '12/3/2022');
-insert into contacts (id, first_name, last_name, email, phone, created_at) values ('39b3117a-2312-40da-80db-6f45ab27c1ec', 'Leyla', 'Semken', 'lsemkenb@tinyurl.com', 87, '10/12/2022');
-insert into contacts (id, first_name, last_name, email, phone, created_at) values ('be663b07-2716-46d1-bb7e-370c81eb24be', 'Lemmie', 'Kyteley', 'lkyteleyc@liveinternet.ru', 43, '6/25/2023');
-insert into contacts (id, first_name, last_name, email, phone, created_at) values ('81367d9c-9bf0-4531-8c5a-6513d7f5ca92', 'Caldwell', 'Pareman', 'cparemand@businessinsider.com', 20, '10/27/2022');
-insert into contacts (id, first_name, last_name, email, phone, created_at) values ('e0e87101-b897-4ef2-b5fb-2081eb376de1', 'Hinze', 'Mapstone', 'hmapstonee@marketwatch.com', 74, '11/26/2022');
-insert into contacts (id, first_name, last_name, email, phone, created_at) values ('ea832ada-2221-4c0c-b437-b5208f403851', 'Haven', 'Latey', 'hlateyf@linkedin.com', 63, '4/29/2023');
-insert into contacts (id, first_name, last_name, email, phone, created_at) values ('414da343-3dec-4618-80ca-0236036c5548', 'Nissy', 'Humm', 'nhummg@cargocollective.com', 63, '7/8/2023');
-insert into contacts (id, first_name, last_name, email, phone, created_at) values ('4bfe1167-3588-45dd-84be-e111b3a38875', 'Killy', 'Naton', 'knatonh@taobao.com', 16, '3/26/2023');
-insert into contacts (id, first_name, last_name, email, phone, created_at) values ('1b4aff71-b270-44c1-b235-87ba07c714d8', 'Hetty', 'Maffetti', 'hmaffettii@goodreads.com', 73, '9/13/2022');
-insert into contacts (id, first_name, last_name, email, phone, created_at) values ('4109f35e-6efb-4c37-b376-76f169536b17', 'Dusty', 'Rowter', 'drowterj@artisteer.com', 45, '3/2/2023');
-insert into contacts (id, first_name, last_name, email, phone, created_at) values ('112aabf1-277a-4714-95fa-a1d90dcf59f4', 'Almire', 'O''Crevy', 'aocrevyk@wunderground.com', 65, '2/1/2023');
-insert into contacts (id, first_name, last_name, email, phone, created_at) values ('f385a4c3-d9c0-4297-954f-730ae7e3050b', 'Alina', 'Marton', 'amartonl@businesswire.com', 96, '12/8/2022');
-insert into contacts (id, first_name, last_name, email, phone, created_at) values ('3c3c064f-9347-41fc-8b92-a5216357ccbc', 'Salim', 'O'' Reagan', 'soreaganm@state.gov', 16, '1/15/2023');
-insert into contacts (id, first_name, last_name, email, phone, created_at) values ('37ba8399-8a13-480f-add3-2f0c2a55477a', 'Camila', 'Sciusscietto', 'csciusscietton@europa.eu', 79, '3/31/2023');
-insert into contacts (id, first_name, last_name, email, phone, created_at) values ('bbf641f8-4321-4aff-9fe9-d5b3d6482b38', 'Willem', 'Butterley', 'wbutterleyo@samsung.com', 96, '9/24/2022');
-insert into contacts (id, first_name, last_name, email, phone, created_at) values ('ff1cd7a1-37e9-4a06-818a-0ef99f4d528f', 'Raul', 'Balshen', 'rbalshenp@arizona.edu', 94, '3/5/2023');
-insert into contacts (id, first_name, last_name, email, phone, created_at) values ('a9ffc72a-869c-4962-9acc-50ac53e84647', 'Timmie', 'D''orsay', 'tdorsayq@aol.com', 4, '4/21/2023');
-insert into contacts (id, first_name, last_name, email, phone, created_at) values ('f34a005e-aaef-46a6-8eb2-d0e18dc16714', 'Tamarah', 'Coulling', 'tcoullingr@mac.com', 33, '8/17/2022');
-insert into contacts (id, first_name, last_name, email, phone, created_at) values ('98c52c9c-5a5d-412d-8b7c-5a436cb9eb5c', 'Stevy', 'Saterthwait', 'ssaterthwaits@xrea.com', 29, '4/10/2023');
-insert into contacts (id, first_name, last_name, email, phone, created_at) values ('0b45381d-abfb-432a-8427-4fbeba1d5766', 'Corinna', 'Outibridge', 'coutibridget@topsy.com', 65, '10/26/2022');
-insert into contacts (id, first_name, last_name, email, phone, created_at) values ('952b32ba-cc52-424b-b98a-1be6dbc0b48e', 'Marlow', 'Farrin', 'mfarrinu@ed.gov', 74, '9/9/2022');
-insert into contacts (id, first_name, last_name, email, phone, created_at) values ('adf666ce-f659-44cb-aec6-17d9e0c55b9a', 'Maje', 'Scholey', 'mscholeyv@icio.us', 13, '3/19/2023');
-insert into contacts (id, first_name, last_name, email, phone, created_at) values ('714badfd-6d1a-4329-afd3-8fea8e78d5ee', 'Emogene', 'Rodders', 'eroddersw@imageshack.us', 83, '7/16/2023');
-insert into contacts (id, first_name, last_name, email, phone, created_at) values ('df8065e9-573d-4965-9b27-b1a0cb241a86', 'Maureene', 'Wimpress', 'mwimpressx@ocn.ne.jp', 82, '7/14/2023');
-insert into contacts (id, first_name, last_name, email, phone, created_at) values ('1906f154-b911-4831-bd25-85ed45e9f5d3', 'Jack', 'Garthshore', 'jgarthshorey@ow.ly', 84, '6/19/2023');
-insert into contacts (id, first_name, last_name, email, phone, created_at) values ('b721279d-6f10-4244-a92d-df297ad2d92f', 'Jerrilee', 'Crewe', 'jcrewez@ucoz.ru', 13, '8/29/2022');
-insert into contacts (id, first_name, last_name, email, phone, created_at) values ('b6e8dfa6-8e1e-4b61-af05-a17715c9c537', 'Ingmar', 'Cary', 'icary10@addtoany.com', 53, '5/10/2023');
-insert into contacts (id, first_name, last_name, email, phone, created_at) values ('02e01888-4784-4e80-81d6-3a5194a729a4', 'Layney', 'Gorst', 'lgorst11@whitehouse.gov', 69, '7/11/2023');
-insert into contacts (id, first_name, last_name, email, phone, created_at) values ('c0089a07-8972-4762-84f2-857ae6a69bbf', 'Colette', 'Ilewicz', 'cilewicz12@pbs.org', 22, '11/27/2022');
-insert into contacts (id, first_name, last_name, email, phone, created_at) values ('60b3d1d5-1389-4e59-9dc4-692de60d73f3', 'Bart', 'Jiggins', 'bjiggins13@unicef.org', 50, '7/27/2022');
-insert into contacts (id, first_name, last_name, email, phone, created_at) values ('00e91a13-4e9f-4638-97f3-600011145681', 'Marcel', 'Cardoe', 'mcardoe14@flavors.me', 23, '3/12/2023');
-insert into contacts (id, first_name, last_name, email, phone, created_at) values ('95922230-7637-48fc-bcc3-e0ed138900ab', 'Kit', 'Rosellini', 'krosellini15@last.fm', 21, '3/10/2023');
-insert into contacts (id, first_name, last_name, email, phone, created_at) values ('b061ada2-cc3d-43a6-92c9-9da750847f6c', 'Bordy', 'Rodwell', 'brodwell16@springer.com', 98, '7/22/2023');
-insert into contacts (id, first_name, last_name, email, phone, created_at) values ('19e636e7-5551-48be-8228-121c994200bb', 'Burg', 'Meegan', 'bmeegan17@wunderground.com', 9, '2/6/2023');
-insert into contacts (id, first_name, last_name, email, phone, created_at) values ('c4a2a6f3-de33-436f-a16e-8989cdea7532', 'Ewart', 'Pavlishchev', 'epavlishchev18@ftc.gov', 70, '4/20/2023');
-insert into contacts (id, first_name, last_name, email, phone, created_at) values ('2ef72694-6e23-491e-8b20-8adac50b7bab', 'Ellyn', 'Genders', 'egenders19@mapquest.com', 88, '11/19/2022');
-insert into contacts (id, first_name, last_name, email, phone, created_at) values ('64ce1fea-fde1-40fd-a7ba-20ec09a7342d', 'Zelig', 'Boig', 'zboig1a@google.nl', 2, '1/18/2023');
-insert into contacts (id, first_name, last_name, email, phone, created_at) values ('318bfd37-d75f-412a-9716-0f9900319250', 'Casandra', 'MacClay', 'cmacclay1b@wsj.com', 86, '5/2/2023');
-insert into contacts (id, first_name, last_name, email, phone, created_at) values ('b3bad50f-2d80-4300-8c3a-1ea38cdac243', 'Rakel', 'Watt', 'rwatt1c@tamu.edu', 6, '10/31/2022');
-insert into contacts (id, first_name, last_name, email, phone, created_at) values ('66e3da9f-2b89-4e0b-b6a5-787b57db37f3', 'Agace', 'Klimczak', 'aklimczak1d@topsy.com', 76, '6/28/2023');
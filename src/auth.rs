@@ -0,0 +1,95 @@
+use actix_web::{
+    dev::Payload,
+    error::ErrorForbidden,
+    http::{header, Method},
+    rt::task,
+    web::Data,
+    FromRequest, HttpRequest,
+};
+use actix_web_httpauth::{
+    extractors::{basic::BasicAuth, AuthenticationError},
+    headers::www_authenticate::basic::Basic,
+};
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use futures_util::future::LocalBoxFuture;
+
+use crate::configuration::AdminSettings;
+
+pub fn hash_password(password: &str) -> Result<String, argon2::password_hash::Error> {
+    let salt = SaltString::generate(&mut OsRng);
+    Ok(Argon2::default()
+        .hash_password(password.as_bytes(), &salt)?
+        .to_string())
+}
+
+pub fn verify_password(hash: &str, password: &str) -> Result<bool, argon2::password_hash::Error> {
+    let hash = PasswordHash::new(hash)?;
+    Ok(Argon2::default()
+        .verify_password(password.as_bytes(), &hash)
+        .is_ok())
+}
+
+// The admin, extracted from HTTP basic auth checked against `AdminSettings`.
+// Take `Option<Admin>` for endpoints that admins only see more of. Browsers
+// send the credentials along with requests other sites make them send, so
+// changes are only taken from pages of this site.
+pub struct Admin {
+    pub username: String,
+}
+
+fn challenge() -> actix_web::Error {
+    AuthenticationError::new(Basic::with_realm("admin")).into()
+}
+
+// Whether a request only reads, or comes from a page of this site by its
+// `Origin`, or its `Referer` for browsers that leave the origin out
+fn same_origin(req: &HttpRequest) -> bool {
+    if matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS) {
+        return true;
+    }
+    let headers = req.headers();
+    let source = headers
+        .get(header::ORIGIN)
+        .or_else(|| headers.get(header::REFERER))
+        .and_then(|value| value.to_str().ok());
+    let host = source
+        .and_then(|source| source.split_once("://"))
+        .and_then(|(_, rest)| rest.split('/').next());
+    host == Some(req.connection_info().host())
+}
+
+impl FromRequest for Admin {
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let credentials = BasicAuth::from_request(req, payload).into_inner();
+        let settings = req.app_data::<Data<AdminSettings>>().cloned();
+        let same_origin = same_origin(req);
+        Box::pin(async move {
+            let credentials = credentials.map_err(|_| challenge())?;
+            let Some(settings) = settings else {
+                return Err(challenge());
+            };
+            let Some(hash) = settings.password_hash.clone() else {
+                return Err(challenge());
+            };
+            if credentials.user_id() != settings.username {
+                return Err(challenge());
+            }
+            let password = credentials.password().unwrap_or_default().to_owned();
+            // hashing is slow on purpose, keep it off the async workers
+            let verified = task::spawn_blocking(move || verify_password(&hash, &password)).await;
+            match verified {
+                Ok(Ok(true)) if !same_origin => Err(ErrorForbidden("Cross-site request")),
+                Ok(Ok(true)) => Ok(Admin {
+                    username: settings.username.clone(),
+                }),
+                _ => Err(challenge()),
+            }
+        })
+    }
+}
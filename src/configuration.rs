@@ -1,8 +1,22 @@
-use std::fs::File;
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    fs::{File, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+};
 
+use chrono::{NaiveDate, Utc};
+
+use lettre::message::Mailbox;
 use mini_markdown::render;
 use serde::{Deserialize, Serialize};
 
+use crate::{
+    models::phone::{region, Region},
+    protection::Form,
+};
+
 // Blog
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Post {
@@ -17,6 +31,51 @@ impl Post {
     pub fn render(&self) -> String {
         render(&self.body)
     }
+
+    /// A post dated today with its slug made of the title, to be written
+    pub fn draft(title: &str, author: &str) -> Post {
+        Post {
+            slug: slug(title),
+            title: title.trim().to_owned(),
+            author: author.trim().to_owned(),
+            date: Utc::now().date_naive().to_string(),
+            body: "Write the post here.".to_owned(),
+        }
+    }
+
+    // The post as an entry of `posts` in blog.yml, in the style of the others
+    fn to_yaml(&self) -> String {
+        let scalar = |value: &str| {
+            serde_yaml::to_string(value)
+                .map(|yaml| yaml.trim_end().to_owned())
+                .unwrap_or_default()
+        };
+        let body: String = self
+            .body
+            .lines()
+            .map(|line| match line {
+                "" => "\n".to_owned(),
+                line => format!("      {line}\n"),
+            })
+            .collect();
+        format!(
+            "  - slug: {}\n    title: {}\n    author: {}\n    date: {}\n    body: >\n{body}",
+            scalar(&self.slug),
+            scalar(&self.title),
+            scalar(&self.author),
+            self.date
+        )
+    }
+}
+
+/// Lower-case letters and digits of `title`, with dashes between the words
+pub fn slug(title: &str) -> String {
+    title
+        .to_lowercase()
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join("-")
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -28,46 +87,784 @@ pub struct Config {
 }
 
 impl Config {
-    pub fn new() -> Self {
-        let file = File::open("./config/blog.yml").expect("Could not open file");
-        let config = serde_yaml::from_reader(file).expect("Could not read values.");
-        config
+    /// The blog of `paths.blog`
+    pub fn load(path: &Path) -> std::io::Result<Self> {
+        let file = File::open(path)?;
+        serde_yaml::from_reader(file)
+            .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))
+    }
+
+    /// What is wrong with the posts, nothing if the blog can be served
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        let mut slugs = HashSet::new();
+        for post in &self.posts {
+            if post.slug.is_empty() || post.slug != slug(&post.slug) {
+                problems.push(format!(
+                    "post {:?}: the slug has lower-case letters, digits and dashes only",
+                    post.slug
+                ));
+            }
+            if !slugs.insert(post.slug.as_str()) {
+                problems.push(format!("post {:?}: the slug is taken", post.slug));
+            }
+            if post.title.trim().is_empty() {
+                problems.push(format!("post {:?}: the title is empty", post.slug));
+            }
+            if post.date.parse::<NaiveDate>().is_err() {
+                problems.push(format!(
+                    "post {:?}: the date {:?} is not YYYY-MM-DD",
+                    post.slug, post.date
+                ));
+            }
+        }
+        if !slugs.contains(self.default.as_str()) {
+            problems.push(format!("default: there is no post {:?}", self.default));
+        }
+        problems
+    }
+
+    /// Adds `post` after the others of the blog at `path`, the rest of the
+    /// file stays as it is written
+    pub fn append_post(path: &Path, post: &Post) -> std::io::Result<()> {
+        let blog = std::fs::read_to_string(path)?;
+        let mut file = OpenOptions::new().append(true).open(path)?;
+        if !blog.is_empty() && !blog.ends_with('\n') {
+            file.write_all(b"\n")?;
+        }
+        file.write_all(post.to_yaml().as_bytes())
     }
 }
 
-// Database
-#[derive(serde::Deserialize)]
+// Everything but the blog, see `get_config`
+#[derive(serde::Deserialize, Clone)]
 pub struct Settings {
+    #[serde(default)]
+    pub application: ApplicationSettings,
+    #[serde(default)]
     pub database: DatabaseSettings,
-    pub application_port: u16,
+    #[serde(default)]
+    pub paths: PathSettings,
+    #[serde(default)]
+    pub chat: ChatSettings,
+    #[serde(default)]
+    pub admin: AdminSettings,
+    #[serde(default)]
+    pub shutdown: ShutdownSettings,
+    #[serde(default)]
+    pub contacts: ContactSettings,
+    #[serde(default)]
+    pub protection: ProtectionSettings,
+    #[serde(default)]
+    pub email: EmailSettings,
+    #[serde(default)]
+    pub newsletter: NewsletterSettings,
 }
 
-#[derive(serde::Deserialize)]
-pub struct DatabaseSettings {
-    pub username: String,
-    pub password: String,
-    pub port: u16,
+// Subscribers confirm within `confirm_token_hours` of subscribing. Links in
+// emails start with `base_url`, where the site is reachable from outside.
+#[derive(serde::Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct NewsletterSettings {
+    pub base_url: String,
+    pub confirm_token_hours: i64,
+}
+
+impl Default for NewsletterSettings {
+    fn default() -> Self {
+        NewsletterSettings {
+            base_url: "http://localhost:8080".to_owned(),
+            confirm_token_hours: 48,
+        }
+    }
+}
+
+// Outgoing email. Messages wait in the outbox until the SMTP server takes
+// them, failed attempts are retried after `backoff_secs`, doubling each time
+// up to `max_backoff_secs`, and given up on after `max_attempts`.
+#[derive(serde::Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct EmailSettings {
     pub host: String,
-    pub database_name: String,
+    pub port: u16,
+    pub security: SmtpSecurity,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    // sender of every message, e.g. "demcru <noreply@example.com>"
+    pub from: String,
+    // who hears about new contacts and inbox messages, nobody without it
+    pub owner: Option<String>,
+    pub poll_interval_secs: u64,
+    pub timeout_secs: u64,
+    pub max_attempts: u32,
+    pub backoff_secs: u64,
+    pub max_backoff_secs: u64,
+}
+
+impl Default for EmailSettings {
+    fn default() -> Self {
+        EmailSettings {
+            host: "localhost".to_owned(),
+            port: 587,
+            security: SmtpSecurity::Starttls,
+            username: None,
+            password: None,
+            from: "demcru <noreply@localhost>".to_owned(),
+            owner: None,
+            poll_interval_secs: 30,
+            timeout_secs: 30,
+            max_attempts: 8,
+            backoff_secs: 60,
+            max_backoff_secs: 6 * 60 * 60,
+        }
+    }
+}
+
+// `none` is for servers on the same host or network only, credentials go in the clear
+#[derive(serde::Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpSecurity {
+    None,
+    #[default]
+    Starttls,
+    Tls,
+}
+
+// Bot protection of the public forms. Pages sign the time they were served
+// with `secret`, submissions faster than `min_submit_secs` or with stale
+// tokens are dropped, and text scoring `spam_threshold` or more is quarantined.
+#[derive(serde::Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct ProtectionSettings {
+    // random on every start without one, which voids the tokens of open pages
+    pub secret: Option<String>,
+    pub min_submit_secs: u64,
+    pub token_max_age_secs: u64,
+    pub spam_threshold: u32,
+    // trust X-Forwarded-For for the address rates are counted by
+    pub behind_proxy: bool,
+    pub rate_limits: RateLimits,
+}
+
+impl Default for ProtectionSettings {
+    fn default() -> Self {
+        ProtectionSettings {
+            secret: None,
+            min_submit_secs: 3,
+            token_max_age_secs: 86400,
+            spam_threshold: 5,
+            behind_proxy: false,
+            rate_limits: RateLimits::default(),
+        }
+    }
+}
+
+// Submissions per minute and address, by form
+#[derive(serde::Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct RateLimits {
+    pub contact: usize,
+    pub like: usize,
+    pub inbox: usize,
+    pub subscribe: usize,
+}
+
+impl RateLimits {
+    pub fn per_minute(&self, form: Form) -> usize {
+        match form {
+            Form::Contact => self.contact,
+            Form::Like => self.like,
+            // chat sessions are limited one by one, by the chat server
+            Form::Chat => usize::MAX,
+            Form::Inbox => self.inbox,
+            Form::Subscribe => self.subscribe,
+        }
+    }
+}
+
+impl Default for RateLimits {
+    fn default() -> Self {
+        RateLimits {
+            contact: 5,
+            like: 10,
+            inbox: 10,
+            subscribe: 5,
+        }
+    }
+}
+
+// The public contact form. A second submission with the same email within
+// `dedupe_window_mins` is taken as a repeat and not stored again.
+#[derive(serde::Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct ContactSettings {
+    pub dedupe_window_mins: i64,
+    // ISO country phone numbers without a `+` are read in, e.g. "US"
+    pub default_region: String,
+}
+
+impl ContactSettings {
+    pub fn region(&self) -> Region {
+        region(&self.default_region)
+    }
+}
+
+impl Default for ContactSettings {
+    fn default() -> Self {
+        ContactSettings {
+            dedupe_window_mins: 60,
+            default_region: "US".to_owned(),
+        }
+    }
+}
+
+// What happens on SIGTERM: chat clients are told to reconnect after
+// `reconnect_after_secs`, and everything has `timeout_secs` to wind down
+#[derive(serde::Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct ShutdownSettings {
+    pub timeout_secs: u64,
+    pub reconnect_after_secs: u64,
+}
+
+impl Default for ShutdownSettings {
+    fn default() -> Self {
+        ShutdownSettings {
+            timeout_secs: 30,
+            reconnect_after_secs: 5,
+        }
+    }
+}
+
+// Admin pages and endpoints are behind HTTP basic auth
+#[derive(serde::Deserialize, Clone, Debug, Default)]
+pub struct AdminSettings {
+    pub username: String,
+    // argon2 hash, `demcru hash-password` makes one. Without it nobody is admin.
+    pub password_hash: Option<String>,
+}
+
+// The database, SQLite or Postgres by the scheme of `url`. SQLite files are
+// created if they are missing. The server migrates it when it starts unless
+// `migrate_on_start` is off, a schema a newer binary migrated is left alone,
+// or refused with `refuse_newer_schema`.
+#[derive(serde::Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct DatabaseSettings {
+    pub url: String,
+    pub max_connections: u32,
+    pub migrate_on_start: bool,
+    pub refuse_newer_schema: bool,
+}
+
+impl Default for DatabaseSettings {
+    fn default() -> Self {
+        DatabaseSettings {
+            url: "sqlite:data.db".to_owned(),
+            max_connections: 5,
+            migrate_on_start: true,
+            refuse_newer_schema: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Backend {
+    Sqlite,
+    Postgres,
 }
 
 impl DatabaseSettings {
-    pub fn connection_string(&self) -> String {
-        format!(
-            "postgres://{}:{}@{}:{}/{}",
-            self.username, self.password, self.host, self.port, self.database_name
-        )
+    pub fn backend(&self) -> Option<Backend> {
+        if self.url.starts_with("sqlite:") {
+            Some(Backend::Sqlite)
+        } else if self.url.starts_with("postgres://") || self.url.starts_with("postgresql://") {
+            Some(Backend::Postgres)
+        } else {
+            None
+        }
+    }
+}
+
+// Where the server listens
+#[derive(serde::Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct ApplicationSettings {
+    pub host: String,
+    pub port: u16,
+}
+
+impl Default for ApplicationSettings {
+    fn default() -> Self {
+        ApplicationSettings {
+            host: "127.0.0.1".to_owned(),
+            port: 8080,
+        }
+    }
+}
+
+impl ApplicationSettings {
+    pub fn address(&self) -> String {
+        format!("{}:{}", self.host, self.port)
+    }
+}
+
+// Files the server reads, relative to where it runs
+#[derive(serde::Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct PathSettings {
+    // the posts, see `Config`
+    pub blog: PathBuf,
+    pub templates: PathBuf,
+    pub static_files: PathBuf,
+}
+
+impl Default for PathSettings {
+    fn default() -> Self {
+        PathSettings {
+            blog: PathBuf::from("config/blog.yml"),
+            templates: PathBuf::from("templates"),
+            static_files: PathBuf::from("static"),
+        }
+    }
+}
+
+// Chat
+#[derive(serde::Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct ChatSettings {
+    // Rooms that are never cleaned up, `main` is always one of them
+    pub permanent_rooms: Vec<String>,
+    // How long an empty room lives before it is removed
+    pub empty_room_grace_secs: u64,
+    // Default member cap for every room, `None` means unlimited
+    pub max_room_members: Option<usize>,
+    // How long a dropped session can be resumed with its token
+    pub resume_grace_secs: u64,
+    // signs resume tokens, random on every start without one. Instances on a
+    // bus need the same one to take requests for each other's sessions.
+    pub resume_secret: Option<String>,
+    pub bots: Vec<BotSettings>,
+    // How long authors can edit or delete their messages
+    pub edit_window_secs: u64,
+    // argon2 hash of the password for `/mod`, moderators can delete any message
+    pub moderator_password: Option<String>,
+    // Where shared files are kept
+    pub attachments_path: String,
+    pub max_attachment_bytes: usize,
+    // Messages and their attachments are removed after this many days
+    pub history_retention_days: u64,
+    // How chat servers of several instances share their rooms
+    pub bus: BusSettings,
+}
+
+// `memory` keeps everything in this process, `postgres` relays rooms between
+// instances over LISTEN/NOTIFY on `channel`, in the Postgres database at `url`
+#[derive(serde::Deserialize, Clone, Debug, Default)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum BusSettings {
+    #[default]
+    Memory,
+    Postgres {
+        channel: String,
+        url: String,
+    },
+}
+
+// A bot answering questions about its keywords
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct BotSettings {
+    pub name: String,
+    pub rooms: Vec<String>,
+    pub replies: Vec<BotReply>,
+}
+
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct BotReply {
+    pub keyword: String,
+    pub reply: String,
+}
+
+impl Default for ChatSettings {
+    fn default() -> Self {
+        ChatSettings {
+            permanent_rooms: Vec::new(),
+            empty_room_grace_secs: 300,
+            max_room_members: None,
+            resume_grace_secs: 30,
+            resume_secret: None,
+            bots: Vec::new(),
+            edit_window_secs: 900,
+            moderator_password: None,
+            attachments_path: "attachments".to_owned(),
+            max_attachment_bytes: 5 * 1024 * 1024,
+            history_retention_days: 30,
+            bus: BusSettings::Memory,
+        }
+    }
+}
+
+/// Which overlay of `configuration/` goes over `base.yaml`, from `APP_ENVIRONMENT`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Environment {
+    Local,
+    Production,
+}
+
+impl Environment {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Environment::Local => "local",
+            Environment::Production => "production",
+        }
+    }
+}
+
+impl TryFrom<String> for Environment {
+    type Error = SettingsError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        match value.to_lowercase().as_str() {
+            "local" => Ok(Environment::Local),
+            "production" => Ok(Environment::Production),
+            _ => Err(SettingsError::invalid(
+                "APP_ENVIRONMENT",
+                format!("{value:?} is neither local nor production"),
+            )),
+        }
     }
 }
-pub fn get_config() -> Result<Settings, config::ConfigError> {
-    // let base_path = std::env::current_dir().expect("Failed to determne the c directory");
-    // let config_dir = base_path.join("configuration");
 
+#[derive(Debug)]
+pub enum SettingsError {
+    Load(config::ConfigError),
+    // `key` as in the files, e.g. `application.port`
+    Invalid { key: String, reason: String },
+}
+
+impl SettingsError {
+    fn invalid(key: &str, reason: impl Into<String>) -> Self {
+        SettingsError::Invalid {
+            key: key.to_owned(),
+            reason: reason.into(),
+        }
+    }
+}
+
+impl fmt::Display for SettingsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SettingsError::Load(err) => write!(f, "Failed to read the configuration: {err}"),
+            SettingsError::Invalid { key, reason } => write!(f, "{key}: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for SettingsError {}
+
+impl From<config::ConfigError> for SettingsError {
+    fn from(err: config::ConfigError) -> Self {
+        SettingsError::Load(err)
+    }
+}
+
+// Secrets that sign tokens, shorter ones are too easy to guess
+const MIN_SECRET_LEN: usize = 32;
+
+impl Settings {
+    /// Catches what would only fail once the server runs, naming the setting
+    pub fn validate(&self) -> Result<(), SettingsError> {
+        let invalid = |key: &str, reason: &str| Err(SettingsError::invalid(key, reason));
+        if self.application.host.trim().is_empty() {
+            return invalid("application.host", "must not be empty");
+        }
+        if self.application.port == 0 {
+            return invalid("application.port", "must be between 1 and 65535");
+        }
+        if self.database.backend().is_none() {
+            return invalid(
+                "database.url",
+                "must be a SQLite or Postgres URL, e.g. sqlite:data.db",
+            );
+        }
+        if self.database.max_connections == 0 {
+            return invalid("database.max_connections", "must be at least 1");
+        }
+        if !self.paths.blog.is_file() {
+            return invalid("paths.blog", "is not a file");
+        }
+        if !self.paths.templates.is_dir() {
+            return invalid("paths.templates", "is not a directory");
+        }
+        if !self.paths.static_files.is_dir() {
+            return invalid("paths.static_files", "is not a directory");
+        }
+        let secrets = [
+            ("chat.resume_secret", &self.chat.resume_secret),
+            ("protection.secret", &self.protection.secret),
+        ];
+        for (key, secret) in secrets {
+            if secret
+                .as_ref()
+                .is_some_and(|secret| secret.len() < MIN_SECRET_LEN)
+            {
+                return invalid(key, "must be at least 32 characters");
+            }
+        }
+        if let BusSettings::Postgres { url, .. } = &self.chat.bus {
+            if !url.starts_with("postgres://") && !url.starts_with("postgresql://") {
+                return invalid("chat.bus.url", "must be a Postgres URL");
+            }
+            // the instances check each other's tokens
+            for (key, secret) in secrets {
+                if secret.is_none() {
+                    return invalid(key, "must be set with a postgres chat.bus");
+                }
+            }
+        }
+        if self.protection.min_submit_secs >= self.protection.token_max_age_secs {
+            return invalid(
+                "protection.min_submit_secs",
+                "must be less than protection.token_max_age_secs",
+            );
+        }
+        if self.email.from.parse::<Mailbox>().is_err() {
+            return invalid("email.from", "is not an email address");
+        }
+        if let Some(owner) = &self.email.owner {
+            if owner.parse::<Mailbox>().is_err() {
+                return invalid("email.owner", "is not an email address");
+            }
+        }
+        if self.email.poll_interval_secs == 0 {
+            return invalid("email.poll_interval_secs", "must be at least 1");
+        }
+        if self.email.max_attempts == 0 {
+            return invalid("email.max_attempts", "must be at least 1");
+        }
+        let base_url = &self.newsletter.base_url;
+        if !(base_url.starts_with("http://") || base_url.starts_with("https://"))
+            || base_url.ends_with('/')
+        {
+            return invalid(
+                "newsletter.base_url",
+                "must be an http(s) URL without a trailing slash",
+            );
+        }
+        Ok(())
+    }
+}
+
+/// The settings of `configuration/`
+pub fn get_config() -> Result<Settings, SettingsError> {
+    get_config_from(Path::new("configuration"))
+}
+
+/// `base.yaml` of `dir`, then the file of the environment, then `APP_` variables
+/// with `__` between keys, e.g. `APP_APPLICATION__PORT=80` for `application.port`
+pub fn get_config_from(dir: &Path) -> Result<Settings, SettingsError> {
+    get_config_with(dir, std::env::vars().collect())
+}
+
+// The settings of `dir` with `variables` in place of those of the process
+fn get_config_with(
+    dir: &Path,
+    variables: HashMap<String, String>,
+) -> Result<Settings, SettingsError> {
+    let environment: Environment = variables
+        .get("APP_ENVIRONMENT")
+        .map_or("local", String::as_str)
+        .to_owned()
+        .try_into()?;
     let settings = config::Config::builder()
-        .add_source(config::File::new(
-            "configuration.yaml",
-            config::FileFormat::Yaml,
+        .add_source(config::File::from(dir.join("base.yaml")))
+        .add_source(config::File::from(
+            dir.join(format!("{}.yaml", environment.as_str())),
         ))
+        .add_source(
+            config::Environment::with_prefix("APP")
+                .prefix_separator("_")
+                .separator("__")
+                .try_parsing(true)
+                .source(Some(variables)),
+        )
         .build()?;
-    settings.try_deserialize::<Settings>()
+    let settings: Settings = settings.try_deserialize()?;
+    settings.validate()?;
+    Ok(settings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    // A configuration directory with `base.yaml` and `local.yaml`, removed when dropped
+    struct ConfigDir(PathBuf);
+
+    impl ConfigDir {
+        fn new(base: &str, local: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!("demcru-config-{}", Uuid::new_v4()));
+            std::fs::create_dir_all(&dir).unwrap();
+            std::fs::write(dir.join("base.yaml"), base).unwrap();
+            std::fs::write(dir.join("local.yaml"), local).unwrap();
+            ConfigDir(dir)
+        }
+
+        // The settings without the variables of whoever runs the tests
+        fn settings(&self) -> Result<Settings, SettingsError> {
+            self.settings_with(&[])
+        }
+
+        fn settings_with(&self, variables: &[(&str, &str)]) -> Result<Settings, SettingsError> {
+            let variables = variables
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect();
+            get_config_with(&self.0, variables)
+        }
+    }
+
+    impl Drop for ConfigDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn invalid_key(result: Result<Settings, SettingsError>) -> String {
+        match result {
+            Err(SettingsError::Invalid { key, .. }) => key,
+            Err(err) => panic!("{err}"),
+            Ok(_) => panic!("the settings passed"),
+        }
+    }
+
+    #[test]
+    fn the_environment_file_goes_over_the_base() {
+        let dir = ConfigDir::new(
+            "application: { host: \"127.0.0.1\" }\ncontacts: { default_region: \"US\", dedupe_window_mins: 5 }\n",
+            "contacts: { default_region: \"GB\" }\n",
+        );
+
+        let settings = dir.settings().unwrap();
+
+        assert_eq!(settings.contacts.default_region, "GB");
+        assert_eq!(settings.contacts.dedupe_window_mins, 5);
+        assert_eq!(settings.application.host, "127.0.0.1");
+        assert_eq!(settings.paths.templates, PathBuf::from("templates"));
+    }
+
+    #[test]
+    fn app_variables_override_the_files() {
+        let dir = ConfigDir::new("email: { security: \"starttls\" }\n", "");
+
+        let settings = dir
+            .settings_with(&[
+                ("APP_APPLICATION__PORT", "9090"),
+                ("APP_EMAIL__SECURITY", "tls"),
+            ])
+            .unwrap();
+
+        assert_eq!(settings.application.port, 9090);
+        assert_eq!(settings.email.security, SmtpSecurity::Tls);
+        let production = dir.settings_with(&[("APP_ENVIRONMENT", "production")]);
+        assert!(production.is_err(), "there is no production.yaml");
+    }
+
+    #[test]
+    fn errors_name_the_setting() {
+        let dir = ConfigDir::new("database: { url: \"mysql://localhost/blog\" }\n", "");
+        assert_eq!(invalid_key(dir.settings()), "database.url");
+
+        let dir = ConfigDir::new("", "paths: { blog: \"missing.yml\" }\n");
+        assert_eq!(invalid_key(dir.settings()), "paths.blog");
+
+        let dir = ConfigDir::new("newsletter: { base_url: \"example.com\" }\n", "");
+        assert_eq!(invalid_key(dir.settings()), "newsletter.base_url");
+
+        let dir = ConfigDir::new("protection: { secret: \"hunter2\" }\n", "");
+        assert_eq!(invalid_key(dir.settings()), "protection.secret");
+
+        let dir = ConfigDir::new("contacts: { dedupe_window_mins: \"soon\" }\n", "");
+        let err = dir.settings().err().unwrap().to_string();
+        assert!(err.contains("contacts.dedupe_window_mins"), "{err}");
+    }
+
+    #[test]
+    fn a_postgres_bus_needs_shared_secrets() {
+        let dir = ConfigDir::new("", "");
+        // as manifests/deploy.yaml sets it
+        let bus = [
+            ("APP_CHAT__BUS__KIND", "postgres"),
+            ("APP_CHAT__BUS__CHANNEL", "chat"),
+            ("APP_CHAT__BUS__URL", "postgres://localhost/chat"),
+        ];
+        assert_eq!(invalid_key(dir.settings_with(&bus)), "chat.resume_secret");
+
+        let secret = "s".repeat(32);
+        let resume = ("APP_CHAT__RESUME_SECRET", secret.as_str());
+        let variables = [&bus[..], &[resume]].concat();
+        assert_eq!(
+            invalid_key(dir.settings_with(&variables)),
+            "protection.secret"
+        );
+
+        let protection = ("APP_PROTECTION__SECRET", secret.as_str());
+        let variables = [&variables[..], &[protection]].concat();
+        let settings = dir.settings_with(&variables).unwrap();
+        assert!(matches!(settings.chat.bus, BusSettings::Postgres { .. }));
+    }
+
+    #[test]
+    fn slugs_are_made_of_titles() {
+        assert_eq!(slug("Low level concurrency"), "low-level-concurrency");
+        assert_eq!(slug("  Rust 2024: what's new?"), "rust-2024-what-s-new");
+        assert_eq!(slug("¿?"), "");
+    }
+
+    #[test]
+    fn drafts_are_appended_and_read_back() {
+        let path = std::env::temp_dir().join(format!("demcru-blog-{}.yml", Uuid::new_v4()));
+        std::fs::write(&path, "title: \"Blog\"\ndescription: \"\"\ndefault: first\nposts:\n  - slug: first\n    title: First\n    author: Neil\n    date: 2023-11-07\n    body: Hi").unwrap();
+
+        let draft = Post::draft("Second: the \"sequel\"", "Neil");
+        Config::append_post(&path, &draft).unwrap();
+
+        let blog = Config::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(blog.problems().is_empty(), "{:?}", blog.problems());
+        let post = &blog.posts[1];
+        assert_eq!(post.slug, "second-the-sequel");
+        assert_eq!(post.title, "Second: the \"sequel\"");
+        assert_eq!(post.body.trim_end(), "Write the post here.");
+    }
+
+    #[test]
+    fn blog_problems_name_the_post() {
+        let post = |slug: &str, date: &str| Post {
+            slug: slug.to_owned(),
+            title: "A title".to_owned(),
+            author: "Neil".to_owned(),
+            date: date.to_owned(),
+            body: String::new(),
+        };
+        let blog = Config {
+            title: "Blog".to_owned(),
+            description: String::new(),
+            default: "gone".to_owned(),
+            posts: vec![
+                post("first", "2023-11-07"),
+                post("first", "2023-11-08"),
+                post("Second Post", "7 Nov 2023"),
+            ],
+        };
+
+        let problems = blog.problems();
+
+        assert_eq!(problems.len(), 4, "{problems:?}");
+        assert!(problems[0].contains("\"first\": the slug is taken"));
+        assert!(problems[1].contains("\"Second Post\": the slug"));
+        assert!(problems[2].contains("is not YYYY-MM-DD"));
+        assert!(problems[3].starts_with("default"));
+    }
 }
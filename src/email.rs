@@ -0,0 +1,361 @@
+use actix::prelude::*;
+use chrono::{DateTime, Utc};
+use handlebars::{Handlebars, RenderError};
+use lettre::{
+    message::{
+        header::{ContentType, HeaderName, HeaderValue},
+        Mailbox,
+    },
+    transport::smtp::{self, authentication::Credentials},
+    AsyncSmtpTransport, AsyncTransport, Message as EmailMessage, Tokio1Executor,
+};
+use serde::Serialize;
+use std::{fmt, sync::Arc, time::Duration};
+use uuid::Uuid;
+
+use crate::{
+    configuration::{EmailSettings, SmtpSecurity},
+    storage::OutboxRepository,
+};
+
+// Emails claimed at once by a delivery round
+const BATCH: i64 = 20;
+// A claimed email is left alone this long, in case another worker sends it
+const LEASE_SECS: i64 = 10 * 60;
+
+#[derive(Debug)]
+pub enum EmailError {
+    Template(RenderError),
+    Address(String),
+    Database(sqlx::Error),
+}
+
+impl fmt::Display for EmailError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EmailError::Template(err) => write!(f, "Failed to render email: {err}"),
+            EmailError::Address(address) => write!(f, "Not an email address: {address}"),
+            EmailError::Database(err) => write!(f, "Database error: {err:?}"),
+        }
+    }
+}
+
+impl std::error::Error for EmailError {}
+
+/// A rendered email, ready for the outbox
+#[derive(Debug, Clone, PartialEq)]
+pub struct Email {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+    // one-click unsubscribe of mailing list emails, RFC 8058
+    pub unsubscribe_url: Option<String>,
+}
+
+impl Email {
+    /// Renders `emails/<template>.subject` and the plain text `emails/<template>`
+    /// with `data`. The templates use `{{{ }}}`, there is no HTML to escape.
+    pub fn render(
+        hb: &Handlebars<'static>,
+        template: &str,
+        to: &str,
+        data: &impl Serialize,
+    ) -> Result<Email, EmailError> {
+        let subject = hb
+            .render(&format!("emails/{template}.subject"), data)
+            .map_err(EmailError::Template)?;
+        let body = hb
+            .render(&format!("emails/{template}"), data)
+            .map_err(EmailError::Template)?;
+        Ok(Email {
+            to: to.to_owned(),
+            subject: subject.trim().to_owned(),
+            body,
+            unsubscribe_url: None,
+        })
+    }
+
+    /// The recipient must be an address SMTP takes
+    pub fn check_address(&self) -> Result<(), EmailError> {
+        self.to
+            .parse::<Mailbox>()
+            .map(|_| ())
+            .map_err(|_| EmailError::Address(self.to.clone()))
+    }
+}
+
+/// A claimed email of the outbox
+#[derive(Debug, sqlx::FromRow)]
+pub struct PendingEmail {
+    pub id: Uuid,
+    pub recipient: String,
+    pub subject: String,
+    pub body: String,
+    pub unsubscribe_url: Option<String>,
+    pub attempts: i64,
+}
+
+/// An email of the outbox and how its delivery went
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct OutboxEntry {
+    pub id: Uuid,
+    pub recipient: String,
+    pub subject: String,
+    pub attempts: i64,
+    pub last_error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub sent_at: Option<DateTime<Utc>>,
+    pub failed_at: Option<DateTime<Utc>>,
+}
+
+/// Puts an email in the outbox, the worker sends it on its next round
+pub async fn queue_email(outbox: &dyn OutboxRepository, email: &Email) -> Result<Uuid, EmailError> {
+    email.check_address()?;
+    outbox
+        .queue_email(email)
+        .await
+        .map_err(EmailError::Database)
+}
+
+/// Queues emails and wakes the worker up for them
+#[derive(Clone)]
+pub struct Outbox {
+    outbox: Arc<dyn OutboxRepository>,
+    owner: Option<String>,
+    worker: Addr<EmailWorker>,
+}
+
+impl Outbox {
+    pub fn new(
+        outbox: Arc<dyn OutboxRepository>,
+        settings: &EmailSettings,
+        worker: Addr<EmailWorker>,
+    ) -> Self {
+        Outbox {
+            outbox,
+            owner: settings.owner.clone(),
+            worker,
+        }
+    }
+
+    pub async fn send(
+        &self,
+        hb: &Handlebars<'static>,
+        template: &str,
+        to: &str,
+        data: &impl Serialize,
+    ) -> Result<Uuid, EmailError> {
+        let email = Email::render(hb, template, to, data)?;
+        let id = queue_email(self.outbox.as_ref(), &email).await?;
+        self.worker.do_send(Deliver);
+        Ok(id)
+    }
+
+    /// Tells the owner, if there is an address for them. A notification that
+    /// cannot be queued is not worth failing a request for.
+    pub async fn notify_owner(
+        &self,
+        hb: &Handlebars<'static>,
+        template: &str,
+        data: &impl Serialize,
+    ) {
+        let Some(owner) = &self.owner else {
+            return;
+        };
+        if let Err(err) = self.send(hb, template, owner, data).await {
+            println!("Failed to queue {template} for the owner: {err}");
+        }
+    }
+}
+
+// Send what is due now
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Deliver;
+
+/// Sends the outbox over SMTP every `poll_interval_secs`, and right away when
+/// something is queued
+pub struct EmailWorker {
+    outbox: Arc<dyn OutboxRepository>,
+    settings: EmailSettings,
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+}
+
+impl EmailWorker {
+    pub fn new(
+        outbox: Arc<dyn OutboxRepository>,
+        settings: EmailSettings,
+    ) -> Result<Self, smtp::Error> {
+        let transport = transport(&settings)?;
+        Ok(EmailWorker {
+            outbox,
+            settings,
+            transport,
+        })
+    }
+}
+
+fn transport(settings: &EmailSettings) -> Result<AsyncSmtpTransport<Tokio1Executor>, smtp::Error> {
+    let builder = match settings.security {
+        SmtpSecurity::None => {
+            AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&settings.host)
+        }
+        SmtpSecurity::Starttls => {
+            AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&settings.host)?
+        }
+        SmtpSecurity::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(&settings.host)?,
+    };
+    let mut builder = builder
+        .port(settings.port)
+        .timeout(Some(Duration::from_secs(settings.timeout_secs)));
+    if let (Some(username), Some(password)) = (&settings.username, &settings.password) {
+        builder = builder.credentials(Credentials::new(username.clone(), password.clone()));
+    }
+    Ok(builder.build())
+}
+
+impl Actor for EmailWorker {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        // whatever was left from before a restart
+        ctx.notify(Deliver);
+        let interval = Duration::from_secs(self.settings.poll_interval_secs);
+        ctx.run_interval(interval, |_, ctx| ctx.notify(Deliver));
+    }
+}
+
+impl Handler<Deliver> for EmailWorker {
+    type Result = ();
+    fn handle(&mut self, _: Deliver, ctx: &mut Context<Self>) {
+        let outbox = self.outbox.clone();
+        let transport = self.transport.clone();
+        let settings = self.settings.clone();
+        // one round at a time, rounds asked for meanwhile wait their turn
+        ctx.wait(
+            async move {
+                if let Err(err) = deliver_due(outbox.as_ref(), &transport, &settings).await {
+                    println!("Failed to deliver the outbox: {err:?}");
+                }
+            }
+            .into_actor(self),
+        );
+    }
+}
+
+/// How long to wait after the `attempts`th failed attempt
+pub fn backoff(settings: &EmailSettings, attempts: u32) -> chrono::Duration {
+    let factor = 2u64.saturating_pow(attempts.saturating_sub(1));
+    let secs = settings
+        .backoff_secs
+        .saturating_mul(factor)
+        .min(settings.max_backoff_secs);
+    chrono::Duration::seconds(secs as i64)
+}
+
+async fn deliver_due(
+    outbox: &dyn OutboxRepository,
+    transport: &AsyncSmtpTransport<Tokio1Executor>,
+    settings: &EmailSettings,
+) -> Result<(), sqlx::Error> {
+    loop {
+        let now = Utc::now();
+        let lease = now + chrono::Duration::seconds(LEASE_SECS);
+        let due = outbox.claim_due_emails(now, lease, BATCH).await?;
+        let claimed = due.len() as i64;
+        for email in due {
+            let result = send(transport, &settings.from, &email).await;
+            record_attempt(outbox, settings, &email, result).await?;
+        }
+        if claimed < BATCH {
+            return Ok(());
+        }
+    }
+}
+
+// Err(permanent, reason)
+async fn send(
+    transport: &AsyncSmtpTransport<Tokio1Executor>,
+    from: &str,
+    email: &PendingEmail,
+) -> Result<(), (bool, String)> {
+    let from: Mailbox = from
+        .parse()
+        .map_err(|_| (false, format!("Not an email address: {from}")))?;
+    let to: Mailbox = email
+        .recipient
+        .parse()
+        .map_err(|_| (true, format!("Not an email address: {}", email.recipient)))?;
+    let mut message = EmailMessage::builder()
+        .from(from)
+        .to(to)
+        .subject(email.subject.clone())
+        .header(ContentType::TEXT_PLAIN);
+    if let Some(url) = &email.unsubscribe_url {
+        message = message
+            .raw_header(HeaderValue::new(
+                HeaderName::new_from_ascii_str("List-Unsubscribe"),
+                format!("<{url}>"),
+            ))
+            .raw_header(HeaderValue::new(
+                HeaderName::new_from_ascii_str("List-Unsubscribe-Post"),
+                "List-Unsubscribe=One-Click".to_owned(),
+            ));
+    }
+    let message = message
+        .body(email.body.clone())
+        .map_err(|err| (true, err.to_string()))?;
+    transport
+        .send(message)
+        .await
+        .map(|_| ())
+        .map_err(|err| (err.is_permanent(), err.to_string()))
+}
+
+async fn record_attempt(
+    outbox: &dyn OutboxRepository,
+    settings: &EmailSettings,
+    email: &PendingEmail,
+    result: Result<(), (bool, String)>,
+) -> Result<(), sqlx::Error> {
+    let now = Utc::now();
+    let attempts = email.attempts + 1;
+    match result {
+        Ok(()) => outbox.email_sent(email.id, attempts, now).await,
+        // the server will not take it, trying again will not change its mind
+        Err((permanent, err)) if permanent || attempts >= settings.max_attempts as i64 => {
+            println!(
+                "Gave up on email {} to {}: {err}",
+                email.id, email.recipient
+            );
+            outbox.email_failed(email.id, attempts, now, &err).await
+        }
+        Err((_, err)) => {
+            let next: DateTime<Utc> = now + backoff(settings, attempts as u32);
+            println!(
+                "Failed to send email {}, retrying at {next}: {err}",
+                email.id
+            );
+            outbox.retry_email(email.id, attempts, next, &err).await
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retries_back_off_exponentially_up_to_the_cap() {
+        let settings = EmailSettings {
+            backoff_secs: 60,
+            max_backoff_secs: 600,
+            ..EmailSettings::default()
+        };
+        let waits: Vec<i64> = (1..=6)
+            .map(|attempts| backoff(&settings, attempts).num_seconds())
+            .collect();
+        assert_eq!(waits, [60, 120, 240, 480, 600, 600]);
+        assert_eq!(backoff(&settings, 200).num_seconds(), 600);
+    }
+}
@@ -1,5 +1,9 @@
+pub mod auth;
 pub mod configuration;
+pub mod email;
+pub mod protection;
 pub mod routes;
 pub mod startup;
 pub mod models;
+pub mod storage;
 pub mod utils;
@@ -1,20 +1,278 @@
+use chrono::{DateTime, Utc};
+use clap::{Parser, Subcommand};
+use demcru::auth::hash_password;
+use demcru::configuration::{get_config, get_config_from, Config, Post, Settings, SettingsError};
+use demcru::routes::{import_contacts, render_transcript, send_post, TranscriptFormat};
+use demcru::startup::{run, templates};
+use demcru::storage::{MigrationState, Storage};
 use std::net::TcpListener;
-use demcru::startup::run;
-use demcru::configuration::get_config;
+use std::path::{Path, PathBuf};
 // use libsql_client::Client;
-use sqlx::sqlite::SqlitePool;
-use std::env;
+
+#[derive(Parser)]
+#[command(version, about = "Blog and chat server")]
+struct Cli {
+    /// Directory of `base.yaml` and the environment files, `configuration` by default
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Run the web server, the default
+    Serve {
+        /// Port to listen on instead of `application.port`
+        #[arg(long)]
+        port: Option<u16>,
+    },
+    /// Write the history of a chat room, deleted messages left out
+    Transcript {
+        room: String,
+        #[arg(long, value_enum, default_value_t)]
+        format: TranscriptFormat,
+        /// Only messages sent at or after this time, e.g. 2026-10-19T09:00:00Z
+        #[arg(long)]
+        from: Option<DateTime<Utc>>,
+        /// Only messages sent before this time
+        #[arg(long)]
+        to: Option<DateTime<Utc>>,
+        /// File to write, standard output by default
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+    },
+    /// Add the contacts of a CSV, e.g. scripts/contacts.csv. Nothing is
+    /// imported if a row is invalid, contacts already there are skipped.
+    ImportContacts { file: PathBuf },
+    /// Email a post to the confirmed subscribers, through the outbox of the
+    /// running server. A post is only ever sent once.
+    SendPost { slug: String },
+    /// Hash a password for `admin.password_hash` or `chat.moderator_password`
+    HashPassword { password: String },
+    /// Show, apply or revert the migrations of the database
+    Migrate {
+        #[command(subcommand)]
+        action: Option<MigrateAction>,
+    },
+    /// Check the settings and the blog, fails if anything is wrong
+    CheckConfig,
+    /// List the posts of the blog or start a new one
+    Posts {
+        #[command(subcommand)]
+        action: PostsAction,
+    },
+    /// Write everything in the database as JSON
+    Export {
+        /// File to write, standard output by default
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+    },
+}
+
+#[derive(Subcommand)]
+enum PostsAction {
+    /// The posts, oldest first, with their date and slug
+    List,
+    /// Add a post dated today to the end of the blog, for its body to be written
+    New {
+        title: String,
+        /// The author of the latest post by default
+        #[arg(long)]
+        author: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum MigrateAction {
+    /// Which migrations are applied, the default
+    Status,
+    /// Apply the migrations the database is missing
+    Apply,
+    /// Revert the latest migration
+    Revert {
+        /// Revert every migration after this version instead, 0 for all of them
+        #[arg(long)]
+        to: Option<i64>,
+    },
+}
+
+// The settings of `--config`, or of `configuration/`
+fn settings(dir: Option<&Path>) -> Result<Settings, SettingsError> {
+    match dir {
+        Some(dir) => get_config_from(dir),
+        None => get_config(),
+    }
+}
 
 #[actix_web::main]
 async fn main() -> anyhow::Result<()> {
-    let config = get_config().expect("Failed to read config");
-    let connection_pool = SqlitePool::connect(&env::var("DATABASE_URL")?)
+    let cli = Cli::parse();
+    let dir = cli.config.as_deref();
+    match cli.command.unwrap_or(Command::Serve { port: None }) {
+        Command::Serve { port } => {
+            let mut config = settings(dir)?;
+            if let Some(port) = port {
+                config.application.port = port;
+            }
+            serve(config).await
+        }
+        Command::Transcript {
+            room,
+            format,
+            from,
+            to,
+            output,
+        } => {
+            let config = settings(dir)?;
+            let storage = Storage::connect(&config.database).await?;
+            let lines = storage.chat.load_transcript(&room, from, to).await?;
+            let transcript = render_transcript(format, &room, &lines);
+            match output {
+                Some(path) => std::fs::write(path, transcript)?,
+                None => print!("{transcript}"),
+            }
+            Ok(())
+        }
+        Command::ImportContacts { file } => {
+            let config = settings(dir)?;
+            let storage = Storage::connect(&config.database).await?;
+            let csv = std::fs::read_to_string(&file)?;
+            let summary =
+                import_contacts(storage.contacts.as_ref(), &csv, config.contacts.region()).await?;
+            println!(
+                "Imported {} contacts, skipped {} duplicates",
+                summary.imported, summary.duplicates
+            );
+            Ok(())
+        }
+        Command::SendPost { slug } => {
+            let config = settings(dir)?;
+            let storage = Storage::connect(&config.database).await?;
+            let blog = Config::load(&config.paths.blog)?;
+            let post = blog
+                .posts
+                .iter()
+                .find(|post| post.slug == slug)
+                .ok_or_else(|| {
+                    anyhow::anyhow!("No post {slug} in {}", config.paths.blog.display())
+                })?;
+            let hb = templates(&config.paths.templates);
+            let queued =
+                send_post(storage.newsletter.as_ref(), &hb, &config.newsletter, post).await?;
+            println!("Queued {queued} emails of \"{}\"", post.title);
+            Ok(())
+        }
+        Command::HashPassword { password } => {
+            let hash = hash_password(&password).map_err(|err| anyhow::anyhow!("{err}"))?;
+            println!("{hash}");
+            Ok(())
+        }
+        Command::Migrate { action } => {
+            let config = settings(dir)?;
+            let storage = Storage::connect(&config.database).await?;
+            match action.unwrap_or(MigrateAction::Status) {
+                MigrateAction::Status => (),
+                MigrateAction::Apply => storage.migrate(config.contacts.region()).await?,
+                MigrateAction::Revert { to } => {
+                    for version in storage.revert(to).await? {
+                        println!("Reverted {version}");
+                    }
+                }
+            }
+            for migration in storage.migrations().await? {
+                let state = match migration.state {
+                    MigrationState::Pending => "pending",
+                    MigrationState::Applied => "applied",
+                    MigrationState::Unknown => "unknown, from a newer binary",
+                };
+                println!("{} {state} {}", migration.version, migration.description);
+            }
+            Ok(())
+        }
+        Command::CheckConfig => {
+            let config = settings(dir)?;
+            let path = config.paths.blog.display();
+            let blog = Config::load(&config.paths.blog)
+                .map_err(|err| anyhow::anyhow!("Failed to read the blog {path}: {err}"))?;
+            let problems = blog.problems();
+            for problem in &problems {
+                eprintln!("{path}: {problem}");
+            }
+            if !problems.is_empty() {
+                anyhow::bail!("{} problems in the blog", problems.len());
+            }
+            println!(
+                "The settings and the {} posts of {path} are fine",
+                blog.posts.len()
+            );
+            Ok(())
+        }
+        Command::Posts { action } => {
+            let config = settings(dir)?;
+            let blog = Config::load(&config.paths.blog)?;
+            match action {
+                PostsAction::List => {
+                    for post in &blog.posts {
+                        println!("{} {} {}", post.date, post.slug, post.title);
+                    }
+                }
+                PostsAction::New { title, author } => {
+                    let author = author
+                        .or_else(|| blog.posts.last().map(|post| post.author.clone()))
+                        .ok_or_else(|| anyhow::anyhow!("Nobody wrote a post yet, pass --author"))?;
+                    let post = Post::draft(&title, &author);
+                    if post.slug.is_empty() {
+                        anyhow::bail!("A title needs letters or digits for the slug");
+                    }
+                    if blog.posts.iter().any(|other| other.slug == post.slug) {
+                        anyhow::bail!("There is a post {} already", post.slug);
+                    }
+                    Config::append_post(&config.paths.blog, &post)?;
+                    println!(
+                        "Added {} to {}, write its body there",
+                        post.slug,
+                        config.paths.blog.display()
+                    );
+                }
+            }
+            Ok(())
+        }
+        Command::Export { output } => {
+            let config = settings(dir)?;
+            let storage = Storage::connect(&config.database).await?;
+            let export = serde_json::to_string_pretty(&storage.export().await?)?;
+            match output {
+                Some(path) => std::fs::write(path, export)?,
+                None => println!("{export}"),
+            }
+            Ok(())
+        }
+    }
+}
+
+async fn serve(config: Settings) -> anyhow::Result<()> {
+    let storage = Storage::connect(&config.database)
         .await
-       .expect("Failed to connect to sqlite.");
+        .expect("Failed to connect to the database.");
+    let region = config.contacts.region();
+    if region.is_none() {
+        println!(
+            "contacts.default_region {:?} is no country code, only international phone numbers are taken",
+            config.contacts.default_region
+        );
+    }
+    if config.database.migrate_on_start {
+        let applied = storage
+            .migrate_on_start(config.database.refuse_newer_schema, region)
+            .await?;
+        if applied > 0 {
+            println!("Applied {applied} migrations");
+        }
+    }
 
     // let _db = Client::from_env().await.unwrap();
-    let address = format!("0.0.0.0:{}", config.application_port);
-    let listener = TcpListener::bind(address)?;
-    run(listener, connection_pool)?.await?;
+    let listener = TcpListener::bind(config.application.address())?;
+    run(listener, storage, config)?.await?;
     Ok(())
 }
@@ -0,0 +1,88 @@
+use actix::Message;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use uuid::Uuid;
+
+// Changes to the persisted chat history
+#[derive(Message, Debug, Clone)]
+#[rtype(result = "()")]
+pub enum HistoryOp {
+    Sent {
+        id: Uuid,
+        room: String,
+        session: Uuid,
+        author: String,
+        text: String,
+        sent_at: DateTime<Utc>,
+        attachment: Option<Uuid>,
+        // sent in a private room
+        private: bool,
+    },
+    Edited {
+        id: Uuid,
+        text: String,
+        edited_at: DateTime<Utc>,
+    },
+    // the text is dropped, only the fact that there was a message remains
+    Deleted {
+        id: Uuid,
+        deleted_at: DateTime<Utc>,
+    },
+    Reacted {
+        id: Uuid,
+        session: Uuid,
+        emoji: String,
+        active: bool,
+    },
+    // `name` was mentioned while nobody had it. Only kept for names that
+    // wrote something before, anyone could be mentioned otherwise.
+    Mentioned {
+        id: Uuid,
+        name: String,
+    },
+}
+
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct PendingMention {
+    pub id: Uuid,
+    pub room: String,
+    pub from: String,
+    pub text: String,
+    pub sent_at: DateTime<Utc>,
+}
+
+// A message as it reads now: deleted ones are left out, edits are applied
+#[derive(Serialize, Debug, Clone, PartialEq, Eq, sqlx::FromRow)]
+pub struct TranscriptLine {
+    pub author: String,
+    pub text: String,
+    pub sent_at: DateTime<Utc>,
+    pub edited_at: Option<DateTime<Utc>>,
+}
+
+// The row of an attachment, its file is on disk
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct AttachmentRecord {
+    pub id: Uuid,
+    pub name: String,
+    pub mime: String,
+    pub size: i64,
+    pub has_thumbnail: bool,
+    pub uploaded_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, Debug, Clone, sqlx::FromRow)]
+pub struct InboxMessage {
+    pub from_owner: bool,
+    pub body: String,
+    pub sent_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, Debug, Clone, sqlx::FromRow)]
+pub struct Conversation {
+    pub id: Uuid,
+    pub name: String,
+    pub updated_at: DateTime<Utc>,
+    // latest message, from either side
+    pub last: String,
+}
@@ -2,13 +2,214 @@ use sqlx::types::Uuid;
 use sqlx::types::chrono::Utc;
 use chrono::DateTime;
 use serde::{Deserialize, Serialize};
+use sqlx::{Database, Encode, QueryBuilder, Type};
 
-#[derive(Debug, sqlx::FromRow, Deserialize, Serialize)]
+#[derive(Debug, Clone, sqlx::FromRow, Deserialize, Serialize)]
 pub struct Contacts {
     pub id: Uuid,
     pub first_name: String,
     pub last_name: String,
-    pub phone: Option<i16>,
+    // E.164, e.g. +15552340123
+    pub phone: Option<String>,
     pub email: String,
+    pub message: Option<String>,
+    // what the site owner keeps about them
+    pub notes: Option<String>,
     pub created_at: DateTime<Utc>,
 }
+
+// Missing fields are empty, so they get an error message rather than a 400
+#[derive(Deserialize, Serialize, Default, Debug, Clone)]
+#[serde(default)]
+pub struct ContactForm {
+    pub first_name: String,
+    pub last_name: String,
+    pub email: String,
+    pub phone: String,
+    pub message: String,
+}
+
+// A submission that passed validation
+#[derive(Serialize, Debug, PartialEq)]
+pub struct NewContact {
+    pub first_name: String,
+    pub last_name: String,
+    // lower-cased
+    pub email: String,
+    // E.164
+    pub phone: Option<String>,
+    pub message: Option<String>,
+}
+
+/// An edit or removal of a contact, with the contact before and after as JSON
+#[derive(Serialize, Debug, sqlx::FromRow)]
+pub struct ContactChange {
+    pub contact_id: Uuid,
+    pub action: String,
+    pub changed_by: String,
+    pub changed_at: DateTime<Utc>,
+    pub before: String,
+    pub after: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SortColumn {
+    FirstName,
+    LastName,
+    Email,
+    #[default]
+    CreatedAt,
+}
+
+impl SortColumn {
+    pub const ALL: [(SortColumn, &'static str); 4] = [
+        (SortColumn::FirstName, "First name"),
+        (SortColumn::LastName, "Last name"),
+        (SortColumn::Email, "Email"),
+        (SortColumn::CreatedAt, "Created at"),
+    ];
+
+    pub fn column(self) -> &'static str {
+        match self {
+            SortColumn::FirstName => "first_name",
+            SortColumn::LastName => "last_name",
+            SortColumn::Email => "email",
+            SortColumn::CreatedAt => "created_at",
+        }
+    }
+
+    // What a contact is sorted by, as it goes into a cursor
+    pub fn value_of(self, contact: &Contacts) -> String {
+        match self {
+            SortColumn::FirstName => contact.first_name.clone(),
+            SortColumn::LastName => contact.last_name.clone(),
+            SortColumn::Email => contact.email.clone(),
+            SortColumn::CreatedAt => contact.created_at.to_rfc3339(),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    Asc,
+    // newest first by default
+    #[default]
+    Desc,
+}
+
+impl Direction {
+    pub fn flip(self) -> Self {
+        match self {
+            Direction::Asc => Direction::Desc,
+            Direction::Desc => Direction::Asc,
+        }
+    }
+}
+
+// Search, sort and page of the contacts table, as in its URL
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct ContactQuery {
+    #[serde(default)]
+    pub q: String,
+    #[serde(default)]
+    pub sort: SortColumn,
+    #[serde(default)]
+    pub dir: Direction,
+    // the last contact of the previous page, from `Cursor::encode`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub after: Option<String>,
+}
+
+impl ContactQuery {
+    pub fn url(&self, path: &str) -> String {
+        match serde_urlencoded::to_string(self) {
+            Ok(query) => format!("{path}?{query}"),
+            Err(_) => path.to_owned(),
+        }
+    }
+}
+
+// Where a page ends: the sort value and id of its last contact. Opaque to
+// clients, the id breaks ties between equal values.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct Cursor {
+    pub value: String,
+    pub id: Uuid,
+}
+
+impl Cursor {
+    pub fn encode(&self) -> String {
+        hex::encode(serde_json::to_vec(self).unwrap_or_default())
+    }
+
+    pub fn decode(cursor: &str) -> Option<Cursor> {
+        serde_json::from_slice(&hex::decode(cursor).ok()?).ok()
+    }
+}
+
+fn escape_like(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+/// The query behind `ContactRepository::find_contacts`, the same for every
+/// backend but the operator matching case-insensitively. None when the
+/// cursor cannot be read.
+pub(crate) fn contacts_sql<'args, DB>(
+    query: &ContactQuery,
+    after: Option<&Cursor>,
+    limit: Option<usize>,
+    like: &str,
+) -> Option<QueryBuilder<'args, DB>>
+where
+    DB: Database,
+    String: Encode<'args, DB> + Type<DB>,
+    DateTime<Utc>: Encode<'args, DB> + Type<DB>,
+    Uuid: Encode<'args, DB> + Type<DB>,
+    i64: Encode<'args, DB> + Type<DB>,
+{
+    let mut sql = QueryBuilder::<DB>::new(
+        "SELECT id, first_name, last_name, email, phone, message, notes, created_at
+        FROM contacts WHERE 1 = 1",
+    );
+    let q = query.q.trim();
+    if !q.is_empty() {
+        let pattern = format!("%{}%", escape_like(q));
+        sql.push(format!(" AND (first_name {like} "))
+            .push_bind(pattern.clone())
+            .push(format!(" ESCAPE '\\' OR last_name {like} "))
+            .push_bind(pattern.clone())
+            .push(format!(
+                " ESCAPE '\\' OR first_name || ' ' || last_name {like} "
+            ))
+            .push_bind(pattern.clone())
+            .push(format!(" ESCAPE '\\' OR email {like} "))
+            .push_bind(pattern)
+            .push(" ESCAPE '\\')");
+    }
+    let column = query.sort.column();
+    let (order, compare) = match query.dir {
+        Direction::Asc => ("ASC", ">"),
+        Direction::Desc => ("DESC", "<"),
+    };
+    if let Some(cursor) = after {
+        sql.push(format!(" AND ({column}, id) {compare} ("));
+        match query.sort {
+            // bound as a timestamp so it compares like the stored ones
+            SortColumn::CreatedAt => {
+                let value = DateTime::parse_from_rfc3339(&cursor.value).ok()?;
+                sql.push_bind(value.with_timezone(&Utc))
+            }
+            _ => sql.push_bind(cursor.value.clone()),
+        };
+        sql.push(", ").push_bind(cursor.id).push(")");
+    }
+    sql.push(format!(" ORDER BY {column} {order}, id {order}"));
+    if let Some(limit) = limit {
+        sql.push(" LIMIT ").push_bind(limit as i64);
+    }
+    Some(sql)
+}
@@ -0,0 +1,57 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::HashSet;
+
+use super::contacts::ContactForm;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum RowStatus {
+    New,
+    // the email is taken, by a contact or an earlier row
+    Duplicate,
+    Invalid,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ImportedContact {
+    pub first_name: String,
+    pub last_name: String,
+    pub email: String,
+    pub phone: Option<String>,
+    pub message: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+// A data row of the CSV and what importing it would do
+#[derive(Serialize, Debug, Clone)]
+pub struct ImportRow {
+    // as a spreadsheet numbers it, the header is line 1
+    pub line: u64,
+    pub status: RowStatus,
+    // the fields as they are in the file
+    pub values: ContactForm,
+    pub contact: Option<ImportedContact>,
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Debug, Default, PartialEq)]
+pub struct ImportSummary {
+    pub imported: usize,
+    pub duplicates: usize,
+}
+
+/// Marks rows whose email is already taken, by a contact or an earlier row
+pub fn mark_duplicates(taken: Vec<String>, rows: &mut [ImportRow]) {
+    let mut taken: HashSet<String> = taken
+        .into_iter()
+        .map(|email| email.to_lowercase())
+        .collect();
+    for row in rows.iter_mut() {
+        if let Some(contact) = &row.contact {
+            if !taken.insert(contact.email.clone()) {
+                row.status = RowStatus::Duplicate;
+            }
+        }
+    }
+}
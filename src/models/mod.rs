@@ -1 +1,6 @@
+pub mod chat;
 pub mod contacts;
+pub mod import;
+pub mod newsletter;
+pub mod phone;
+pub mod quarantine;
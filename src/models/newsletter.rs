@@ -0,0 +1,19 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+/// Whom a post goes to
+#[derive(Debug, sqlx::FromRow)]
+pub struct Subscriber {
+    pub email: String,
+    pub unsubscribe_token: String,
+}
+
+/// A subscription as the admin sees it
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct Subscription {
+    pub email: String,
+    pub status: String,
+    pub created_at: DateTime<Utc>,
+    pub confirmed_at: Option<DateTime<Utc>>,
+    pub unsubscribed_at: Option<DateTime<Utc>>,
+}
@@ -0,0 +1,85 @@
+use phonenumber::{country, Mode, PhoneNumber};
+
+use crate::storage::ContactRepository;
+
+// Country a number without a `+` prefix is read in, e.g. `US` or `MX`.
+// `None` takes only international numbers.
+pub type Region = Option<country::Id>;
+
+pub fn region(code: &str) -> Region {
+    code.trim().to_uppercase().parse().ok()
+}
+
+fn parse(input: &str, region: Region) -> Option<PhoneNumber> {
+    let number = phonenumber::parse(region, input).ok()?;
+    phonenumber::is_valid(&number).then_some(number)
+}
+
+/// A phone number as typed, normalized to E.164, e.g. `+15552340123`
+pub fn normalize_phone(input: &str, region: Region) -> Result<String, String> {
+    parse(input, region)
+        .map(|number| number.format().mode(Mode::E164).to_string())
+        .ok_or_else(|| "That does not look like a phone number".to_owned())
+}
+
+/// An E.164 number in international format for people, e.g. `+1 555-234-0123`
+pub fn display_phone(e164: &str) -> String {
+    match parse(e164, None) {
+        Some(number) => number.format().mode(Mode::International).to_string(),
+        None => e164.to_owned(),
+    }
+}
+
+/// Converts the numbers contacts had before phones were E.164, the ones
+/// that are not numbers in `region` are moved to their notes. Returns how
+/// many were converted and how many moved.
+pub async fn convert_legacy_phones(
+    contacts: &dyn ContactRepository,
+    region: Region,
+) -> Result<(usize, usize), sqlx::Error> {
+    let (mut converted, mut moved) = (0, 0);
+    for (id, legacy_phone) in contacts.legacy_phones().await? {
+        let old = legacy_phone.to_string();
+        match normalize_phone(&old, region) {
+            Ok(phone) => {
+                contacts.set_phone(id, &phone).await?;
+                converted += 1;
+            }
+            Err(_) => {
+                let note = format!("Phone before E.164: {old}");
+                contacts.move_legacy_phone_to_notes(id, &note).await?;
+                moved += 1;
+            }
+        }
+    }
+    Ok((converted, moved))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn numbers_are_read_in_the_default_region() {
+        let us = region("us");
+        assert_eq!(
+            normalize_phone("(201) 555-0123", us).unwrap(),
+            "+12015550123"
+        );
+        assert_eq!(
+            normalize_phone("+52 55 1234 5678", us).unwrap(),
+            "+525512345678"
+        );
+        // without a region only international numbers are taken
+        assert!(normalize_phone("(201) 555-0123", None).is_err());
+        assert!(normalize_phone("59", us).is_err());
+        assert!(normalize_phone("call me", us).is_err());
+        assert_eq!(region("nowhere"), None);
+    }
+
+    #[test]
+    fn numbers_are_displayed_internationally() {
+        assert_eq!(display_phone("+12015550123"), "+1 201-555-0123");
+        assert_eq!(display_phone("not a number"), "not a number");
+    }
+}
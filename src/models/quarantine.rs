@@ -0,0 +1,14 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use uuid::Uuid;
+
+#[derive(Serialize, Debug, sqlx::FromRow)]
+pub struct Quarantined {
+    pub id: Uuid,
+    pub form: String,
+    pub ip: String,
+    pub payload: String,
+    pub score: i64,
+    pub reasons: String,
+    pub received_at: DateTime<Utc>,
+}
@@ -0,0 +1,370 @@
+use crate::{configuration::ProtectionSettings, storage::QuarantineRepository};
+use actix_web::HttpRequest;
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+// Past this many tracked addresses the limiter forgets the idle ones
+const MAX_TRACKED: usize = 4096;
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+// Words that turn up in spam and rarely in a note to a portfolio
+const SPAM_WORDS: [&str; 12] = [
+    "casino",
+    "viagra",
+    "cialis",
+    "crypto",
+    "bitcoin",
+    "forex",
+    "loan",
+    "backlinks",
+    "seo services",
+    "porn",
+    "click here",
+    "work from home",
+];
+
+/// The public forms, each with a rate limit of its own
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Form {
+    Contact,
+    Like,
+    Chat,
+    Inbox,
+    Subscribe,
+}
+
+impl Form {
+    pub fn name(self) -> &'static str {
+        match self {
+            Form::Contact => "contact",
+            Form::Like => "like",
+            Form::Chat => "chat",
+            Form::Inbox => "inbox",
+            Form::Subscribe => "subscribe",
+        }
+    }
+}
+
+/// The fields every protected form sends along with its own
+#[derive(Deserialize, Serialize, Default, Debug, Clone)]
+#[serde(default)]
+pub struct FormProof {
+    // from `FormGuard::issue_token` when the page was rendered
+    pub form_token: String,
+    // the honeypot, empty unless a bot filled it in
+    pub website: String,
+}
+
+/// A form with its proof, `T` is what the handler reads
+#[derive(Deserialize, Debug)]
+pub struct Protected<T> {
+    #[serde(flatten)]
+    pub proof: FormProof,
+    #[serde(flatten)]
+    pub fields: T,
+}
+
+/// What to do with a submission. Rejected and quarantined ones are answered
+/// like accepted ones, so bots have nothing to learn from.
+#[derive(Debug, PartialEq)]
+pub enum Verdict {
+    Accept,
+    Reject(&'static str),
+    Quarantine { score: u32, reasons: Vec<String> },
+}
+
+/// Honeypots, signed timing tokens, per-address rate limits and content
+/// scoring, shared by every public form
+pub struct FormGuard {
+    settings: ProtectionSettings,
+    key: Vec<u8>,
+    hits: Mutex<HashMap<(String, Form), VecDeque<Instant>>>,
+}
+
+// Leaves out the settings too, they hold the secret of the key
+impl fmt::Debug for FormGuard {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("FormGuard").finish_non_exhaustive()
+    }
+}
+
+impl FormGuard {
+    pub fn new(settings: ProtectionSettings) -> Self {
+        // without a configured secret, tokens do not outlive the process
+        let key = match &settings.secret {
+            Some(secret) => secret.as_bytes().to_vec(),
+            None => rand::thread_rng().gen::<[u8; 32]>().to_vec(),
+        };
+        FormGuard {
+            settings,
+            key,
+            hits: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn mac(&self, form: Form, issued: i64) -> Hmac<Sha256> {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.key).expect("hmac takes keys of any size");
+        mac.update(format!("{}:{issued}", form.name()).as_bytes());
+        mac
+    }
+
+    /// A token for a page that shows `form`, signed with the time it was issued
+    pub fn issue_token(&self, form: Form) -> String {
+        self.token_at(form, Utc::now().timestamp())
+    }
+
+    fn token_at(&self, form: Form, issued: i64) -> String {
+        let signature = self.mac(form, issued).finalize().into_bytes();
+        format!("{issued}.{}", hex::encode(signature))
+    }
+
+    // Seconds since a valid token was issued
+    fn token_age(&self, form: Form, token: &str) -> Option<i64> {
+        let (issued, signature) = token.split_once('.')?;
+        let issued: i64 = issued.parse().ok()?;
+        let signature = hex::decode(signature).ok()?;
+        self.mac(form, issued).verify_slice(&signature).ok()?;
+        Some(Utc::now().timestamp() - issued)
+    }
+
+    /// Whether `token` was issued for `form` and is not stale yet, for
+    /// connections that check it once, when they open
+    pub fn valid_token(&self, form: Form, token: &str) -> bool {
+        self.token_age(form, token)
+            .is_some_and(|age| age <= self.settings.token_max_age_secs as i64)
+    }
+
+    /// Whom a request came from, the proxy's word for it only when configured
+    pub fn client_ip(&self, req: &HttpRequest) -> String {
+        let ip = if self.settings.behind_proxy {
+            req.connection_info()
+                .realip_remote_addr()
+                .map(str::to_owned)
+        } else {
+            req.peer_addr().map(|addr| addr.ip().to_string())
+        };
+        ip.unwrap_or_else(|| "unknown".to_owned())
+    }
+
+    // Counts a submission, false once the address is over the limit of the form
+    fn within_rate(&self, ip: &str, form: Form) -> bool {
+        let limit = self.settings.rate_limits.per_minute(form);
+        let now = Instant::now();
+        let mut hits = self.hits.lock().expect("rate limiter lock poisoned");
+        if hits.len() > MAX_TRACKED {
+            hits.retain(|_, times| {
+                times.retain(|time| now.duration_since(*time) < RATE_WINDOW);
+                !times.is_empty()
+            });
+        }
+        let times = hits.entry((ip.to_owned(), form)).or_default();
+        while times
+            .front()
+            .is_some_and(|time| now.duration_since(*time) >= RATE_WINDOW)
+        {
+            times.pop_front();
+        }
+        if times.len() >= limit {
+            return false;
+        }
+        times.push_back(now);
+        true
+    }
+
+    /// Checks a submission of `form`, `text` is what people wrote in it
+    pub fn check(&self, ip: &str, form: Form, proof: &FormProof, text: Option<&str>) -> Verdict {
+        // every attempt counts against the limit, even the ones turned away
+        if !self.within_rate(ip, form) {
+            return Verdict::Reject("rate limit");
+        }
+        if !proof.website.is_empty() {
+            return Verdict::Reject("honeypot");
+        }
+        match self.token_age(form, &proof.form_token) {
+            None => return Verdict::Reject("bad token"),
+            Some(age) if age < self.settings.min_submit_secs as i64 => {
+                return Verdict::Reject("too fast")
+            }
+            Some(age) if age > self.settings.token_max_age_secs as i64 => {
+                return Verdict::Reject("stale token")
+            }
+            Some(_) => (),
+        }
+        match text {
+            Some(text) => self.score(text),
+            None => Verdict::Accept,
+        }
+    }
+
+    /// What `check` makes of `text` alone, for connections whose token was
+    /// checked once when they opened
+    pub fn score(&self, text: &str) -> Verdict {
+        let (score, reasons) = spam_score(text);
+        if score >= self.settings.spam_threshold {
+            Verdict::Quarantine { score, reasons }
+        } else {
+            Verdict::Accept
+        }
+    }
+}
+
+/// Keeps a submission that scored as spam for the admin to review
+pub async fn quarantine(
+    repository: &dyn QuarantineRepository,
+    form: Form,
+    ip: &str,
+    payload: serde_json::Value,
+    score: u32,
+    reasons: &[String],
+) -> Result<(), sqlx::Error> {
+    println!(
+        "Quarantined a {} submission from {ip}, scored {score}",
+        form.name()
+    );
+    repository
+        .quarantine(
+            form.name(),
+            ip,
+            &payload.to_string(),
+            score as i64,
+            &reasons.join(", "),
+        )
+        .await
+}
+
+/// How spammy a text looks, with what gave it away
+pub fn spam_score(text: &str) -> (u32, Vec<String>) {
+    let lower = text.to_lowercase();
+    let mut score = 0;
+    let mut reasons = Vec::new();
+
+    let links = ["http://", "https://", "www."]
+        .iter()
+        .map(|prefix| lower.matches(prefix).count() as u32)
+        .sum::<u32>();
+    if links > 0 {
+        score += 2 * links;
+        reasons.push(format!("{links} links"));
+    }
+    if lower.contains("[url") || lower.contains("<a href") {
+        score += 3;
+        reasons.push("link markup".to_owned());
+    }
+    for word in SPAM_WORDS {
+        if lower.contains(word) {
+            score += 2;
+            reasons.push(format!("mentions {word}"));
+        }
+    }
+    let letters: Vec<char> = text.chars().filter(|c| c.is_alphabetic()).collect();
+    let upper = letters.iter().filter(|c| c.is_uppercase()).count();
+    if letters.len() >= 20 && upper * 10 >= letters.len() * 7 {
+        score += 1;
+        reasons.push("shouting".to_owned());
+    }
+    (score, reasons)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::configuration::ProtectionSettings;
+
+    fn guard() -> FormGuard {
+        FormGuard::new(ProtectionSettings {
+            secret: Some("test".to_owned()),
+            ..ProtectionSettings::default()
+        })
+    }
+
+    fn proof(form_token: String) -> FormProof {
+        FormProof {
+            form_token,
+            website: String::new(),
+        }
+    }
+
+    #[test]
+    fn tokens_must_be_signed_for_the_form_and_old_enough() {
+        let guard = guard();
+        let old = Utc::now().timestamp() - 10;
+        let token = guard.token_at(Form::Contact, old);
+        assert_eq!(
+            guard.check("a", Form::Contact, &proof(token.clone()), None),
+            Verdict::Accept
+        );
+        // a token is only good for its own form
+        assert_eq!(
+            guard.check("a", Form::Chat, &proof(token.clone()), None),
+            Verdict::Reject("bad token")
+        );
+        let forged = token.replacen(&old.to_string(), &(old - 1).to_string(), 1);
+        assert_eq!(
+            guard.check("a", Form::Contact, &proof(forged), None),
+            Verdict::Reject("bad token")
+        );
+        assert_eq!(
+            guard.check(
+                "a",
+                Form::Contact,
+                &proof(guard.issue_token(Form::Contact)),
+                None
+            ),
+            Verdict::Reject("too fast")
+        );
+        let stale = guard.token_at(Form::Contact, old - 2 * 86400);
+        assert_eq!(
+            guard.check("a", Form::Contact, &proof(stale), None),
+            Verdict::Reject("stale token")
+        );
+    }
+
+    #[test]
+    fn honeypots_and_rates_are_checked_per_address_and_form() {
+        let guard = guard();
+        let token = guard.token_at(Form::Contact, Utc::now().timestamp() - 10);
+        let mut filled = proof(token.clone());
+        filled.website = "http://spam.example".to_owned();
+        assert_eq!(
+            guard.check("a", Form::Contact, &filled, None),
+            Verdict::Reject("honeypot")
+        );
+
+        let limit = guard.settings.rate_limits.contact;
+        // the honeypot submission counted too
+        for _ in 1..limit {
+            assert_eq!(
+                guard.check("a", Form::Contact, &proof(token.clone()), None),
+                Verdict::Accept
+            );
+        }
+        assert_eq!(
+            guard.check("a", Form::Contact, &proof(token.clone()), None),
+            Verdict::Reject("rate limit")
+        );
+        assert_eq!(
+            guard.check("b", Form::Contact, &proof(token), None),
+            Verdict::Accept
+        );
+    }
+
+    #[test]
+    fn links_and_spam_words_add_up() {
+        assert_eq!(spam_score("Hi, I liked your post on actors!").0, 0);
+        assert_eq!(spam_score("My work: https://example.com").0, 2);
+        let (score, reasons) =
+            spam_score("BEST CASINO BONUS [url=http://a.example]here[/url] www.b.example");
+        assert!(score >= 5, "{score}");
+        assert!(reasons.contains(&"mentions casino".to_owned()));
+        assert!(reasons.contains(&"link markup".to_owned()));
+    }
+}
@@ -2,31 +2,42 @@ use serde_json::json;
 use handlebars::Handlebars;
 use actix_web::{web, Responder, HttpResponse};
 
-use crate::configuration::Config;
+use crate::{
+    configuration::Config,
+    protection::{Form, FormGuard},
+};
 
-pub async fn blog(hb: web::Data<Handlebars<'_>>, config: web::Data<Config>) -> impl Responder {
+pub async fn blog(
+    hb: web::Data<Handlebars<'_>>,
+    config: web::Data<Config>,
+    guard: web::Data<FormGuard>,
+) -> impl Responder {
     let default = config.default.clone();
-    current(hb, config, default)
+    current(hb, config, guard, default)
 }
 
 pub async fn detail(
     hb: web::Data<Handlebars<'_>>,
     config: web::Data<Config>,
+    guard: web::Data<FormGuard>,
     path: web::Path<String>,
 ) -> impl Responder {
-    current(hb, config, path.into_inner())
+    current(hb, config, guard, path.into_inner())
 }
 
 pub fn current(
     hb: web::Data<Handlebars>,
     config: web::Data<Config>,
+    guard: web::Data<FormGuard>,
     current: String,
 ) -> impl Responder {
     let data = json!({
         "title": config.title,
         "description": config.description,
         "posts": config.posts,
-        "current": current
+        "current": current,
+        // the subscribe form
+        "form_token": guard.issue_token(Form::Subscribe),
     });
     let body = hb.render("blog", &data).unwrap();
     HttpResponse::Ok().body(body)
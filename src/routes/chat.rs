@@ -1,377 +0,0 @@
-use actix::prelude::*;
-use actix_web::{
-    web::{self, Data},
-    Error, HttpRequest, HttpResponse,
-};
-use actix_web_actors::ws;
-use handlebars::Handlebars;
-use rand::{self, rngs::ThreadRng, Rng};
-use serde_json::json;
-use std::{
-    collections::{HashMap, HashSet},
-    sync::{
-        atomic::{AtomicUsize, Ordering},
-        Arc,
-    },
-    time::{Duration, Instant},
-};
-
-// Server
-// Chat server sends this message to session
-#[derive(Message)]
-#[rtype(result = "()")]
-pub struct Message(pub String);
-
-// New chat session is created
-#[derive(Message)]
-#[rtype(usize)]
-pub struct Connect {
-    pub addr: Recipient<Message>,
-}
-
-// Session is disconected
-#[derive(Message)]
-#[rtype(result = "()")]
-pub struct Disconnect {
-    pub id: usize,
-}
-
-// Send message to specific room
-#[derive(Message)]
-#[rtype(result = "()")]
-pub struct ClientMessage {
-    pub id: usize,
-    pub msg: String,
-    pub room: String,
-}
-
-pub struct ListRooms;
-
-impl actix::Message for ListRooms {
-    type Result = Vec<String>;
-}
-
-// Join room, if room does not exists create new one.
-#[derive(Message)]
-#[rtype(result = "()")]
-pub struct Join {
-    pub id: usize,
-    pub name: String,
-}
-
-// `ChatServer` manages chat rooms and responsible for coordinating chat session
-pub struct ChatServer {
-    sessions: HashMap<usize, Recipient<Message>>,
-    rooms: HashMap<String, HashSet<usize>>,
-    rng: ThreadRng,
-    visitor_count: Arc<AtomicUsize>,
-}
-
-impl ChatServer {
-    pub fn new(visitor_count: Arc<AtomicUsize>) -> ChatServer {
-        let mut rooms = HashMap::new();
-        rooms.insert("main".to_owned(), HashSet::new());
-        ChatServer {
-            sessions: HashMap::new(),
-            rooms,
-            rng: rand::thread_rng(),
-            visitor_count,
-        }
-    }
-}
-
-impl ChatServer {
-    fn send_message(&self, room: &str, message: &str, skip_id: usize) {
-        if let Some(sessions) = self.rooms.get(room) {
-            for id in sessions {
-                if *id != skip_id {
-                    if let Some(addr) = self.sessions.get(id) {
-                        addr.do_send(Message(message.to_owned()));
-                    }
-                }
-            }
-        }
-    }
-}
-
-impl Actor for ChatServer {
-    // Simple Context to communicate with other actors.
-    type Context = Context<Self>;
-}
-
-// Register new session and assign unique id to this session
-impl Handler<Connect> for ChatServer {
-    type Result = usize;
-    fn handle(&mut self, msg: Connect, _: &mut Self::Context) -> Self::Result {
-        println!("Someone joined");
-        self.send_message("main", "Someone joined", 0);
-
-        // Register session with random id
-        let id = self.rng.gen::<usize>();
-        self.sessions.insert(id, msg.addr);
-
-        // auto join session to main room
-        self.rooms.entry("main".to_owned()).or_default().insert(id);
-
-        let count = self.visitor_count.fetch_add(1, Ordering::SeqCst);
-        self.send_message("main", &format!("Total visitors {count}"), 0);
-        id
-    }
-}
-
-// Handler for Disconnect message.
-impl Handler<Disconnect> for ChatServer {
-    type Result = ();
-    fn handle(&mut self, msg: Disconnect, _: &mut Context<Self>) {
-        println!("Someone disconnected");
-
-        let mut rooms: Vec<String> = Vec::new();
-        if self.sessions.remove(&msg.id).is_some() {
-            // remove session from all rooms
-            for (name, sessions) in &mut self.rooms {
-                if sessions.remove(&msg.id) {
-                    rooms.push(name.to_owned());
-                }
-            }
-        }
-        // send messages to another users
-        for room in rooms {
-            self.send_message(&room, "Someone dissconnected", 0);
-        }
-    }
-}
-
-// Handler for Message message
-impl Handler<ClientMessage> for ChatServer {
-    type Result = ();
-    fn handle(&mut self, msg: ClientMessage, _: &mut Context<Self>) {
-        self.send_message(&msg.room, msg.msg.as_str(), msg.id);
-    }
-}
-
-// Handler for `ListRooms` message.
-impl Handler<ListRooms> for ChatServer {
-    type Result = MessageResult<ListRooms>;
-    fn handle(&mut self, _: ListRooms, _: &mut Context<Self>) -> Self::Result {
-        let mut rooms = Vec::new();
-        for key in self.rooms.keys() {
-            rooms.push(key.to_owned())
-        }
-        MessageResult(rooms)
-    }
-}
-
-// Join room, send disconnect message to old rooms
-// send join message to new room
-impl Handler<Join> for ChatServer {
-    type Result = ();
-    fn handle(&mut self, msg: Join, _: &mut Context<Self>) {
-        let Join { id, name } = msg;
-        let mut rooms = Vec::new();
-        // remove session from all rooms
-        for (n, sessions) in &mut self.rooms {
-            if sessions.remove(&id) {
-                rooms.push(n.to_owned());
-            }
-        }
-        // send message to other users
-        for room in rooms {
-            self.send_message(&room, "Someone dissconneted", 0);
-        }
-        self.rooms.entry(name.clone()).or_default().insert(id);
-        self.send_message(&name, "Someone connected", id);
-    }
-}
-// Session
-const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
-// How long before lack of client response causes a timeout
-const CLIENT_TIMEOUT: Duration = Duration::from_secs(10);
-#[derive(Debug)]
-struct WsChatSession {
-    pub id: usize,
-    pub hb: Instant,
-    pub room: String,
-    pub name: Option<String>,
-    pub addr: Addr<ChatServer>,
-}
-
-impl WsChatSession {
-    // helper function that sends ping to client every 5 seconds
-    fn hb(&self, ctx: &mut ws::WebsocketContext<Self>) {
-        ctx.run_interval(HEARTBEAT_INTERVAL, |act, ctx| {
-            if Instant::now().duration_since(act.hb) > CLIENT_TIMEOUT {
-                println!("Websocket client heartbeat failed, disconnecting!");
-                // notify chat server
-                act.addr.do_send(Disconnect { id: act.id });
-                ctx.stop();
-                return;
-            }
-            ctx.ping(b"");
-        });
-    }
-}
-
-impl Actor for WsChatSession {
-    type Context = ws::WebsocketContext<Self>;
-    // Method is called on actor start.
-    // We register ws session with ChatServer
-    fn started(&mut self, ctx: &mut Self::Context) {
-        self.hb(ctx);
-        // register self in chat server. `AsyncContext::wait` register
-        // future within context, but context waits until this future resolves
-        // before processing any other events.
-        // HttpContext::state() is instance of WsChatSessionState, state is share
-        // across all routes within application
-        let addr = ctx.address();
-        self.addr
-            .send(Connect {
-                addr: addr.recipient(),
-            })
-            .into_actor(self)
-            .then(|res, act, ctx| {
-                match res {
-                    Ok(res) => act.id = res,
-                    _ => ctx.stop(),
-                }
-                fut::ready(())
-            })
-            .wait(ctx);
-    }
-
-    fn stopping(&mut self, _: &mut Self::Context) -> Running {
-        self.addr.do_send(Disconnect { id: self.id });
-        Running::Stop
-    }
-}
-
-impl Handler<Message> for WsChatSession {
-    type Result = ();
-    fn handle(&mut self, msg: Message, ctx: &mut Self::Context) {
-        ctx.text(msg.0);
-    }
-}
-
-impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WsChatSession {
-    fn handle(&mut self, msg: Result<ws::Message, ws::ProtocolError>, ctx: &mut Self::Context) {
-        let msg = match msg {
-            Err(_) => {
-                ctx.stop();
-                return;
-            }
-            Ok(msg) => msg,
-        };
-        log::debug!("WEBSOCKET MESSAGE: {msg:?}");
-        match msg {
-            ws::Message::Ping(msg) => {
-                self.hb = Instant::now();
-                ctx.pong(&msg)
-            }
-            ws::Message::Pong(_) => {
-                self.hb = Instant::now();
-            }
-            ws::Message::Text(text) => {
-                let m = text.trim();
-                if m.starts_with('/') {
-                    let v: Vec<&str> = m.splitn(2, ' ').collect();
-                    match v[0] {
-                        "/list" => {
-                            println!("List rooms");
-                            self.addr
-                                .send(ListRooms)
-                                .into_actor(self)
-                                .then(|res, _, ctx| {
-                                    match res {
-                                        Ok(rooms) => {
-                                            for room in rooms {
-                                                ctx.text(room);
-                                            }
-                                        }
-                                        _ => println!("Something is wrong"),
-                                    }
-                                    fut::ready(())
-                                })
-                                .wait(ctx)
-                        }
-                        "/join" => {
-                            if v.len() == 2 {
-                                self.room = v[1].to_owned();
-                                self.addr.do_send(Join {
-                                    id: self.id,
-                                    name: self.room.clone(),
-                                });
-                                ctx.text("joined")
-                            } else {
-                                ctx.text("!!! room name is required");
-                            }
-                        }
-                        "/name" => {
-                            if v.len() == 2 {
-                                self.name = Some(v[1].to_owned());
-                            } else {
-                                ctx.text("!!! name is required");
-                            }
-                        }
-                        _ => ctx.text(format!("!!! unknown command: {m:?}")),
-                    }
-                } else {
-                    let msg = if let Some(ref name) = self.name {
-                        format!("{name}: {m}")
-                    } else {
-                        m.to_owned()
-                    };
-                    // send message to chat server
-                    self.addr.do_send(ClientMessage {
-                        id: self.id,
-                        msg,
-                        room: self.room.clone(),
-                    })
-                }
-            }
-            ws::Message::Binary(_) => println!("Unexpected binary"),
-            ws::Message::Close(reason) => {
-                ctx.close(reason);
-                ctx.stop();
-            }
-            ws::Message::Continuation(_) => {
-                ctx.stop();
-            }
-            ws::Message::Nop => (),
-        }
-    }
-}
-
-// Routes
-pub async fn chat(hb: Data<Handlebars<'static>>) -> HttpResponse {
-    let content = hb.render("chat", &json!({})).unwrap();
-    HttpResponse::Ok()
-        .content_type("text/html; charset=utf-8")
-        .body(content)
-}
-
-pub async fn chat_route(
-    req: HttpRequest,
-    stream: web::Payload,
-    srv: web::Data<Addr<ChatServer>>,
-) -> Result<HttpResponse, Error> {
-    ws::start(
-        WsChatSession {
-            id: 0,
-            hb: Instant::now(),
-            room: "main".to_owned(),
-            name: None,
-            addr: srv.get_ref().clone(),
-        },
-        &req,
-        stream,
-    )
-}
-
-/// Displays state
-pub async fn get_count(count: web::Data<AtomicUsize>) -> HttpResponse {
-    let current_count = count.load(Ordering::SeqCst);
-    HttpResponse::Ok()
-        .body(format!(
-            r#"{}"#,
-            current_count
-        ))
-}
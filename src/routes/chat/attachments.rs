@@ -0,0 +1,507 @@
+use actix::Addr;
+use actix_files::NamedFile;
+use actix_web::{
+    http::header::{self, ContentDisposition, DispositionParam, DispositionType},
+    web, HttpRequest, HttpResponse, Responder, ResponseError,
+};
+use chrono::{DateTime, Utc};
+use futures_util::StreamExt;
+use image::ImageOutputFormat;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::{
+    io::{self, Cursor},
+    path::PathBuf,
+};
+use uuid::Uuid;
+
+use super::{ChatServer, MayShare, ShareAttachment, ShareError, VerifyToken};
+use crate::{
+    configuration::ChatSettings,
+    models::chat::AttachmentRecord,
+    storage::{ChatRepository, Storage},
+};
+
+// Longest side of an image thumbnail, in pixels
+const THUMBNAIL_SIZE: u32 = 240;
+
+// Files shared in the chat, kept on disk next to their metadata in SQLite
+#[derive(Clone, Debug)]
+pub struct Attachments {
+    dir: PathBuf,
+    max_bytes: usize,
+}
+
+// What a room is told about an attachment
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Attachment {
+    pub id: Uuid,
+    pub name: String,
+    pub mime: String,
+    pub size: usize,
+    pub url: String,
+    pub thumbnail: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum AttachmentError {
+    TooLarge(usize),
+    Unsupported,
+    Io(io::Error),
+    Database(sqlx::Error),
+}
+
+impl std::fmt::Display for AttachmentError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            AttachmentError::TooLarge(max) => write!(f, "files can be at most {max} bytes"),
+            AttachmentError::Unsupported => {
+                write!(f, "only images, PDF and plain text can be shared")
+            }
+            AttachmentError::Io(err) => write!(f, "Failed to store attachment: {err:?}"),
+            AttachmentError::Database(err) => write!(f, "Database error: {err:?}"),
+        }
+    }
+}
+
+impl ResponseError for AttachmentError {
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            AttachmentError::TooLarge(_) => HttpResponse::PayloadTooLarge().body(self.to_string()),
+            AttachmentError::Unsupported => {
+                HttpResponse::UnsupportedMediaType().body(self.to_string())
+            }
+            AttachmentError::Io(_) | AttachmentError::Database(_) => {
+                HttpResponse::InternalServerError().body("Failed to store attachment")
+            }
+        }
+    }
+}
+
+impl From<io::Error> for AttachmentError {
+    fn from(err: io::Error) -> Self {
+        AttachmentError::Io(err)
+    }
+}
+
+impl From<sqlx::Error> for AttachmentError {
+    fn from(err: sqlx::Error) -> Self {
+        AttachmentError::Database(err)
+    }
+}
+
+impl Attachments {
+    pub fn new(settings: &ChatSettings) -> Self {
+        Attachments {
+            dir: PathBuf::from(&settings.attachments_path),
+            max_bytes: settings.max_attachment_bytes,
+        }
+    }
+
+    pub fn max_bytes(&self) -> usize {
+        self.max_bytes
+    }
+
+    fn path(&self, id: Uuid) -> PathBuf {
+        self.dir.join(id.simple().to_string())
+    }
+
+    fn thumbnail_path(&self, id: Uuid) -> PathBuf {
+        self.dir.join(format!("{}.thumb.png", id.simple()))
+    }
+
+    // Check and keep an upload. The type comes from the content, never from
+    // what the client claims, so nothing is served as something it is not.
+    pub async fn store(
+        &self,
+        chat: &dyn ChatRepository,
+        name: &str,
+        bytes: Vec<u8>,
+    ) -> Result<Attachment, AttachmentError> {
+        if bytes.len() > self.max_bytes {
+            return Err(AttachmentError::TooLarge(self.max_bytes));
+        }
+        let mime = sniff_mime(&bytes).ok_or(AttachmentError::Unsupported)?;
+        let id = Uuid::new_v4();
+        let name = clean_name(name, mime);
+        let size = bytes.len();
+
+        // decoding images is too slow for the async workers
+        let (thumbnail, bytes) = if mime.starts_with("image/") {
+            actix_web::rt::task::spawn_blocking(move || (thumbnail(&bytes), bytes))
+                .await
+                .map_err(|err| AttachmentError::Io(io::Error::other(err)))?
+        } else {
+            (None, bytes)
+        };
+
+        tokio::fs::create_dir_all(&self.dir).await?;
+        tokio::fs::write(self.path(id), &bytes).await?;
+        if let Some(ref thumbnail) = thumbnail {
+            tokio::fs::write(self.thumbnail_path(id), thumbnail).await?;
+        }
+        let record = AttachmentRecord {
+            id,
+            name,
+            mime: mime.to_owned(),
+            size: size as i64,
+            has_thumbnail: thumbnail.is_some(),
+            uploaded_at: Utc::now(),
+        };
+        let saved = chat.save_attachment(&record).await;
+        if let Err(err) = saved {
+            self.remove_files(id).await;
+            return Err(err.into());
+        }
+        Ok(attachment(
+            id,
+            record.name,
+            record.mime,
+            size,
+            record.has_thumbnail,
+        ))
+    }
+
+    pub async fn remove_files(&self, id: Uuid) {
+        for path in [self.path(id), self.thumbnail_path(id)] {
+            match tokio::fs::remove_file(&path).await {
+                Ok(()) => (),
+                Err(err) if err.kind() == io::ErrorKind::NotFound => (),
+                Err(err) => println!("Failed to remove attachment {path:?}: {err:?}"),
+            }
+        }
+    }
+
+    // Remove an upload that was never shared
+    pub async fn remove(&self, chat: &dyn ChatRepository, id: Uuid) -> Result<(), sqlx::Error> {
+        self.remove_files(id).await;
+        chat.delete_attachment(id).await
+    }
+
+    // Remove the attachments of a deleted message
+    pub async fn remove_for_message(
+        &self,
+        chat: &dyn ChatRepository,
+        message: Uuid,
+    ) -> Result<(), sqlx::Error> {
+        for id in chat.attachments_of_message(message).await? {
+            self.remove_files(id).await;
+        }
+        chat.delete_attachments_of_message(message).await
+    }
+
+    // Remove attachments of messages sent before `before`, and uploads that
+    // were never shared in a room since `unshared_before`
+    pub async fn expire(
+        &self,
+        chat: &dyn ChatRepository,
+        before: DateTime<Utc>,
+        unshared_before: DateTime<Utc>,
+    ) -> Result<usize, sqlx::Error> {
+        let expired = chat.expired_attachments(before, unshared_before).await?;
+        for &id in &expired {
+            self.remove_files(id).await;
+            chat.delete_attachment(id).await?;
+        }
+        Ok(expired.len())
+    }
+}
+
+fn attachment(
+    id: Uuid,
+    name: String,
+    mime: String,
+    size: usize,
+    has_thumbnail: bool,
+) -> Attachment {
+    let url = format!("/chat/attachments/{id}");
+    Attachment {
+        id,
+        name,
+        mime,
+        size,
+        thumbnail: has_thumbnail.then(|| format!("{url}/thumbnail")),
+        url,
+    }
+}
+
+// Allowed types, recognised by their first bytes
+fn sniff_mime(bytes: &[u8]) -> Option<&'static str> {
+    const SIGNATURES: [(&[u8], &str); 5] = [
+        (b"\x89PNG\r\n\x1a\n", "image/png"),
+        (b"\xff\xd8\xff", "image/jpeg"),
+        (b"GIF8", "image/gif"),
+        (b"%PDF-", "application/pdf"),
+        (b"RIFF", "image/webp"),
+    ];
+    for (signature, mime) in SIGNATURES {
+        if bytes.starts_with(signature) {
+            // RIFF is a container, only WEBP is welcome
+            if mime == "image/webp" && bytes.get(8..12) != Some(b"WEBP") {
+                return None;
+            }
+            return Some(mime);
+        }
+    }
+    match std::str::from_utf8(bytes) {
+        Ok(text) if !text.is_empty() && !text.contains('\0') => Some("text/plain"),
+        _ => None,
+    }
+}
+
+// File names end up in headers and markup, keep a short and plain one
+fn clean_name(name: &str, mime: &str) -> String {
+    let name: String = name
+        .rsplit(['/', '\\'])
+        .next()
+        .unwrap_or_default()
+        .chars()
+        .filter(|c| c.is_alphanumeric() || matches!(c, '.' | '-' | '_' | ' '))
+        .take(100)
+        .collect();
+    let name = name.trim();
+    if !name.is_empty() {
+        return name.to_owned();
+    }
+    let extension = match mime {
+        "image/png" => "png",
+        "image/jpeg" => "jpg",
+        "image/gif" => "gif",
+        "image/webp" => "webp",
+        "application/pdf" => "pdf",
+        _ => "txt",
+    };
+    format!("upload.{extension}")
+}
+
+fn thumbnail(bytes: &[u8]) -> Option<Vec<u8>> {
+    let image = image::load_from_memory(bytes).ok()?;
+    let mut thumbnail = Cursor::new(Vec::new());
+    image
+        .thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE)
+        .write_to(&mut thumbnail, ImageOutputFormat::Png)
+        .ok()?;
+    Some(thumbnail.into_inner())
+}
+
+#[derive(Deserialize)]
+pub struct UploadParams {
+    // resume token of the session sharing the file
+    session: String,
+    name: Option<String>,
+}
+
+// Upload a file and share it in the room of the session, the body is the file
+pub async fn upload_attachment(
+    params: web::Query<UploadParams>,
+    mut payload: web::Payload,
+    srv: web::Data<Addr<ChatServer>>,
+    storage: web::Data<Storage>,
+    attachments: web::Data<Attachments>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let UploadParams { session, name } = params.into_inner();
+    let id = srv
+        .send(VerifyToken(session))
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    let Some(id) = id else {
+        return Ok(HttpResponse::NotFound().body("Unknown session"));
+    };
+    // before anything is kept of the upload
+    let allowed = srv
+        .send(MayShare { id })
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    match allowed {
+        Ok(()) => (),
+        Err(err @ ShareError::NoRoom) => return Ok(HttpResponse::Conflict().body(err.to_string())),
+        Err(err @ ShareError::TooFast) => {
+            return Ok(HttpResponse::TooManyRequests().body(err.to_string()))
+        }
+    }
+
+    let mut bytes = Vec::new();
+    while let Some(chunk) = payload.next().await {
+        bytes.extend_from_slice(&chunk?);
+        if bytes.len() > attachments.max_bytes() {
+            return Err(AttachmentError::TooLarge(attachments.max_bytes()).into());
+        }
+    }
+    let name = name.unwrap_or_default();
+    let attachment = attachments
+        .store(storage.chat.as_ref(), &name, bytes)
+        .await?;
+    let attachment_id = attachment.id;
+    let shared = srv
+        .send(ShareAttachment { id, attachment })
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    if !shared {
+        // the session left its room while uploading
+        attachments
+            .remove(storage.chat.as_ref(), attachment_id)
+            .await
+            .map_err(AttachmentError::Database)?;
+        return Ok(HttpResponse::Conflict().body(ShareError::NoRoom.to_string()));
+    }
+    Ok(HttpResponse::Created().json(json!({ "id": attachment_id })))
+}
+
+async fn open(
+    req: &HttpRequest,
+    chat: &dyn ChatRepository,
+    id: Uuid,
+    path: PathBuf,
+    thumbnail: bool,
+) -> Result<HttpResponse, actix_web::Error> {
+    let row = chat
+        .find_attachment(id)
+        .await
+        .map_err(AttachmentError::Database)?;
+    let Some(row) = row else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    if thumbnail && !row.has_thumbnail {
+        return Ok(HttpResponse::NotFound().finish());
+    }
+    let (mime, disposition) = match thumbnail {
+        true => ("image/png".to_owned(), DispositionType::Inline),
+        // images are shown in the page, anything else is downloaded
+        false if row.mime.starts_with("image/") => (row.mime, DispositionType::Inline),
+        false => (row.mime, DispositionType::Attachment),
+    };
+    let file = NamedFile::open_async(path)
+        .await?
+        .set_content_type(
+            mime.parse()
+                .map_err(actix_web::error::ErrorInternalServerError)?,
+        )
+        .set_content_disposition(ContentDisposition {
+            disposition,
+            parameters: vec![DispositionParam::Filename(row.name)],
+        });
+    let mut response = file.respond_to(req).map_into_boxed_body();
+    response.headers_mut().insert(
+        header::X_CONTENT_TYPE_OPTIONS,
+        header::HeaderValue::from_static("nosniff"),
+    );
+    Ok(response)
+}
+
+pub async fn get_attachment(
+    req: HttpRequest,
+    path: web::Path<Uuid>,
+    storage: web::Data<Storage>,
+    attachments: web::Data<Attachments>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let id = path.into_inner();
+    open(&req, storage.chat.as_ref(), id, attachments.path(id), false).await
+}
+
+pub async fn get_thumbnail(
+    req: HttpRequest,
+    path: web::Path<Uuid>,
+    storage: web::Data<Storage>,
+    attachments: web::Data<Attachments>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let id = path.into_inner();
+    open(
+        &req,
+        storage.chat.as_ref(),
+        id,
+        attachments.thumbnail_path(id),
+        true,
+    )
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::SQLITE_MIGRATIONS;
+    use sqlx::sqlite::SqlitePoolOptions;
+
+    async fn setup() -> (Storage, Attachments) {
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        SQLITE_MIGRATIONS.run(&pool).await.unwrap();
+        let dir = std::env::temp_dir().join(format!("demcru-{}", Uuid::new_v4()));
+        let attachments = Attachments {
+            dir,
+            max_bytes: 64 * 1024,
+        };
+        (Storage::sqlite(pool), attachments)
+    }
+
+    fn png() -> Vec<u8> {
+        let mut png = Cursor::new(Vec::new());
+        image::RgbImage::new(400, 300)
+            .write_to(&mut png, ImageOutputFormat::Png)
+            .unwrap();
+        png.into_inner()
+    }
+
+    #[actix::test]
+    async fn images_get_a_thumbnail_and_expire_with_their_files() {
+        let (storage, attachments) = setup().await;
+        let stored = attachments
+            .store(storage.chat.as_ref(), "cat.png", png())
+            .await
+            .unwrap();
+        assert_eq!(stored.mime, "image/png");
+        assert!(stored.thumbnail.is_some());
+        let thumbnail = std::fs::read(attachments.thumbnail_path(stored.id)).unwrap();
+        let thumbnail = image::load_from_memory(&thumbnail).unwrap();
+        assert_eq!((thumbnail.width(), thumbnail.height()), (240, 180));
+
+        // never shared in a room
+        let later = Utc::now() + chrono::Duration::seconds(1);
+        assert_eq!(
+            attachments
+                .expire(storage.chat.as_ref(), later, later)
+                .await
+                .unwrap(),
+            1
+        );
+        assert!(!attachments.path(stored.id).exists());
+        assert!(!attachments.thumbnail_path(stored.id).exists());
+    }
+
+    #[actix::test]
+    async fn large_and_unknown_files_are_refused() {
+        let (storage, attachments) = setup().await;
+        let large = vec![b'a'; 64 * 1024 + 1];
+        assert!(matches!(
+            attachments
+                .store(storage.chat.as_ref(), "notes.txt", large)
+                .await,
+            Err(AttachmentError::TooLarge(_))
+        ));
+        assert!(matches!(
+            attachments
+                .store(storage.chat.as_ref(), "a.exe", b"MZ\x90\0".to_vec())
+                .await,
+            Err(AttachmentError::Unsupported)
+        ));
+    }
+
+    #[test]
+    fn types_come_from_the_content() {
+        assert_eq!(sniff_mime(b"\x89PNG\r\n\x1a\n...."), Some("image/png"));
+        assert_eq!(sniff_mime(b"RIFF\0\0\0\0WEBPVP8 "), Some("image/webp"));
+        assert_eq!(sniff_mime(b"RIFF\0\0\0\0AVI LIST"), None);
+        assert_eq!(sniff_mime(b"just some notes"), Some("text/plain"));
+        assert_eq!(sniff_mime(b"MZ\x90\0\x03\0\0\0"), None);
+        assert_eq!(sniff_mime(b""), None);
+    }
+
+    #[test]
+    fn file_names_are_cleaned() {
+        assert_eq!(clean_name("../../etc/passwd", "text/plain"), "passwd");
+        assert_eq!(clean_name("<script>.png", "image/png"), "script.png");
+        assert_eq!(clean_name("", "application/pdf"), "upload.pdf");
+    }
+}
@@ -0,0 +1,53 @@
+use crate::configuration::BotSettings;
+
+// A server-side participant. Bots sit in their rooms like any member and
+// may answer the messages sent there.
+pub trait ChatBot {
+    fn name(&self) -> &str;
+    // rooms the bot joins when it is added, they are kept around for it
+    fn rooms(&self) -> Vec<String>;
+    // Called for every message of a user in one of the bot's rooms,
+    // the reply is sent to the room under the bot's name
+    fn on_message(&mut self, room: &str, from: &str, text: &str) -> Option<String>;
+}
+
+// Answers messages that mention one of its keywords, set up in the configuration
+pub struct FaqBot {
+    name: String,
+    rooms: Vec<String>,
+    // lowercase keyword and reply, the first keyword found wins
+    replies: Vec<(String, String)>,
+}
+
+impl FaqBot {
+    pub fn new(settings: &BotSettings) -> Self {
+        let replies = settings
+            .replies
+            .iter()
+            .map(|reply| (reply.keyword.to_lowercase(), reply.reply.to_owned()))
+            .collect();
+        FaqBot {
+            name: settings.name.to_owned(),
+            rooms: settings.rooms.clone(),
+            replies,
+        }
+    }
+}
+
+impl ChatBot for FaqBot {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn rooms(&self) -> Vec<String> {
+        self.rooms.clone()
+    }
+
+    fn on_message(&mut self, _: &str, _: &str, text: &str) -> Option<String> {
+        let text = text.to_lowercase();
+        self.replies
+            .iter()
+            .find(|(keyword, _)| text.contains(keyword.as_str()))
+            .map(|(_, reply)| reply.to_owned())
+    }
+}
@@ -0,0 +1,223 @@
+use actix::prelude::*;
+use futures_util::future::LocalBoxFuture;
+use serde::{Deserialize, Serialize};
+use sqlx::{
+    postgres::{PgListener, PgPool},
+    query,
+};
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::sync::{mpsc, oneshot};
+use uuid::Uuid;
+
+use super::{Attachment, Member};
+
+// How long to wait before trying to listen again when the database is away
+const RETRY_INTERVAL: Duration = Duration::from_secs(5);
+
+// What one chat server tells the chat servers of the other instances
+#[derive(Message, Serialize, Deserialize, Debug, Clone)]
+#[rtype(result = "()")]
+pub struct BusMessage {
+    // instance that published it, every instance hears its own messages too
+    pub origin: Uuid,
+    pub event: BusEvent,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum BusEvent {
+    // A private room was created on the origin. Rooms carry the password hash
+    // they have on the origin, `secret`, and are only the same room on another
+    // instance if it has the same one there.
+    PrivateRoom {
+        room: String,
+        secret: String,
+    },
+    // a protocol event, already JSON, for everyone in a room
+    Room {
+        room: String,
+        secret: Option<String>,
+        event: String,
+    },
+    // who is in a room on the origin, replaces whatever it said before
+    Members {
+        room: String,
+        secret: Option<String>,
+        members: Vec<BusMember>,
+    },
+    // a `mention` event for whoever has the name, for members of the room only if it is private
+    Mention {
+        room: String,
+        name: String,
+        secret: Option<String>,
+        event: String,
+    },
+    // a protocol event for a single session, whichever instance has it
+    Direct {
+        session: Uuid,
+        event: String,
+    },
+    // input of a session, for the instance that has it, from the one it was posted to
+    Input {
+        session: Uuid,
+        text: String,
+    },
+    // an upload to share in the room of a session, likewise
+    Attachment {
+        session: Uuid,
+        attachment: Attachment,
+    },
+    // a session changes a message, for the instance it was posted on
+    Change {
+        session: Uuid,
+        // whether the session is a moderator of every room on its instance
+        moderator: bool,
+        message: Uuid,
+        change: MessageChange,
+    },
+    // a new instance is listening, the others tell it who is where
+    Hello,
+    // the origin shuts down, its members are gone with it
+    Gone,
+}
+
+// What a session does to a message of another instance
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum MessageChange {
+    Edit { text: String },
+    Delete,
+    React { emoji: String },
+}
+
+// A member of a room on the origin. Other instances know its session by it,
+// to take requests for it that come to them.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BusMember {
+    pub session: Uuid,
+    #[serde(flatten)]
+    pub member: Member,
+}
+
+// Fan-out of chat events between the instances of the app
+pub trait ChatBus {
+    fn publish(&self, message: &BusMessage);
+    // Deliver what is published to `server`, resolves once it is listening
+    fn subscribe(&self, server: Recipient<BusMessage>) -> LocalBoxFuture<'static, ()>;
+}
+
+// Bus within one process, the default. With a single chat server there is
+// nobody else on it, which is how a lone instance works.
+#[derive(Clone, Default)]
+pub struct LocalBus {
+    subscribers: Arc<Mutex<Vec<Recipient<BusMessage>>>>,
+}
+
+impl ChatBus for LocalBus {
+    fn publish(&self, message: &BusMessage) {
+        let subscribers = self.subscribers.lock().expect("bus lock poisoned");
+        for subscriber in subscribers.iter() {
+            subscriber.do_send(message.clone());
+        }
+    }
+
+    fn subscribe(&self, server: Recipient<BusMessage>) -> LocalBoxFuture<'static, ()> {
+        self.subscribers
+            .lock()
+            .expect("bus lock poisoned")
+            .push(server);
+        Box::pin(async {})
+    }
+}
+
+// Bus over Postgres LISTEN/NOTIFY, every instance listens on the same channel.
+// Payloads are capped at 8000 bytes by Postgres, bigger events are dropped.
+pub struct PgBus {
+    pool: PgPool,
+    channel: String,
+    // notifications go out one at a time so they arrive in the order they were sent
+    outbox: mpsc::UnboundedSender<String>,
+}
+
+impl PgBus {
+    pub fn new(pool: PgPool, channel: impl Into<String>) -> Self {
+        let channel = channel.into();
+        let (outbox, mut pending) = mpsc::unbounded_channel::<String>();
+        let (notify_pool, notify_channel) = (pool.clone(), channel.clone());
+        // connections have to be dropped within Tokio, which the actors' local tasks are not
+        tokio::spawn(async move {
+            while let Some(payload) = pending.recv().await {
+                let sent = query("SELECT pg_notify($1, $2)")
+                    .bind(&notify_channel)
+                    .bind(&payload)
+                    .execute(&notify_pool)
+                    .await;
+                if let Err(err) = sent {
+                    println!("Failed to publish on the chat bus: {err:?}");
+                }
+            }
+        });
+        PgBus {
+            pool,
+            channel,
+            outbox,
+        }
+    }
+}
+
+impl ChatBus for PgBus {
+    fn publish(&self, message: &BusMessage) {
+        match serde_json::to_string(message) {
+            Ok(payload) => {
+                let _ = self.outbox.send(payload);
+            }
+            Err(err) => println!("Failed to serialize bus message: {err:?}"),
+        }
+    }
+
+    fn subscribe(&self, server: Recipient<BusMessage>) -> LocalBoxFuture<'static, ()> {
+        let (pool, channel) = (self.pool.clone(), self.channel.clone());
+        let (ready, listening) = oneshot::channel();
+        tokio::spawn(async move {
+            let mut listener = loop {
+                match listen(&pool, &channel).await {
+                    Ok(listener) => break listener,
+                    Err(err) => {
+                        println!("Failed to listen on the chat bus: {err:?}");
+                        tokio::time::sleep(RETRY_INTERVAL).await;
+                    }
+                }
+            };
+            let _ = ready.send(());
+            // until the chat server is gone
+            while server.connected() {
+                // the listener reconnects by itself, notifications sent
+                // while it was away are lost
+                match listener.recv().await {
+                    Ok(notification) => {
+                        match serde_json::from_str::<BusMessage>(notification.payload()) {
+                            Ok(message) => server.do_send(message),
+                            Err(err) => println!("Ignoring bus message: {err:?}"),
+                        }
+                    }
+                    Err(err) => {
+                        println!("Chat bus connection failed: {err:?}");
+                        tokio::time::sleep(RETRY_INTERVAL).await;
+                    }
+                }
+            }
+        });
+        Box::pin(async move {
+            let _ = listening.await;
+        })
+    }
+}
+
+async fn listen(pool: &PgPool, channel: &str) -> Result<PgListener, sqlx::Error> {
+    let mut listener = PgListener::connect_with(pool).await?;
+    listener.listen(channel).await?;
+    Ok(listener)
+}
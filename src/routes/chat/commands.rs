@@ -0,0 +1,504 @@
+use actix::prelude::*;
+use chrono::Utc;
+use rand::Rng;
+use serde::Serialize;
+use std::{collections::BTreeMap, rc::Rc};
+use uuid::Uuid;
+
+use super::{
+    Authenticate, ChatEvent, ChatServer, CreateInvite, DeleteMessage, EditError, EditMessage, Join,
+    ListRooms, React, SetLimit, SetName, SetTopic, Typing,
+};
+use crate::configuration::Post;
+
+// A slash command. `/name args` runs the command registered as `name`
+// with everything after the first space as `args`.
+pub trait ChatCommand {
+    fn name(&self) -> &'static str;
+    // arguments as shown by `/help`, e.g. "room [secret]"
+    fn usage(&self) -> &'static str {
+        ""
+    }
+    fn description(&self) -> &'static str;
+    // commands sent by the chat page itself are left out of `/help`
+    fn hidden(&self) -> bool {
+        false
+    }
+    fn run(&self, server: &mut ChatServer, id: Uuid, args: &str, ctx: &mut Context<ChatServer>);
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct CommandHelp {
+    pub usage: String,
+    pub description: String,
+}
+
+// Commands known to the chat server, looked up by name
+#[derive(Clone)]
+pub struct CommandRegistry {
+    commands: BTreeMap<&'static str, Rc<dyn ChatCommand>>,
+}
+
+impl CommandRegistry {
+    pub fn empty() -> Self {
+        CommandRegistry {
+            commands: BTreeMap::new(),
+        }
+    }
+
+    // Add a command, replacing one registered under the same name
+    pub fn register(&mut self, command: impl ChatCommand + 'static) {
+        self.commands.insert(command.name(), Rc::new(command));
+    }
+
+    pub fn get(&self, name: &str) -> Option<Rc<dyn ChatCommand>> {
+        self.commands.get(name).cloned()
+    }
+
+    pub fn help(&self) -> Vec<CommandHelp> {
+        self.commands
+            .values()
+            .filter(|command| !command.hidden())
+            .map(|command| CommandHelp {
+                usage: format!("/{} {}", command.name(), command.usage())
+                    .trim_end()
+                    .to_owned(),
+                description: command.description().to_owned(),
+            })
+            .collect()
+    }
+}
+
+// Everything but `/post`, which needs the blog posts
+impl Default for CommandRegistry {
+    fn default() -> Self {
+        let mut registry = CommandRegistry::empty();
+        registry.register(ListCommand);
+        registry.register(JoinCommand);
+        registry.register(NameCommand);
+        registry.register(InviteCommand);
+        registry.register(TopicCommand);
+        registry.register(LimitCommand);
+        registry.register(TypingCommand);
+        registry.register(RollCommand);
+        registry.register(TimeCommand);
+        registry.register(HelpCommand);
+        registry.register(EditCommand);
+        registry.register(DeleteCommand);
+        registry.register(ReactCommand);
+        registry.register(ModCommand);
+        registry
+    }
+}
+
+pub struct ListCommand;
+
+impl ChatCommand for ListCommand {
+    fn name(&self) -> &'static str {
+        "list"
+    }
+    fn description(&self) -> &'static str {
+        "list rooms"
+    }
+    fn run(&self, server: &mut ChatServer, id: Uuid, _: &str, ctx: &mut Context<ChatServer>) {
+        println!("List rooms");
+        let rooms = Handler::<ListRooms>::handle(server, ListRooms, ctx).0;
+        server.send_to(id, &ChatEvent::Rooms { rooms });
+    }
+}
+
+pub struct JoinCommand;
+
+impl ChatCommand for JoinCommand {
+    fn name(&self) -> &'static str {
+        "join"
+    }
+    fn usage(&self) -> &'static str {
+        "room [secret]"
+    }
+    fn description(&self) -> &'static str {
+        "join or create a room, a secret makes a new room private"
+    }
+    fn run(&self, server: &mut ChatServer, id: Uuid, args: &str, ctx: &mut Context<ChatServer>) {
+        if args.is_empty() {
+            server.send_to(id, &ChatEvent::error("room name is required"));
+            return;
+        }
+        // "/join room" or "/join room secret"
+        let mut args = args.splitn(2, ' ');
+        let name = args.next().unwrap_or_default().to_owned();
+        let secret = args.next().map(|secret| secret.trim().to_owned());
+        let join = Handler::<Join>::handle(server, Join { id, name, secret }, ctx);
+        ctx.spawn(join.map(move |res, act, _| match res {
+            Ok(()) => act.send_to(id, &ChatEvent::notice("joined")),
+            Err(err) => act.send_to(id, &ChatEvent::error(err.to_string())),
+        }));
+    }
+}
+
+pub struct NameCommand;
+
+impl ChatCommand for NameCommand {
+    fn name(&self) -> &'static str {
+        "name"
+    }
+    fn usage(&self) -> &'static str {
+        "name"
+    }
+    fn description(&self) -> &'static str {
+        "set session name"
+    }
+    fn run(&self, server: &mut ChatServer, id: Uuid, args: &str, ctx: &mut Context<ChatServer>) {
+        if args.is_empty() {
+            server.send_to(id, &ChatEvent::error("name is required"));
+            return;
+        }
+        let name = args.to_owned();
+        Handler::<SetName>::handle(server, SetName { id, name }, ctx);
+    }
+}
+
+pub struct InviteCommand;
+
+impl ChatCommand for InviteCommand {
+    fn name(&self) -> &'static str {
+        "invite"
+    }
+    fn description(&self) -> &'static str {
+        "create a one-time invite for the private room you are in"
+    }
+    fn run(&self, server: &mut ChatServer, id: Uuid, _: &str, ctx: &mut Context<ChatServer>) {
+        Handler::<CreateInvite>::handle(server, CreateInvite { id }, ctx);
+    }
+}
+
+pub struct TopicCommand;
+
+impl ChatCommand for TopicCommand {
+    fn name(&self) -> &'static str {
+        "topic"
+    }
+    fn usage(&self) -> &'static str {
+        "[text]"
+    }
+    fn description(&self) -> &'static str {
+        "set the topic of a room you created, without text show the current one"
+    }
+    fn run(&self, server: &mut ChatServer, id: Uuid, args: &str, ctx: &mut Context<ChatServer>) {
+        let topic = (!args.is_empty()).then(|| args.to_owned());
+        Handler::<SetTopic>::handle(server, SetTopic { id, topic }, ctx);
+    }
+}
+
+pub struct LimitCommand;
+
+impl ChatCommand for LimitCommand {
+    fn name(&self) -> &'static str {
+        "limit"
+    }
+    fn usage(&self) -> &'static str {
+        "n|off"
+    }
+    fn description(&self) -> &'static str {
+        "cap the number of members of a room you created"
+    }
+    fn run(&self, server: &mut ChatServer, id: Uuid, args: &str, ctx: &mut Context<ChatServer>) {
+        let limit = match args.parse::<usize>() {
+            Ok(limit) if limit > 0 => Some(limit),
+            _ if args == "off" => None,
+            _ => {
+                let error = ChatEvent::error("limit must be a positive number or off");
+                server.send_to(id, &error);
+                return;
+            }
+        };
+        Handler::<SetLimit>::handle(server, SetLimit { id, limit }, ctx);
+    }
+}
+
+// Sent by the chat page while the user is typing, "/typing stop" once they stop
+pub struct TypingCommand;
+
+impl ChatCommand for TypingCommand {
+    fn name(&self) -> &'static str {
+        "typing"
+    }
+    fn description(&self) -> &'static str {
+        "typing indicator"
+    }
+    fn hidden(&self) -> bool {
+        true
+    }
+    fn run(&self, server: &mut ChatServer, id: Uuid, args: &str, ctx: &mut Context<ChatServer>) {
+        let typing = args != "stop";
+        Handler::<Typing>::handle(server, Typing { id, typing }, ctx);
+    }
+}
+
+// Largest roll, `/roll 20d1000`
+const MAX_DICE: u32 = 20;
+const MAX_SIDES: u32 = 1000;
+
+pub struct RollCommand;
+
+impl RollCommand {
+    // "2d6", "d20" or nothing for a single six sided die
+    fn parse(args: &str) -> Option<(u32, u32)> {
+        if args.is_empty() {
+            return Some((1, 6));
+        }
+        let (dice, sides) = args.to_lowercase().split_once('d').map(|(dice, sides)| {
+            let dice = if dice.is_empty() { Ok(1) } else { dice.parse() };
+            (dice, sides.parse())
+        })?;
+        match (dice, sides) {
+            (Ok(dice), Ok(sides))
+                if (1..=MAX_DICE).contains(&dice) && (1..=MAX_SIDES).contains(&sides) =>
+            {
+                Some((dice, sides))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl ChatCommand for RollCommand {
+    fn name(&self) -> &'static str {
+        "roll"
+    }
+    fn usage(&self) -> &'static str {
+        "[NdM]"
+    }
+    fn description(&self) -> &'static str {
+        "roll dice for the whole room to see"
+    }
+    fn run(&self, server: &mut ChatServer, id: Uuid, args: &str, _: &mut Context<ChatServer>) {
+        let Some((dice, sides)) = RollCommand::parse(args) else {
+            let error = format!("dice look like 2d6, at most {MAX_DICE}d{MAX_SIDES}");
+            server.send_to(id, &ChatEvent::error(error));
+            return;
+        };
+        let Some(room) = server.room_of(id) else {
+            return;
+        };
+        let mut rng = rand::thread_rng();
+        let rolls: Vec<u32> = (0..dice).map(|_| rng.gen_range(1..=sides)).collect();
+        let total: u32 = rolls.iter().sum();
+        let rolls: Vec<String> = rolls.iter().map(u32::to_string).collect();
+        let text = format!(
+            "{} rolled {dice}d{sides}: {} = {total}",
+            server.name_of(id),
+            rolls.join(" + ")
+        );
+        server.send_notice(&room, &text, Uuid::nil());
+    }
+}
+
+pub struct TimeCommand;
+
+impl ChatCommand for TimeCommand {
+    fn name(&self) -> &'static str {
+        "time"
+    }
+    fn description(&self) -> &'static str {
+        "show the server time"
+    }
+    fn run(&self, server: &mut ChatServer, id: Uuid, _: &str, _: &mut Context<ChatServer>) {
+        let now = Utc::now().format("%Y-%m-%d %H:%M:%S UTC");
+        server.send_to(id, &ChatEvent::notice(format!("Server time is {now}")));
+    }
+}
+
+pub struct HelpCommand;
+
+impl ChatCommand for HelpCommand {
+    fn name(&self) -> &'static str {
+        "help"
+    }
+    fn description(&self) -> &'static str {
+        "list the commands"
+    }
+    fn run(&self, server: &mut ChatServer, id: Uuid, _: &str, _: &mut Context<ChatServer>) {
+        let commands = server.commands.help();
+        server.send_to(id, &ChatEvent::Help { commands });
+    }
+}
+
+// Message commands take the message id first, the chat page fills it in
+fn message_id(server: &mut ChatServer, id: Uuid, arg: &str) -> Option<Uuid> {
+    let message = Uuid::parse_str(arg).ok();
+    if message.is_none() {
+        server.send_to(id, &ChatEvent::error("message id is required"));
+    }
+    message
+}
+
+fn report(server: &mut ChatServer, id: Uuid, res: Result<(), EditError>) {
+    if let Err(err) = res {
+        server.send_to(id, &ChatEvent::error(err.to_string()));
+    }
+}
+
+pub struct EditCommand;
+
+impl ChatCommand for EditCommand {
+    fn name(&self) -> &'static str {
+        "edit"
+    }
+    fn usage(&self) -> &'static str {
+        "id text"
+    }
+    fn description(&self) -> &'static str {
+        "change the text of one of your recent messages"
+    }
+    fn run(&self, server: &mut ChatServer, id: Uuid, args: &str, ctx: &mut Context<ChatServer>) {
+        let (message, text) = args.split_once(' ').unwrap_or((args, ""));
+        let Some(message) = message_id(server, id, message) else {
+            return;
+        };
+        let text = text.trim().to_owned();
+        if text.is_empty() {
+            server.send_to(
+                id,
+                &ChatEvent::error("text is required, use /delete to remove"),
+            );
+            return;
+        }
+        let edit = EditMessage { id, message, text };
+        let res = Handler::<EditMessage>::handle(server, edit, ctx);
+        report(server, id, res);
+    }
+}
+
+pub struct DeleteCommand;
+
+impl ChatCommand for DeleteCommand {
+    fn name(&self) -> &'static str {
+        "delete"
+    }
+    fn usage(&self) -> &'static str {
+        "id"
+    }
+    fn description(&self) -> &'static str {
+        "remove one of your recent messages, moderators can remove any"
+    }
+    fn run(&self, server: &mut ChatServer, id: Uuid, args: &str, ctx: &mut Context<ChatServer>) {
+        let Some(message) = message_id(server, id, args) else {
+            return;
+        };
+        let res = Handler::<DeleteMessage>::handle(server, DeleteMessage { id, message }, ctx);
+        report(server, id, res);
+    }
+}
+
+pub struct ReactCommand;
+
+impl ChatCommand for ReactCommand {
+    fn name(&self) -> &'static str {
+        "react"
+    }
+    fn usage(&self) -> &'static str {
+        "id emoji"
+    }
+    fn description(&self) -> &'static str {
+        "react to a message, again to take it back"
+    }
+    fn run(&self, server: &mut ChatServer, id: Uuid, args: &str, ctx: &mut Context<ChatServer>) {
+        let (message, emoji) = args.split_once(' ').unwrap_or((args, ""));
+        let Some(message) = message_id(server, id, message) else {
+            return;
+        };
+        let emoji = emoji.trim().to_owned();
+        let res = Handler::<React>::handle(server, React { id, message, emoji }, ctx);
+        report(server, id, res);
+    }
+}
+
+pub struct ModCommand;
+
+impl ChatCommand for ModCommand {
+    fn name(&self) -> &'static str {
+        "mod"
+    }
+    fn usage(&self) -> &'static str {
+        "password"
+    }
+    fn description(&self) -> &'static str {
+        "become a moderator"
+    }
+    fn run(&self, server: &mut ChatServer, id: Uuid, args: &str, ctx: &mut Context<ChatServer>) {
+        let password = args.to_owned();
+        let auth = Handler::<Authenticate>::handle(server, Authenticate { id, password }, ctx);
+        ctx.spawn(auth.map(move |moderator, act, _| match moderator {
+            true => act.send_to(id, &ChatEvent::notice("you are a moderator now")),
+            false => act.send_to(id, &ChatEvent::error("wrong password")),
+        }));
+    }
+}
+
+// Links a blog post into the current room
+pub struct PostCommand {
+    posts: Vec<Post>,
+}
+
+impl PostCommand {
+    pub fn new(posts: Vec<Post>) -> Self {
+        PostCommand { posts }
+    }
+}
+
+impl ChatCommand for PostCommand {
+    fn name(&self) -> &'static str {
+        "post"
+    }
+    fn usage(&self) -> &'static str {
+        "slug"
+    }
+    fn description(&self) -> &'static str {
+        "share a blog post with the room"
+    }
+    fn run(&self, server: &mut ChatServer, id: Uuid, args: &str, _: &mut Context<ChatServer>) {
+        let Some(post) = self.posts.iter().find(|post| post.slug == args) else {
+            let error = format!("no post named {args:?}");
+            server.send_to(id, &ChatEvent::error(error));
+            return;
+        };
+        let Some(room) = server.room_of(id) else {
+            return;
+        };
+        let event = ChatEvent::Post {
+            from: server.name_of(id),
+            title: post.title.clone(),
+            url: format!("/blog/{}", post.slug),
+        };
+        server.send_event(&room, &event, Uuid::nil());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dice_are_parsed_within_limits() {
+        assert_eq!(RollCommand::parse(""), Some((1, 6)));
+        assert_eq!(RollCommand::parse("2d6"), Some((2, 6)));
+        assert_eq!(RollCommand::parse("D20"), Some((1, 20)));
+        assert_eq!(RollCommand::parse("0d6"), None);
+        assert_eq!(RollCommand::parse("21d6"), None);
+        assert_eq!(RollCommand::parse("2d0"), None);
+        assert_eq!(RollCommand::parse("six"), None);
+    }
+
+    #[test]
+    fn help_lists_visible_commands_in_order() {
+        let help = CommandRegistry::default().help();
+        let usages: Vec<&str> = help.iter().map(|command| command.usage.as_str()).collect();
+        assert!(usages.contains(&"/join room [secret]"));
+        assert!(usages.contains(&"/list"));
+        assert!(!usages.iter().any(|usage| usage.starts_with("/typing")));
+        let mut sorted = usages.clone();
+        sorted.sort();
+        assert_eq!(usages, sorted);
+    }
+}
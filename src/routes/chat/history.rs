@@ -0,0 +1,232 @@
+use actix::prelude::*;
+use chrono::{DateTime, Utc};
+use std::{sync::Arc, time::Duration};
+
+use super::Attachments;
+use crate::{
+    models::chat::{HistoryOp, PendingMention},
+    storage::ChatRepository,
+};
+
+// How often expired messages are looked for
+const EXPIRY_INTERVAL: Duration = Duration::from_secs(60 * 60);
+// Uploads that never made it into a room are removed after this long
+const UNSHARED_UPLOAD_TTL_HOURS: i64 = 1;
+
+// Hand over the mentions kept for a name, oldest first, and forget them
+#[derive(Message)]
+#[rtype(result = "Vec<PendingMention>")]
+pub struct TakeMentions {
+    pub name: String,
+}
+
+// Answered once every change sent before it is written
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Flush;
+
+// Writes the history in the order the chat server sent the changes,
+// without holding the chat server up while the database works
+pub struct HistoryWriter {
+    chat: Arc<dyn ChatRepository>,
+    attachments: Attachments,
+    // messages older than this are removed, with their attachments
+    retention: chrono::Duration,
+}
+
+impl HistoryWriter {
+    pub fn new(
+        chat: Arc<dyn ChatRepository>,
+        attachments: Attachments,
+        retention_days: u64,
+    ) -> Self {
+        HistoryWriter {
+            chat,
+            attachments,
+            retention: chrono::Duration::days(retention_days as i64),
+        }
+    }
+
+    fn expire(&self, ctx: &mut Context<Self>) {
+        let chat = self.chat.clone();
+        let attachments = self.attachments.clone();
+        let now = Utc::now();
+        let before = now - self.retention;
+        let unshared_before = now - chrono::Duration::hours(UNSHARED_UPLOAD_TTL_HOURS);
+        ctx.wait(
+            async move {
+                if let Err(err) = expire(chat.as_ref(), &attachments, before, unshared_before).await
+                {
+                    println!("Failed to expire chat history: {err:?}");
+                }
+            }
+            .into_actor(self),
+        );
+    }
+}
+
+impl Actor for HistoryWriter {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        self.expire(ctx);
+        ctx.run_interval(EXPIRY_INTERVAL, |act, ctx| act.expire(ctx));
+    }
+}
+
+impl Handler<HistoryOp> for HistoryWriter {
+    type Result = ();
+    fn handle(&mut self, op: HistoryOp, ctx: &mut Context<Self>) {
+        let chat = self.chat.clone();
+        let attachments = self.attachments.clone();
+        // `wait` keeps later changes queued until this one is written,
+        // an edit must never overtake the message it edits
+        ctx.wait(
+            async move {
+                if let Err(err) = write(chat.as_ref(), &attachments, op).await {
+                    println!("Failed to write chat history: {err:?}");
+                }
+            }
+            .into_actor(self),
+        );
+    }
+}
+
+impl Handler<Flush> for HistoryWriter {
+    type Result = ();
+    // changes are written one at a time, so there is nothing left to wait for
+    fn handle(&mut self, _: Flush, _: &mut Context<Self>) {}
+}
+
+impl Handler<TakeMentions> for HistoryWriter {
+    type Result = ResponseFuture<Vec<PendingMention>>;
+    fn handle(&mut self, msg: TakeMentions, _: &mut Context<Self>) -> Self::Result {
+        let chat = self.chat.clone();
+        Box::pin(async move {
+            chat.take_mentions(&msg.name).await.unwrap_or_else(|err| {
+                println!("Failed to read chat mentions: {err:?}");
+                Vec::new()
+            })
+        })
+    }
+}
+
+async fn expire(
+    chat: &dyn ChatRepository,
+    attachments: &Attachments,
+    before: DateTime<Utc>,
+    unshared_before: DateTime<Utc>,
+) -> Result<(), sqlx::Error> {
+    attachments.expire(chat, before, unshared_before).await?;
+    chat.delete_messages_before(before).await
+}
+
+async fn write(
+    chat: &dyn ChatRepository,
+    attachments: &Attachments,
+    op: HistoryOp,
+) -> Result<(), sqlx::Error> {
+    chat.write_history(&op).await?;
+    if let HistoryOp::Deleted { id, .. } = op {
+        attachments.remove_for_message(chat, id).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        configuration::ChatSettings,
+        storage::{Storage, SQLITE_MIGRATIONS},
+    };
+    use sqlx::{query, sqlite::SqlitePool, sqlite::SqlitePoolOptions};
+    use uuid::Uuid;
+
+    async fn pool() -> SqlitePool {
+        // every connection to an in-memory database gets its own, keep just one
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        SQLITE_MIGRATIONS.run(&pool).await.unwrap();
+        pool
+    }
+
+    #[actix::test]
+    async fn edits_and_deletions_reach_the_history() {
+        let pool = pool().await;
+        let settings = ChatSettings {
+            attachments_path: std::env::temp_dir()
+                .join(format!("demcru-{}", Uuid::new_v4()))
+                .to_string_lossy()
+                .into_owned(),
+            ..ChatSettings::default()
+        };
+        let writer = HistoryWriter::new(
+            Storage::sqlite(pool.clone()).chat,
+            Attachments::new(&settings),
+            30,
+        )
+        .start();
+        let (edited, deleted) = (Uuid::new_v4(), Uuid::new_v4());
+        for id in [edited, deleted] {
+            writer.do_send(HistoryOp::Sent {
+                id,
+                room: "main".to_owned(),
+                session: Uuid::new_v4(),
+                author: "alice".to_owned(),
+                text: "helo".to_owned(),
+                sent_at: Utc::now(),
+                attachment: None,
+                private: false,
+            });
+        }
+        writer.do_send(HistoryOp::Edited {
+            id: edited,
+            text: "hello".to_owned(),
+            edited_at: Utc::now(),
+        });
+        writer.do_send(HistoryOp::Reacted {
+            id: deleted,
+            session: Uuid::new_v4(),
+            emoji: "👍".to_owned(),
+            active: true,
+        });
+        writer
+            .send(HistoryOp::Deleted {
+                id: deleted,
+                deleted_at: Utc::now(),
+            })
+            .await
+            .unwrap();
+
+        let edited = query!(
+            "SELECT body, edited_at IS NOT NULL AS edited FROM chat_messages WHERE id = ?1",
+            edited
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!(edited.body, "hello");
+        assert_eq!(edited.edited, 1);
+        let deleted_row = query!(
+            "SELECT body, deleted_at IS NOT NULL AS deleted FROM chat_messages WHERE id = ?1",
+            deleted
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!(deleted_row.body, "");
+        assert_eq!(deleted_row.deleted, 1);
+        let reactions = query!(
+            "SELECT COUNT(*) AS count FROM chat_reactions WHERE message_id = ?1",
+            deleted
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!(reactions.count, 0);
+    }
+}
@@ -0,0 +1,249 @@
+use actix::Addr;
+use actix_web::{
+    http::header::{self, LOCATION},
+    web::{self, Data},
+    Either, Error, HttpRequest, HttpResponse,
+};
+use actix_web_httpauth::{
+    extractors::AuthenticationError, headers::www_authenticate::bearer::Bearer,
+};
+use chrono::Utc;
+use handlebars::Handlebars;
+use serde::Deserialize;
+use serde_json::json;
+use uuid::Uuid;
+
+use super::{ChatServer, OwnerReply, SessionName, VerifyToken, VISITOR_COOKIE};
+use crate::{
+    auth::Admin,
+    email::Outbox,
+    protection::{quarantine, Form, FormGuard, Protected, Verdict},
+    storage::Storage,
+    utils::CustomError,
+};
+
+// Longest message a visitor or the owner can write
+const MAX_INBOX_MESSAGE: usize = 4000;
+
+// Visitors prove who they are with the resume token of their chat session,
+// as a bearer token or a `session` field, so replies can reach that session
+#[derive(Deserialize)]
+pub struct InboxPost {
+    message: String,
+    session: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct InboxQuery {
+    session: Option<String>,
+}
+
+fn unauthorized() -> Error {
+    AuthenticationError::new(Bearer::default()).into()
+}
+
+fn bearer(req: &HttpRequest) -> Option<String> {
+    let value = req.headers().get(header::AUTHORIZATION)?.to_str().ok()?;
+    value.strip_prefix("Bearer ").map(str::to_owned)
+}
+
+// Chat session of the visitor behind a request
+async fn visitor_session(
+    req: &HttpRequest,
+    field: Option<String>,
+    srv: &Addr<ChatServer>,
+) -> Result<Uuid, Error> {
+    let Some(token) = bearer(req).or(field) else {
+        return Err(unauthorized());
+    };
+    let session = srv
+        .send(VerifyToken(token))
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    session.ok_or_else(unauthorized)
+}
+
+fn visitor_of(req: &HttpRequest) -> Option<Uuid> {
+    req.cookie(VISITOR_COOKIE)
+        .and_then(|cookie| Uuid::parse_str(cookie.value()).ok())
+}
+
+fn valid_message(message: &str) -> Result<&str, Error> {
+    let message = message.trim();
+    if message.is_empty() || message.chars().count() > MAX_INBOX_MESSAGE {
+        return Err(actix_web::error::ErrorBadRequest(format!(
+            "A message has 1 to {MAX_INBOX_MESSAGE} characters"
+        )));
+    }
+    Ok(message)
+}
+
+/// A visitor writes to the site owner, as JSON or a form. Messages the form
+/// guard turns away get a conversation that does not exist.
+pub async fn chat_with_me(
+    req: HttpRequest,
+    body: Either<web::Json<Protected<InboxPost>>, web::Form<Protected<InboxPost>>>,
+    srv: Data<Addr<ChatServer>>,
+    storage: Data<Storage>,
+    guard: Data<FormGuard>,
+    hb: Data<Handlebars<'static>>,
+    outbox: Data<Outbox>,
+) -> Result<HttpResponse, Error> {
+    let Protected {
+        proof,
+        fields: post,
+    } = body.into_inner();
+    let session = visitor_session(&req, post.session, srv.get_ref()).await?;
+    let message = valid_message(&post.message)?;
+    let name = srv
+        .send(SessionName(session))
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?
+        .ok_or_else(unauthorized)?;
+    let ip = guard.client_ip(&req);
+    let pretend = || HttpResponse::Created().json(json!({ "conversation": Uuid::new_v4() }));
+    match guard.check(&ip, Form::Inbox, &proof, Some(message)) {
+        Verdict::Accept => (),
+        Verdict::Reject(reason) => {
+            println!("Dropped a message to the inbox from {ip}: {reason}");
+            return Ok(pretend());
+        }
+        Verdict::Quarantine { score, reasons } => {
+            let payload = json!({ "name": name, "message": message });
+            quarantine(
+                storage.quarantine.as_ref(),
+                Form::Inbox,
+                &ip,
+                payload,
+                score,
+                &reasons,
+            )
+            .await
+            .map_err(CustomError::DatabaseError)?;
+            return Ok(pretend());
+        }
+    }
+    let conversation = storage
+        .inbox
+        .store_visitor_message(visitor_of(&req), session, &name, message)
+        .await
+        .map_err(CustomError::DatabaseError)?;
+    outbox
+        .notify_owner(
+            &hb,
+            "inbox_message",
+            &json!({ "name": name, "message": message, "conversation": conversation }),
+        )
+        .await;
+    Ok(HttpResponse::Created().json(json!({ "conversation": conversation })))
+}
+
+/// The conversation of a visitor with the owner, replies included
+pub async fn my_conversation(
+    req: HttpRequest,
+    params: web::Query<InboxQuery>,
+    srv: Data<Addr<ChatServer>>,
+    storage: Data<Storage>,
+) -> Result<HttpResponse, Error> {
+    let session = visitor_session(&req, params.into_inner().session, srv.get_ref()).await?;
+    let conversation = storage
+        .inbox
+        .find_conversation(visitor_of(&req), session)
+        .await
+        .map_err(CustomError::DatabaseError)?;
+    let messages = match conversation {
+        Some(id) => storage
+            .inbox
+            .inbox_messages(id)
+            .await
+            .map_err(CustomError::DatabaseError)?,
+        None => Vec::new(),
+    };
+    Ok(HttpResponse::Ok().json(json!({ "messages": messages })))
+}
+
+/// Conversations of the owner, the most recent first
+pub async fn inbox(
+    _: Admin,
+    hb: Data<Handlebars<'static>>,
+    storage: Data<Storage>,
+) -> Result<HttpResponse, Error> {
+    let conversations = storage
+        .inbox
+        .conversations()
+        .await
+        .map_err(CustomError::DatabaseError)?;
+    let body = hb
+        .render("inbox", &json!({ "conversations": conversations }))
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    Ok(HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(body))
+}
+
+/// One conversation with a form to reply
+pub async fn inbox_conversation(
+    _: Admin,
+    path: web::Path<Uuid>,
+    hb: Data<Handlebars<'static>>,
+    storage: Data<Storage>,
+) -> Result<HttpResponse, Error> {
+    let id = path.into_inner();
+    let name = storage
+        .inbox
+        .conversation_name(id)
+        .await
+        .map_err(CustomError::DatabaseError)?;
+    let Some(name) = name else {
+        return Ok(HttpResponse::NotFound().body("No such conversation"));
+    };
+    let messages = storage
+        .inbox
+        .inbox_messages(id)
+        .await
+        .map_err(CustomError::DatabaseError)?;
+    let body = hb
+        .render(
+            "inbox_conversation",
+            &json!({ "id": id, "name": name, "messages": messages }),
+        )
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    Ok(HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(body))
+}
+
+#[derive(Deserialize)]
+pub struct ReplyForm {
+    message: String,
+}
+
+/// The owner answers, live over the chat if the visitor is still connected
+pub async fn reply_to_visitor(
+    _: Admin,
+    path: web::Path<Uuid>,
+    form: web::Form<ReplyForm>,
+    srv: Data<Addr<ChatServer>>,
+    storage: Data<Storage>,
+) -> Result<HttpResponse, Error> {
+    let id = path.into_inner();
+    let message = valid_message(&form.message)?;
+    let now = Utc::now();
+    let session = storage
+        .inbox
+        .store_reply(id, message, now)
+        .await
+        .map_err(CustomError::DatabaseError)?;
+    let Some(session) = session else {
+        return Ok(HttpResponse::NotFound().body("No such conversation"));
+    };
+
+    srv.do_send(OwnerReply {
+        session,
+        text: message.to_owned(),
+        sent_at: now,
+    });
+    Ok(HttpResponse::SeeOther()
+        .insert_header((LOCATION, format!("/admin/inbox/{id}")))
+        .finish())
+}
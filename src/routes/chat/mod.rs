@@ -0,0 +1,183 @@
+use actix::Addr;
+use actix_web::{
+    cookie::{time::Duration as CookieDuration, Cookie},
+    web::{self, Data},
+    Error, HttpRequest, HttpResponse,
+};
+use handlebars::Handlebars;
+use serde::Deserialize;
+use serde_json::json;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use uuid::Uuid;
+
+use crate::{
+    protection::{quarantine, Form, FormGuard, FormProof, Verdict},
+    storage::{AnalyticsRepository, QuarantineRepository, Storage},
+    utils::CustomError,
+};
+
+mod attachments;
+mod bots;
+mod bus;
+mod commands;
+mod history;
+mod inbox;
+mod server;
+mod sse;
+mod transcript;
+mod ws;
+
+pub use attachments::*;
+pub use bots::*;
+pub use bus::*;
+pub use commands::*;
+pub use history::*;
+pub use inbox::*;
+pub use server::*;
+pub use sse::*;
+pub use transcript::*;
+pub use ws::*;
+
+// Both transports take `?resume=<token>` to pick up a dropped session
+#[derive(Deserialize, Default)]
+pub struct ResumeParams {
+    pub resume: Option<String>,
+}
+
+impl ResumeParams {
+    fn from_request(req: &HttpRequest) -> Self {
+        web::Query::<Self>::from_query(req.query_string())
+            .map(|params| params.into_inner())
+            .unwrap_or_default()
+    }
+}
+
+// Both transports check the chat token of the page once, when they open
+fn has_chat_token(req: &HttpRequest, guard: &FormGuard) -> bool {
+    let proof = web::Query::<FormProof>::from_query(req.query_string())
+        .map(|proof| proof.into_inner())
+        .unwrap_or_default();
+    proof.website.is_empty() && guard.valid_token(Form::Chat, &proof.form_token)
+}
+
+// Message text that scores as spam. Commands and typing indicators are left
+// to the chat server, which limits every session.
+fn chat_spam(guard: &FormGuard, text: &str) -> Option<(u32, Vec<String>)> {
+    if text.trim_start().starts_with('/') {
+        return None;
+    }
+    match guard.score(text) {
+        Verdict::Quarantine { score, reasons } => Some((score, reasons)),
+        _ => None,
+    }
+}
+
+// Spam is kept for the admin instead of reaching the room, it counts against
+// the limit of the session like a message and is dropped once over it
+async fn quarantine_chat(
+    srv: &Addr<ChatServer>,
+    repository: &dyn QuarantineRepository,
+    ip: &str,
+    id: Uuid,
+    text: String,
+    (score, reasons): (u32, Vec<String>),
+) -> Result<(), Error> {
+    let counted = srv
+        .send(CountAgainstLimit { id })
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    if counted {
+        let payload = json!({ "text": text });
+        quarantine(repository, Form::Chat, ip, payload, score, &reasons)
+            .await
+            .map_err(CustomError::DatabaseError)?;
+    }
+    Ok(())
+}
+
+// Routes
+// Visitors are told apart by this cookie, handed out by the chat page
+const VISITOR_COOKIE: &str = "visitor_id";
+
+pub async fn chat(
+    hb: Data<Handlebars<'static>>,
+    guard: Data<FormGuard>,
+    req: HttpRequest,
+) -> HttpResponse {
+    let content = hb
+        .render(
+            "chat",
+            &json!({
+                "chat_token": guard.issue_token(Form::Chat),
+                "inbox_token": guard.issue_token(Form::Inbox),
+            }),
+        )
+        .unwrap();
+    let mut response = HttpResponse::Ok();
+    if req.cookie(VISITOR_COOKIE).is_none() {
+        response.cookie(
+            Cookie::build(VISITOR_COOKIE, Uuid::new_v4().to_string())
+                .http_only(true)
+                .path("/")
+                .max_age(CookieDuration::days(365))
+                .finish(),
+        );
+    }
+    response
+        .content_type("text/html; charset=utf-8")
+        .body(content)
+}
+
+// Remember the visitor behind a chat connection, if the chat page gave them a cookie
+async fn record_visit(req: &HttpRequest, analytics: &dyn AnalyticsRepository) {
+    let visitor = req
+        .cookie(VISITOR_COOKIE)
+        .and_then(|cookie| Uuid::parse_str(cookie.value()).ok());
+    if let Some(visitor) = visitor {
+        // analytics must never keep anyone out of the chat
+        if let Err(err) = analytics.record_visitor(visitor).await {
+            println!("Failed to record visitor: {err:?}");
+        }
+    }
+}
+
+/// Displays the number of sessions connected right now
+pub async fn get_count(count: web::Data<AtomicUsize>) -> HttpResponse {
+    let current_count = count.load(Ordering::SeqCst);
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(format!("{current_count} online"))
+}
+
+/// Displays how many sessions are in each public room
+pub async fn get_room_count(
+    srv: web::Data<Addr<ChatServer>>,
+    hb: Data<Handlebars<'static>>,
+) -> Result<HttpResponse, Error> {
+    let rooms = srv
+        .send(RoomOccupancy)
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    let rooms: Vec<_> = rooms
+        .into_iter()
+        .map(|(name, count)| json!({ "name": name, "count": count }))
+        .collect();
+    let body = hb
+        .render("room_count", &json!({ "rooms": rooms }))
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    Ok(HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(body))
+}
+
+/// Displays the number of distinct visitors the chat ever had
+pub async fn get_unique_count(storage: web::Data<Storage>) -> Result<HttpResponse, CustomError> {
+    let count = storage
+        .analytics
+        .count_visitors()
+        .await
+        .map_err(CustomError::DatabaseError)?;
+    Ok(HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(format!("{count} visitors all time")))
+}
@@ -0,0 +1,2974 @@
+use actix::prelude::*;
+use actix_web::rt::task;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use rand::{self, Rng};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque},
+    net::IpAddr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+use uuid::Uuid;
+
+use super::{
+    Attachment, BusEvent, BusMember, BusMessage, ChatBot, ChatBus, ChatCommand, CommandHelp,
+    CommandRegistry, FaqBot, Flush, HistoryWriter, LocalBus, MessageChange, TakeMentions,
+};
+use crate::{
+    auth::{hash_password, verify_password},
+    configuration::ChatSettings,
+    models::chat::HistoryOp,
+};
+
+// Protocol
+// Every frame the server pushes to a client is one of these events, serialized as JSON
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ChatEvent {
+    // first event of every connection, the token lets the client resume the
+    // session after a reconnect
+    Session {
+        id: Uuid,
+        token: String,
+        resumed: bool,
+    },
+    Message {
+        id: Uuid,
+        from: String,
+        text: String,
+        sent_at: DateTime<Utc>,
+        // set on the copy sent to the author, so the page knows what it may edit
+        mine: bool,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        attachment: Option<Attachment>,
+        // names `@mentioned` in the text
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        mentions: Vec<String>,
+    },
+    // the session's name was mentioned, sent on top of the message so the page
+    // can highlight it, or on its own for a name that was away at the time
+    Mention {
+        id: Uuid,
+        room: String,
+        from: String,
+        text: String,
+        sent_at: DateTime<Utc>,
+    },
+    Edited {
+        id: Uuid,
+        text: String,
+        edited_at: DateTime<Utc>,
+    },
+    Deleted {
+        id: Uuid,
+    },
+    // how many sessions reacted with each emoji
+    Reactions {
+        id: Uuid,
+        reactions: BTreeMap<String, usize>,
+    },
+    Notice {
+        text: String,
+    },
+    Error {
+        text: String,
+    },
+    Rooms {
+        rooms: Vec<String>,
+    },
+    Topic {
+        room: String,
+        topic: Option<String>,
+    },
+    Invite {
+        room: String,
+        token: String,
+    },
+    Typing {
+        name: String,
+        typing: bool,
+    },
+    Presence {
+        name: String,
+        status: Presence,
+    },
+    Members {
+        room: String,
+        members: Vec<Member>,
+    },
+    Help {
+        commands: Vec<CommandHelp>,
+    },
+    // a blog post shared with `/post`
+    Post {
+        from: String,
+        title: String,
+        url: String,
+    },
+    // an answer of the site owner to what the session wrote to `/chat-with-me`
+    OwnerReply {
+        text: String,
+        sent_at: DateTime<Utc>,
+    },
+    // the server is going down, the connection closes right after this
+    Restarting {
+        reconnect_after_secs: u64,
+    },
+}
+
+impl ChatEvent {
+    pub fn notice(text: impl Into<String>) -> Self {
+        ChatEvent::Notice { text: text.into() }
+    }
+
+    pub fn error(text: impl Into<String>) -> Self {
+        ChatEvent::Error { text: text.into() }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("chat events are always serializable")
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Presence {
+    Online,
+    Away,
+    Offline,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Member {
+    pub name: String,
+    pub status: Presence,
+}
+
+// Server
+// Chat server sends this message to session
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Message(pub String);
+
+// Chat server tells a connection to close, it is shutting down
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Close;
+
+// Stop the chat gracefully: every connection is told to come back after
+// `reconnect_after` and closed, then pending history is written
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Shutdown {
+    pub reconnect_after: Duration,
+}
+
+// New chat session is created, or a session that dropped is resumed
+#[derive(Message)]
+#[rtype(result = "Connected")]
+pub struct Connect {
+    pub addr: Recipient<Message>,
+    pub close: Recipient<Close>,
+    // remote address of the client, failed room passwords are rate limited by it
+    pub peer: Option<IpAddr>,
+    // token handed out by an earlier connection of the same session
+    pub resume: Option<String>,
+}
+
+#[derive(MessageResponse, Debug, Clone, Copy)]
+pub struct Connected {
+    pub id: Uuid,
+    pub resumed: bool,
+}
+
+// Connection of a session is gone. The session itself is kept for
+// `resume_grace_secs` in case the client comes back.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Disconnect {
+    pub id: Uuid,
+    // the connection that went away, a resumed session has a newer one
+    pub addr: Recipient<Message>,
+}
+
+// Find the session a resume token was issued for, here or on another instance
+#[derive(Message)]
+#[rtype(result = "Option<Uuid>")]
+pub struct VerifyToken(pub String);
+
+// Name a session goes by, `None` if there is no such session
+#[derive(Message)]
+#[rtype(result = "Option<String>")]
+pub struct SessionName(pub Uuid);
+
+// The site owner answered a visitor through the inbox. Returns whether the
+// session is on this instance, other instances are told in case it is there.
+#[derive(Message)]
+#[rtype(result = "bool")]
+pub struct OwnerReply {
+    pub session: Uuid,
+    pub text: String,
+    pub sent_at: DateTime<Utc>,
+}
+
+// Send message to specific room
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct ClientMessage {
+    pub id: Uuid,
+    pub msg: String,
+    pub room: String,
+}
+
+// Raw input of a user, whatever the transport: a slash command or a message
+// for the current room. Returns false if the session is unknown.
+#[derive(Message)]
+#[rtype(result = "bool")]
+pub struct ClientText {
+    pub id: Uuid,
+    pub text: String,
+}
+
+pub struct ListRooms;
+
+impl actix::Message for ListRooms {
+    type Result = Vec<String>;
+}
+
+// Number of sessions in each public room
+pub struct RoomOccupancy;
+
+impl actix::Message for RoomOccupancy {
+    type Result = Vec<(String, usize)>;
+}
+
+// Join room, if room does not exists create new one.
+// Creating a room with a secret makes it private, joining a private room
+// requires either its password or an invite token.
+#[derive(Message)]
+#[rtype(result = "Result<(), JoinError>")]
+pub struct Join {
+    pub id: Uuid,
+    pub name: String,
+    pub secret: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum JoinError {
+    Full,
+    Private,
+    WrongSecret,
+    TooManyAttempts,
+}
+
+impl std::fmt::Display for JoinError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            JoinError::Full => write!(f, "room is full"),
+            JoinError::Private => write!(f, "room is private, a password or invite is required"),
+            JoinError::WrongSecret => write!(f, "wrong password or invite"),
+            JoinError::TooManyAttempts => write!(f, "too many failed attempts, try again later"),
+        }
+    }
+}
+
+// Create a one-time invite for the private room the session is in
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct CreateInvite {
+    pub id: Uuid,
+}
+
+// Set the topic of the room the session is in, or ask for the current one
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct SetTopic {
+    pub id: Uuid,
+    pub topic: Option<String>,
+}
+
+// Change the member cap of the room the session is in, `None` lifts it
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct SetLimit {
+    pub id: Uuid,
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum EditError {
+    NotFound,
+    NotAllowed,
+    TooLate,
+    InvalidEmoji,
+}
+
+impl std::fmt::Display for EditError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            EditError::NotFound => write!(f, "message not found"),
+            EditError::NotAllowed => write!(f, "that is not your message"),
+            EditError::TooLate => write!(f, "message is too old to change"),
+            EditError::InvalidEmoji => write!(f, "reactions are a single emoji"),
+        }
+    }
+}
+
+// Change the text of a message, authors only
+#[derive(Message)]
+#[rtype(result = "Result<(), EditError>")]
+pub struct EditMessage {
+    pub id: Uuid,
+    pub message: Uuid,
+    pub text: String,
+}
+
+// Remove a message, authors or moderators
+#[derive(Message)]
+#[rtype(result = "Result<(), EditError>")]
+pub struct DeleteMessage {
+    pub id: Uuid,
+    pub message: Uuid,
+}
+
+// Add a reaction to a message, or take it back if the session already reacted so
+#[derive(Message)]
+#[rtype(result = "Result<(), EditError>")]
+pub struct React {
+    pub id: Uuid,
+    pub message: Uuid,
+    pub emoji: String,
+}
+
+// Whether a session is in a room, transcripts of private rooms are for members only
+#[derive(Message)]
+#[rtype(result = "bool")]
+pub struct IsMember {
+    pub id: Uuid,
+    pub room: String,
+}
+
+// Whether a session may share an upload, asked before it is stored. Uploads
+// count against the same limit as messages, sessions of other instances
+// only need to be in a room.
+#[derive(Message)]
+#[rtype(result = "Result<(), ShareError>")]
+pub struct MayShare {
+    pub id: Uuid,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ShareError {
+    NoRoom,
+    TooFast,
+}
+
+impl std::fmt::Display for ShareError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ShareError::NoRoom => write!(f, "Join a room to share files"),
+            ShareError::TooFast => write!(f, "slow down, you are sending too fast"),
+        }
+    }
+}
+
+// Share a stored upload in the room of the session, `MayShare` said it may
+#[derive(Message)]
+#[rtype(result = "bool")]
+pub struct ShareAttachment {
+    pub id: Uuid,
+    pub attachment: Attachment,
+}
+
+// Become a moderator of every room with the moderator password
+#[derive(Message)]
+#[rtype(result = "bool")]
+pub struct Authenticate {
+    pub id: Uuid,
+    pub password: String,
+}
+
+// Session picked a display name
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct SetName {
+    pub id: Uuid,
+    pub name: String,
+}
+
+// Session started or stopped typing in its current room
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Typing {
+    pub id: Uuid,
+    pub typing: bool,
+}
+
+// Sent by sessions on every heartbeat, idle sessions are then shown as away
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Heartbeat {
+    pub id: Uuid,
+}
+
+// How often the server looks for stale typing indicators
+const TYPING_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+// A typing indicator not refreshed for this long is cleared
+const TYPING_TIMEOUT: Duration = Duration::from_secs(5);
+// How long without any input from the user before they are shown as away
+const AWAY_TIMEOUT: Duration = Duration::from_secs(120);
+// Messages and commands a session may send in a burst, refilled at `RATE_LIMIT_PER_SEC`
+const RATE_LIMIT_BURST: f64 = 10.0;
+const RATE_LIMIT_PER_SEC: f64 = 1.0;
+// Messages that can still be edited, deleted or reacted to, the oldest are forgotten first
+const MAX_TRACKED_MESSAGES: usize = 1000;
+// Events kept for a detached session, older ones are dropped first
+const MAX_MISSED_EVENTS: usize = 200;
+// Failed private room joins allowed per client within `FAILED_JOIN_WINDOW`
+const MAX_FAILED_JOINS: u32 = 5;
+const FAILED_JOIN_WINDOW: Duration = Duration::from_secs(300);
+// Names a single message can mention
+const MAX_MENTIONS: usize = 10;
+
+// Server side view of a connected session
+struct Session {
+    addr: Recipient<Message>,
+    close: Recipient<Close>,
+    peer: Option<IpAddr>,
+    name: Option<String>,
+    status: Presence,
+    typing_since: Option<Instant>,
+    // last time the user sent anything
+    active: Instant,
+    // token bucket shared by every transport of the session
+    allowance: f64,
+    // set while the connection is gone and the session waits to be resumed
+    detached_since: Option<Instant>,
+    missed: VecDeque<String>,
+    // knew the moderator password
+    moderator: bool,
+}
+
+impl Session {
+    fn new(addr: Recipient<Message>, close: Recipient<Close>, peer: Option<IpAddr>) -> Session {
+        Session {
+            addr,
+            close,
+            peer,
+            name: None,
+            status: Presence::Online,
+            typing_since: None,
+            active: Instant::now(),
+            allowance: RATE_LIMIT_BURST,
+            detached_since: None,
+            missed: VecDeque::new(),
+            moderator: false,
+        }
+    }
+
+    // Hand an event to the connection, or keep it until the session is resumed
+    fn deliver(&mut self, message: String) {
+        if self.detached_since.is_none() {
+            self.addr.do_send(Message(message));
+            return;
+        }
+        if self.missed.len() == MAX_MISSED_EVENTS {
+            self.missed.pop_front();
+        }
+        self.missed.push_back(message);
+    }
+
+    // Take a token from the bucket, refilled for the time since the last input
+    fn allow(&mut self) -> bool {
+        let now = Instant::now();
+        let refill = now.duration_since(self.active).as_secs_f64() * RATE_LIMIT_PER_SEC;
+        self.allowance = (self.allowance + refill).min(RATE_LIMIT_BURST);
+        self.active = now;
+        if self.allowance < 1.0 {
+            return false;
+        }
+        self.allowance -= 1.0;
+        true
+    }
+
+    fn display_name(&self, id: Uuid) -> String {
+        match self.name {
+            Some(ref name) => name.clone(),
+            None => guest_name(id),
+        }
+    }
+}
+
+struct Room {
+    members: HashSet<Uuid>,
+    topic: Option<String>,
+    limit: Option<usize>,
+    // permanent rooms survive being empty
+    permanent: bool,
+    empty_since: Option<Instant>,
+    // argon2 hash of the password of a private room
+    secret: Option<String>,
+    // sha256 digests of unused invite tokens
+    invites: HashSet<String>,
+    // session that created the room, it moderates the room
+    owner: Option<Uuid>,
+}
+
+// A recent message, kept to check who may change it
+struct ChatRecord {
+    room: String,
+    author: Uuid,
+    sent_at: DateTime<Utc>,
+    // sessions that reacted, by emoji
+    reactions: BTreeMap<String, BTreeSet<Uuid>>,
+}
+
+impl Room {
+    fn new(limit: Option<usize>, permanent: bool) -> Room {
+        Room {
+            members: HashSet::new(),
+            topic: None,
+            limit,
+            permanent,
+            empty_since: Some(Instant::now()),
+            secret: None,
+            invites: HashSet::new(),
+            owner: None,
+        }
+    }
+
+    fn is_private(&self) -> bool {
+        self.secret.is_some()
+    }
+
+    fn is_full(&self) -> bool {
+        matches!(self.limit, Some(limit) if self.members.len() >= limit)
+    }
+}
+
+// `ChatServer` manages chat rooms and responsible for coordinating chat session
+pub struct ChatServer {
+    sessions: HashMap<Uuid, Session>,
+    rooms: HashMap<String, Room>,
+    // signs resume tokens, shared by the instances of a bus to take each other's
+    resume_key: Vec<u8>,
+    // sessions currently connected
+    visitor_count: Arc<AtomicUsize>,
+    settings: ChatSettings,
+    // failed private room joins per client: count and start of the window
+    failed_joins: HashMap<String, (u32, Instant)>,
+    pub(super) commands: CommandRegistry,
+    bots: Vec<Bot>,
+    messages: HashMap<Uuid, ChatRecord>,
+    // ids in `messages` from oldest to newest
+    message_order: VecDeque<Uuid>,
+    history: Option<Addr<HistoryWriter>>,
+    // tells the chat servers of other instances what happens in the rooms
+    instance: Uuid,
+    bus: Box<dyn ChatBus>,
+    // members of each room on the other instances, by instance
+    remote_members: HashMap<Uuid, HashMap<String, Vec<BusMember>>>,
+}
+
+struct Bot {
+    rooms: HashSet<String>,
+    bot: Box<dyn ChatBot>,
+}
+
+impl ChatServer {
+    pub fn new(visitor_count: Arc<AtomicUsize>, settings: ChatSettings) -> ChatServer {
+        let mut rooms = HashMap::new();
+        rooms.insert("main".to_owned(), Room::new(None, true));
+        for name in &settings.permanent_rooms {
+            rooms.insert(name.to_owned(), Room::new(settings.max_room_members, true));
+        }
+        let bots = settings.bots.clone();
+        // without a configured secret, tokens do not outlive the process
+        let resume_key = match &settings.resume_secret {
+            Some(secret) => secret.as_bytes().to_vec(),
+            None => rand::thread_rng().gen::<[u8; 32]>().to_vec(),
+        };
+        let server = ChatServer {
+            sessions: HashMap::new(),
+            rooms,
+            resume_key,
+            visitor_count,
+            settings,
+            failed_joins: HashMap::new(),
+            commands: CommandRegistry::default(),
+            bots: Vec::new(),
+            messages: HashMap::new(),
+            message_order: VecDeque::new(),
+            history: None,
+            instance: Uuid::new_v4(),
+            bus: Box::new(LocalBus::default()),
+            remote_members: HashMap::new(),
+        };
+        bots.iter()
+            .fold(server, |server, bot| server.with_bot(FaqBot::new(bot)))
+    }
+
+    // Add a slash command, replacing a built-in one with the same name
+    pub fn with_command(mut self, command: impl ChatCommand + 'static) -> Self {
+        self.commands.register(command);
+        self
+    }
+
+    // Share rooms with the chat servers of other instances
+    pub fn with_bus(mut self, bus: impl ChatBus + 'static) -> Self {
+        self.bus = Box::new(bus);
+        self
+    }
+
+    // Persist messages and their changes
+    pub fn with_history(mut self, history: Addr<HistoryWriter>) -> Self {
+        self.history = Some(history);
+        self
+    }
+
+    // Seat a bot in its rooms, which are created if needed and kept for it
+    pub fn with_bot(mut self, bot: impl ChatBot + 'static) -> Self {
+        let rooms: HashSet<String> = bot.rooms().into_iter().collect();
+        for name in &rooms {
+            let max_members = self.settings.max_room_members;
+            let room = self
+                .rooms
+                .entry(name.to_owned())
+                .or_insert_with(|| Room::new(max_members, true));
+            room.permanent = true;
+        }
+        self.bots.push(Bot {
+            rooms,
+            bot: Box::new(bot),
+        });
+        self
+    }
+}
+
+impl ChatServer {
+    // Send an event to a room, here and on every other instance
+    pub(super) fn send_event(&mut self, room: &str, event: &ChatEvent, skip_id: Uuid) {
+        let message = event.to_json();
+        self.deliver_local(room, &message, skip_id);
+        self.publish(BusEvent::Room {
+            room: room.to_owned(),
+            secret: self.room_secret(room),
+            event: message,
+        });
+    }
+
+    fn room_secret(&self, room: &str) -> Option<String> {
+        self.rooms.get(room).and_then(|room| room.secret.clone())
+    }
+
+    // Whether a room of another instance is the room of that name here,
+    // a private room is only ever the same as one with its password hash
+    fn same_room(&self, room: &str, secret: &Option<String>) -> bool {
+        match self.rooms.get(room) {
+            Some(room) => room.secret == *secret,
+            None => secret.is_none(),
+        }
+    }
+
+    // A session of another instance, as a member of one of its rooms
+    fn remote_session(&self, id: Uuid) -> Option<(&str, &Member)> {
+        self.remote_members
+            .values()
+            .flat_map(|rooms| rooms.iter())
+            .find_map(|(room, members)| {
+                let member = members.iter().find(|member| member.session == id)?;
+                Some((room.as_str(), &member.member))
+            })
+    }
+
+    fn deliver_local(&mut self, room: &str, message: &str, skip_id: Uuid) {
+        if let Some(room) = self.rooms.get(room) {
+            for id in &room.members {
+                if *id != skip_id {
+                    if let Some(session) = self.sessions.get_mut(id) {
+                        session.deliver(message.to_owned());
+                    }
+                }
+            }
+        }
+    }
+
+    fn publish(&self, event: BusEvent) {
+        self.bus.publish(&BusMessage {
+            origin: self.instance,
+            event,
+        });
+    }
+
+    pub(super) fn send_notice(&mut self, room: &str, text: &str, skip_id: Uuid) {
+        self.send_event(room, &ChatEvent::notice(text), skip_id);
+    }
+
+    // Send an event to a single session
+    pub(super) fn send_to(&mut self, id: Uuid, event: &ChatEvent) {
+        if let Some(session) = self.sessions.get_mut(&id) {
+            session.deliver(event.to_json());
+        }
+    }
+
+    fn mac(&self, id: Uuid) -> Hmac<Sha256> {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.resume_key).expect("hmac takes keys of any size");
+        mac.update(id.as_bytes());
+        mac
+    }
+
+    // Resume tokens are the session id signed with the server key
+    fn resume_token(&self, id: Uuid) -> String {
+        let signature = self.mac(id).finalize().into_bytes();
+        format!("{}.{}", id.simple(), hex::encode(signature))
+    }
+
+    fn verify_token(&self, token: &str) -> Option<Uuid> {
+        let (id, signature) = token.split_once('.')?;
+        let id = Uuid::parse_str(id).ok()?;
+        let signature = hex::decode(signature).ok()?;
+        self.mac(id).verify_slice(&signature).ok()?;
+        Some(id)
+    }
+
+    fn resume_grace(&self) -> Duration {
+        Duration::from_secs(self.settings.resume_grace_secs)
+    }
+
+    // Remove a session whose connection did not come back within the grace window
+    fn expire_session(&mut self, id: Uuid, ctx: &mut Context<Self>) {
+        let grace = self.resume_grace();
+        let expired = match self.sessions.get(&id) {
+            Some(session) => {
+                matches!(session.detached_since, Some(since) if since.elapsed() >= grace)
+            }
+            None => false,
+        };
+        if !expired {
+            return;
+        }
+        println!("Someone disconnected");
+        let name = self.name_of(id);
+        self.sessions.remove(&id);
+        // remove session from all rooms
+        let rooms = self.leave_rooms(id, ctx);
+        // send messages to another users
+        for room in rooms {
+            self.send_notice(&room, "Someone dissconnected", Uuid::nil());
+            let event = ChatEvent::Presence {
+                name: name.clone(),
+                status: Presence::Offline,
+            };
+            self.send_event(&room, &event, Uuid::nil());
+            self.send_members(&room);
+        }
+    }
+
+    pub(super) fn room_of(&self, id: Uuid) -> Option<String> {
+        self.rooms
+            .iter()
+            .find(|(_, room)| room.members.contains(&id))
+            .map(|(name, _)| name.to_owned())
+    }
+
+    // Remove session from all rooms, returning the rooms it was in.
+    // Rooms left empty are scheduled for removal after the grace period.
+    fn leave_rooms(&mut self, id: Uuid, ctx: &mut Context<Self>) -> Vec<String> {
+        let mut left = Vec::new();
+        for (name, room) in &mut self.rooms {
+            if room.members.remove(&id) {
+                left.push(name.to_owned());
+            }
+        }
+        for name in &left {
+            self.expire_if_unused(name, ctx);
+        }
+        left
+    }
+
+    // Schedule the removal of a room nobody is in, here or on another instance
+    fn expire_if_unused(&mut self, name: &str, ctx: &mut Context<Self>) {
+        let (grace, remote) = (self.grace(), self.has_remote_members(name));
+        let Some(room) = self.rooms.get_mut(name) else {
+            return;
+        };
+        if room.permanent || !room.members.is_empty() || remote {
+            return;
+        }
+        room.empty_since = Some(Instant::now());
+        let name = name.to_owned();
+        ctx.run_later(grace, move |act, _| act.remove_if_empty(&name));
+    }
+
+    fn has_remote_members(&self, name: &str) -> bool {
+        self.remote_members
+            .values()
+            .any(|rooms| rooms.get(name).is_some_and(|members| !members.is_empty()))
+    }
+
+    fn grace(&self) -> Duration {
+        Duration::from_secs(self.settings.empty_room_grace_secs)
+    }
+
+    // Drop a room that has stayed empty for the whole grace period
+    fn remove_if_empty(&mut self, name: &str) {
+        let grace = self.grace();
+        let remote = self.has_remote_members(name);
+        let expired = match self.rooms.get(name) {
+            Some(room) => {
+                !room.permanent
+                    && room.members.is_empty()
+                    && !remote
+                    && matches!(room.empty_since, Some(since) if since.elapsed() >= grace)
+            }
+            None => false,
+        };
+        if expired {
+            println!("Removing empty room {name}");
+            self.rooms.remove(name);
+        }
+    }
+
+    // Failed joins are counted per remote address, so reconnecting does not reset them
+    fn client_key(&self, id: Uuid) -> String {
+        match self.sessions.get(&id).and_then(|session| session.peer) {
+            Some(peer) => peer.to_string(),
+            None => format!("session-{id}"),
+        }
+    }
+
+    fn is_locked_out(&mut self, key: &str) -> bool {
+        match self.failed_joins.get(key) {
+            Some((_, since)) if since.elapsed() > FAILED_JOIN_WINDOW => {
+                self.failed_joins.remove(key);
+                false
+            }
+            Some((count, _)) => *count >= MAX_FAILED_JOINS,
+            None => false,
+        }
+    }
+
+    fn record_failed_join(&mut self, key: String) {
+        let entry = self.failed_joins.entry(key).or_insert((0, Instant::now()));
+        entry.0 += 1;
+    }
+
+    // Move a session into a room that it is allowed to enter, creating the room if needed
+    fn enter_room(
+        &mut self,
+        id: Uuid,
+        name: &str,
+        ctx: &mut Context<Self>,
+    ) -> Result<(), JoinError> {
+        if let Some(room) = self.rooms.get(name) {
+            if room.is_full() && !room.members.contains(&id) {
+                return Err(JoinError::Full);
+            }
+        }
+        self.stop_typing(id);
+        // remove session from all rooms
+        let rooms = self.leave_rooms(id, ctx);
+        // send message to other users
+        for room in rooms {
+            self.send_notice(&room, "Someone dissconneted", Uuid::nil());
+            self.send_members(&room);
+        }
+        let limit = self.settings.max_room_members;
+        let room = self.rooms.entry(name.to_owned()).or_insert_with(|| {
+            let mut room = Room::new(limit, false);
+            room.owner = Some(id);
+            room
+        });
+        room.members.insert(id);
+        room.empty_since = None;
+        let topic = ChatEvent::Topic {
+            room: name.to_owned(),
+            topic: room.topic.clone(),
+        };
+        self.send_to(id, &topic);
+        self.send_notice(name, "Someone connected", id);
+        self.send_members(name);
+        Ok(())
+    }
+
+    fn record(&self, op: HistoryOp) {
+        if let Some(ref history) = self.history {
+            history.do_send(op);
+        }
+    }
+
+    // Give a message its id and timestamp, send it to the room and keep it
+    // for later changes. Bots post as the nil session.
+    fn post_message(
+        &mut self,
+        room: &str,
+        author: Uuid,
+        from: String,
+        text: String,
+        attachment: Option<Attachment>,
+    ) {
+        let id = Uuid::new_v4();
+        let sent_at = Utc::now();
+        let attachment_id = attachment.as_ref().map(|attachment| attachment.id);
+        let mentions: Vec<String> = parse_mentions(&text)
+            .into_iter()
+            .filter(|name| *name != from)
+            .collect();
+        let mut event = ChatEvent::Message {
+            id,
+            from: from.clone(),
+            text: text.clone(),
+            sent_at,
+            mine: false,
+            attachment,
+            mentions: mentions.clone(),
+        };
+        self.send_event(room, &event, author);
+        if let ChatEvent::Message { ref mut mine, .. } = event {
+            *mine = true;
+        }
+        self.send_to(author, &event);
+
+        if self.message_order.len() == MAX_TRACKED_MESSAGES {
+            if let Some(oldest) = self.message_order.pop_front() {
+                self.messages.remove(&oldest);
+            }
+        }
+        self.message_order.push_back(id);
+        let record = ChatRecord {
+            room: room.to_owned(),
+            author,
+            sent_at,
+            reactions: BTreeMap::new(),
+        };
+        self.messages.insert(id, record);
+        let private = self.rooms.get(room).is_some_and(Room::is_private);
+        self.record(HistoryOp::Sent {
+            id,
+            room: room.to_owned(),
+            session: author,
+            author: from.clone(),
+            text: text.clone(),
+            sent_at,
+            attachment: attachment_id,
+            private,
+        });
+
+        let mention = ChatEvent::Mention {
+            id,
+            room: room.to_owned(),
+            from,
+            text,
+            sent_at,
+        }
+        .to_json();
+        for name in mentions {
+            let here = self.deliver_mention(room, &name, private, &mention);
+            let elsewhere = self
+                .remote_members
+                .values()
+                .flat_map(|rooms| rooms.values())
+                .flatten()
+                .any(|member| member.member.name == name);
+            self.publish(BusEvent::Mention {
+                room: room.to_owned(),
+                name: name.clone(),
+                secret: self.room_secret(room),
+                event: mention.clone(),
+            });
+            // whoever takes the name next gets it, which must not leak a private room
+            if !here && !elsewhere && !private {
+                self.record(HistoryOp::Mentioned { id, name });
+            }
+        }
+    }
+
+    // Hand a mention to the sessions going by `name`, returns whether there are any
+    fn deliver_mention(&mut self, room: &str, name: &str, private: bool, mention: &str) -> bool {
+        let members = self.rooms.get(room).map(|room| &room.members);
+        let ids: Vec<(Uuid, bool)> = self
+            .sessions
+            .iter()
+            .filter(|(id, session)| session.display_name(**id) == name)
+            .map(|(id, _)| (*id, members.is_some_and(|members| members.contains(id))))
+            .collect();
+        for (id, member) in &ids {
+            if *member || !private {
+                if let Some(session) = self.sessions.get_mut(id) {
+                    session.deliver(mention.to_owned());
+                }
+            }
+        }
+        !ids.is_empty()
+    }
+
+    // Moderators of every room and the owner of the room
+    fn is_moderator(&self, id: Uuid, room: &str) -> bool {
+        self.moderates(id, self.moderates_all(id), room)
+    }
+
+    fn moderates_all(&self, id: Uuid) -> bool {
+        matches!(self.sessions.get(&id), Some(session) if session.moderator)
+    }
+
+    // `global` as the instance of the session has it, which need not be this one
+    fn moderates(&self, id: Uuid, global: bool, room: &str) -> bool {
+        let owner = matches!(self.rooms.get(room), Some(room) if room.owner == Some(id));
+        global || owner
+    }
+
+    // Messages live on the instance they were posted on. With others around,
+    // a change to a message unknown here is sent to them and true is returned.
+    fn forward_change(&self, id: Uuid, message: Uuid, change: MessageChange) -> bool {
+        let others = self.remote_members.values().any(|rooms| !rooms.is_empty());
+        if self.messages.contains_key(&message) || !others {
+            return false;
+        }
+        self.publish(BusEvent::Change {
+            session: id,
+            moderator: self.moderates_all(id),
+            message,
+            change,
+        });
+        true
+    }
+
+    fn within_edit_window(&self, sent_at: DateTime<Utc>) -> bool {
+        let window = chrono::Duration::seconds(self.settings.edit_window_secs as i64);
+        Utc::now() - sent_at <= window
+    }
+
+    pub(super) fn name_of(&self, id: Uuid) -> String {
+        match self.sessions.get(&id) {
+            Some(session) => session.display_name(id),
+            None => guest_name(id),
+        }
+    }
+
+    // Members of a room on this instance, bots left out as every instance has them
+    fn local_members(&self, room: &str) -> Vec<BusMember> {
+        let Some(ids) = self.rooms.get(room).map(|room| &room.members) else {
+            return Vec::new();
+        };
+        ids.iter()
+            .filter_map(|id| {
+                self.sessions.get(id).map(|session| BusMember {
+                    session: *id,
+                    member: Member {
+                        name: session.display_name(*id),
+                        status: session.status,
+                    },
+                })
+            })
+            .collect()
+    }
+
+    // Tell the other instances who is in the room here, then everyone in it
+    fn send_members(&mut self, room: &str) {
+        self.publish(BusEvent::Members {
+            room: room.to_owned(),
+            secret: self.room_secret(room),
+            members: self.local_members(room),
+        });
+        self.deliver_members(room);
+    }
+
+    fn deliver_members(&mut self, room: &str) {
+        if !self.rooms.contains_key(room) {
+            return;
+        }
+        let bots = self
+            .bots
+            .iter()
+            .filter(|bot| bot.rooms.contains(room))
+            .map(|bot| Member {
+                name: bot.bot.name().to_owned(),
+                status: Presence::Online,
+            });
+        let remote = self
+            .remote_members
+            .values()
+            .filter_map(|rooms| rooms.get(room))
+            .flatten()
+            .map(|member| member.member.clone());
+        let mut members: Vec<Member> = self
+            .local_members(room)
+            .into_iter()
+            .map(|member| member.member)
+            .chain(bots)
+            .chain(remote)
+            .collect();
+        members.sort_by(|a, b| a.name.cmp(&b.name));
+        let event = ChatEvent::Members {
+            room: room.to_owned(),
+            members,
+        };
+        self.deliver_local(room, &event.to_json(), Uuid::nil());
+    }
+
+    // Clear the typing indicator of a session and tell its room, if it was set
+    fn stop_typing(&mut self, id: Uuid) {
+        let name = self.name_of(id);
+        let was_typing = match self.sessions.get_mut(&id) {
+            Some(session) => session.typing_since.take().is_some(),
+            None => false,
+        };
+        if was_typing {
+            if let Some(room) = self.room_of(id) {
+                let event = ChatEvent::Typing {
+                    name,
+                    typing: false,
+                };
+                self.send_event(&room, &event, id);
+            }
+        }
+    }
+
+    // Broadcast presence changes to the room of the session
+    fn set_presence(&mut self, id: Uuid, status: Presence) {
+        let Some(session) = self.sessions.get_mut(&id) else {
+            return;
+        };
+        if session.status == status {
+            return;
+        }
+        session.status = status;
+        if let Some(room) = self.room_of(id) {
+            let event = ChatEvent::Presence {
+                name: self.name_of(id),
+                status,
+            };
+            self.send_event(&room, &event, Uuid::nil());
+            self.send_members(&room);
+        }
+    }
+
+    fn expire_typing(&mut self) {
+        let now = Instant::now();
+        let expired: Vec<Uuid> = self
+            .sessions
+            .iter()
+            .filter_map(|(id, session)| match session.typing_since {
+                Some(since) if now.duration_since(since) > TYPING_TIMEOUT => Some(*id),
+                _ => None,
+            })
+            .collect();
+        for id in expired {
+            self.stop_typing(id);
+        }
+    }
+}
+
+impl Actor for ChatServer {
+    // Simple Context to communicate with other actors.
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.run_interval(TYPING_CHECK_INTERVAL, |act, _| act.expire_typing());
+        let subscribed = self.bus.subscribe(ctx.address().recipient());
+        ctx.spawn(
+            subscribed
+                .into_actor(self)
+                .map(|_, act, _| act.publish(BusEvent::Hello)),
+        );
+    }
+}
+
+// Something happened on another instance
+impl Handler<BusMessage> for ChatServer {
+    type Result = ();
+    fn handle(&mut self, msg: BusMessage, ctx: &mut Context<Self>) {
+        if msg.origin == self.instance {
+            return;
+        }
+        match msg.event {
+            BusEvent::PrivateRoom { room: name, secret } => {
+                let limit = self.settings.max_room_members;
+                let room = self
+                    .rooms
+                    .entry(name.clone())
+                    .or_insert_with(|| Room::new(limit, false));
+                // a room in use here stays what it is, events of the other are not delivered to it
+                if room.members.is_empty() && !room.permanent {
+                    room.secret = Some(secret);
+                    // removed like the rooms of this instance, once nobody is in it anywhere
+                    self.expire_if_unused(&name, ctx);
+                }
+            }
+            BusEvent::Room {
+                room,
+                secret,
+                event,
+            } => {
+                if self.same_room(&room, &secret) {
+                    self.deliver_local(&room, &event, Uuid::nil());
+                }
+            }
+            BusEvent::Members {
+                room,
+                secret,
+                members,
+            } => {
+                let same = self.same_room(&room, &secret);
+                let rooms = self.remote_members.entry(msg.origin).or_default();
+                if members.is_empty() || !same {
+                    rooms.remove(&room);
+                    self.expire_if_unused(&room, ctx);
+                } else {
+                    rooms.insert(room.clone(), members);
+                }
+                self.deliver_members(&room);
+            }
+            BusEvent::Mention {
+                room,
+                name,
+                secret,
+                event,
+            } => {
+                let private = secret.is_some();
+                if !private || self.same_room(&room, &secret) {
+                    self.deliver_mention(&room, &name, private, &event);
+                }
+            }
+            BusEvent::Direct { session, event } => {
+                if let Some(session) = self.sessions.get_mut(&session) {
+                    session.deliver(event);
+                }
+            }
+            BusEvent::Input { session, text } => {
+                if self.sessions.contains_key(&session) {
+                    ctx.notify(ClientText { id: session, text });
+                }
+            }
+            BusEvent::Attachment {
+                session,
+                attachment,
+            } => {
+                if self.sessions.contains_key(&session) {
+                    ctx.notify(ShareAttachment {
+                        id: session,
+                        attachment,
+                    });
+                }
+            }
+            BusEvent::Change {
+                session,
+                moderator,
+                message,
+                change,
+            } => {
+                if self.messages.contains_key(&message) {
+                    let res = match change {
+                        MessageChange::Edit { text } => {
+                            self.edit_message(session, moderator, message, text)
+                        }
+                        MessageChange::Delete => self.delete_message(session, moderator, message),
+                        MessageChange::React { emoji } => self.react(session, message, emoji),
+                    };
+                    if let Err(err) = res {
+                        let event = ChatEvent::error(err.to_string()).to_json();
+                        self.publish(BusEvent::Direct { session, event });
+                    }
+                }
+            }
+            BusEvent::Hello => {
+                let rooms: Vec<String> = self
+                    .rooms
+                    .iter()
+                    .filter(|(_, room)| !room.members.is_empty())
+                    .map(|(name, _)| name.to_owned())
+                    .collect();
+                for room in rooms {
+                    let secret = self.room_secret(&room);
+                    if let Some(secret) = &secret {
+                        self.publish(BusEvent::PrivateRoom {
+                            room: room.clone(),
+                            secret: secret.clone(),
+                        });
+                    }
+                    self.publish(BusEvent::Members {
+                        members: self.local_members(&room),
+                        secret,
+                        room,
+                    });
+                }
+            }
+            BusEvent::Gone => {
+                let rooms = self.remote_members.remove(&msg.origin).unwrap_or_default();
+                for room in rooms.keys() {
+                    self.expire_if_unused(room, ctx);
+                    self.deliver_members(room);
+                }
+            }
+        }
+    }
+}
+
+// Register new session and assign unique id to this session,
+// or attach the new connection to the session named by a valid resume token
+impl Handler<Connect> for ChatServer {
+    type Result = Connected;
+    fn handle(&mut self, msg: Connect, _: &mut Self::Context) -> Self::Result {
+        let resumed = msg
+            .resume
+            .as_deref()
+            .and_then(|token| self.verify_token(token))
+            .filter(|id| self.sessions.contains_key(id));
+        if let Some(id) = resumed {
+            self.resume(id, msg.addr, msg.close);
+            return Connected { id, resumed: true };
+        }
+
+        println!("Someone joined");
+        self.send_notice("main", "Someone joined", Uuid::nil());
+
+        // Register session with random id
+        let id = Uuid::new_v4();
+        self.sessions
+            .insert(id, Session::new(msg.addr, msg.close, msg.peer));
+        let event = ChatEvent::Session {
+            id,
+            token: self.resume_token(id),
+            resumed: false,
+        };
+        self.send_to(id, &event);
+
+        // auto join session to main room, it is never capped so nobody is left without a room
+        if let Some(main) = self.rooms.get_mut("main") {
+            main.members.insert(id);
+            main.empty_since = None;
+        }
+
+        let count = self.visitor_count.fetch_add(1, Ordering::SeqCst) + 1;
+        self.send_notice("main", &format!("Visitors online {count}"), Uuid::nil());
+        self.send_members("main");
+        Connected { id, resumed: false }
+    }
+}
+
+impl ChatServer {
+    // Swap in the new connection and replay what it missed
+    fn resume(&mut self, id: Uuid, addr: Recipient<Message>, close: Recipient<Close>) {
+        let token = self.resume_token(id);
+        let Some(session) = self.sessions.get_mut(&id) else {
+            return;
+        };
+        let was_detached = session.detached_since.take().is_some();
+        session.addr = addr;
+        session.close = close;
+        let missed: Vec<String> = session.missed.drain(..).collect();
+        if was_detached {
+            self.visitor_count.fetch_add(1, Ordering::SeqCst);
+        }
+        let event = ChatEvent::Session {
+            id,
+            token,
+            resumed: true,
+        };
+        self.send_to(id, &event);
+        if let Some(session) = self.sessions.get(&id) {
+            for message in missed {
+                session.addr.do_send(Message(message));
+            }
+        }
+        if let Some(name) = self.room_of(id) {
+            let topic = ChatEvent::Topic {
+                room: name.clone(),
+                topic: self.rooms.get(&name).and_then(|room| room.topic.clone()),
+            };
+            self.send_to(id, &topic);
+            self.set_presence(id, Presence::Online);
+            self.send_members(&name);
+        }
+    }
+}
+
+// Handler for Disconnect message.
+impl Handler<Disconnect> for ChatServer {
+    type Result = ();
+    fn handle(&mut self, msg: Disconnect, ctx: &mut Context<Self>) {
+        let grace = self.resume_grace();
+        let Some(session) = self.sessions.get_mut(&msg.id) else {
+            return;
+        };
+        // heartbeat timeouts and closing sockets both report here, and the
+        // connection replaced by a resume reports late; only count a session once
+        if session.detached_since.is_some() || session.addr != msg.addr {
+            return;
+        }
+        session.detached_since = Some(Instant::now());
+        self.visitor_count.fetch_sub(1, Ordering::SeqCst);
+        self.stop_typing(msg.id);
+        self.set_presence(msg.id, Presence::Away);
+        let id = msg.id;
+        ctx.run_later(grace, move |act, ctx| act.expire_session(id, ctx));
+    }
+}
+
+impl Handler<Shutdown> for ChatServer {
+    type Result = ResponseActFuture<Self, ()>;
+    fn handle(&mut self, msg: Shutdown, _: &mut Context<Self>) -> Self::Result {
+        let event = ChatEvent::Restarting {
+            reconnect_after_secs: msg.reconnect_after.as_secs(),
+        }
+        .to_json();
+        // detached sessions have nobody to tell, they are gone with the process
+        for session in self.sessions.values() {
+            if session.detached_since.is_none() {
+                session.addr.do_send(Message(event.clone()));
+                session.close.do_send(Close);
+            }
+        }
+        self.publish(BusEvent::Gone);
+        // the writer handles one change at a time, once it answers all are written
+        let history = self.history.clone();
+        Box::pin(
+            async move {
+                if let Some(history) = history {
+                    if let Err(err) = history.send(Flush).await {
+                        println!("Failed to flush chat history: {err:?}");
+                    }
+                }
+            }
+            .into_actor(self),
+        )
+    }
+}
+
+impl Handler<SessionName> for ChatServer {
+    type Result = Option<String>;
+    fn handle(&mut self, msg: SessionName, _: &mut Context<Self>) -> Self::Result {
+        match self.sessions.get(&msg.0) {
+            Some(session) => Some(session.display_name(msg.0)),
+            None => self
+                .remote_session(msg.0)
+                .map(|(_, member)| member.name.clone()),
+        }
+    }
+}
+
+impl Handler<OwnerReply> for ChatServer {
+    type Result = bool;
+    fn handle(&mut self, msg: OwnerReply, _: &mut Context<Self>) -> Self::Result {
+        let event = ChatEvent::OwnerReply {
+            text: msg.text,
+            sent_at: msg.sent_at,
+        }
+        .to_json();
+        match self.sessions.get_mut(&msg.session) {
+            Some(session) => {
+                session.deliver(event);
+                true
+            }
+            None => {
+                self.publish(BusEvent::Direct {
+                    session: msg.session,
+                    event,
+                });
+                false
+            }
+        }
+    }
+}
+
+impl Handler<VerifyToken> for ChatServer {
+    type Result = Option<Uuid>;
+    fn handle(&mut self, msg: VerifyToken, _: &mut Context<Self>) -> Self::Result {
+        self.verify_token(&msg.0)
+            .filter(|id| self.sessions.contains_key(id) || self.remote_session(*id).is_some())
+    }
+}
+
+// Handler for Message message
+impl Handler<ClientMessage> for ChatServer {
+    type Result = ();
+    fn handle(&mut self, msg: ClientMessage, _: &mut Context<Self>) {
+        self.stop_typing(msg.id);
+        let from = self.name_of(msg.id);
+        self.post_message(&msg.room, msg.id, from.clone(), msg.msg.clone(), None);
+
+        // bots answer everyone, the author included
+        let replies: Vec<(String, String)> = self
+            .bots
+            .iter_mut()
+            .filter(|bot| bot.rooms.contains(&msg.room))
+            .filter_map(|bot| {
+                let reply = bot.bot.on_message(&msg.room, &from, &msg.msg)?;
+                Some((bot.bot.name().to_owned(), reply))
+            })
+            .collect();
+        for (name, reply) in replies {
+            self.post_message(&msg.room, Uuid::nil(), name, reply, None);
+        }
+    }
+}
+
+// Every transport hands user input to the server, so commands, names and
+// rate limits behave the same whether the user is on a websocket or on SSE
+impl Handler<ClientText> for ChatServer {
+    type Result = bool;
+    fn handle(&mut self, msg: ClientText, ctx: &mut Context<Self>) -> bool {
+        let ClientText { id, text } = msg;
+        let m = text.trim();
+        let typing = m == "/typing" || m == "/typing stop";
+        let Some(session) = self.sessions.get_mut(&id) else {
+            // the instance that has the session takes it from here
+            let remote = self.remote_session(id).is_some();
+            if remote {
+                self.publish(BusEvent::Input { session: id, text });
+            }
+            return remote;
+        };
+        // typing indicators are debounced on their own and do not count against the limit
+        if !typing && !session.allow() {
+            self.send_to(id, &ChatEvent::error("slow down, you are sending too fast"));
+            return true;
+        }
+        if session.status == Presence::Away {
+            self.set_presence(id, Presence::Online);
+        }
+
+        if !m.starts_with('/') {
+            if let Some(room) = self.room_of(id) {
+                // send message to chat server
+                Handler::<ClientMessage>::handle(
+                    self,
+                    ClientMessage {
+                        id,
+                        msg: m.to_owned(),
+                        room,
+                    },
+                    ctx,
+                );
+            }
+            return true;
+        }
+
+        let (name, args) = m[1..].split_once(' ').unwrap_or((&m[1..], ""));
+        match self.commands.get(name) {
+            Some(command) => command.run(self, id, args.trim(), ctx),
+            None => self.send_to(id, &ChatEvent::error(format!("unknown command: {m:?}"))),
+        }
+        true
+    }
+}
+
+// A message kept from the room, quarantined as spam, counts against the
+// limit of its session all the same. False once over it or for an unknown
+// session, sessions of other instances are counted by the messages they post.
+#[derive(Message)]
+#[rtype(result = "bool")]
+pub struct CountAgainstLimit {
+    pub id: Uuid,
+}
+
+impl Handler<CountAgainstLimit> for ChatServer {
+    type Result = bool;
+    fn handle(&mut self, msg: CountAgainstLimit, _: &mut Context<Self>) -> bool {
+        match self.sessions.get_mut(&msg.id) {
+            Some(session) => session.allow(),
+            None => self.remote_session(msg.id).is_some(),
+        }
+    }
+}
+
+// Handler for `ListRooms` message.
+impl Handler<ListRooms> for ChatServer {
+    type Result = MessageResult<ListRooms>;
+    fn handle(&mut self, _: ListRooms, _: &mut Context<Self>) -> Self::Result {
+        let mut rooms = Vec::new();
+        for (key, room) in &self.rooms {
+            // private rooms are only reachable by those who know about them
+            if !room.is_private() {
+                rooms.push(key.to_owned())
+            }
+        }
+        rooms.sort();
+        MessageResult(rooms)
+    }
+}
+
+impl Handler<RoomOccupancy> for ChatServer {
+    type Result = MessageResult<RoomOccupancy>;
+    fn handle(&mut self, _: RoomOccupancy, _: &mut Context<Self>) -> Self::Result {
+        let mut rooms: Vec<(String, usize)> = self
+            .rooms
+            .iter()
+            .filter(|(_, room)| !room.is_private())
+            .map(|(name, room)| (name.to_owned(), room.members.len()))
+            .collect();
+        rooms.sort();
+        MessageResult(rooms)
+    }
+}
+
+// Join room, send disconnect message to old rooms
+// send join message to new room
+// Password hashing runs on the blocking pool so the server keeps
+// serving other sessions meanwhile.
+impl Handler<Join> for ChatServer {
+    type Result = ResponseActFuture<Self, Result<(), JoinError>>;
+    fn handle(&mut self, msg: Join, ctx: &mut Context<Self>) -> Self::Result {
+        let Join { id, name, secret } = msg;
+        let (stored, is_member) = match self.rooms.get(&name) {
+            Some(room) => (room.secret.clone(), room.members.contains(&id)),
+            None => (None, false),
+        };
+        let exists = self.rooms.contains_key(&name);
+
+        match (exists, stored, secret) {
+            // a new room created with a secret becomes private
+            (false, _, Some(secret)) => Box::pin(
+                task::spawn_blocking(move || hash_password(&secret))
+                    .into_actor(self)
+                    .map(move |hash, act, ctx| {
+                        if act.rooms.contains_key(&name) {
+                            // somebody else created it while the password was hashed
+                            return Err(JoinError::Private);
+                        }
+                        let Ok(Ok(hash)) = hash else {
+                            println!("Failed to hash room password");
+                            return Err(JoinError::Private);
+                        };
+                        let limit = act.settings.max_room_members;
+                        let mut room = Room::new(limit, false);
+                        room.secret = Some(hash.clone());
+                        room.owner = Some(id);
+                        act.rooms.insert(name.clone(), room);
+                        // the other instances learn it is private before anything happens in it
+                        act.publish(BusEvent::PrivateRoom {
+                            room: name.clone(),
+                            secret: hash,
+                        });
+                        act.enter_room(id, &name, ctx)
+                    }),
+            ),
+            (true, Some(_), _) if is_member => Box::pin(fut::ready(Ok(()))),
+            (true, Some(_), None) => Box::pin(fut::ready(Err(JoinError::Private))),
+            (true, Some(hash), Some(secret)) => {
+                let key = self.client_key(id);
+                if self.is_locked_out(&key) {
+                    return Box::pin(fut::ready(Err(JoinError::TooManyAttempts)));
+                }
+                // an invite token is only good once
+                let invited = self
+                    .rooms
+                    .get_mut(&name)
+                    .map(|room| room.invites.remove(&hash_token(&secret)))
+                    .unwrap_or(false);
+                if invited {
+                    return Box::pin(fut::ready(self.enter_room(id, &name, ctx)));
+                }
+                Box::pin(
+                    task::spawn_blocking(move || verify_password(&hash, &secret))
+                        .into_actor(self)
+                        .map(move |verified, act, ctx| {
+                            if matches!(verified, Ok(Ok(true))) {
+                                act.enter_room(id, &name, ctx)
+                            } else {
+                                act.record_failed_join(key);
+                                Err(JoinError::WrongSecret)
+                            }
+                        }),
+                )
+            }
+            _ => Box::pin(fut::ready(self.enter_room(id, &name, ctx))),
+        }
+    }
+}
+
+// Invites are handed to the member that asked for them, only their digest is kept
+impl Handler<CreateInvite> for ChatServer {
+    type Result = ();
+    fn handle(&mut self, msg: CreateInvite, _: &mut Context<Self>) {
+        let Some(name) = self.room_of(msg.id) else {
+            return;
+        };
+        let Some(room) = self.rooms.get_mut(&name) else {
+            return;
+        };
+        if !room.is_private() {
+            self.send_to(
+                msg.id,
+                &ChatEvent::error("invites are only needed for private rooms"),
+            );
+            return;
+        }
+        let token = Uuid::new_v4().simple().to_string();
+        room.invites.insert(hash_token(&token));
+        self.send_to(msg.id, &ChatEvent::Invite { room: name, token });
+    }
+}
+
+impl Handler<IsMember> for ChatServer {
+    type Result = bool;
+    fn handle(&mut self, msg: IsMember, _: &mut Context<Self>) -> bool {
+        let local = self
+            .rooms
+            .get(&msg.room)
+            .is_some_and(|room| room.members.contains(&msg.id));
+        local || matches!(self.remote_session(msg.id), Some((room, _)) if room == msg.room)
+    }
+}
+
+impl Handler<MayShare> for ChatServer {
+    type Result = Result<(), ShareError>;
+    fn handle(&mut self, msg: MayShare, _: &mut Context<Self>) -> Self::Result {
+        if self.room_of(msg.id).is_none() {
+            return match self.remote_session(msg.id) {
+                Some(_) => Ok(()),
+                None => Err(ShareError::NoRoom),
+            };
+        }
+        let allowed = self
+            .sessions
+            .get_mut(&msg.id)
+            .is_some_and(|session| session.allow());
+        if allowed {
+            Ok(())
+        } else {
+            Err(ShareError::TooFast)
+        }
+    }
+}
+
+impl Handler<ShareAttachment> for ChatServer {
+    type Result = bool;
+    fn handle(&mut self, msg: ShareAttachment, _: &mut Context<Self>) -> bool {
+        if !self.sessions.contains_key(&msg.id) {
+            // shared by the instance that has the session
+            let remote = self.remote_session(msg.id).is_some();
+            if remote {
+                self.publish(BusEvent::Attachment {
+                    session: msg.id,
+                    attachment: msg.attachment,
+                });
+            }
+            return remote;
+        }
+        let Some(room) = self.room_of(msg.id) else {
+            return false;
+        };
+        let from = self.name_of(msg.id);
+        let text = msg.attachment.name.clone();
+        self.post_message(&room, msg.id, from, text, Some(msg.attachment));
+        true
+    }
+}
+
+impl Handler<EditMessage> for ChatServer {
+    type Result = Result<(), EditError>;
+    fn handle(&mut self, msg: EditMessage, _: &mut Context<Self>) -> Self::Result {
+        let change = MessageChange::Edit {
+            text: msg.text.clone(),
+        };
+        if self.forward_change(msg.id, msg.message, change) {
+            return Ok(());
+        }
+        let moderator = self.moderates_all(msg.id);
+        self.edit_message(msg.id, moderator, msg.message, msg.text)
+    }
+}
+
+impl Handler<DeleteMessage> for ChatServer {
+    type Result = Result<(), EditError>;
+    fn handle(&mut self, msg: DeleteMessage, _: &mut Context<Self>) -> Self::Result {
+        if self.forward_change(msg.id, msg.message, MessageChange::Delete) {
+            return Ok(());
+        }
+        let moderator = self.moderates_all(msg.id);
+        self.delete_message(msg.id, moderator, msg.message)
+    }
+}
+
+impl Handler<React> for ChatServer {
+    type Result = Result<(), EditError>;
+    fn handle(&mut self, msg: React, _: &mut Context<Self>) -> Self::Result {
+        if !is_emoji(&msg.emoji) {
+            return Err(EditError::InvalidEmoji);
+        }
+        let change = MessageChange::React {
+            emoji: msg.emoji.clone(),
+        };
+        if self.forward_change(msg.id, msg.message, change) {
+            return Ok(());
+        }
+        self.react(msg.id, msg.message, msg.emoji)
+    }
+}
+
+// Changes to the messages of this instance, by sessions of any instance
+impl ChatServer {
+    fn edit_message(
+        &mut self,
+        id: Uuid,
+        moderator: bool,
+        message: Uuid,
+        text: String,
+    ) -> Result<(), EditError> {
+        let record = self.messages.get(&message).ok_or(EditError::NotFound)?;
+        if record.author != id {
+            return Err(EditError::NotAllowed);
+        }
+        if !self.within_edit_window(record.sent_at) && !self.moderates(id, moderator, &record.room)
+        {
+            return Err(EditError::TooLate);
+        }
+        let room = record.room.clone();
+        let edited_at = Utc::now();
+        let event = ChatEvent::Edited {
+            id: message,
+            text: text.clone(),
+            edited_at,
+        };
+        self.send_event(&room, &event, Uuid::nil());
+        self.record(HistoryOp::Edited {
+            id: message,
+            text,
+            edited_at,
+        });
+        Ok(())
+    }
+
+    fn delete_message(
+        &mut self,
+        id: Uuid,
+        moderator: bool,
+        message: Uuid,
+    ) -> Result<(), EditError> {
+        let record = self.messages.get(&message).ok_or(EditError::NotFound)?;
+        if !self.moderates(id, moderator, &record.room) {
+            if record.author != id {
+                return Err(EditError::NotAllowed);
+            }
+            if !self.within_edit_window(record.sent_at) {
+                return Err(EditError::TooLate);
+            }
+        }
+        let room = record.room.clone();
+        self.messages.remove(&message);
+        self.message_order.retain(|id| *id != message);
+        let event = ChatEvent::Deleted { id: message };
+        self.send_event(&room, &event, Uuid::nil());
+        self.record(HistoryOp::Deleted {
+            id: message,
+            deleted_at: Utc::now(),
+        });
+        Ok(())
+    }
+
+    fn react(&mut self, id: Uuid, message: Uuid, emoji: String) -> Result<(), EditError> {
+        let record = self.messages.get_mut(&message).ok_or(EditError::NotFound)?;
+        let sessions = record.reactions.entry(emoji.clone()).or_default();
+        // reacting twice takes the reaction back
+        let active = sessions.insert(id);
+        if !active {
+            sessions.remove(&id);
+        }
+        record.reactions.retain(|_, sessions| !sessions.is_empty());
+        let reactions = record
+            .reactions
+            .iter()
+            .map(|(emoji, sessions)| (emoji.clone(), sessions.len()))
+            .collect();
+        let room = record.room.clone();
+        let event = ChatEvent::Reactions {
+            id: message,
+            reactions,
+        };
+        self.send_event(&room, &event, Uuid::nil());
+        self.record(HistoryOp::Reacted {
+            id: message,
+            session: id,
+            emoji,
+            active,
+        });
+        Ok(())
+    }
+}
+
+// Checked like a private room password, failures count against the same lockout
+impl Handler<Authenticate> for ChatServer {
+    type Result = ResponseActFuture<Self, bool>;
+    fn handle(&mut self, msg: Authenticate, _: &mut Context<Self>) -> Self::Result {
+        let Some(hash) = self.settings.moderator_password.clone() else {
+            return Box::pin(fut::ready(false));
+        };
+        let key = self.client_key(msg.id);
+        if self.is_locked_out(&key) {
+            return Box::pin(fut::ready(false));
+        }
+        let Authenticate { id, password } = msg;
+        Box::pin(
+            task::spawn_blocking(move || verify_password(&hash, &password))
+                .into_actor(self)
+                .map(move |verified, act, _| {
+                    if !matches!(verified, Ok(Ok(true))) {
+                        act.record_failed_join(key);
+                        return false;
+                    }
+                    match act.sessions.get_mut(&id) {
+                        Some(session) => {
+                            session.moderator = true;
+                            true
+                        }
+                        None => false,
+                    }
+                }),
+        )
+    }
+}
+
+// A reaction is a short run of non-ASCII characters, enough for emoji with
+// skin tones and joiners while keeping words and markup out
+fn is_emoji(text: &str) -> bool {
+    let count = text.chars().count();
+    (1..=8).contains(&count) && text.chars().all(|c| !c.is_ascii() && !c.is_whitespace())
+}
+
+// `@name` words of a message, each name once. Trailing punctuation is not part
+// of the name, names with spaces cannot be mentioned.
+fn parse_mentions(text: &str) -> Vec<String> {
+    let mut names: Vec<String> = Vec::new();
+    for word in text.split_whitespace() {
+        let Some(name) = word.strip_prefix('@') else {
+            continue;
+        };
+        let name = name.trim_end_matches(|c: char| c.is_ascii_punctuation() && c != '_');
+        if !name.is_empty() && !names.iter().any(|known| known == name) {
+            names.push(name.to_owned());
+        }
+        if names.len() == MAX_MENTIONS {
+            break;
+        }
+    }
+    names
+}
+
+fn guest_name(id: Uuid) -> String {
+    format!("guest-{}", &id.simple().to_string()[..4])
+}
+
+// Invite tokens are random, a plain digest is enough to keep them out of memory dumps
+fn hash_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+// Set the topic and announce it to the room, without a topic just report the current one
+impl Handler<SetTopic> for ChatServer {
+    type Result = ();
+    fn handle(&mut self, msg: SetTopic, _: &mut Context<Self>) {
+        let Some(name) = self.room_of(msg.id) else {
+            return;
+        };
+        if msg.topic.is_some() && !self.is_moderator(msg.id, &name) {
+            let error =
+                ChatEvent::error("only the creator of the room or a moderator sets its topic");
+            self.send_to(msg.id, &error);
+            return;
+        }
+        let Some(room) = self.rooms.get_mut(&name) else {
+            return;
+        };
+        match msg.topic {
+            Some(topic) => {
+                room.topic = Some(topic.clone());
+                let event = ChatEvent::Topic {
+                    room: name.clone(),
+                    topic: Some(topic),
+                };
+                self.send_event(&name, &event, Uuid::nil());
+            }
+            None => {
+                let event = ChatEvent::Topic {
+                    room: name.clone(),
+                    topic: room.topic.clone(),
+                };
+                self.send_to(msg.id, &event);
+            }
+        }
+    }
+}
+
+impl Handler<SetLimit> for ChatServer {
+    type Result = ();
+    fn handle(&mut self, msg: SetLimit, _: &mut Context<Self>) {
+        let Some(name) = self.room_of(msg.id) else {
+            return;
+        };
+        let Some(room) = self.rooms.get(&name) else {
+            return;
+        };
+        // everyone must always be able to get into these
+        if name == "main" || room.permanent {
+            self.send_to(msg.id, &ChatEvent::error("this room cannot be limited"));
+            return;
+        }
+        if !self.is_moderator(msg.id, &name) {
+            let error = ChatEvent::error("only the creator of the room or a moderator limits it");
+            self.send_to(msg.id, &error);
+            return;
+        }
+        if let Some(room) = self.rooms.get_mut(&name) {
+            room.limit = msg.limit;
+        }
+        let text = match msg.limit {
+            Some(limit) => format!("Room is now limited to {limit} members"),
+            None => "Room is no longer limited".to_owned(),
+        };
+        self.send_notice(&name, &text, Uuid::nil());
+    }
+}
+
+// Rename a session and refresh the member list of its room
+impl Handler<SetName> for ChatServer {
+    type Result = ();
+    fn handle(&mut self, msg: SetName, ctx: &mut Context<Self>) {
+        if self.bots.iter().any(|bot| bot.bot.name() == msg.name) {
+            self.send_to(msg.id, &ChatEvent::error("that name belongs to a bot"));
+            return;
+        }
+        self.stop_typing(msg.id);
+        if let Some(session) = self.sessions.get_mut(&msg.id) {
+            session.name = Some(msg.name.clone());
+        }
+        if let Some(room) = self.room_of(msg.id) {
+            self.send_members(&room);
+        }
+        // mentions of the name from while nobody had it
+        if let Some(history) = self.history.clone() {
+            let id = msg.id;
+            let pending = history.send(TakeMentions { name: msg.name });
+            ctx.spawn(pending.into_actor(self).map(move |res, act, _| {
+                for mention in res.unwrap_or_default() {
+                    let event = ChatEvent::Mention {
+                        id: mention.id,
+                        room: mention.room,
+                        from: mention.from,
+                        text: mention.text,
+                        sent_at: mention.sent_at,
+                    };
+                    act.send_to(id, &event);
+                }
+            }));
+        }
+    }
+}
+
+// Typing indicators are debounced: only the first start and the final stop
+// are broadcast, refreshes in between just push the expiry further away
+impl Handler<Typing> for ChatServer {
+    type Result = ();
+    fn handle(&mut self, msg: Typing, _: &mut Context<Self>) {
+        if !msg.typing {
+            self.stop_typing(msg.id);
+            return;
+        }
+        let Some(session) = self.sessions.get_mut(&msg.id) else {
+            return;
+        };
+        let already_typing = session.typing_since.replace(Instant::now()).is_some();
+        if !already_typing {
+            if let Some(room) = self.room_of(msg.id) {
+                let event = ChatEvent::Typing {
+                    name: self.name_of(msg.id),
+                    typing: true,
+                };
+                self.send_event(&room, &event, msg.id);
+            }
+        }
+    }
+}
+
+// Sessions report on every heartbeat, the ones idle for too long are shown as away
+impl Handler<Heartbeat> for ChatServer {
+    type Result = ();
+    fn handle(&mut self, msg: Heartbeat, _: &mut Context<Self>) {
+        let idle = match self.sessions.get(&msg.id) {
+            Some(session) => {
+                session.status == Presence::Online && session.active.elapsed() > AWAY_TIMEOUT
+            }
+            None => false,
+        };
+        if idle {
+            self.set_presence(msg.id, Presence::Away);
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+    use std::sync::Mutex;
+
+    // Stands in for a websocket session and records every event it is sent
+    struct Probe(Arc<Mutex<Vec<Value>>>);
+
+    impl Actor for Probe {
+        type Context = Context<Self>;
+    }
+
+    impl Handler<Message> for Probe {
+        type Result = ();
+        fn handle(&mut self, msg: Message, _: &mut Context<Self>) {
+            let event = serde_json::from_str(&msg.0).expect("events are JSON");
+            self.0.lock().unwrap().push(event);
+        }
+    }
+
+    impl Handler<Close> for Probe {
+        type Result = ();
+        fn handle(&mut self, _: Close, _: &mut Context<Self>) {
+            self.0.lock().unwrap().push(json!({ "type": "closed" }));
+        }
+    }
+
+    // Runs a closure against the server state, for assertions on internals
+    struct Inspect<F>(F);
+
+    impl<F: FnOnce(&ChatServer) -> R + 'static, R: 'static> actix::Message for Inspect<F> {
+        type Result = R;
+    }
+
+    impl<F: FnOnce(&ChatServer) -> R + 'static, R: 'static> Handler<Inspect<F>> for ChatServer {
+        type Result = MessageResult<Inspect<F>>;
+        fn handle(&mut self, msg: Inspect<F>, _: &mut Context<Self>) -> Self::Result {
+            MessageResult((msg.0)(self))
+        }
+    }
+
+    fn start_server(settings: ChatSettings) -> Addr<ChatServer> {
+        ChatServer::new(Arc::new(AtomicUsize::new(0)), settings).start()
+    }
+
+    async fn connect(server: &Addr<ChatServer>) -> (Uuid, Arc<Mutex<Vec<Value>>>) {
+        let (connected, events) = resume(server, None).await;
+        (connected.id, events)
+    }
+
+    async fn resume(
+        server: &Addr<ChatServer>,
+        token: Option<String>,
+    ) -> (Connected, Arc<Mutex<Vec<Value>>>) {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let addr = Probe(events.clone()).start();
+        let connected = server
+            .send(Connect {
+                addr: addr.clone().recipient(),
+                close: addr.recipient(),
+                peer: None,
+                resume: token,
+            })
+            .await
+            .unwrap();
+        (connected, events)
+    }
+
+    // Drop the current connection of a session, as its transport would
+    async fn disconnect(server: &Addr<ChatServer>, id: Uuid) {
+        let addr = server
+            .send(Inspect(move |server: &ChatServer| {
+                server.sessions.get(&id).map(|session| session.addr.clone())
+            }))
+            .await
+            .unwrap();
+        if let Some(addr) = addr {
+            server.send(Disconnect { id, addr }).await.unwrap();
+        }
+    }
+
+    fn token_of(events: &Arc<Mutex<Vec<Value>>>) -> String {
+        let events = events.lock().unwrap();
+        let session = events
+            .iter()
+            .find(|event| event["type"] == "session")
+            .expect("session event is sent on connect");
+        session["token"].as_str().unwrap().to_owned()
+    }
+
+    async fn join(server: &Addr<ChatServer>, id: Uuid, name: &str) -> Result<(), JoinError> {
+        join_with(server, id, name, None).await
+    }
+
+    async fn join_with(
+        server: &Addr<ChatServer>,
+        id: Uuid,
+        name: &str,
+        secret: Option<&str>,
+    ) -> Result<(), JoinError> {
+        server
+            .send(Join {
+                id,
+                name: name.to_owned(),
+                secret: secret.map(str::to_owned),
+            })
+            .await
+            .unwrap()
+    }
+
+    async fn rooms(server: &Addr<ChatServer>) -> Vec<String> {
+        server.send(ListRooms).await.unwrap()
+    }
+
+    #[actix::test]
+    async fn empty_rooms_are_removed_after_grace_period() {
+        let server = start_server(ChatSettings {
+            empty_room_grace_secs: 1,
+            ..ChatSettings::default()
+        });
+        let (id, _) = connect(&server).await;
+        join(&server, id, "foo").await.unwrap();
+        join(&server, id, "main").await.unwrap();
+
+        assert!(rooms(&server).await.contains(&"foo".to_owned()));
+        actix::clock::sleep(Duration::from_millis(1200)).await;
+        assert_eq!(rooms(&server).await, vec!["main".to_owned()]);
+    }
+
+    #[actix::test]
+    async fn rooms_rejoined_within_grace_period_are_kept() {
+        let server = start_server(ChatSettings {
+            empty_room_grace_secs: 1,
+            ..ChatSettings::default()
+        });
+        let (id, _) = connect(&server).await;
+        join(&server, id, "foo").await.unwrap();
+        join(&server, id, "main").await.unwrap();
+        join(&server, id, "foo").await.unwrap();
+
+        actix::clock::sleep(Duration::from_millis(1200)).await;
+        assert!(rooms(&server).await.contains(&"foo".to_owned()));
+    }
+
+    #[actix::test]
+    async fn permanent_rooms_are_never_removed() {
+        let server = start_server(ChatSettings {
+            permanent_rooms: vec!["rust".to_owned()],
+            empty_room_grace_secs: 0,
+            resume_grace_secs: 0,
+            ..ChatSettings::default()
+        });
+        let (id, _) = connect(&server).await;
+        join(&server, id, "rust").await.unwrap();
+        join(&server, id, "foo").await.unwrap();
+        disconnect(&server, id).await;
+
+        actix::clock::sleep(Duration::from_millis(50)).await;
+        assert_eq!(
+            rooms(&server).await,
+            vec!["main".to_owned(), "rust".to_owned()]
+        );
+    }
+
+    #[actix::test]
+    async fn topic_is_shown_on_join() {
+        let server = start_server(ChatSettings::default());
+        let (alice, _) = connect(&server).await;
+        let (bob, bob_events) = connect(&server).await;
+        join(&server, alice, "foo").await.unwrap();
+        server
+            .send(SetTopic {
+                id: alice,
+                topic: Some("rust async".to_owned()),
+            })
+            .await
+            .unwrap();
+        join(&server, bob, "foo").await.unwrap();
+
+        actix::clock::sleep(Duration::from_millis(50)).await;
+        let events = bob_events.lock().unwrap();
+        let topic = events
+            .iter()
+            .find(|event| event["type"] == "topic")
+            .expect("topic is sent on join");
+        assert_eq!(topic["room"], "foo");
+        assert_eq!(topic["topic"], "rust async");
+    }
+
+    #[actix::test]
+    async fn full_rooms_reject_new_members() {
+        let server = start_server(ChatSettings {
+            max_room_members: Some(1),
+            ..ChatSettings::default()
+        });
+        let (alice, _) = connect(&server).await;
+        let (bob, _) = connect(&server).await;
+        join(&server, alice, "foo").await.unwrap();
+
+        assert_eq!(join(&server, bob, "foo").await, Err(JoinError::Full));
+
+        server
+            .send(SetLimit {
+                id: alice,
+                limit: None,
+            })
+            .await
+            .unwrap();
+        assert_eq!(join(&server, bob, "foo").await, Ok(()));
+    }
+
+    #[actix::test]
+    async fn only_creators_and_moderators_set_topics_and_limits() {
+        let server = start_server(ChatSettings {
+            permanent_rooms: vec!["rust".to_owned()],
+            ..ChatSettings::default()
+        });
+        let (alice, _) = connect(&server).await;
+        let (bob, bob_events) = connect(&server).await;
+        join(&server, alice, "foo").await.unwrap();
+        join(&server, bob, "foo").await.unwrap();
+
+        let topic = |id, topic: &str| SetTopic {
+            id,
+            topic: Some(topic.to_owned()),
+        };
+        server.send(topic(bob, "spam")).await.unwrap();
+        server
+            .send(SetLimit {
+                id: bob,
+                limit: Some(1),
+            })
+            .await
+            .unwrap();
+        server.send(topic(alice, "rust async")).await.unwrap();
+        let (carol, _) = connect(&server).await;
+        assert_eq!(join(&server, carol, "foo").await, Ok(()));
+
+        // nobody owns main or the permanent rooms, and they are never capped
+        for room in ["main", "rust"] {
+            join(&server, bob, room).await.unwrap();
+            server.send(topic(bob, "spam")).await.unwrap();
+            server
+                .send(SetLimit {
+                    id: bob,
+                    limit: Some(1),
+                })
+                .await
+                .unwrap();
+            assert_eq!(join(&server, carol, room).await, Ok(()));
+        }
+
+        actix::clock::sleep(Duration::from_millis(50)).await;
+        let events = bob_events.lock().unwrap();
+        let errors = events
+            .iter()
+            .filter(|event| event["type"] == "error")
+            .count();
+        assert_eq!(errors, 6);
+        let topics: Vec<_> = events
+            .iter()
+            .filter(|event| event["type"] == "topic" && event["topic"].is_string())
+            .map(|event| event["topic"].as_str().unwrap())
+            .collect();
+        assert_eq!(topics, ["rust async"]);
+    }
+
+    #[actix::test]
+    async fn private_rooms_are_hidden_and_need_their_password() {
+        let server = start_server(ChatSettings::default());
+        let (alice, _) = connect(&server).await;
+        let (bob, _) = connect(&server).await;
+        join_with(&server, alice, "secret-club", Some("hunter2"))
+            .await
+            .unwrap();
+
+        assert!(!rooms(&server).await.contains(&"secret-club".to_owned()));
+        assert_eq!(
+            join(&server, bob, "secret-club").await,
+            Err(JoinError::Private)
+        );
+        assert_eq!(
+            join_with(&server, bob, "secret-club", Some("hunter3")).await,
+            Err(JoinError::WrongSecret)
+        );
+        assert_eq!(
+            join_with(&server, bob, "secret-club", Some("hunter2")).await,
+            Ok(())
+        );
+    }
+
+    #[actix::test]
+    async fn room_secrets_are_not_stored_in_plain_text() {
+        let server = start_server(ChatSettings::default());
+        let (alice, _) = connect(&server).await;
+        join_with(&server, alice, "secret-club", Some("hunter2"))
+            .await
+            .unwrap();
+
+        let secret = server
+            .send(Inspect(|server: &ChatServer| {
+                server.rooms["secret-club"].secret.clone()
+            }))
+            .await
+            .unwrap()
+            .expect("room is private");
+        assert!(!secret.contains("hunter2"));
+        assert!(secret.starts_with("$argon2"));
+    }
+
+    #[actix::test]
+    async fn invites_can_be_used_once() {
+        let server = start_server(ChatSettings::default());
+        let (alice, alice_events) = connect(&server).await;
+        let (bob, _) = connect(&server).await;
+        let (carol, _) = connect(&server).await;
+        join_with(&server, alice, "secret-club", Some("hunter2"))
+            .await
+            .unwrap();
+        server.send(CreateInvite { id: alice }).await.unwrap();
+
+        actix::clock::sleep(Duration::from_millis(50)).await;
+        let token = alice_events
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|event| event["type"] == "invite")
+            .map(|event| event["token"].as_str().unwrap().to_owned())
+            .expect("invite is sent to its creator");
+        assert_eq!(
+            join_with(&server, bob, "secret-club", Some(&token)).await,
+            Ok(())
+        );
+        assert_eq!(
+            join_with(&server, carol, "secret-club", Some(&token)).await,
+            Err(JoinError::WrongSecret)
+        );
+    }
+
+    #[actix::test]
+    async fn failed_joins_are_rate_limited() {
+        let server = start_server(ChatSettings::default());
+        let (alice, _) = connect(&server).await;
+        let (bob, _) = connect(&server).await;
+        join_with(&server, alice, "secret-club", Some("hunter2"))
+            .await
+            .unwrap();
+
+        for _ in 0..MAX_FAILED_JOINS {
+            assert_eq!(
+                join_with(&server, bob, "secret-club", Some("guess")).await,
+                Err(JoinError::WrongSecret)
+            );
+        }
+        assert_eq!(
+            join_with(&server, bob, "secret-club", Some("hunter2")).await,
+            Err(JoinError::TooManyAttempts)
+        );
+    }
+
+    #[actix::test]
+    async fn connected_count_follows_connects_and_disconnects() {
+        let count = Arc::new(AtomicUsize::new(0));
+        let server = ChatServer::new(count.clone(), ChatSettings::default()).start();
+        let (alice, _) = connect(&server).await;
+        let (bob, _) = connect(&server).await;
+        assert_eq!(count.load(Ordering::SeqCst), 2);
+
+        disconnect(&server, alice).await;
+        // a session stopping after its heartbeat timed out disconnects twice
+        disconnect(&server, alice).await;
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+
+        disconnect(&server, bob).await;
+        assert_eq!(count.load(Ordering::SeqCst), 0);
+    }
+
+    #[actix::test]
+    async fn client_text_is_rate_limited() {
+        let server = start_server(ChatSettings::default());
+        let (alice, alice_events) = connect(&server).await;
+        for i in 0..RATE_LIMIT_BURST as usize + 1 {
+            let text = format!("message {i}");
+            assert!(server.send(ClientText { id: alice, text }).await.unwrap());
+        }
+
+        actix::clock::sleep(Duration::from_millis(50)).await;
+        let events = alice_events.lock().unwrap();
+        assert!(events.iter().any(|event| event["type"] == "error"
+            && event["text"].as_str().unwrap().contains("slow down")));
+    }
+
+    #[actix::test]
+    async fn commands_are_handled_by_the_server() {
+        let server = start_server(ChatSettings::default());
+        let (alice, _) = connect(&server).await;
+        let (_, bob_events) = connect(&server).await;
+        for text in ["/name alice", "hello"] {
+            let text = text.to_owned();
+            server.send(ClientText { id: alice, text }).await.unwrap();
+        }
+
+        assert!(!server
+            .send(ClientText {
+                id: Uuid::new_v4(),
+                text: "hello".to_owned()
+            })
+            .await
+            .unwrap());
+
+        actix::clock::sleep(Duration::from_millis(50)).await;
+        let events = bob_events.lock().unwrap();
+        let message = events
+            .iter()
+            .find(|event| event["type"] == "message")
+            .expect("message is broadcast to the room");
+        assert_eq!(message["from"], "alice");
+        assert_eq!(message["text"], "hello");
+    }
+
+    #[actix::test]
+    async fn sessions_resumed_within_grace_period_get_missed_messages() {
+        let server = start_server(ChatSettings::default());
+        let (alice, alice_events) = connect(&server).await;
+        let (bob, _) = connect(&server).await;
+        join(&server, alice, "foo").await.unwrap();
+        join(&server, bob, "foo").await.unwrap();
+        actix::clock::sleep(Duration::from_millis(50)).await;
+        let token = token_of(&alice_events);
+
+        disconnect(&server, alice).await;
+        let text = "while you were away".to_owned();
+        server.send(ClientText { id: bob, text }).await.unwrap();
+        let (connected, events) = resume(&server, Some(token)).await;
+        assert_eq!(connected.id, alice);
+        assert!(connected.resumed);
+
+        actix::clock::sleep(Duration::from_millis(50)).await;
+        let events = events.lock().unwrap();
+        assert!(events
+            .iter()
+            .any(|event| event["type"] == "message" && event["text"] == "while you were away"));
+        assert!(events
+            .iter()
+            .any(|event| event["type"] == "topic" && event["room"] == "foo"));
+    }
+
+    #[actix::test]
+    async fn forged_or_expired_tokens_start_a_new_session() {
+        let server = start_server(ChatSettings {
+            resume_grace_secs: 0,
+            ..ChatSettings::default()
+        });
+        let (alice, alice_events) = connect(&server).await;
+        actix::clock::sleep(Duration::from_millis(50)).await;
+        let token = token_of(&alice_events);
+
+        let forged = format!("{}.{}", alice.simple(), "00".repeat(32));
+        let (connected, _) = resume(&server, Some(forged)).await;
+        assert_ne!(connected.id, alice);
+        assert!(!connected.resumed);
+
+        disconnect(&server, alice).await;
+        actix::clock::sleep(Duration::from_millis(50)).await;
+        let (connected, _) = resume(&server, Some(token)).await;
+        assert_ne!(connected.id, alice);
+        assert!(!connected.resumed);
+    }
+
+    struct Echo;
+
+    impl ChatBot for Echo {
+        fn name(&self) -> &str {
+            "echo"
+        }
+        fn rooms(&self) -> Vec<String> {
+            vec!["bots".to_owned()]
+        }
+        fn on_message(&mut self, _: &str, from: &str, text: &str) -> Option<String> {
+            text.starts_with("echo ")
+                .then(|| format!("{from} said {}", &text[5..]))
+        }
+    }
+
+    #[actix::test]
+    async fn bots_sit_in_their_rooms_and_answer_messages() {
+        let server = ChatServer::new(Arc::new(AtomicUsize::new(0)), ChatSettings::default())
+            .with_bot(Echo)
+            .start();
+        let (alice, alice_events) = connect(&server).await;
+        assert!(rooms(&server).await.contains(&"bots".to_owned()));
+        join(&server, alice, "bots").await.unwrap();
+        for text in ["/name alice", "echo hi", "no echo"] {
+            let text = text.to_owned();
+            server.send(ClientText { id: alice, text }).await.unwrap();
+        }
+
+        actix::clock::sleep(Duration::from_millis(50)).await;
+        let events = alice_events.lock().unwrap();
+        let members = events
+            .iter()
+            .rev()
+            .find(|event| event["type"] == "members")
+            .unwrap();
+        assert!(members["members"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|member| member["name"] == "echo"));
+        let replies: Vec<&Value> = events
+            .iter()
+            .filter(|event| event["type"] == "message" && event["from"] == "echo")
+            .collect();
+        assert_eq!(replies.len(), 1);
+        assert_eq!(replies[0]["text"], "alice said hi");
+    }
+
+    #[actix::test]
+    async fn help_is_generated_from_the_registry() {
+        let server = start_server(ChatSettings::default());
+        let (alice, alice_events) = connect(&server).await;
+        let text = "/help".to_owned();
+        server.send(ClientText { id: alice, text }).await.unwrap();
+
+        actix::clock::sleep(Duration::from_millis(50)).await;
+        let events = alice_events.lock().unwrap();
+        let help = events
+            .iter()
+            .find(|event| event["type"] == "help")
+            .expect("help lists the commands");
+        let usages: Vec<&str> = help["commands"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|command| command["usage"].as_str().unwrap())
+            .collect();
+        assert!(usages.contains(&"/roll [NdM]"));
+        assert!(usages.contains(&"/help"));
+    }
+
+    // Send a message and return the id the server gave it
+    async fn say(
+        server: &Addr<ChatServer>,
+        id: Uuid,
+        events: &Arc<Mutex<Vec<Value>>>,
+        text: &str,
+    ) -> Uuid {
+        let text = text.to_owned();
+        server.send(ClientText { id, text }).await.unwrap();
+        actix::clock::sleep(Duration::from_millis(50)).await;
+        let events = events.lock().unwrap();
+        let message = events
+            .iter()
+            .rev()
+            .find(|event| event["type"] == "message" && event["mine"] == true)
+            .expect("authors get their message back");
+        Uuid::parse_str(message["id"].as_str().unwrap()).unwrap()
+    }
+
+    #[actix::test]
+    async fn only_authors_edit_and_owners_moderate() {
+        let server = start_server(ChatSettings::default());
+        let (alice, _) = connect(&server).await;
+        let (bob, bob_events) = connect(&server).await;
+        join(&server, alice, "foo").await.unwrap();
+        join(&server, bob, "foo").await.unwrap();
+        let message = say(&server, bob, &bob_events, "helo").await;
+
+        let edit = |id| EditMessage {
+            id,
+            message,
+            text: "hello".to_owned(),
+        };
+        assert_eq!(
+            server.send(edit(alice)).await.unwrap(),
+            Err(EditError::NotAllowed)
+        );
+        assert_eq!(server.send(edit(bob)).await.unwrap(), Ok(()));
+        // alice created the room
+        let delete = DeleteMessage { id: alice, message };
+        assert_eq!(server.send(delete).await.unwrap(), Ok(()));
+        assert_eq!(
+            server.send(edit(bob)).await.unwrap(),
+            Err(EditError::NotFound)
+        );
+
+        actix::clock::sleep(Duration::from_millis(50)).await;
+        let events = bob_events.lock().unwrap();
+        assert!(events
+            .iter()
+            .any(|event| event["type"] == "edited" && event["text"] == "hello"));
+        assert!(events.iter().any(|event| event["type"] == "deleted"));
+    }
+
+    #[actix::test]
+    async fn messages_can_only_be_changed_within_the_edit_window() {
+        let server = start_server(ChatSettings {
+            edit_window_secs: 0,
+            ..ChatSettings::default()
+        });
+        let (alice, alice_events) = connect(&server).await;
+        let message = say(&server, alice, &alice_events, "hello").await;
+
+        let delete = DeleteMessage { id: alice, message };
+        assert_eq!(server.send(delete).await.unwrap(), Err(EditError::TooLate));
+    }
+
+    #[actix::test]
+    async fn reactions_toggle_and_are_counted() {
+        let server = start_server(ChatSettings::default());
+        let (alice, alice_events) = connect(&server).await;
+        let (bob, _) = connect(&server).await;
+        let message = say(&server, alice, &alice_events, "hello").await;
+
+        for id in [alice, bob, bob] {
+            let react = React {
+                id,
+                message,
+                emoji: "👍".to_owned(),
+            };
+            assert_eq!(server.send(react).await.unwrap(), Ok(()));
+        }
+        let react = React {
+            id: bob,
+            message,
+            emoji: "+1".to_owned(),
+        };
+        assert_eq!(
+            server.send(react).await.unwrap(),
+            Err(EditError::InvalidEmoji)
+        );
+
+        actix::clock::sleep(Duration::from_millis(50)).await;
+        let events = alice_events.lock().unwrap();
+        let counts: Vec<&Value> = events
+            .iter()
+            .filter(|event| event["type"] == "reactions")
+            .map(|event| &event["reactions"]["👍"])
+            .collect();
+        assert_eq!(counts, vec![&json!(1), &json!(2), &json!(1)]);
+    }
+
+    #[actix::test]
+    async fn shutdown_tells_connected_sessions_to_come_back() {
+        let server = start_server(ChatSettings::default());
+        let (_, alice) = connect(&server).await;
+        let (bob, bob_events) = connect(&server).await;
+        disconnect(&server, bob).await;
+        bob_events.lock().unwrap().clear();
+
+        server
+            .send(Shutdown {
+                reconnect_after: Duration::from_secs(5),
+            })
+            .await
+            .unwrap();
+        actix::clock::sleep(Duration::from_millis(50)).await;
+
+        let events = alice.lock().unwrap();
+        let last: Vec<_> = events.iter().rev().take(2).rev().collect();
+        assert_eq!(
+            last,
+            vec![
+                &json!({ "type": "restarting", "reconnect_after_secs": 5 }),
+                &json!({ "type": "closed" })
+            ]
+        );
+        // a detached session has no connection to close
+        assert!(bob_events.lock().unwrap().is_empty());
+    }
+
+    #[actix::test]
+    async fn rooms_are_shared_over_the_bus() {
+        let bus = LocalBus::default();
+        let start = |bus: &LocalBus| {
+            ChatServer::new(Arc::new(AtomicUsize::new(0)), ChatSettings::default())
+                .with_bus(bus.clone())
+                .start()
+        };
+        let (first, second) = (start(&bus), start(&bus));
+        let (alice, alice_events) = connect(&first).await;
+        let (bob, bob_events) = connect(&second).await;
+        join(&first, alice, "rust").await.unwrap();
+        join(&second, bob, "rust").await.unwrap();
+        say(&first, alice, &alice_events, "hello from one").await;
+
+        let bob_events = bob_events.lock().unwrap();
+        assert!(bob_events
+            .iter()
+            .any(|event| event["type"] == "message" && event["text"] == "hello from one"));
+        let members = bob_events
+            .iter()
+            .rev()
+            .find(|event| event["type"] == "members")
+            .unwrap();
+        let names: Vec<_> = members["members"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|member| member["name"].as_str().unwrap())
+            .collect();
+        assert_eq!(names.len(), 2);
+        assert!(names.contains(&guest_name(alice).as_str()));
+    }
+
+    #[actix::test]
+    async fn private_rooms_of_other_instances_expire_here_too() {
+        let bus = LocalBus::default();
+        let settings = ChatSettings {
+            empty_room_grace_secs: 0,
+            ..ChatSettings::default()
+        };
+        let start = |bus: &LocalBus| {
+            ChatServer::new(Arc::new(AtomicUsize::new(0)), settings.clone())
+                .with_bus(bus.clone())
+                .start()
+        };
+        let (first, second) = (start(&bus), start(&bus));
+        let (alice, _) = connect(&first).await;
+        let (bob, _) = connect(&second).await;
+        join_with(&first, alice, "club", Some("hunter2"))
+            .await
+            .unwrap();
+        join(&first, alice, "main").await.unwrap();
+        actix::clock::sleep(Duration::from_millis(50)).await;
+
+        // gone on both instances, so the name is free for a room of its own
+        join_with(&second, bob, "club", Some("swordfish"))
+            .await
+            .unwrap();
+    }
+
+    #[actix::test]
+    async fn messages_of_other_instances_are_changed_where_they_live() {
+        let bus = LocalBus::default();
+        let start = |bus: &LocalBus| {
+            ChatServer::new(Arc::new(AtomicUsize::new(0)), ChatSettings::default())
+                .with_bus(bus.clone())
+                .start()
+        };
+        let (first, second) = (start(&bus), start(&bus));
+        let (alice, alice_events) = connect(&first).await;
+        let (bob, bob_events) = connect(&second).await;
+        let message = say(&first, alice, &alice_events, "react to this").await;
+
+        for text in [
+            format!("/react {message} 👍"),
+            format!("/edit {message} mine now"),
+        ] {
+            second.send(ClientText { id: bob, text }).await.unwrap();
+        }
+        actix::clock::sleep(Duration::from_millis(50)).await;
+
+        let alice_events = alice_events.lock().unwrap();
+        let reactions = alice_events
+            .iter()
+            .find(|event| event["type"] == "reactions")
+            .unwrap();
+        assert_eq!(reactions["reactions"]["👍"], 1);
+        assert!(!alice_events.iter().any(|event| event["type"] == "edited"));
+        let bob_events = bob_events.lock().unwrap();
+        let refused = bob_events.last().unwrap();
+        assert_eq!(refused["type"], "error");
+        assert_eq!(refused["text"], "that is not your message");
+    }
+
+    #[test]
+    fn mentions_are_parsed_once_without_punctuation() {
+        assert_eq!(
+            parse_mentions("@bob, ask @alice_2 and @bob! email@example.com @ @"),
+            vec!["bob".to_owned(), "alice_2".to_owned()]
+        );
+    }
+
+    fn name(server: &Addr<ChatServer>, id: Uuid, name: &str) {
+        server.do_send(SetName {
+            id,
+            name: name.to_owned(),
+        });
+    }
+
+    #[actix::test]
+    async fn mentioned_sessions_are_told() {
+        let server = start_server(ChatSettings::default());
+        let (alice, alice_events) = connect(&server).await;
+        let (bob, bob_events) = connect(&server).await;
+        let (carol, carol_events) = connect(&server).await;
+        name(&server, bob, "bob");
+        join(&server, carol, "rust").await.unwrap();
+        name(&server, carol, "carol");
+        say(&server, alice, &alice_events, "@bob @carol look").await;
+
+        let mentions_of = |events: &Arc<Mutex<Vec<Value>>>| {
+            events
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|event| event["type"] == "mention")
+                .count()
+        };
+        let bob_events = bob_events.lock().unwrap();
+        let message = bob_events
+            .iter()
+            .find(|event| event["type"] == "message")
+            .unwrap();
+        assert_eq!(message["mentions"], json!(["bob", "carol"]));
+        assert_eq!(bob_events.last().unwrap()["type"], "mention");
+        // carol is in another room, but the room is public
+        assert_eq!(mentions_of(&carol_events), 1);
+        assert_eq!(mentions_of(&alice_events), 0);
+    }
+
+    #[actix::test]
+    async fn mentions_of_absent_names_wait_for_them() {
+        let pool = sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        crate::storage::SQLITE_MIGRATIONS.run(&pool).await.unwrap();
+        let settings = ChatSettings {
+            attachments_path: std::env::temp_dir()
+                .join(format!("demcru-{}", Uuid::new_v4()))
+                .to_string_lossy()
+                .into_owned(),
+            ..ChatSettings::default()
+        };
+        let history = HistoryWriter::new(
+            crate::storage::Storage::sqlite(pool).chat,
+            crate::routes::Attachments::new(&settings),
+            30,
+        );
+        let server = ChatServer::new(Arc::new(AtomicUsize::new(0)), settings)
+            .with_history(history.start())
+            .start();
+        let (alice, alice_events) = connect(&server).await;
+        let (bob, bob_events) = connect(&server).await;
+        name(&server, bob, "bob");
+        say(&server, bob, &bob_events, "hi").await;
+        name(&server, bob, "robert");
+        say(&server, alice, &alice_events, "where is @bob?").await;
+        // a name that never wrote anything is not kept
+        say(&server, alice, &alice_events, "@nobody").await;
+
+        let (carol, carol_events) = connect(&server).await;
+        name(&server, carol, "nobody");
+        name(&server, carol, "bob");
+        actix::clock::sleep(Duration::from_millis(100)).await;
+        let mentions: Vec<_> = carol_events
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|event| event["type"] == "mention")
+            .map(|event| event["text"].clone())
+            .collect();
+        assert_eq!(mentions, vec![json!("where is @bob?")]);
+
+        // handed over once
+        name(&server, carol, "bob");
+        actix::clock::sleep(Duration::from_millis(100)).await;
+        let count = carol_events
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|event| event["type"] == "mention")
+            .count();
+        assert_eq!(count, 1);
+    }
+}
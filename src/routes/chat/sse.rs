@@ -0,0 +1,195 @@
+use actix::prelude::*;
+use actix_web::{web, web::Bytes, Error, HttpRequest, HttpResponse};
+use futures_util::stream;
+use serde::Deserialize;
+use std::{net::IpAddr, time::Duration};
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+use super::{
+    chat_spam, has_chat_token, quarantine_chat, record_visit, ChatEvent, ChatServer, ClientText,
+    Close, Connect, Disconnect, Heartbeat, Join, Message, ResumeParams, VerifyToken,
+};
+use crate::{protection::FormGuard, storage::Storage};
+
+// Server-Sent Events fallback for clients whose proxies block websocket upgrades.
+// Events flow down an SSE stream, input goes up through `chat_send`.
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(15);
+// Events buffered for a slow client before it is dropped
+const STREAM_BUFFER: usize = 64;
+
+struct SseChatSession {
+    id: Uuid,
+    // room joined once the session is registered
+    room: String,
+    resume: Option<String>,
+    addr: Addr<ChatServer>,
+    peer: Option<IpAddr>,
+    tx: mpsc::Sender<Bytes>,
+}
+
+impl SseChatSession {
+    // Queue a frame for the client, a client that cannot keep up is dropped
+    fn push(&self, frame: String, ctx: &mut Context<Self>) {
+        if self.tx.try_send(Bytes::from(frame)).is_err() {
+            ctx.stop();
+        }
+    }
+
+    fn event(&self, event: &ChatEvent, ctx: &mut Context<Self>) {
+        self.push(format!("data: {}\n\n", event.to_json()), ctx);
+    }
+
+    fn join(&self, ctx: &mut Context<Self>) {
+        let join = Join {
+            id: self.id,
+            name: self.room.clone(),
+            secret: None,
+        };
+        ctx.spawn(self.addr.send(join).into_actor(self).map(|res, act, ctx| {
+            if let Ok(Err(err)) = res {
+                act.event(&ChatEvent::error(err.to_string()), ctx);
+            }
+        }));
+    }
+
+    // SSE has no ping frames, a comment keeps proxies from closing the stream
+    // and tells us when the client went away
+    fn hb(&self, ctx: &mut Context<Self>) {
+        ctx.run_interval(KEEP_ALIVE_INTERVAL, |act, ctx| {
+            if act.tx.is_closed() {
+                ctx.stop();
+                return;
+            }
+            act.addr.do_send(Heartbeat { id: act.id });
+            act.push(": keep-alive\n\n".to_owned(), ctx);
+        });
+    }
+}
+
+impl Actor for SseChatSession {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        self.hb(ctx);
+        let addr = ctx.address();
+        self.addr
+            .send(Connect {
+                addr: addr.clone().recipient(),
+                close: addr.recipient(),
+                peer: self.peer,
+                resume: self.resume.take(),
+            })
+            .into_actor(self)
+            .then(|res, act, ctx| {
+                match res {
+                    Ok(connected) => {
+                        act.id = connected.id;
+                        // a resumed session is already back in its room
+                        if !connected.resumed && act.room != "main" {
+                            act.join(ctx);
+                        }
+                    }
+                    _ => ctx.stop(),
+                }
+                fut::ready(())
+            })
+            .wait(ctx);
+    }
+
+    fn stopping(&mut self, ctx: &mut Self::Context) -> Running {
+        self.addr.do_send(Disconnect {
+            id: self.id,
+            addr: ctx.address().recipient(),
+        });
+        Running::Stop
+    }
+}
+
+impl Handler<Message> for SseChatSession {
+    type Result = ();
+    fn handle(&mut self, msg: Message, ctx: &mut Self::Context) {
+        self.push(format!("data: {}\n\n", msg.0), ctx);
+    }
+}
+
+impl Handler<Close> for SseChatSession {
+    type Result = ();
+    // the stream ends once the events already queued are sent
+    fn handle(&mut self, _: Close, ctx: &mut Self::Context) {
+        ctx.stop();
+    }
+}
+
+// Stream the events of a room, for pages that hand out the chat token
+pub async fn chat_events(
+    req: HttpRequest,
+    path: web::Path<String>,
+    srv: web::Data<Addr<ChatServer>>,
+    guard: web::Data<FormGuard>,
+    storage: web::Data<Storage>,
+) -> HttpResponse {
+    if !has_chat_token(&req, &guard) {
+        return HttpResponse::Forbidden().body("Bad form token");
+    }
+    record_visit(&req, storage.analytics.as_ref()).await;
+    let (tx, rx) = mpsc::channel(STREAM_BUFFER);
+    SseChatSession {
+        id: Uuid::nil(),
+        room: path.into_inner(),
+        resume: ResumeParams::from_request(&req).resume,
+        addr: srv.get_ref().clone(),
+        peer: req.peer_addr().map(|addr| addr.ip()),
+        tx,
+    }
+    .start();
+    // the session stops once this stream, and with it the receiver, is dropped
+    let body = stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|frame| (Ok::<_, Error>(frame), rx))
+    });
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(("Cache-Control", "no-cache"))
+        .streaming(body)
+}
+
+#[derive(Deserialize)]
+pub struct ChatInput {
+    // resume token from the `session` event, a bare id would let anyone post as anyone
+    session: String,
+    text: String,
+}
+
+// Input of an SSE session, same commands and messages as a websocket frame.
+// The chat token was checked when its stream opened, spam is accepted and
+// never delivered.
+pub async fn chat_send(
+    req: HttpRequest,
+    input: web::Json<ChatInput>,
+    srv: web::Data<Addr<ChatServer>>,
+    guard: web::Data<FormGuard>,
+    storage: web::Data<Storage>,
+) -> Result<HttpResponse, Error> {
+    let ChatInput { session, text } = input.into_inner();
+    let id = srv
+        .send(VerifyToken(session))
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    let Some(id) = id else {
+        return Ok(HttpResponse::NotFound().body("Unknown session"));
+    };
+    if let Some(spam) = chat_spam(&guard, &text) {
+        let ip = guard.client_ip(&req);
+        quarantine_chat(&srv, storage.quarantine.as_ref(), &ip, id, text, spam).await?;
+        return Ok(HttpResponse::Accepted().finish());
+    }
+    let known = srv
+        .send(ClientText { id, text })
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    if known {
+        Ok(HttpResponse::Accepted().finish())
+    } else {
+        Ok(HttpResponse::NotFound().body("Unknown session"))
+    }
+}
@@ -0,0 +1,194 @@
+use actix::Addr;
+use actix_web::{
+    http::header::{ContentDisposition, DispositionParam, DispositionType},
+    web, HttpResponse,
+};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use std::fmt::Write;
+
+use super::{ChatServer, IsMember, VerifyToken};
+use crate::{auth::Admin, models::chat::TranscriptLine, storage::Storage, utils::CustomError};
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum TranscriptFormat {
+    #[default]
+    Markdown,
+    Text,
+    Jsonl,
+}
+
+impl TranscriptFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            TranscriptFormat::Markdown => "text/markdown; charset=utf-8",
+            TranscriptFormat::Text => "text/plain; charset=utf-8",
+            TranscriptFormat::Jsonl => "application/x-ndjson",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            TranscriptFormat::Markdown => "md",
+            TranscriptFormat::Text => "txt",
+            TranscriptFormat::Jsonl => "jsonl",
+        }
+    }
+}
+
+pub fn render_transcript(format: TranscriptFormat, room: &str, lines: &[TranscriptLine]) -> String {
+    let mut out = String::new();
+    match format {
+        TranscriptFormat::Markdown => {
+            let _ = writeln!(out, "# Transcript of {room}\n");
+            for line in lines {
+                let edited = if line.edited_at.is_some() {
+                    " _(edited)_"
+                } else {
+                    ""
+                };
+                let _ = writeln!(
+                    out,
+                    "**{}** _{}_{edited}\n\n{}\n",
+                    line.author,
+                    line.sent_at.format("%Y-%m-%d %H:%M:%S UTC"),
+                    line.text
+                );
+            }
+        }
+        TranscriptFormat::Text => {
+            for line in lines {
+                let edited = if line.edited_at.is_some() {
+                    " (edited)"
+                } else {
+                    ""
+                };
+                let _ = writeln!(
+                    out,
+                    "[{}] {}: {}{edited}",
+                    line.sent_at.format("%Y-%m-%d %H:%M:%S"),
+                    line.author,
+                    line.text
+                );
+            }
+        }
+        TranscriptFormat::Jsonl => {
+            for line in lines {
+                let _ = writeln!(out, "{}", serde_json::to_string(line).unwrap_or_default());
+            }
+        }
+    }
+    out
+}
+
+#[derive(Deserialize)]
+pub struct TranscriptParams {
+    #[serde(default)]
+    format: TranscriptFormat,
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+    // resume token, members of a private room prove they are in it with it
+    session: Option<String>,
+}
+
+// Download the persisted history of a room
+pub async fn get_transcript(
+    path: web::Path<String>,
+    params: web::Query<TranscriptParams>,
+    admin: Option<Admin>,
+    srv: web::Data<Addr<ChatServer>>,
+    storage: web::Data<Storage>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let room = path.into_inner();
+    let params = params.into_inner();
+    let private = storage
+        .chat
+        .is_private_room(&room)
+        .await
+        .map_err(CustomError::DatabaseError)?;
+    if private && admin.is_none() {
+        let id = match params.session {
+            Some(token) => srv
+                .send(VerifyToken(token))
+                .await
+                .map_err(actix_web::error::ErrorInternalServerError)?,
+            None => None,
+        };
+        let member = match id {
+            Some(id) => srv
+                .send(IsMember {
+                    id,
+                    room: room.clone(),
+                })
+                .await
+                .map_err(actix_web::error::ErrorInternalServerError)?,
+            None => false,
+        };
+        if !member {
+            return Ok(HttpResponse::Forbidden().body("Only members can export a private room"));
+        }
+    }
+
+    let lines = storage
+        .chat
+        .load_transcript(&room, params.from, params.to)
+        .await
+        .map_err(CustomError::DatabaseError)?;
+    let body = render_transcript(params.format, &room, &lines);
+    Ok(HttpResponse::Ok()
+        .content_type(params.format.content_type())
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename(format!(
+                "{room}-transcript.{}",
+                params.format.extension()
+            ))],
+        })
+        .body(body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn lines() -> Vec<TranscriptLine> {
+        vec![
+            TranscriptLine {
+                author: "alice".to_owned(),
+                text: "hello".to_owned(),
+                sent_at: Utc.with_ymd_and_hms(2026, 10, 19, 9, 0, 0).unwrap(),
+                edited_at: None,
+            },
+            TranscriptLine {
+                author: "bob".to_owned(),
+                text: "hi there".to_owned(),
+                sent_at: Utc.with_ymd_and_hms(2026, 10, 19, 9, 1, 0).unwrap(),
+                edited_at: Some(Utc.with_ymd_and_hms(2026, 10, 19, 9, 2, 0).unwrap()),
+            },
+        ]
+    }
+
+    #[test]
+    fn transcripts_render_in_every_format() {
+        let text = render_transcript(TranscriptFormat::Text, "rust", &lines());
+        assert_eq!(
+            text,
+            "[2026-10-19 09:00:00] alice: hello\n[2026-10-19 09:01:00] bob: hi there (edited)\n"
+        );
+
+        let markdown = render_transcript(TranscriptFormat::Markdown, "rust", &lines());
+        assert!(markdown.starts_with("# Transcript of rust\n"));
+        assert!(markdown.contains("**bob** _2026-10-19 09:01:00 UTC_ _(edited)_\n\nhi there\n"));
+
+        let jsonl = render_transcript(TranscriptFormat::Jsonl, "rust", &lines());
+        let parsed: Vec<serde_json::Value> = jsonl
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(parsed.len(), 2);
+        assert_eq!(parsed[1]["text"], "hi there");
+        assert_eq!(parsed[0]["edited_at"], serde_json::Value::Null);
+    }
+}
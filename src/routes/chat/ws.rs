@@ -0,0 +1,204 @@
+use actix::prelude::*;
+use actix_web::{web, Error, HttpRequest, HttpResponse};
+use actix_web_actors::ws;
+use std::{
+    net::IpAddr,
+    time::{Duration, Instant},
+};
+use uuid::Uuid;
+
+use super::{
+    chat_spam, has_chat_token, quarantine_chat, record_visit, Attachments, ChatEvent, ChatServer,
+    ClientText, Close, Connect, Disconnect, Heartbeat, Message, ResumeParams, ShareAttachment,
+};
+use crate::{protection::FormGuard, storage::Storage};
+
+// Session
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
+// How long before lack of client response causes a timeout
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(10);
+#[derive(Debug)]
+struct WsChatSession {
+    pub id: Uuid,
+    pub hb: Instant,
+    pub addr: Addr<ChatServer>,
+    pub peer: Option<IpAddr>,
+    // token of the session this connection picks up again
+    pub resume: Option<String>,
+    pub storage: Storage,
+    pub attachments: Attachments,
+    pub guard: web::Data<FormGuard>,
+    // as the form guard tells it, for the quarantine
+    pub ip: String,
+}
+
+impl WsChatSession {
+    // helper function that sends ping to client every 5 seconds
+    // and lets the server know the session is still around
+    fn hb(&self, ctx: &mut ws::WebsocketContext<Self>) {
+        ctx.run_interval(HEARTBEAT_INTERVAL, |act, ctx| {
+            if Instant::now().duration_since(act.hb) > CLIENT_TIMEOUT {
+                println!("Websocket client heartbeat failed, disconnecting!");
+                // notify chat server
+                act.addr.do_send(Disconnect {
+                    id: act.id,
+                    addr: ctx.address().recipient(),
+                });
+                ctx.stop();
+                return;
+            }
+            act.addr.do_send(Heartbeat { id: act.id });
+            ctx.ping(b"");
+        });
+    }
+
+    // Text frames are for the chat server, but message text that scores as spam
+    fn text(&self, text: String, ctx: &mut ws::WebsocketContext<Self>) {
+        let Some(spam) = chat_spam(&self.guard, &text) else {
+            self.addr.do_send(ClientText { id: self.id, text });
+            return;
+        };
+        let (srv, repository) = (self.addr.clone(), self.storage.quarantine.clone());
+        let (ip, id) = (self.ip.clone(), self.id);
+        let kept =
+            async move { quarantine_chat(&srv, repository.as_ref(), &ip, id, text, spam).await };
+        ctx.spawn(kept.into_actor(self).map(|res, _, _| {
+            if let Err(err) = res {
+                println!("Failed to quarantine a chat message: {err:?}");
+            }
+        }));
+    }
+
+    // Binary frames are files to share with the room, their type is taken
+    // from the content as there is no room for a name
+    fn upload(&self, bytes: Vec<u8>, ctx: &mut ws::WebsocketContext<Self>) {
+        let (id, chat, attachments) =
+            (self.id, self.storage.chat.clone(), self.attachments.clone());
+        let stored = async move { attachments.store(chat.as_ref(), "", bytes).await };
+        ctx.spawn(stored.into_actor(self).map(move |res, act, ctx| match res {
+            Ok(attachment) => act.addr.do_send(ShareAttachment { id, attachment }),
+            Err(err) => ctx.text(ChatEvent::error(err.to_string()).to_json()),
+        }));
+    }
+}
+
+impl Actor for WsChatSession {
+    type Context = ws::WebsocketContext<Self>;
+    // Method is called on actor start.
+    // We register ws session with ChatServer
+    fn started(&mut self, ctx: &mut Self::Context) {
+        self.hb(ctx);
+        // register self in chat server. `AsyncContext::wait` register
+        // future within context, but context waits until this future resolves
+        // before processing any other events.
+        // HttpContext::state() is instance of WsChatSessionState, state is share
+        // across all routes within application
+        let addr = ctx.address();
+        self.addr
+            .send(Connect {
+                addr: addr.clone().recipient(),
+                close: addr.recipient(),
+                peer: self.peer,
+                resume: self.resume.take(),
+            })
+            .into_actor(self)
+            .then(|res, act, ctx| {
+                match res {
+                    Ok(res) => act.id = res.id,
+                    _ => ctx.stop(),
+                }
+                fut::ready(())
+            })
+            .wait(ctx);
+    }
+
+    fn stopping(&mut self, ctx: &mut Self::Context) -> Running {
+        self.addr.do_send(Disconnect {
+            id: self.id,
+            addr: ctx.address().recipient(),
+        });
+        Running::Stop
+    }
+}
+
+impl Handler<Message> for WsChatSession {
+    type Result = ();
+    fn handle(&mut self, msg: Message, ctx: &mut Self::Context) {
+        ctx.text(msg.0);
+    }
+}
+
+impl Handler<Close> for WsChatSession {
+    type Result = ();
+    // 1012, the client should come back once the server is up again
+    fn handle(&mut self, _: Close, ctx: &mut Self::Context) {
+        ctx.close(Some(ws::CloseReason {
+            code: ws::CloseCode::Restart,
+            description: Some("Server restarting".to_owned()),
+        }));
+        ctx.stop();
+    }
+}
+
+impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WsChatSession {
+    fn handle(&mut self, msg: Result<ws::Message, ws::ProtocolError>, ctx: &mut Self::Context) {
+        let msg = match msg {
+            Err(_) => {
+                ctx.stop();
+                return;
+            }
+            Ok(msg) => msg,
+        };
+        log::debug!("WEBSOCKET MESSAGE: {msg:?}");
+        match msg {
+            ws::Message::Ping(msg) => {
+                self.hb = Instant::now();
+                ctx.pong(&msg)
+            }
+            ws::Message::Pong(_) => {
+                self.hb = Instant::now();
+            }
+            // commands and messages are interpreted by the chat server
+            ws::Message::Text(text) => self.text(text.to_string(), ctx),
+            ws::Message::Binary(bytes) => self.upload(bytes.to_vec(), ctx),
+            ws::Message::Close(reason) => {
+                ctx.close(reason);
+                ctx.stop();
+            }
+            ws::Message::Continuation(_) => {
+                ctx.stop();
+            }
+            ws::Message::Nop => (),
+        }
+    }
+}
+
+pub async fn chat_route(
+    req: HttpRequest,
+    stream: web::Payload,
+    srv: web::Data<Addr<ChatServer>>,
+    storage: web::Data<Storage>,
+    attachments: web::Data<Attachments>,
+    guard: web::Data<FormGuard>,
+) -> Result<HttpResponse, Error> {
+    if !has_chat_token(&req, &guard) {
+        return Ok(HttpResponse::Forbidden().body("Bad form token"));
+    }
+    record_visit(&req, storage.analytics.as_ref()).await;
+    let params = ResumeParams::from_request(&req);
+    let session = WsChatSession {
+        id: Uuid::nil(),
+        hb: Instant::now(),
+        addr: srv.get_ref().clone(),
+        peer: req.peer_addr().map(|addr| addr.ip()),
+        resume: params.resume,
+        storage: storage.get_ref().clone(),
+        attachments: attachments.get_ref().clone(),
+        ip: guard.client_ip(&req),
+        guard,
+    };
+    // frames have to fit a whole attachment
+    ws::WsResponseBuilder::new(session, &req, stream)
+        .frame_size(attachments.max_bytes())
+        .start()
+}
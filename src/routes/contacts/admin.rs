@@ -0,0 +1,229 @@
+use actix_web::{
+    error::ErrorBadRequest,
+    http::StatusCode,
+    web::{self, Data},
+    Error, HttpRequest, HttpResponse,
+};
+use handlebars::Handlebars;
+use serde::Serialize;
+use serde_json::json;
+use uuid::Uuid;
+
+use super::is_htmx;
+use crate::{
+    auth::Admin,
+    configuration::ContactSettings,
+    models::contacts::{ContactForm, ContactQuery, Cursor, Direction, SortColumn},
+    storage::Storage,
+    utils::CustomError,
+};
+
+// Rows per page of the admin table
+const PAGE_SIZE: usize = 25;
+// Changes listed under the table
+const RECENT_CHANGES: i64 = 20;
+
+#[derive(Serialize)]
+struct SortHeader {
+    label: &'static str,
+    url: String,
+    // ▲ or ▼ on the column sorted by
+    arrow: &'static str,
+}
+
+fn sort_headers(query: &ContactQuery) -> Vec<SortHeader> {
+    SortColumn::ALL
+        .iter()
+        .map(|&(sort, label)| {
+            let current = sort == query.sort;
+            let dir = if current {
+                query.dir.flip()
+            } else {
+                Direction::Asc
+            };
+            let arrow = match (current, query.dir) {
+                (false, _) => "",
+                (true, Direction::Asc) => "▲",
+                (true, Direction::Desc) => "▼",
+            };
+            let url = ContactQuery {
+                q: query.q.clone(),
+                sort,
+                dir,
+                after: None,
+            }
+            .url("/admin/contacts");
+            SortHeader { label, url, arrow }
+        })
+        .collect()
+}
+
+pub(super) fn html(
+    status: StatusCode,
+    body: Result<String, handlebars::RenderError>,
+) -> HttpResponse {
+    match body {
+        Ok(body) => HttpResponse::build(status)
+            .content_type("text/html; charset=utf-8")
+            .body(body),
+        Err(err) => {
+            println!("Failed to render contacts: {err:?}");
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// The contacts table, htmx searches and "load more" get just the rows
+pub async fn admin_contacts(
+    _: Admin,
+    req: HttpRequest,
+    params: web::Query<ContactQuery>,
+    hb: Data<Handlebars<'static>>,
+    storage: Data<Storage>,
+) -> Result<HttpResponse, Error> {
+    let query = params.into_inner();
+    let after = match query.after.as_deref() {
+        Some(cursor) => Some(Cursor::decode(cursor).ok_or_else(|| ErrorBadRequest("Bad cursor"))?),
+        None => None,
+    };
+    // one more than a page tells whether there is a next one
+    let mut contacts = storage
+        .contacts
+        .find_contacts(&query, after.as_ref(), Some(PAGE_SIZE + 1))
+        .await
+        .map_err(CustomError::DatabaseError)?;
+    let next = if contacts.len() > PAGE_SIZE {
+        contacts.truncate(PAGE_SIZE);
+        contacts.last().map(|last| {
+            let cursor = Cursor {
+                value: query.sort.value_of(last),
+                id: last.id,
+            };
+            ContactQuery {
+                after: Some(cursor.encode()),
+                ..query.clone()
+            }
+            .url("/admin/contacts")
+        })
+    } else {
+        None
+    };
+
+    let mut data = json!({ "contacts": contacts, "next": next, "query": query });
+    if is_htmx(&req) {
+        return Ok(html(StatusCode::OK, hb.render("contact_rows", &data)));
+    }
+    data["headers"] = json!(sort_headers(&query));
+    let export = ContactQuery {
+        after: None,
+        ..query
+    };
+    data["export_csv"] = json!(export.url("/admin/contacts/export.csv"));
+    data["export_vcard"] = json!(export.url("/admin/contacts/export.vcf"));
+    data["changes"] = json!(storage
+        .contacts
+        .recent_changes(RECENT_CHANGES)
+        .await
+        .map_err(CustomError::DatabaseError)?);
+    data["description"] = json!("Contacts");
+    Ok(html(StatusCode::OK, hb.render("contact", &data)))
+}
+
+fn not_found() -> HttpResponse {
+    HttpResponse::NotFound().body("No such contact")
+}
+
+/// One row of the table, where cancelling an edit goes back to
+pub async fn admin_contact_row(
+    _: Admin,
+    path: web::Path<Uuid>,
+    hb: Data<Handlebars<'static>>,
+    storage: Data<Storage>,
+) -> Result<HttpResponse, Error> {
+    let contact = storage
+        .contacts
+        .find_contact(path.into_inner())
+        .await
+        .map_err(CustomError::DatabaseError)?;
+    let Some(contact) = contact else {
+        return Ok(not_found());
+    };
+    Ok(html(StatusCode::OK, hb.render("contact_row", &contact)))
+}
+
+/// The row of a contact as a form
+pub async fn edit_contact(
+    _: Admin,
+    path: web::Path<Uuid>,
+    hb: Data<Handlebars<'static>>,
+    storage: Data<Storage>,
+) -> Result<HttpResponse, Error> {
+    let contact = storage
+        .contacts
+        .find_contact(path.into_inner())
+        .await
+        .map_err(CustomError::DatabaseError)?;
+    let Some(contact) = contact else {
+        return Ok(not_found());
+    };
+    Ok(html(
+        StatusCode::OK,
+        hb.render(
+            "contact_edit_row",
+            &json!({ "id": contact.id, "values": contact }),
+        ),
+    ))
+}
+
+/// Names, email and phone of a contact, validated like the public form
+pub async fn update_contact(
+    admin: Admin,
+    path: web::Path<Uuid>,
+    form: web::Form<ContactForm>,
+    hb: Data<Handlebars<'static>>,
+    storage: Data<Storage>,
+    settings: Data<ContactSettings>,
+) -> Result<HttpResponse, Error> {
+    let id = path.into_inner();
+    let form = form.into_inner();
+    let valid = match form.validate(settings.region()) {
+        Ok(valid) => valid,
+        Err(errors) => {
+            return Ok(html(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                hb.render(
+                    "contact_edit_row",
+                    &json!({ "id": id, "values": form, "errors": errors }),
+                ),
+            ))
+        }
+    };
+    // the message is what they wrote, it is not edited
+    let after = storage
+        .contacts
+        .update_contact(id, &valid, &admin.username)
+        .await
+        .map_err(CustomError::DatabaseError)?;
+    let Some(after) = after else {
+        return Ok(not_found());
+    };
+    Ok(html(StatusCode::OK, hb.render("contact_row", &after)))
+}
+
+/// Removes a contact, htmx swaps its row for nothing
+pub async fn delete_contact(
+    admin: Admin,
+    path: web::Path<Uuid>,
+    storage: Data<Storage>,
+) -> Result<HttpResponse, Error> {
+    let id = path.into_inner();
+    let removed = storage
+        .contacts
+        .delete_contact(id, &admin.username)
+        .await
+        .map_err(CustomError::DatabaseError)?;
+    if removed.is_none() {
+        return Ok(not_found());
+    }
+    Ok(HttpResponse::Ok().finish())
+}
@@ -0,0 +1,223 @@
+use crate::{
+    auth::Admin,
+    models::contacts::{ContactQuery, Contacts},
+    storage::{ContactRepository, Storage},
+    utils::CustomError,
+};
+use actix_web::{
+    http::header::{ContentDisposition, DispositionParam, DispositionType},
+    web::{self, Data},
+    Error, HttpResponse,
+};
+
+// vCard lines longer than this many bytes are folded
+const VCARD_LINE: usize = 75;
+
+// Spreadsheets run cells starting with these as formulas
+fn defuse_formula(value: &str) -> String {
+    if value.starts_with(['=', '+', '-', '@', '\t', '\r']) {
+        format!("'{value}")
+    } else {
+        value.to_owned()
+    }
+}
+
+/// Contacts as CSV, with the columns an import reads
+pub fn contacts_csv(contacts: &[Contacts]) -> Result<String, csv::Error> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer.write_record([
+        "first_name",
+        "last_name",
+        "email",
+        "phone",
+        "message",
+        "created_at",
+    ])?;
+    for contact in contacts {
+        writer.write_record([
+            defuse_formula(&contact.first_name),
+            defuse_formula(&contact.last_name),
+            defuse_formula(&contact.email),
+            contact.phone.clone().unwrap_or_default(),
+            defuse_formula(contact.message.as_deref().unwrap_or_default()),
+            contact.created_at.to_rfc3339(),
+        ])?;
+    }
+    let bytes = writer.into_inner().map_err(|err| err.into_error())?;
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+// Escapes a vCard text value, RFC 6350 section 3.4
+fn vcard_text(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            ',' => escaped.push_str("\\,"),
+            ';' => escaped.push_str("\\;"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => (),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// Folds a content line into lines of at most 75 bytes, continuations start
+// with a space. Never splits a character.
+fn fold(line: &str, out: &mut String) {
+    let mut width = 0;
+    for c in line.chars() {
+        if width + c.len_utf8() > VCARD_LINE {
+            out.push_str("\r\n ");
+            width = 1;
+        }
+        out.push(c);
+        width += c.len_utf8();
+    }
+    out.push_str("\r\n");
+}
+
+/// Contacts as vCard 4.0, RFC 6350
+pub fn contacts_vcard(contacts: &[Contacts]) -> String {
+    let mut out = String::new();
+    for contact in contacts {
+        let mut lines = vec![
+            "BEGIN:VCARD".to_owned(),
+            "VERSION:4.0".to_owned(),
+            format!("UID:urn:uuid:{}", contact.id),
+            format!(
+                "FN:{} {}",
+                vcard_text(&contact.first_name),
+                vcard_text(&contact.last_name)
+            ),
+            format!(
+                "N:{};{};;;",
+                vcard_text(&contact.last_name),
+                vcard_text(&contact.first_name)
+            ),
+            format!("EMAIL:{}", vcard_text(&contact.email)),
+        ];
+        if let Some(phone) = &contact.phone {
+            lines.push(format!("TEL;VALUE=uri:tel:{phone}"));
+        }
+        if let Some(message) = &contact.message {
+            lines.push(format!("NOTE:{}", vcard_text(message)));
+        }
+        lines.push(format!(
+            "REV:{}",
+            contact.created_at.format("%Y%m%dT%H%M%SZ")
+        ));
+        lines.push("END:VCARD".to_owned());
+        for line in lines {
+            fold(&line, &mut out);
+        }
+    }
+    out
+}
+
+fn attachment(content_type: &str, filename: &str, body: String) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type(content_type)
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename(filename.to_owned())],
+        })
+        .body(body)
+}
+
+async fn filtered(
+    contacts: &dyn ContactRepository,
+    query: ContactQuery,
+) -> Result<Vec<Contacts>, Error> {
+    // the whole list, in the order of the table
+    let query = ContactQuery {
+        after: None,
+        ..query
+    };
+    Ok(contacts
+        .find_contacts(&query, None, None)
+        .await
+        .map_err(CustomError::DatabaseError)?)
+}
+
+/// The contacts of the admin table, search and order included, as CSV
+pub async fn export_contacts_csv(
+    _: Admin,
+    params: web::Query<ContactQuery>,
+    storage: Data<Storage>,
+) -> Result<HttpResponse, Error> {
+    let contacts = filtered(storage.contacts.as_ref(), params.into_inner()).await?;
+    let csv = contacts_csv(&contacts).map_err(actix_web::error::ErrorInternalServerError)?;
+    Ok(attachment("text/csv; charset=utf-8", "contacts.csv", csv))
+}
+
+/// The contacts of the admin table as vCards
+pub async fn export_contacts_vcard(
+    _: Admin,
+    params: web::Query<ContactQuery>,
+    storage: Data<Storage>,
+) -> Result<HttpResponse, Error> {
+    let contacts = filtered(storage.contacts.as_ref(), params.into_inner()).await?;
+    Ok(attachment(
+        "text/vcard; charset=utf-8",
+        "contacts.vcf",
+        contacts_vcard(&contacts),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::routes::parse_csv;
+    use chrono::{TimeZone, Utc};
+    use uuid::Uuid;
+
+    fn contact(first_name: &str, message: Option<&str>) -> Contacts {
+        Contacts {
+            id: Uuid::nil(),
+            first_name: first_name.to_owned(),
+            last_name: "O'Crevy".to_owned(),
+            email: "aocrevyk@wunderground.com".to_owned(),
+            phone: Some("+525512345678".to_owned()),
+            message: message.map(str::to_owned),
+            notes: None,
+            created_at: Utc.with_ymd_and_hms(2023, 2, 1, 0, 0, 0).unwrap(),
+        }
+    }
+
+    #[test]
+    fn exported_csv_imports_back() {
+        let csv = contacts_csv(&[contact("Almire", Some("Hi, \"you\"\nthere"))]).unwrap();
+        let rows = parse_csv(&csv, None).unwrap();
+        let imported = rows[0].contact.as_ref().unwrap();
+        assert_eq!(imported.first_name, "Almire");
+        assert_eq!(imported.phone.as_deref(), Some("+525512345678"));
+        assert_eq!(imported.message.as_deref(), Some("Hi, \"you\"\nthere"));
+        assert_eq!(
+            imported.created_at.to_rfc3339(),
+            "2023-02-01T00:00:00+00:00"
+        );
+    }
+
+    #[test]
+    fn formulas_are_not_exported_as_formulas() {
+        let csv = contacts_csv(&[contact("=HYPERLINK(1)", None)]).unwrap();
+        assert!(csv.contains("'=HYPERLINK(1)"));
+    }
+
+    #[test]
+    fn vcards_are_escaped_and_folded() {
+        let message = format!("Call me; maybe, {}", "é".repeat(80));
+        let vcard = contacts_vcard(&[contact("Almire", Some(&message))]);
+        assert!(vcard.starts_with("BEGIN:VCARD\r\nVERSION:4.0\r\n"));
+        assert!(vcard.contains("\r\nN:O'Crevy;Almire;;;\r\n"));
+        assert!(vcard.contains("\r\nNOTE:Call me\\; maybe\\, "));
+        assert!(vcard.contains("\r\nTEL;VALUE=uri:tel:+525512345678\r\n"));
+        assert!(vcard.contains("\r\nREV:20230201T000000Z\r\n"));
+        assert!(vcard.ends_with("END:VCARD\r\n"));
+        for line in vcard.split("\r\n") {
+            assert!(line.len() <= VCARD_LINE, "{line}");
+        }
+    }
+}
@@ -0,0 +1,316 @@
+use actix_web::{
+    http::StatusCode,
+    web::{self, Data},
+    Error, HttpResponse,
+};
+use chrono::{DateTime, NaiveDate, Utc};
+use handlebars::Handlebars;
+use serde::Deserialize;
+use serde_json::json;
+use std::fmt;
+
+use super::admin::html;
+use crate::{
+    auth::Admin,
+    configuration::ContactSettings,
+    models::{
+        contacts::ContactForm,
+        import::{mark_duplicates, ImportRow, ImportSummary, ImportedContact, RowStatus},
+        phone::Region,
+    },
+    storage::{ContactRepository, Storage},
+    utils::CustomError,
+};
+
+// Largest CSV taken at once, a few thousand contacts
+pub const MAX_IMPORT_BYTES: usize = 1024 * 1024;
+const REQUIRED_COLUMNS: [&str; 3] = ["first_name", "last_name", "email"];
+
+#[derive(Debug)]
+pub enum ImportError {
+    // the file as a whole is unusable, nothing was imported
+    Csv(String),
+    // some rows are invalid, nothing was imported
+    InvalidRows(Vec<ImportRow>),
+    Database(sqlx::Error),
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ImportError::Csv(err) => write!(f, "{err}"),
+            ImportError::InvalidRows(rows) => {
+                for row in rows.iter().filter(|row| row.status == RowStatus::Invalid) {
+                    writeln!(
+                        f,
+                        "line {}: {}",
+                        row.line,
+                        row.error.as_deref().unwrap_or("")
+                    )?;
+                }
+                Ok(())
+            }
+            ImportError::Database(err) => write!(f, "Database error: {err:?}"),
+        }
+    }
+}
+
+impl std::error::Error for ImportError {}
+
+impl From<sqlx::Error> for ImportError {
+    fn from(err: sqlx::Error) -> Self {
+        ImportError::Database(err)
+    }
+}
+
+// RFC 3339 or a plain date, now when empty
+fn parse_created_at(created_at: &str) -> Result<DateTime<Utc>, String> {
+    let created_at = created_at.trim();
+    if created_at.is_empty() {
+        return Ok(Utc::now());
+    }
+    if let Ok(at) = DateTime::parse_from_rfc3339(created_at) {
+        return Ok(at.with_timezone(&Utc));
+    }
+    NaiveDate::parse_from_str(created_at, "%Y-%m-%d")
+        .ok()
+        .and_then(|date| date.and_hms_opt(0, 0, 0))
+        .map(|at| at.and_utc())
+        .ok_or_else(|| "created_at: Use 2022-11-05 or 2022-11-05T10:00:00Z".to_owned())
+}
+
+/// Reads a CSV with a header row. `first_name`, `last_name` and `email`
+/// columns are required, `phone`, `message` and `created_at` are optional,
+/// others are ignored. Headers match regardless of case and spaces, phone
+/// numbers without a country code are read as in `region`.
+pub fn parse_csv(csv: &str, region: Region) -> Result<Vec<ImportRow>, ImportError> {
+    let mut reader = csv::ReaderBuilder::new()
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .from_reader(csv.as_bytes());
+    let headers: Vec<String> = reader
+        .headers()
+        .map_err(|err| ImportError::Csv(format!("Unreadable header: {err}")))?
+        .iter()
+        .map(|header| header.to_lowercase().replace(' ', "_"))
+        .collect();
+    let column = |name: &str| headers.iter().position(|header| header == name);
+    for required in REQUIRED_COLUMNS {
+        if column(required).is_none() {
+            return Err(ImportError::Csv(format!(
+                "The {required} column is missing"
+            )));
+        }
+    }
+
+    let mut rows = Vec::new();
+    for (n, record) in reader.records().enumerate() {
+        let line = n as u64 + 2;
+        let record = match record {
+            Ok(record) => record,
+            Err(err) => {
+                rows.push(ImportRow {
+                    line,
+                    status: RowStatus::Invalid,
+                    values: ContactForm::default(),
+                    contact: None,
+                    error: Some(format!("Unreadable row: {err}")),
+                });
+                continue;
+            }
+        };
+        let field = |name: &str| {
+            column(name)
+                .and_then(|index| record.get(index))
+                .unwrap_or_default()
+                .to_owned()
+        };
+        let values = ContactForm {
+            first_name: field("first_name"),
+            last_name: field("last_name"),
+            email: field("email"),
+            phone: field("phone"),
+            message: field("message"),
+        };
+        let mut errors: Vec<String> = Vec::new();
+        if record.len() != headers.len() {
+            errors.push(format!(
+                "{} fields where the header has {}",
+                record.len(),
+                headers.len()
+            ));
+        }
+        let valid = values.validate(region).map_err(|field_errors| {
+            for (field, error) in field_errors {
+                errors.push(format!("{field}: {error}"));
+            }
+        });
+        let created_at = parse_created_at(&field("created_at")).map_err(|err| errors.push(err));
+        let contact = match (valid, created_at) {
+            (Ok(valid), Ok(created_at)) if errors.is_empty() => Some(ImportedContact {
+                first_name: valid.first_name,
+                last_name: valid.last_name,
+                email: valid.email,
+                phone: valid.phone,
+                message: valid.message,
+                created_at,
+            }),
+            _ => None,
+        };
+        rows.push(ImportRow {
+            line,
+            status: if contact.is_some() {
+                RowStatus::New
+            } else {
+                RowStatus::Invalid
+            },
+            values,
+            contact,
+            error: (!errors.is_empty()).then(|| errors.join(", ")),
+        });
+    }
+    Ok(rows)
+}
+
+/// What importing `csv` would do, row by row
+pub async fn preview_import(
+    contacts: &dyn ContactRepository,
+    csv: &str,
+    region: Region,
+) -> Result<Vec<ImportRow>, ImportError> {
+    let mut rows = parse_csv(csv, region)?;
+    mark_duplicates(contacts.contact_emails().await?, &mut rows);
+    Ok(rows)
+}
+
+/// Adds the new contacts of `csv`, skipping duplicates, all at once. With any
+/// invalid row nothing is imported.
+pub async fn import_contacts(
+    contacts: &dyn ContactRepository,
+    csv: &str,
+    region: Region,
+) -> Result<ImportSummary, ImportError> {
+    let rows = parse_csv(csv, region)?;
+    if rows.iter().any(|row| row.status == RowStatus::Invalid) {
+        return Err(ImportError::InvalidRows(rows));
+    }
+    // duplicates are checked by the repository, contacts may have come in since the preview
+    Ok(contacts.import_contacts(rows).await?)
+}
+
+#[derive(Deserialize)]
+pub struct ImportForm {
+    csv: String,
+}
+
+/// Where a CSV is picked and previewed
+pub async fn import_page(_: Admin, hb: Data<Handlebars<'static>>) -> HttpResponse {
+    html(
+        StatusCode::OK,
+        hb.render(
+            "contact_import",
+            &json!({ "description": "Import contacts" }),
+        ),
+    )
+}
+
+fn import_failed(hb: &Handlebars<'static>, err: ImportError) -> Result<HttpResponse, Error> {
+    match err {
+        ImportError::Database(err) => Err(CustomError::DatabaseError(err).into()),
+        ImportError::InvalidRows(rows) => Ok(html(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            hb.render("contact_import_preview", &json!({ "rows": rows })),
+        )),
+        ImportError::Csv(error) => Ok(html(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            hb.render("contact_import_preview", &json!({ "error": error })),
+        )),
+    }
+}
+
+/// The rows of a CSV marked new, duplicate or invalid, with a button to
+/// import it when none is invalid
+pub async fn preview_contacts_import(
+    _: Admin,
+    form: web::Form<ImportForm>,
+    hb: Data<Handlebars<'static>>,
+    storage: Data<Storage>,
+    settings: Data<ContactSettings>,
+) -> Result<HttpResponse, Error> {
+    let rows = match preview_import(storage.contacts.as_ref(), &form.csv, settings.region()).await {
+        Ok(rows) => rows,
+        Err(err) => return import_failed(&hb, err),
+    };
+    let count = |status| rows.iter().filter(|row| row.status == status).count();
+    let data = json!({
+        "rows": rows,
+        "csv": form.csv,
+        "new": count(RowStatus::New),
+        "duplicates": count(RowStatus::Duplicate),
+        "invalid": count(RowStatus::Invalid),
+    });
+    Ok(html(
+        StatusCode::OK,
+        hb.render("contact_import_preview", &data),
+    ))
+}
+
+/// Imports a previewed CSV
+pub async fn import_contacts_csv(
+    _: Admin,
+    form: web::Form<ImportForm>,
+    hb: Data<Handlebars<'static>>,
+    storage: Data<Storage>,
+    settings: Data<ContactSettings>,
+) -> Result<HttpResponse, Error> {
+    match import_contacts(storage.contacts.as_ref(), &form.csv, settings.region()).await {
+        Ok(summary) => Ok(html(
+            StatusCode::OK,
+            hb.render("contact_import_done", &summary),
+        )),
+        Err(err) => import_failed(&hb, err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::phone::region;
+
+    #[test]
+    fn rows_are_read_by_their_header() {
+        let csv = "Email,First Name,Last Name,Phone,Created At,Company\n\
+            JSayburn0@tamu.edu,Joelie,Sayburn,(201) 555-0123,2022-11-05,ACME\n\
+            edwane1@diigo.com,Eal,Dwane,,2022-09-16T10:00:00Z,\n";
+        let rows = parse_csv(csv, region("US")).unwrap();
+        assert_eq!(rows.len(), 2);
+        let joelie = rows[0].contact.as_ref().unwrap();
+        assert_eq!(joelie.email, "jsayburn0@tamu.edu");
+        assert_eq!(joelie.phone.as_deref(), Some("+12015550123"));
+        assert_eq!(joelie.created_at.to_rfc3339(), "2022-11-05T00:00:00+00:00");
+        assert_eq!(rows[1].contact.as_ref().unwrap().phone, None);
+        assert!(rows.iter().all(|row| row.status == RowStatus::New));
+    }
+
+    #[test]
+    fn invalid_rows_say_what_is_wrong_on_which_line() {
+        let csv = "first_name,last_name,email,created_at\n\
+            Joelie,Sayburn,jsayburn0@tamu.edu,2022-11-05\n\
+            ,Dwane,edwane1,yesterday\n\
+            Cristen,Keys\n";
+        let rows = parse_csv(csv, None).unwrap();
+        assert_eq!(rows[0].status, RowStatus::New);
+        assert_eq!((rows[1].line, rows[1].status), (3, RowStatus::Invalid));
+        let error = rows[1].error.as_deref().unwrap();
+        assert!(error.contains("first_name:"), "{error}");
+        assert!(error.contains("email:"), "{error}");
+        assert!(error.contains("created_at:"), "{error}");
+        assert_eq!((rows[2].line, rows[2].status), (4, RowStatus::Invalid));
+    }
+
+    #[test]
+    fn required_columns_are_checked_first() {
+        let err = parse_csv("first_name,email\nJoelie,jsayburn0@tamu.edu\n", None).unwrap_err();
+        assert_eq!(err.to_string(), "The last_name column is missing");
+    }
+}
@@ -0,0 +1,311 @@
+use crate::{
+    configuration::ContactSettings,
+    email::Outbox,
+    models::{
+        contacts::{ContactForm, NewContact},
+        phone::{normalize_phone, Region},
+    },
+    protection::{quarantine, Form, FormGuard, Protected, Verdict},
+    storage::{ContactRepository, Storage},
+    utils::CustomError,
+};
+use actix_web::{
+    http::StatusCode,
+    web::{self, Data},
+    HttpRequest, HttpResponse,
+};
+use chrono::{Duration, Utc};
+use handlebars::Handlebars;
+use serde_json::json;
+use std::collections::BTreeMap;
+
+mod admin;
+mod export;
+mod import;
+
+pub use admin::*;
+pub use export::*;
+pub use import::*;
+
+const MAX_NAME: usize = 64;
+const MAX_EMAIL: usize = 254;
+const MAX_MESSAGE: usize = 2000;
+// Characters that have no business in a name and a lot in markup
+const FORBIDDEN_NAME_CHARACTERS: [char; 9] = ['/', '(', ')', '"', '<', '>', '\\', '{', '}'];
+
+// What is wrong with each field, by field name
+pub type FieldErrors = BTreeMap<&'static str, String>;
+
+fn validate_name(name: &str) -> Result<String, String> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err("Please tell us your name".to_owned());
+    }
+    if name.chars().count() > MAX_NAME {
+        return Err(format!("At most {MAX_NAME} characters"));
+    }
+    if name
+        .chars()
+        .any(|c| c.is_control() || FORBIDDEN_NAME_CHARACTERS.contains(&c))
+    {
+        return Err("Letters, spaces, dashes and apostrophes only".to_owned());
+    }
+    Ok(name.to_owned())
+}
+
+pub(crate) fn validate_email(email: &str) -> Result<String, String> {
+    let email = email.trim();
+    if email.is_empty() {
+        return Err("Please tell us your email".to_owned());
+    }
+    let valid = email.len() <= MAX_EMAIL
+        && !email.chars().any(char::is_whitespace)
+        && match email.split_once('@') {
+            Some((local, domain)) => {
+                !local.is_empty()
+                    && !domain.contains('@')
+                    && domain.contains('.')
+                    && !domain.starts_with('.')
+                    && !domain.ends_with('.')
+            }
+            None => false,
+        };
+    if !valid {
+        return Err("That does not look like an email address".to_owned());
+    }
+    Ok(email.to_lowercase())
+}
+
+fn validate_phone(phone: &str, region: Region) -> Result<Option<String>, String> {
+    let phone = phone.trim();
+    if phone.is_empty() {
+        return Ok(None);
+    }
+    normalize_phone(phone, region).map(Some)
+}
+
+fn validate_message(message: &str) -> Result<Option<String>, String> {
+    let message = message.trim();
+    if message.chars().count() > MAX_MESSAGE {
+        return Err(format!("At most {MAX_MESSAGE} characters"));
+    }
+    Ok((!message.is_empty()).then(|| message.to_owned()))
+}
+
+// The value of a valid field, or None with its error noted
+fn check<T>(errors: &mut FieldErrors, field: &'static str, result: Result<T, String>) -> Option<T> {
+    result.map_err(|err| errors.insert(field, err)).ok()
+}
+
+impl ContactForm {
+    // Numbers without a country code are read as in `region`
+    pub fn validate(&self, region: Region) -> Result<NewContact, FieldErrors> {
+        let mut errors = FieldErrors::new();
+        let first_name = check(&mut errors, "first_name", validate_name(&self.first_name));
+        let last_name = check(&mut errors, "last_name", validate_name(&self.last_name));
+        let email = check(&mut errors, "email", validate_email(&self.email));
+        let phone = check(&mut errors, "phone", validate_phone(&self.phone, region));
+        let message = check(&mut errors, "message", validate_message(&self.message));
+        match (first_name, last_name, email, phone, message) {
+            (Some(first_name), Some(last_name), Some(email), Some(phone), Some(message)) => {
+                Ok(NewContact {
+                    first_name,
+                    last_name,
+                    email,
+                    phone,
+                    message,
+                })
+            }
+            _ => Err(errors),
+        }
+    }
+}
+
+// Whether htmx made the request and only wants the fragment back, boosted
+// links swap in whole pages
+pub(crate) fn is_htmx(req: &HttpRequest) -> bool {
+    let headers = req.headers();
+    headers.contains_key("HX-Request") && !headers.contains_key("HX-Boosted")
+}
+
+fn render(
+    hb: &Handlebars<'static>,
+    req: &HttpRequest,
+    status: StatusCode,
+    fragment: &str,
+    mut data: serde_json::Value,
+) -> HttpResponse {
+    let body = if is_htmx(req) {
+        hb.render(fragment, &data)
+    } else {
+        // the whole page around the fragment
+        data["sent"] = json!(fragment == "contact_sent");
+        data["description"] = json!("Get in touch.");
+        hb.render("contact_page", &data)
+    };
+    match body {
+        Ok(body) => HttpResponse::build(status)
+            .content_type("text/html; charset=utf-8")
+            .body(body),
+        Err(err) => {
+            println!("Failed to render {fragment}: {err:?}");
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// Stores a contact unless the same email wrote within the dedupe window,
+/// true if it was stored
+pub async fn save_contact(
+    contacts: &dyn ContactRepository,
+    contact: &NewContact,
+    dedupe_window_mins: i64,
+) -> Result<bool, sqlx::Error> {
+    let since = Utc::now() - Duration::minutes(dedupe_window_mins);
+    contacts.save_contact(contact, since).await
+}
+
+/// The contact form
+pub async fn contact_page(
+    hb: Data<Handlebars<'static>>,
+    guard: Data<FormGuard>,
+    req: HttpRequest,
+) -> HttpResponse {
+    render(
+        &hb,
+        &req,
+        StatusCode::OK,
+        "contact_form",
+        json!({
+            "values": ContactForm::default(),
+            "form_token": guard.issue_token(Form::Contact),
+        }),
+    )
+}
+
+/// A visitor leaves their details. Invalid forms come back with an error per
+/// field, repeats within the dedupe window are thanked for but not stored.
+/// Bots are thanked too, and their submissions dropped or quarantined.
+pub async fn submit_contact(
+    req: HttpRequest,
+    form: web::Form<Protected<ContactForm>>,
+    hb: Data<Handlebars<'static>>,
+    storage: Data<Storage>,
+    settings: Data<ContactSettings>,
+    guard: Data<FormGuard>,
+    outbox: Data<Outbox>,
+) -> Result<HttpResponse, CustomError> {
+    let Protected {
+        proof,
+        fields: form,
+    } = form.into_inner();
+    let sent = |first_name: &str| {
+        render(
+            &hb,
+            &req,
+            StatusCode::OK,
+            "contact_sent",
+            json!({ "first_name": first_name }),
+        )
+    };
+    let ip = guard.client_ip(&req);
+    let text = format!("{} {} {}", form.first_name, form.last_name, form.message);
+    let verdict = guard.check(&ip, Form::Contact, &proof, Some(&text));
+    if let Verdict::Reject(reason) = verdict {
+        println!("Dropped a contact submission from {ip}: {reason}");
+        return Ok(sent(form.first_name.trim()));
+    }
+
+    let contact = match form.validate(settings.region()) {
+        Ok(contact) => contact,
+        Err(errors) => {
+            return Ok(render(
+                &hb,
+                &req,
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "contact_form",
+                json!({ "values": form, "errors": errors, "form_token": proof.form_token }),
+            ))
+        }
+    };
+    if let Verdict::Quarantine { score, reasons } = verdict {
+        quarantine(
+            storage.quarantine.as_ref(),
+            Form::Contact,
+            &ip,
+            json!(form),
+            score,
+            &reasons,
+        )
+        .await
+        .map_err(CustomError::DatabaseError)?;
+        return Ok(sent(&contact.first_name));
+    }
+    let stored = save_contact(
+        storage.contacts.as_ref(),
+        &contact,
+        settings.dedupe_window_mins,
+    )
+    .await
+    .map_err(CustomError::DatabaseError)?;
+    if stored {
+        outbox.notify_owner(&hb, "contact_received", &contact).await;
+    }
+    Ok(sent(&contact.first_name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::phone::region;
+
+    fn form(first_name: &str, last_name: &str, email: &str) -> ContactForm {
+        ContactForm {
+            first_name: first_name.to_owned(),
+            last_name: last_name.to_owned(),
+            email: email.to_owned(),
+            phone: String::new(),
+            message: String::new(),
+        }
+    }
+
+    #[test]
+    fn valid_forms_are_trimmed_and_emails_lower_cased() {
+        let mut form = form(" Almire ", "O'Crevy", "AOcrevy@Example.com ");
+        form.phone = "(201) 555-0123".to_owned();
+        let contact = form.validate(region("US")).unwrap();
+        assert_eq!(
+            contact,
+            NewContact {
+                first_name: "Almire".to_owned(),
+                last_name: "O'Crevy".to_owned(),
+                email: "aocrevy@example.com".to_owned(),
+                phone: Some("+12015550123".to_owned()),
+                message: None,
+            }
+        );
+    }
+
+    #[test]
+    fn every_invalid_field_gets_its_error() {
+        let mut form = form("", "<script>", "not an email");
+        form.phone = "59".to_owned();
+        let errors = form.validate(region("US")).unwrap_err();
+        assert_eq!(
+            errors.keys().copied().collect::<Vec<_>>(),
+            ["email", "first_name", "last_name", "phone"]
+        );
+
+        for email in [
+            "a@b",
+            "@example.com",
+            "a@@example.com",
+            "a b@example.com",
+            "a@.com",
+        ] {
+            assert!(validate_email(email).is_err(), "{email}");
+        }
+        assert!(validate_name(&"a".repeat(MAX_NAME + 1)).is_err());
+        assert!(validate_message(&"a".repeat(MAX_MESSAGE + 1)).is_err());
+    }
+}
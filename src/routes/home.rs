@@ -5,52 +5,78 @@ use actix_web::{
 };
 use handlebars::Handlebars;
 use serde_json::json;
-use sqlx::query;
-use sqlx::sqlite::SqlitePool;
 use uuid::Uuid;
 
-use crate::utils::CustomError;
+use crate::{
+    protection::{Form, FormGuard, FormProof, Verdict},
+    storage::Storage,
+    utils::CustomError,
+};
 
 pub async fn health_check() -> HttpResponse {
     HttpResponse::Ok().finish()
 }
 
-pub async fn index(hb: Data<Handlebars<'static>>, req: HttpRequest) -> HttpResponse {
-    let user_uuid = match req.cookie("user_uuid") {
-        Some(_) => true,
-        None => false,
-    };
-    let content = if user_uuid {
-        hb.render(
-            "index",
-            &json!({"cookie": true, "description": "Personal portfolio and blog."}),
-        )
-        .unwrap()
-    } else {
-        hb.render(
+pub async fn index(
+    hb: Data<Handlebars<'static>>,
+    guard: Data<FormGuard>,
+    req: HttpRequest,
+) -> HttpResponse {
+    let user_uuid = req.cookie("user_uuid").is_some();
+    let content = hb
+        .render(
             "index",
-            &json!({"cookie": false, "description": "Personal portfolio and blog."}),
+            &json!({
+                "cookie": user_uuid,
+                "description": "Personal portfolio and blog.",
+                "form_token": guard.issue_token(Form::Like),
+            }),
         )
-        .unwrap()
-    };
+        .unwrap();
     HttpResponse::Ok()
         .content_type("text/html; charset=utf-8")
         .body(content)
 }
 
+// The like button, `liked` shows the heart. It keeps the token of the page.
+fn like_button(
+    hb: &Handlebars<'static>,
+    liked: bool,
+    form_token: &str,
+) -> Result<String, CustomError> {
+    hb.render(
+        "like_button",
+        &json!({ "cookie": liked, "form_token": form_token }),
+    )
+    .map_err(|_| CustomError::ParsingError)
+}
+
 pub async fn like(
     req: HttpRequest,
-    pool: web::Data<SqlitePool>,
+    proof: web::Form<FormProof>,
+    hb: Data<Handlebars<'static>>,
+    guard: Data<FormGuard>,
+    storage: web::Data<Storage>,
 ) -> Result<HttpResponse, CustomError> {
+    let liked = req.cookie("user_uuid").is_some();
+    let ip = guard.client_ip(&req);
+    if let Verdict::Reject(reason) = guard.check(&ip, Form::Like, &proof, None) {
+        // the button flips as if it worked, nothing is stored
+        println!("Dropped a like from {ip}: {reason}");
+        return Ok(HttpResponse::Ok()
+            .content_type("text/html; charset=utf-8")
+            .body(like_button(&hb, !liked, &proof.form_token)?));
+    }
     let user_uuid = match req.cookie("user_uuid") {
         Some(c) => {
             // Delete like from db
             let uuid_str = c.value();
             let id = Uuid::parse_str(uuid_str).map_err(|_| CustomError::ParsingError)?;
-            query!("DELETE FROM likes WHERE id = ?1", id)
-                .execute(pool.get_ref())
+            storage
+                .likes
+                .remove_like(id)
                 .await
-                .map_err(|e| CustomError::DatabaseError(e))?;
+                .map_err(CustomError::DatabaseError)?;
 
             // Delete cookie
             let expiration_cookie = Cookie::build("user_uuid", "")
@@ -60,32 +86,14 @@ pub async fn like(
             HttpResponse::Ok()
                 .cookie(expiration_cookie)
                 .content_type("text/html; charset=utf-8")
-                .body(
-                    r#"<img src="/images/dislike.svg" class="w-6 h-6 hover:w-7 
-                    hover:h-7 m-2 cursor-pointer"
-                    hx-post="/like" hx-swap="outerHTML"
-                    _="on htmx:afterOnLoad put 'Thank you!' into #message2 wait 1s put '' into #message2"
-                    />
-                    "#,
-                )
+                .body(like_button(&hb, false, &proof.form_token)?)
         }
         None => {
-            let count = query!("SELECT COUNT(*) as count FROM likes")
-                .fetch_one(pool.get_ref())
+            let new_uuid = storage
+                .likes
+                .add_like()
                 .await
-                .map_err(|e| CustomError::DatabaseError(e))?;
-
-            let current: i32 = count.count;
-            let new = current + 1;
-            let new_uuid = Uuid::new_v4();
-            let _ = query!(
-                "INSERT INTO likes (id, counter) VALUES (?1, ?2)",
-                new_uuid,
-                new
-            )
-            .execute(pool.get_ref())
-            .await
-            .map_err(|e| CustomError::DatabaseError(e))?;
+                .map_err(CustomError::DatabaseError)?;
 
             HttpResponse::Ok()
                 .cookie(
@@ -95,14 +103,7 @@ pub async fn like(
                         .finish(),
                 )
                 .content_type("text/html; charset=utf-8")
-                .body(
-                    r#"<img src="/images/heart.svg" class="w-6 h-6 hover:w-7 
-                    hover:h-7 m-2 cursor-pointer"
-                    hx-post="/like" hx-swap="outerHTML"
-                    _="on htmx:afterOnLoad put 'Thank you!' into #message2 wait 1s put '' into #message2"
-           />
-            "#,
-                )
+                .body(like_button(&hb, true, &proof.form_token)?)
         }
     };
     Ok(user_uuid)
@@ -1,7 +1,13 @@
 mod blog;
 mod chat;
+mod contacts;
 mod home;
+mod newsletter;
+mod quarantine;
 
 pub use blog::*;
 pub use chat::*;
+pub use contacts::*;
 pub use home::*;
+pub use newsletter::*;
+pub use quarantine::*;
@@ -0,0 +1,303 @@
+use actix_web::{
+    http::StatusCode,
+    web::{self, Data},
+    Error, HttpRequest, HttpResponse,
+};
+use chrono::{Duration, Utc};
+use handlebars::Handlebars;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::fmt;
+
+use super::{is_htmx, validate_email};
+use crate::{
+    configuration::{NewsletterSettings, Post},
+    email::{Email, EmailError, Outbox},
+    protection::{Form, FormGuard, Protected, Verdict},
+    storage::{NewsletterRepository, Storage},
+    utils::CustomError,
+};
+
+// Characters of a post that make it into its email
+const EXCERPT: usize = 400;
+
+#[derive(Deserialize, Serialize, Default, Debug)]
+#[serde(default)]
+pub struct SubscribeForm {
+    pub email: String,
+}
+
+#[derive(Deserialize)]
+pub struct TokenQuery {
+    token: String,
+}
+
+// Unguessable, for links in emails
+fn new_token() -> String {
+    hex::encode(rand::thread_rng().gen::<[u8; 32]>())
+}
+
+fn page(
+    hb: &Handlebars<'static>,
+    status: StatusCode,
+    template: &str,
+    data: serde_json::Value,
+) -> HttpResponse {
+    match hb.render(template, &data) {
+        Ok(body) => HttpResponse::build(status)
+            .content_type("text/html; charset=utf-8")
+            .body(body),
+        Err(err) => {
+            println!("Failed to render {template}: {err:?}");
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+// The outcome of a subscription link, on a page of its own
+fn outcome(hb: &Handlebars<'static>, status: StatusCode, message: &str) -> HttpResponse {
+    page(
+        hb,
+        status,
+        "subscription",
+        json!({ "message": message, "description": "Newsletter" }),
+    )
+}
+
+fn invalid_link(hb: &Handlebars<'static>) -> HttpResponse {
+    outcome(
+        hb,
+        StatusCode::BAD_REQUEST,
+        "This link is not valid anymore. Please subscribe again.",
+    )
+}
+
+// Sets an address up for confirmation, a new token for it every time.
+// None if it is confirmed already.
+async fn pending_subscription(
+    newsletter: &dyn NewsletterRepository,
+    email: &str,
+) -> Result<Option<String>, sqlx::Error> {
+    let token = new_token();
+    let pending = newsletter
+        .add_pending_subscription(email, &token, &new_token())
+        .await?;
+    Ok(pending.then_some(token))
+}
+
+/// Someone subscribes from the blog and gets an email to confirm it. Everyone
+/// gets the same answer, subscribers or bots.
+pub async fn subscribe(
+    req: HttpRequest,
+    form: web::Form<Protected<SubscribeForm>>,
+    hb: Data<Handlebars<'static>>,
+    storage: Data<Storage>,
+    guard: Data<FormGuard>,
+    outbox: Data<Outbox>,
+    settings: Data<NewsletterSettings>,
+) -> Result<HttpResponse, Error> {
+    let Protected {
+        proof,
+        fields: form,
+    } = form.into_inner();
+    let done = || {
+        let template = if is_htmx(&req) {
+            "subscribe_done"
+        } else {
+            "subscription"
+        };
+        page(
+            &hb,
+            StatusCode::OK,
+            template,
+            json!({ "message": "Almost there, please confirm with the link we emailed you." }),
+        )
+    };
+    let ip = guard.client_ip(&req);
+    if let Verdict::Reject(reason) = guard.check(&ip, Form::Subscribe, &proof, None) {
+        println!("Dropped a subscription from {ip}: {reason}");
+        return Ok(done());
+    }
+    let email = match validate_email(&form.email) {
+        Ok(email) => email,
+        Err(error) if is_htmx(&req) => {
+            return Ok(page(
+                &hb,
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "subscribe_form",
+                json!({ "values": form, "error": error, "form_token": proof.form_token }),
+            ))
+        }
+        Err(error) => return Ok(outcome(&hb, StatusCode::UNPROCESSABLE_ENTITY, &error)),
+    };
+    let token = pending_subscription(storage.newsletter.as_ref(), &email)
+        .await
+        .map_err(CustomError::DatabaseError)?;
+    if let Some(token) = token {
+        let confirm_url = format!("{}/subscriptions/confirm?token={token}", settings.base_url);
+        outbox
+            .send(
+                &hb,
+                "confirm_subscription",
+                &email,
+                &json!({ "confirm_url": confirm_url, "hours": settings.confirm_token_hours }),
+            )
+            .await
+            .map_err(actix_web::error::ErrorInternalServerError)?;
+    }
+    Ok(done())
+}
+
+/// The link of a confirmation email, it works once
+pub async fn confirm_subscription(
+    params: web::Query<TokenQuery>,
+    hb: Data<Handlebars<'static>>,
+    storage: Data<Storage>,
+    settings: Data<NewsletterSettings>,
+) -> Result<HttpResponse, Error> {
+    let since = Utc::now() - Duration::hours(settings.confirm_token_hours);
+    let confirmed = storage
+        .newsletter
+        .confirm_subscription(&params.token, since)
+        .await
+        .map_err(CustomError::DatabaseError)?;
+    if !confirmed {
+        return Ok(invalid_link(&hb));
+    }
+    Ok(outcome(
+        &hb,
+        StatusCode::OK,
+        "You are subscribed, new posts will come to your inbox.",
+    ))
+}
+
+/// Where the unsubscribe link of an email leads. Mail scanners follow links,
+/// so this only asks; the button, or a mail client's one-click, posts.
+pub async fn unsubscribe_page(
+    params: web::Query<TokenQuery>,
+    hb: Data<Handlebars<'static>>,
+) -> HttpResponse {
+    page(
+        &hb,
+        StatusCode::OK,
+        "subscription",
+        json!({ "unsubscribe_token": params.token, "description": "Newsletter" }),
+    )
+}
+
+/// Unsubscribes in one step, RFC 8058 one-click included
+pub async fn unsubscribe(
+    params: web::Query<TokenQuery>,
+    hb: Data<Handlebars<'static>>,
+    storage: Data<Storage>,
+) -> Result<HttpResponse, Error> {
+    let found = storage
+        .newsletter
+        .unsubscribe(&params.token)
+        .await
+        .map_err(CustomError::DatabaseError)?;
+    if !found {
+        return Ok(invalid_link(&hb));
+    }
+    Ok(outcome(
+        &hb,
+        StatusCode::OK,
+        "You are unsubscribed, you will not hear from us again.",
+    ))
+}
+
+#[derive(Debug)]
+pub enum NewsletterError {
+    AlreadySent(String),
+    Email(EmailError),
+}
+
+impl fmt::Display for NewsletterError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NewsletterError::AlreadySent(slug) => write!(f, "{slug} was sent already"),
+            NewsletterError::Email(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl std::error::Error for NewsletterError {}
+
+impl From<sqlx::Error> for NewsletterError {
+    fn from(err: sqlx::Error) -> Self {
+        NewsletterError::Email(EmailError::Database(err))
+    }
+}
+
+// The start of a post, cut at a word
+fn excerpt(body: &str) -> String {
+    let body = body.trim();
+    if body.chars().count() <= EXCERPT {
+        return body.to_owned();
+    }
+    let cut: String = body.chars().take(EXCERPT).collect();
+    let cut = cut
+        .rsplit_once(' ')
+        .map_or(cut.as_str(), |(start, _)| start);
+    format!("{cut}…")
+}
+
+/// Queues `post` for every confirmed subscriber, the running server sends
+/// them. A post goes out once, the number of emails is returned.
+pub async fn send_post(
+    newsletter: &dyn NewsletterRepository,
+    hb: &Handlebars<'static>,
+    settings: &NewsletterSettings,
+    post: &Post,
+) -> Result<usize, NewsletterError> {
+    let subscribers = newsletter.confirmed_subscribers().await?;
+    // nothing went out, the post can still go to whoever confirms later
+    if subscribers.is_empty() {
+        return Ok(0);
+    }
+    let url = format!("{}/blog/{}", settings.base_url, post.slug);
+    let mut emails = Vec::with_capacity(subscribers.len());
+    for subscriber in &subscribers {
+        let unsubscribe_url = format!(
+            "{}/subscriptions/unsubscribe?token={}",
+            settings.base_url, subscriber.unsubscribe_token
+        );
+        let data = json!({
+            "title": post.title,
+            "author": post.author,
+            "date": post.date,
+            "excerpt": excerpt(&post.body),
+            "url": url,
+            "unsubscribe_url": unsubscribe_url,
+        });
+        let email = Email {
+            unsubscribe_url: Some(unsubscribe_url.clone()),
+            ..Email::render(hb, "new_post", &subscriber.email, &data)
+                .map_err(NewsletterError::Email)?
+        };
+        email.check_address().map_err(NewsletterError::Email)?;
+        emails.push(email);
+    }
+    if !newsletter
+        .record_issue(&post.slug, &post.title, &emails)
+        .await?
+    {
+        return Err(NewsletterError::AlreadySent(post.slug.clone()));
+    }
+    Ok(emails.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn excerpts_are_cut_between_words() {
+        assert_eq!(excerpt("  Short post\n"), "Short post");
+        let long = "word ".repeat(100);
+        let cut = excerpt(&long);
+        assert!(cut.ends_with("word…"));
+        assert!(cut.chars().count() <= EXCERPT + 1);
+    }
+}
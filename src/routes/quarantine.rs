@@ -0,0 +1,98 @@
+use actix_web::{
+    web::{self, Data},
+    Error, HttpResponse,
+};
+use handlebars::Handlebars;
+use serde_json::json;
+use uuid::Uuid;
+
+use super::save_contact;
+use crate::{
+    auth::Admin, configuration::ContactSettings, models::contacts::ContactForm, storage::Storage,
+    utils::CustomError,
+};
+
+// Entries shown on the review page, the most recent first
+const REVIEW_LIMIT: i64 = 200;
+
+fn not_found() -> HttpResponse {
+    HttpResponse::NotFound().body("No such submission")
+}
+
+/// Submissions that scored as spam, to release or delete
+pub async fn admin_quarantine(
+    _: Admin,
+    hb: Data<Handlebars<'static>>,
+    storage: Data<Storage>,
+) -> Result<HttpResponse, Error> {
+    let entries = storage
+        .quarantine
+        .quarantined(REVIEW_LIMIT)
+        .await
+        .map_err(CustomError::DatabaseError)?;
+    let body = hb
+        .render("quarantine", &json!({ "entries": entries }))
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    Ok(HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(body))
+}
+
+/// A contact submission that was not spam after all is saved as if it had
+/// just come in. Chat and inbox messages are long gone, they can only be deleted.
+pub async fn release_quarantined(
+    _: Admin,
+    path: web::Path<Uuid>,
+    storage: Data<Storage>,
+    settings: Data<ContactSettings>,
+) -> Result<HttpResponse, Error> {
+    let id = path.into_inner();
+    let entry = storage
+        .quarantine
+        .find_quarantined(id)
+        .await
+        .map_err(CustomError::DatabaseError)?;
+    let Some(entry) = entry else {
+        return Ok(not_found());
+    };
+    if entry.form != "contact" {
+        return Ok(HttpResponse::BadRequest().body("Only contact submissions can be released"));
+    }
+    let form: ContactForm =
+        serde_json::from_str(&entry.payload).map_err(actix_web::error::ErrorInternalServerError)?;
+    // it passed validation on the way in
+    let contact = form
+        .validate(settings.region())
+        .map_err(|_| actix_web::error::ErrorBadRequest("The submission is not valid"))?;
+    save_contact(
+        storage.contacts.as_ref(),
+        &contact,
+        settings.dedupe_window_mins,
+    )
+    .await
+    .map_err(CustomError::DatabaseError)?;
+    storage
+        .quarantine
+        .delete_quarantined(id)
+        .await
+        .map_err(CustomError::DatabaseError)?;
+    println!("Released quarantined contact {id}");
+    Ok(HttpResponse::Ok().finish())
+}
+
+pub async fn delete_quarantined(
+    _: Admin,
+    path: web::Path<Uuid>,
+    storage: Data<Storage>,
+) -> Result<HttpResponse, Error> {
+    let id = path.into_inner();
+    let deleted = storage
+        .quarantine
+        .delete_quarantined(id)
+        .await
+        .map_err(CustomError::DatabaseError)?;
+    if !deleted {
+        return Ok(not_found());
+    }
+    Ok(HttpResponse::Ok().finish())
+}
@@ -1,38 +1,96 @@
 use crate::{
-    configuration::Config,
+    configuration::{BusSettings, Config, Settings, ShutdownSettings},
+    email::{EmailWorker, Outbox},
+    models::phone::display_phone,
+    protection::FormGuard,
     routes::{
-        blog, chat, chat_route, content, detail, get_count, health_check, index, like, ChatServer,
+        admin_contact_row, admin_contacts, admin_quarantine, blog, chat, chat_events, chat_route,
+        chat_send, chat_with_me, confirm_subscription, contact_page, content, delete_contact,
+        delete_quarantined, detail, edit_contact, export_contacts_csv, export_contacts_vcard,
+        get_attachment, get_count, get_room_count, get_thumbnail, get_transcript, get_unique_count,
+        health_check, import_contacts_csv, import_page, inbox, inbox_conversation, index, like,
+        my_conversation, preview_contacts_import, release_quarantined, reply_to_visitor,
+        submit_contact, subscribe, unsubscribe, unsubscribe_page, update_contact,
+        upload_attachment, Attachments, ChatServer, HistoryWriter, PgBus, PostCommand, Shutdown,
+        MAX_IMPORT_BYTES,
     },
+    storage::Storage,
 };
-use actix::Actor;
+use actix::{Actor, Addr};
 use actix_files::Files;
 use actix_session::{storage::CookieSessionStore, SessionMiddleware};
 use actix_web::{
     cookie::Key,
-    dev::Server,
+    dev::{Server, ServerHandle},
     web::{self, Data},
     App, HttpServer,
 };
 use anyhow::Result;
-use handlebars::{DirectorySourceOptions, Handlebars};
-use sqlx::sqlite::SqlitePool;
+use handlebars::{handlebars_helper, DirectorySourceOptions, Handlebars};
+use sqlx::postgres::PgPool;
 use std::{
     net::TcpListener,
+    path::Path,
     sync::{atomic::AtomicUsize, Arc},
+    time::{Duration, Instant},
 };
 
-pub fn run(listener: TcpListener, db_pool: SqlitePool) -> Result<Server, std::io::Error> {
-    // Wrap the connections in a smart poiner
-    let config = Config::new();
+// `{{phone number}}` shows an E.164 number the way people write it
+handlebars_helper!(phone: |number: Json| number.as_str().map(display_phone).unwrap_or_default());
+
+/// The pages and emails of `dir`, `paths.templates`, with their helpers
+pub fn templates(dir: &Path) -> Handlebars<'static> {
     let mut handlebars = Handlebars::new();
     handlebars
-        .register_templates_directory("templates/", DirectorySourceOptions::default())
+        .register_templates_directory(dir, DirectorySourceOptions::default())
         .unwrap();
+    handlebars.register_helper("phone", Box::new(phone));
+    handlebars
+}
+
+pub fn run(
+    listener: TcpListener,
+    storage: Storage,
+    settings: Settings,
+) -> Result<Server, std::io::Error> {
+    // Wrap the connections in a smart poiner
+    let config = Config::load(&settings.paths.blog)?;
+    let handlebars = templates(&settings.paths.templates);
     let secret_key = Key::generate();
-    let conn = Data::new(db_pool);
+    let conn = Data::new(storage.clone());
     // ws
     let app_state = Arc::new(AtomicUsize::new(0));
-    let chat_server = ChatServer::new(app_state.clone()).start();
+    let attachments = Attachments::new(&settings.chat);
+    let chat_server = ChatServer::new(app_state.clone(), settings.chat.clone())
+        .with_command(PostCommand::new(config.posts.clone()))
+        .with_history(
+            HistoryWriter::new(
+                storage.chat.clone(),
+                attachments.clone(),
+                settings.chat.history_retention_days,
+            )
+            .start(),
+        );
+    let chat_server = match &settings.chat.bus {
+        BusSettings::Memory => chat_server,
+        BusSettings::Postgres { channel, url } => {
+            let pool = PgPool::connect_lazy(url).map_err(std::io::Error::other)?;
+            chat_server.with_bus(PgBus::new(pool, channel))
+        }
+    }
+    .start();
+    let guard = Data::new(FormGuard::new(settings.protection.clone()));
+    let email_worker = EmailWorker::new(storage.outbox.clone(), settings.email.clone())
+        .map_err(std::io::Error::other)?
+        .start();
+    let outbox = Data::new(Outbox::new(
+        storage.outbox.clone(),
+        &settings.email,
+        email_worker,
+    ));
+    let shutdown = settings.shutdown.clone();
+    let chat_handle = chat_server.clone();
+    let static_files = settings.paths.static_files.clone();
     let server = HttpServer::new(move || {
         App::new()
             .wrap(
@@ -43,24 +101,142 @@ pub fn run(listener: TcpListener, db_pool: SqlitePool) -> Result<Server, std::io
             .app_data(Data::from(app_state.clone()))
             .app_data(Data::new(chat_server.clone()))
             .app_data(conn.clone())
+            .app_data(Data::new(attachments.clone()))
+            .app_data(Data::new(settings.admin.clone()))
+            .app_data(Data::new(settings.contacts.clone()))
+            .app_data(guard.clone())
+            .app_data(outbox.clone())
+            .app_data(Data::new(settings.newsletter.clone()))
             .app_data(web::Data::new(config.clone()))
             .app_data(web::Data::new(handlebars.clone()))
             .route("/", web::get().to(index))
             .route("/health-check", web::get().to(health_check))
             .route("/like", web::post().to(like))
+            .route("/contacts", web::get().to(contact_page))
+            .route("/contacts", web::post().to(submit_contact))
+            .route("/admin/contacts", web::get().to(admin_contacts))
+            // before `{id}`, which would take them for ids
+            .route(
+                "/admin/contacts/export.csv",
+                web::get().to(export_contacts_csv),
+            )
+            .route(
+                "/admin/contacts/export.vcf",
+                web::get().to(export_contacts_vcard),
+            )
+            .service(
+                web::resource("/admin/contacts/import")
+                    .app_data(web::FormConfig::default().limit(MAX_IMPORT_BYTES))
+                    .route(web::get().to(import_page))
+                    .route(web::post().to(import_contacts_csv)),
+            )
+            .service(
+                web::resource("/admin/contacts/import/preview")
+                    .app_data(web::FormConfig::default().limit(MAX_IMPORT_BYTES))
+                    .route(web::post().to(preview_contacts_import)),
+            )
+            .route("/admin/contacts/{id}", web::get().to(admin_contact_row))
+            .route("/admin/contacts/{id}", web::put().to(update_contact))
+            .route("/admin/contacts/{id}", web::delete().to(delete_contact))
+            .route("/admin/contacts/{id}/edit", web::get().to(edit_contact))
+            .route("/admin/quarantine", web::get().to(admin_quarantine))
+            .route(
+                "/admin/quarantine/{id}",
+                web::delete().to(delete_quarantined),
+            )
+            .route(
+                "/admin/quarantine/{id}/release",
+                web::post().to(release_quarantined),
+            )
+            .route("/subscriptions", web::post().to(subscribe))
+            .route(
+                "/subscriptions/confirm",
+                web::get().to(confirm_subscription),
+            )
+            .route(
+                "/subscriptions/unsubscribe",
+                web::get().to(unsubscribe_page),
+            )
+            .route("/subscriptions/unsubscribe", web::post().to(unsubscribe))
             .route("/blog/{current}", web::get().to(detail))
             .route("/blog", web::get().to(blog))
             .route("/blog/content/{slug}", web::get().to(content))
             .route("/chat", web::get().to(chat))
             .route("/ws", web::get().to(chat_route))
+            .route("/chat/rooms/{room}/events", web::get().to(chat_events))
+            .route("/chat/send", web::post().to(chat_send))
+            .route(
+                "/chat/rooms/{room}/transcript",
+                web::get().to(get_transcript),
+            )
+            .route("/chat/attachments", web::post().to(upload_attachment))
+            .route("/chat/attachments/{id}", web::get().to(get_attachment))
+            .route(
+                "/chat/attachments/{id}/thumbnail",
+                web::get().to(get_thumbnail),
+            )
+            .route("/chat-with-me", web::post().to(chat_with_me))
+            .route("/chat-with-me", web::get().to(my_conversation))
+            .route("/admin/inbox", web::get().to(inbox))
+            .route("/admin/inbox/{id}", web::get().to(inbox_conversation))
+            .route("/admin/inbox/{id}/reply", web::post().to(reply_to_visitor))
             .route("/count", web::get().to(get_count))
+            .route("/count/rooms", web::get().to(get_room_count))
+            .route("/count/unique", web::get().to(get_unique_count))
             .service(
-                Files::new("/", "./static")
+                Files::new("/", &static_files)
                     .prefer_utf8(true)
                     .use_last_modified(true),
             )
     })
+    // signals are handled below, the chat has to say goodbye first
+    .disable_signals()
+    .shutdown_timeout(shutdown.timeout_secs)
     .listen(listener)?
     .run();
+    actix_web::rt::spawn(stop_on_signal(server.handle(), chat_handle, shutdown));
     Ok(server)
 }
+
+// Wait for SIGTERM or Ctrl-C
+async fn signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut terminate = signal(SignalKind::terminate()).expect("Failed to listen for SIGTERM");
+        tokio::select! {
+            _ = terminate.recv() => (),
+            _ = tokio::signal::ctrl_c() => (),
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+// Stop taking connections, send chat clients off with a reconnect delay and
+// write what is pending, then let requests in flight finish. Whatever is left
+// when the timeout runs out is dropped.
+async fn stop_on_signal(server: ServerHandle, chat: Addr<ChatServer>, settings: ShutdownSettings) {
+    signal().await;
+    println!("Shutting down");
+    let deadline = Instant::now() + Duration::from_secs(settings.timeout_secs);
+    server.pause().await;
+    let drained = chat.send(Shutdown {
+        reconnect_after: Duration::from_secs(settings.reconnect_after_secs),
+    });
+    if tokio::time::timeout_at(deadline.into(), drained)
+        .await
+        .is_err()
+    {
+        println!("Chat did not shut down in time");
+    }
+    // visitor analytics are written inside their requests, which stopping waits for
+    let stopped = server.stop(true);
+    if tokio::time::timeout_at(deadline.into(), stopped)
+        .await
+        .is_err()
+    {
+        println!("Requests did not finish in time");
+        server.stop(false).await;
+    }
+}
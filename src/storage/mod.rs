@@ -0,0 +1,522 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde_json::json;
+use sqlx::{
+    migrate::{AppliedMigration, Migrate, MigrateError, Migrator},
+    postgres::{PgPool, PgPoolOptions},
+    sqlite::{SqliteConnectOptions, SqlitePool, SqlitePoolOptions},
+    Pool,
+};
+use std::{collections::HashSet, fmt, str::FromStr, sync::Arc};
+use uuid::Uuid;
+
+use crate::{
+    configuration::{Backend, DatabaseSettings},
+    email::{Email, OutboxEntry, PendingEmail},
+    models::{
+        chat::{
+            AttachmentRecord, Conversation, HistoryOp, InboxMessage, PendingMention, TranscriptLine,
+        },
+        contacts::{ContactChange, ContactQuery, Contacts, Cursor, NewContact},
+        import::{ImportRow, ImportSummary},
+        newsletter::{Subscriber, Subscription},
+        phone::{convert_legacy_phones, Region},
+        quarantine::Quarantined,
+    },
+};
+
+mod postgres;
+mod sqlite;
+
+pub use postgres::PostgresStorage;
+pub use sqlite::SqliteStorage;
+
+pub type Result<T> = std::result::Result<T, sqlx::Error>;
+
+pub static SQLITE_MIGRATIONS: Migrator = sqlx::migrate!("./migrations/sqlite");
+pub static POSTGRES_MIGRATIONS: Migrator = sqlx::migrate!("./migrations/postgres");
+
+// Phones became E.164 text here, the old numbers kept in `legacy_phone`
+const PHONES_E164: i64 = 20261019170000;
+// and `legacy_phone` is dropped here, once they are converted
+const LEGACY_PHONES_DROPPED: i64 = 20261019210000;
+
+#[async_trait]
+pub trait LikeRepository: Send + Sync {
+    /// A new like, numbered after the ones there are
+    async fn add_like(&self) -> Result<Uuid>;
+    async fn remove_like(&self, id: Uuid) -> Result<()>;
+    async fn count_likes(&self) -> Result<i64>;
+}
+
+#[async_trait]
+pub trait AnalyticsRepository: Send + Sync {
+    /// Remembers a visitor of the chat, the first time only
+    async fn record_visitor(&self, id: Uuid) -> Result<()>;
+    async fn count_visitors(&self) -> Result<i64>;
+}
+
+#[async_trait]
+pub trait ContactRepository: Send + Sync {
+    /// Stores a contact unless the same email wrote since `since`, true if it was stored
+    async fn save_contact(&self, contact: &NewContact, since: DateTime<Utc>) -> Result<bool>;
+    /// Contacts matching `query.q` in their names or email, in the order asked
+    /// for, from after the cursor on. `limit` of `None` gets all of them.
+    async fn find_contacts(
+        &self,
+        query: &ContactQuery,
+        after: Option<&Cursor>,
+        limit: Option<usize>,
+    ) -> Result<Vec<Contacts>>;
+    async fn find_contact(&self, id: Uuid) -> Result<Option<Contacts>>;
+    /// Takes the names, email and phone of `edit`, the message stays what they
+    /// wrote. The change is noted with `changed_by`, the contact as it is now returned.
+    async fn update_contact(
+        &self,
+        id: Uuid,
+        edit: &NewContact,
+        changed_by: &str,
+    ) -> Result<Option<Contacts>>;
+    /// The removed contact, the removal noted with `changed_by`
+    async fn delete_contact(&self, id: Uuid, changed_by: &str) -> Result<Option<Contacts>>;
+    async fn recent_changes(&self, limit: i64) -> Result<Vec<ContactChange>>;
+    async fn contact_emails(&self) -> Result<Vec<String>>;
+    /// Adds the new rows at once, duplicates are looked for again in the same transaction
+    async fn import_contacts(&self, rows: Vec<ImportRow>) -> Result<ImportSummary>;
+    /// Numbers from before phones were E.164, only there while migrating
+    async fn legacy_phones(&self) -> Result<Vec<(Uuid, i64)>>;
+    async fn set_phone(&self, id: Uuid, phone: &str) -> Result<()>;
+    async fn move_legacy_phone_to_notes(&self, id: Uuid, note: &str) -> Result<()>;
+}
+
+#[async_trait]
+pub trait ChatRepository: Send + Sync {
+    /// The database side of a change, files of attachments are left to `Attachments`
+    async fn write_history(&self, op: &HistoryOp) -> Result<()>;
+    /// The mentions kept for a name, oldest first, forgotten once handed over
+    async fn take_mentions(&self, name: &str) -> Result<Vec<PendingMention>>;
+    async fn delete_messages_before(&self, before: DateTime<Utc>) -> Result<()>;
+    /// Messages of a room sent in `[from, to)`, oldest first, deleted ones left out
+    async fn load_transcript(
+        &self,
+        room: &str,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+    ) -> Result<Vec<TranscriptLine>>;
+    async fn is_private_room(&self, room: &str) -> Result<bool>;
+    /// Rooms with messages kept, by name
+    async fn chat_rooms(&self) -> Result<Vec<String>>;
+    async fn save_attachment(&self, attachment: &AttachmentRecord) -> Result<()>;
+    async fn find_attachment(&self, id: Uuid) -> Result<Option<AttachmentRecord>>;
+    async fn attachments_of_message(&self, message: Uuid) -> Result<Vec<Uuid>>;
+    async fn delete_attachments_of_message(&self, message: Uuid) -> Result<()>;
+    /// Attachments of messages sent before `before`, and uploads that were
+    /// never shared in a room since `unshared_before`
+    async fn expired_attachments(
+        &self,
+        before: DateTime<Utc>,
+        unshared_before: DateTime<Utc>,
+    ) -> Result<Vec<Uuid>>;
+    async fn delete_attachment(&self, id: Uuid) -> Result<()>;
+}
+
+#[async_trait]
+pub trait InboxRepository: Send + Sync {
+    /// The conversation of a visitor, by their cookie or else their chat session
+    async fn find_conversation(&self, visitor: Option<Uuid>, session: Uuid)
+        -> Result<Option<Uuid>>;
+    /// Adds to the conversation of the visitor, started if they have none
+    async fn store_visitor_message(
+        &self,
+        visitor: Option<Uuid>,
+        session: Uuid,
+        name: &str,
+        message: &str,
+    ) -> Result<Uuid>;
+    async fn inbox_messages(&self, conversation: Uuid) -> Result<Vec<InboxMessage>>;
+    /// The most recent first
+    async fn conversations(&self) -> Result<Vec<Conversation>>;
+    async fn conversation_name(&self, id: Uuid) -> Result<Option<String>>;
+    /// Adds the owner's reply, the session it goes to if there is such a conversation
+    async fn store_reply(
+        &self,
+        conversation: Uuid,
+        message: &str,
+        sent_at: DateTime<Utc>,
+    ) -> Result<Option<Uuid>>;
+}
+
+#[async_trait]
+pub trait QuarantineRepository: Send + Sync {
+    async fn quarantine(
+        &self,
+        form: &str,
+        ip: &str,
+        payload: &str,
+        score: i64,
+        reasons: &str,
+    ) -> Result<()>;
+    /// The most recent first
+    async fn quarantined(&self, limit: i64) -> Result<Vec<Quarantined>>;
+    async fn find_quarantined(&self, id: Uuid) -> Result<Option<Quarantined>>;
+    async fn delete_quarantined(&self, id: Uuid) -> Result<bool>;
+}
+
+#[async_trait]
+pub trait OutboxRepository: Send + Sync {
+    async fn queue_email(&self, email: &Email) -> Result<Uuid>;
+    /// Up to `limit` emails due at `now`, left alone until `lease` in case
+    /// another worker sends them
+    async fn claim_due_emails(
+        &self,
+        now: DateTime<Utc>,
+        lease: DateTime<Utc>,
+        limit: i64,
+    ) -> Result<Vec<PendingEmail>>;
+    async fn email_sent(&self, id: Uuid, attempts: i64, sent_at: DateTime<Utc>) -> Result<()>;
+    async fn email_failed(
+        &self,
+        id: Uuid,
+        attempts: i64,
+        failed_at: DateTime<Utc>,
+        error: &str,
+    ) -> Result<()>;
+    async fn retry_email(
+        &self,
+        id: Uuid,
+        attempts: i64,
+        next_attempt_at: DateTime<Utc>,
+        error: &str,
+    ) -> Result<()>;
+    /// Every email, the oldest first
+    async fn outbox(&self) -> Result<Vec<OutboxEntry>>;
+}
+
+#[async_trait]
+pub trait NewsletterRepository: Send + Sync {
+    /// Sets `email` up for confirmation with `token`, false if it is confirmed
+    /// already. `unsubscribe_token` is only taken by new subscriptions.
+    async fn add_pending_subscription(
+        &self,
+        email: &str,
+        token: &str,
+        unsubscribe_token: &str,
+    ) -> Result<bool>;
+    /// Uses up a confirmation token, false if there was none issued since `since`
+    async fn confirm_subscription(&self, token: &str, since: DateTime<Utc>) -> Result<bool>;
+    async fn unsubscribe(&self, unsubscribe_token: &str) -> Result<bool>;
+    /// In the order they confirmed
+    async fn confirmed_subscribers(&self) -> Result<Vec<Subscriber>>;
+    /// Queues the emails of a post and notes it as sent, false if it was sent before
+    async fn record_issue(&self, slug: &str, title: &str, emails: &[Email]) -> Result<bool>;
+    async fn subscriptions(&self) -> Result<Vec<Subscription>>;
+}
+
+/// Where the database is with a migration
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MigrationState {
+    Pending,
+    Applied,
+    /// applied by a newer binary, this one does not have it
+    Unknown,
+}
+
+#[derive(Debug, Clone)]
+pub struct MigrationStatus {
+    pub version: i64,
+    pub description: String,
+    pub state: MigrationState,
+}
+
+async fn applied_migrations<DB>(
+    pool: &Pool<DB>,
+) -> std::result::Result<Vec<AppliedMigration>, MigrateError>
+where
+    DB: sqlx::Database,
+    DB::Connection: Migrate,
+{
+    let mut connection = pool.acquire().await?;
+    connection.ensure_migrations_table().await?;
+    connection.list_applied_migrations().await
+}
+
+// Applies the migrations up to `until` that are not applied yet
+async fn apply_until<DB>(
+    pool: &Pool<DB>,
+    migrator: &Migrator,
+    until: i64,
+) -> std::result::Result<(), MigrateError>
+where
+    DB: sqlx::Database,
+    DB::Connection: Migrate,
+{
+    let mut connection = pool.acquire().await?;
+    connection.ensure_migrations_table().await?;
+    let applied: HashSet<i64> = connection
+        .list_applied_migrations()
+        .await?
+        .iter()
+        .map(|migration| migration.version)
+        .collect();
+    for migration in migrator.iter() {
+        if migration.migration_type.is_down_migration()
+            || migration.version > until
+            || applied.contains(&migration.version)
+        {
+            continue;
+        }
+        connection.apply(migration).await?;
+    }
+    Ok(())
+}
+
+/// The pool underneath, for migrations
+#[derive(Clone, Debug)]
+pub enum Database {
+    Sqlite(SqlitePool),
+    Postgres(PgPool),
+}
+
+/// Everything the site keeps, in SQLite or Postgres
+#[derive(Clone)]
+pub struct Storage {
+    pub likes: Arc<dyn LikeRepository>,
+    pub analytics: Arc<dyn AnalyticsRepository>,
+    pub contacts: Arc<dyn ContactRepository>,
+    pub chat: Arc<dyn ChatRepository>,
+    pub inbox: Arc<dyn InboxRepository>,
+    pub quarantine: Arc<dyn QuarantineRepository>,
+    pub outbox: Arc<dyn OutboxRepository>,
+    pub newsletter: Arc<dyn NewsletterRepository>,
+    database: Database,
+}
+
+impl fmt::Debug for Storage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Storage")
+            .field("database", &self.database)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Storage {
+    fn new<R>(repository: R, database: Database) -> Self
+    where
+        R: LikeRepository
+            + AnalyticsRepository
+            + ContactRepository
+            + ChatRepository
+            + InboxRepository
+            + QuarantineRepository
+            + OutboxRepository
+            + NewsletterRepository
+            + 'static,
+    {
+        let repository = Arc::new(repository);
+        Storage {
+            likes: repository.clone(),
+            analytics: repository.clone(),
+            contacts: repository.clone(),
+            chat: repository.clone(),
+            inbox: repository.clone(),
+            quarantine: repository.clone(),
+            outbox: repository.clone(),
+            newsletter: repository,
+            database,
+        }
+    }
+
+    pub fn sqlite(pool: SqlitePool) -> Self {
+        Storage::new(SqliteStorage::new(pool.clone()), Database::Sqlite(pool))
+    }
+
+    pub fn postgres(pool: PgPool) -> Self {
+        Storage::new(PostgresStorage::new(pool.clone()), Database::Postgres(pool))
+    }
+
+    /// The database of `database.url`
+    pub async fn connect(settings: &DatabaseSettings) -> Result<Self> {
+        match settings.backend() {
+            Some(Backend::Sqlite) => {
+                let options =
+                    SqliteConnectOptions::from_str(&settings.url)?.create_if_missing(true);
+                let pool = SqlitePoolOptions::new()
+                    .max_connections(settings.max_connections)
+                    .connect_with(options)
+                    .await?;
+                Ok(Storage::sqlite(pool))
+            }
+            Some(Backend::Postgres) => {
+                let pool = PgPoolOptions::new()
+                    .max_connections(settings.max_connections)
+                    .connect(&settings.url)
+                    .await?;
+                Ok(Storage::postgres(pool))
+            }
+            None => Err(sqlx::Error::Configuration(
+                format!("{} is no SQLite or Postgres URL", settings.url).into(),
+            )),
+        }
+    }
+
+    pub fn database(&self) -> &Database {
+        &self.database
+    }
+
+    fn migrator(&self) -> &'static Migrator {
+        match &self.database {
+            Database::Sqlite(_) => &SQLITE_MIGRATIONS,
+            Database::Postgres(_) => &POSTGRES_MIGRATIONS,
+        }
+    }
+
+    /// Runs the migrations of the backend that are not applied yet. A database
+    /// a newer binary migrated is refused. Phones from before they were E.164
+    /// are read as in `region` on the way, see `convert_legacy_phones`.
+    pub async fn migrate(&self, region: Region) -> std::result::Result<(), MigrateError> {
+        let legacy_phones = self.migrations().await?.iter().any(|migration| {
+            migration.version == LEGACY_PHONES_DROPPED && migration.state == MigrationState::Pending
+        });
+        if legacy_phones {
+            match &self.database {
+                Database::Sqlite(pool) => {
+                    apply_until(pool, &SQLITE_MIGRATIONS, PHONES_E164).await?
+                }
+                Database::Postgres(pool) => {
+                    apply_until(pool, &POSTGRES_MIGRATIONS, PHONES_E164).await?
+                }
+            }
+            let (converted, moved) = convert_legacy_phones(self.contacts.as_ref(), region)
+                .await
+                .map_err(MigrateError::Execute)?;
+            if converted + moved > 0 {
+                println!(
+                    "Converted {converted} phone numbers, moved {moved} to the notes of their contacts"
+                );
+            }
+        }
+        match &self.database {
+            Database::Sqlite(pool) => SQLITE_MIGRATIONS.run(pool).await,
+            Database::Postgres(pool) => POSTGRES_MIGRATIONS.run(pool).await,
+        }
+    }
+
+    /// The migrations of this binary by version, then the ones it does not know
+    pub async fn migrations(&self) -> std::result::Result<Vec<MigrationStatus>, MigrateError> {
+        let applied = match &self.database {
+            Database::Sqlite(pool) => applied_migrations(pool).await?,
+            Database::Postgres(pool) => applied_migrations(pool).await?,
+        };
+        let applied: HashSet<i64> = applied.iter().map(|migration| migration.version).collect();
+        let mut known = HashSet::new();
+        let mut migrations = Vec::new();
+        for migration in self.migrator().iter() {
+            if migration.migration_type.is_down_migration() {
+                continue;
+            }
+            known.insert(migration.version);
+            let state = if applied.contains(&migration.version) {
+                MigrationState::Applied
+            } else {
+                MigrationState::Pending
+            };
+            migrations.push(MigrationStatus {
+                version: migration.version,
+                description: migration.description.to_string(),
+                state,
+            });
+        }
+        let mut unknown: Vec<i64> = applied.difference(&known).copied().collect();
+        unknown.sort_unstable();
+        migrations.extend(unknown.into_iter().map(|version| MigrationStatus {
+            version,
+            description: String::new(),
+            state: MigrationState::Unknown,
+        }));
+        Ok(migrations)
+    }
+
+    /// Brings the schema up to this binary, returns how many migrations ran.
+    /// A schema a newer binary migrated is left as it is, or refused with
+    /// `refuse_newer`.
+    pub async fn migrate_on_start(
+        &self,
+        refuse_newer: bool,
+        region: Region,
+    ) -> std::result::Result<usize, MigrateError> {
+        let migrations = self.migrations().await?;
+        let newer = migrations
+            .iter()
+            .find(|migration| migration.state == MigrationState::Unknown);
+        if let Some(newer) = newer {
+            if refuse_newer {
+                return Err(MigrateError::VersionMissing(newer.version));
+            }
+            println!(
+                "The database has migration {} this binary does not know, leaving it as it is",
+                newer.version
+            );
+            return Ok(0);
+        }
+        let pending = migrations
+            .iter()
+            .filter(|migration| migration.state == MigrationState::Pending)
+            .count();
+        self.migrate(region).await?;
+        Ok(pending)
+    }
+
+    /// Reverts the migrations after `to`, the latest one only without it.
+    /// Returns the versions reverted, the latest first.
+    pub async fn revert(&self, to: Option<i64>) -> std::result::Result<Vec<i64>, MigrateError> {
+        let mut applied: Vec<i64> = self
+            .migrations()
+            .await?
+            .into_iter()
+            .filter(|migration| migration.state == MigrationState::Applied)
+            .map(|migration| migration.version)
+            .collect();
+        applied.reverse();
+        let target = match to {
+            Some(to) => to,
+            None => applied.get(1).copied().unwrap_or(0),
+        };
+        match &self.database {
+            Database::Sqlite(pool) => SQLITE_MIGRATIONS.undo(pool, target).await?,
+            Database::Postgres(pool) => POSTGRES_MIGRATIONS.undo(pool, target).await?,
+        }
+        applied.retain(|&version| version > target);
+        Ok(applied)
+    }
+
+    /// Everything kept, as JSON. Chat rooms hold their messages as in a
+    /// transcript, the outbox leaves out the bodies of the emails.
+    pub async fn export(&self) -> Result<serde_json::Value> {
+        let contacts = self
+            .contacts
+            .find_contacts(&ContactQuery::default(), None, None)
+            .await?;
+        let mut conversations = Vec::new();
+        for conversation in self.inbox.conversations().await? {
+            let messages = self.inbox.inbox_messages(conversation.id).await?;
+            conversations.push(json!({ "conversation": conversation, "messages": messages }));
+        }
+        let mut rooms = serde_json::Map::new();
+        for room in self.chat.chat_rooms().await? {
+            let messages = self.chat.load_transcript(&room, None, None).await?;
+            rooms.insert(room, json!(messages));
+        }
+        Ok(json!({
+            "exported_at": Utc::now(),
+            "likes": self.likes.count_likes().await?,
+            "visitors": self.analytics.count_visitors().await?,
+            "contacts": contacts,
+            "contact_changes": self.contacts.recent_changes(i64::MAX).await?,
+            "subscriptions": self.newsletter.subscriptions().await?,
+            "inbox": conversations,
+            "chat_rooms": rooms,
+            "quarantine": self.quarantine.quarantined(i64::MAX).await?,
+            "email_outbox": self.outbox.outbox().await?,
+        }))
+    }
+}
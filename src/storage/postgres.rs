@@ -0,0 +1,961 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use sqlx::{postgres::PgPool, query, query_as, query_scalar, Postgres, Transaction};
+use uuid::Uuid;
+
+use super::{
+    AnalyticsRepository, ChatRepository, ContactRepository, InboxRepository, LikeRepository,
+    NewsletterRepository, OutboxRepository, QuarantineRepository, Result,
+};
+use crate::{
+    email::{Email, OutboxEntry, PendingEmail},
+    models::{
+        chat::{
+            AttachmentRecord, Conversation, HistoryOp, InboxMessage, PendingMention, TranscriptLine,
+        },
+        contacts::{contacts_sql, ContactChange, ContactQuery, Contacts, Cursor, NewContact},
+        import::{mark_duplicates, ImportRow, ImportSummary, RowStatus},
+        newsletter::{Subscriber, Subscription},
+        quarantine::Quarantined,
+    },
+};
+
+const CONTACT_COLUMNS: &str = "id, first_name, last_name, email, phone, message, notes, created_at";
+
+/// The repositories on Postgres. The query macros only know the SQLite
+/// schema, these queries are checked when they run.
+pub struct PostgresStorage {
+    pool: PgPool,
+}
+
+impl PostgresStorage {
+    pub fn new(pool: PgPool) -> Self {
+        PostgresStorage { pool }
+    }
+}
+
+#[async_trait]
+impl LikeRepository for PostgresStorage {
+    async fn add_like(&self) -> Result<Uuid> {
+        let id = Uuid::new_v4();
+        query("INSERT INTO likes (id, counter) SELECT $1, COUNT(*) + 1 FROM likes")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(id)
+    }
+
+    async fn remove_like(&self, id: Uuid) -> Result<()> {
+        query("DELETE FROM likes WHERE id = $1")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn count_likes(&self) -> Result<i64> {
+        query_scalar("SELECT COUNT(*) FROM likes")
+            .fetch_one(&self.pool)
+            .await
+    }
+}
+
+#[async_trait]
+impl AnalyticsRepository for PostgresStorage {
+    async fn record_visitor(&self, id: Uuid) -> Result<()> {
+        query("INSERT INTO visitors (id, first_seen) VALUES ($1, $2) ON CONFLICT DO NOTHING")
+            .bind(id)
+            .bind(Utc::now())
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn count_visitors(&self) -> Result<i64> {
+        query_scalar("SELECT COUNT(*) FROM visitors")
+            .fetch_one(&self.pool)
+            .await
+    }
+}
+
+async fn record_change(
+    tx: &mut Transaction<'_, Postgres>,
+    changed_by: &str,
+    before: &Contacts,
+    after: Option<&Contacts>,
+) -> Result<()> {
+    let action = if after.is_some() { "update" } else { "delete" };
+    query(
+        "INSERT INTO contact_changes (id, contact_id, action, changed_by, changed_at, before, after)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)",
+    )
+    .bind(Uuid::new_v4())
+    .bind(before.id)
+    .bind(action)
+    .bind(changed_by)
+    .bind(Utc::now())
+    .bind(serde_json::to_string(before).unwrap_or_default())
+    .bind(after.map(|after| serde_json::to_string(after).unwrap_or_default()))
+    .execute(&mut **tx)
+    .await?;
+    Ok(())
+}
+
+#[async_trait]
+impl ContactRepository for PostgresStorage {
+    async fn save_contact(&self, contact: &NewContact, since: DateTime<Utc>) -> Result<bool> {
+        let mut tx = self.pool.begin().await?;
+        // two saves of one email wait for each other, so they cannot both miss
+        // the other; saves of other emails and admin changes go on
+        query("SELECT pg_advisory_xact_lock(hashtext(lower($1)))")
+            .bind(&contact.email)
+            .execute(&mut *tx)
+            .await?;
+        let saved = query(
+            "INSERT INTO contacts (id, first_name, last_name, email, phone, message, created_at)
+            SELECT $1, $2, $3, $4, $5, $6, $7
+            WHERE NOT EXISTS (SELECT 1 FROM contacts WHERE email = $4 AND created_at > $8)",
+        )
+        .bind(Uuid::new_v4())
+        .bind(&contact.first_name)
+        .bind(&contact.last_name)
+        .bind(&contact.email)
+        .bind(&contact.phone)
+        .bind(&contact.message)
+        .bind(Utc::now())
+        .bind(since)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok(saved.rows_affected() == 1)
+    }
+
+    async fn find_contacts(
+        &self,
+        query: &ContactQuery,
+        after: Option<&Cursor>,
+        limit: Option<usize>,
+    ) -> Result<Vec<Contacts>> {
+        let Some(mut sql) = contacts_sql::<Postgres>(query, after, limit, "ILIKE") else {
+            return Ok(Vec::new());
+        };
+        sql.build_query_as::<Contacts>().fetch_all(&self.pool).await
+    }
+
+    async fn find_contact(&self, id: Uuid) -> Result<Option<Contacts>> {
+        query_as::<_, Contacts>(&format!(
+            "SELECT {CONTACT_COLUMNS} FROM contacts WHERE id = $1"
+        ))
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    async fn update_contact(
+        &self,
+        id: Uuid,
+        edit: &NewContact,
+        changed_by: &str,
+    ) -> Result<Option<Contacts>> {
+        let mut tx = self.pool.begin().await?;
+        let before = query_as::<_, Contacts>(&format!(
+            "SELECT {CONTACT_COLUMNS} FROM contacts WHERE id = $1 FOR UPDATE"
+        ))
+        .bind(id)
+        .fetch_optional(&mut *tx)
+        .await?;
+        let Some(before) = before else {
+            return Ok(None);
+        };
+        let after = Contacts {
+            first_name: edit.first_name.clone(),
+            last_name: edit.last_name.clone(),
+            email: edit.email.clone(),
+            phone: edit.phone.clone(),
+            ..before.clone()
+        };
+        query(
+            "UPDATE contacts SET first_name = $2, last_name = $3, email = $4, phone = $5
+            WHERE id = $1",
+        )
+        .bind(id)
+        .bind(&after.first_name)
+        .bind(&after.last_name)
+        .bind(&after.email)
+        .bind(&after.phone)
+        .execute(&mut *tx)
+        .await?;
+        record_change(&mut tx, changed_by, &before, Some(&after)).await?;
+        tx.commit().await?;
+        Ok(Some(after))
+    }
+
+    async fn delete_contact(&self, id: Uuid, changed_by: &str) -> Result<Option<Contacts>> {
+        let mut tx = self.pool.begin().await?;
+        let before = query_as::<_, Contacts>(&format!(
+            "DELETE FROM contacts WHERE id = $1 RETURNING {CONTACT_COLUMNS}"
+        ))
+        .bind(id)
+        .fetch_optional(&mut *tx)
+        .await?;
+        let Some(before) = before else {
+            return Ok(None);
+        };
+        record_change(&mut tx, changed_by, &before, None).await?;
+        tx.commit().await?;
+        Ok(Some(before))
+    }
+
+    async fn recent_changes(&self, limit: i64) -> Result<Vec<ContactChange>> {
+        query_as(
+            "SELECT contact_id, action, changed_by, changed_at, before, after
+            FROM contact_changes ORDER BY changed_at DESC LIMIT $1",
+        )
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    async fn contact_emails(&self) -> Result<Vec<String>> {
+        query_scalar("SELECT email FROM contacts")
+            .fetch_all(&self.pool)
+            .await
+    }
+
+    async fn import_contacts(&self, mut rows: Vec<ImportRow>) -> Result<ImportSummary> {
+        let mut tx = self.pool.begin().await?;
+        // an import at a time, so two cannot both take the same new email
+        query("LOCK TABLE contacts IN SHARE ROW EXCLUSIVE MODE")
+            .execute(&mut *tx)
+            .await?;
+        let taken = query_scalar("SELECT email FROM contacts")
+            .fetch_all(&mut *tx)
+            .await?;
+        mark_duplicates(taken, &mut rows);
+        let mut summary = ImportSummary::default();
+        for row in rows {
+            let Some(contact) = row.contact.filter(|_| row.status == RowStatus::New) else {
+                summary.duplicates += 1;
+                continue;
+            };
+            query(
+                "INSERT INTO contacts (id, first_name, last_name, email, phone, message, created_at)
+                VALUES ($1, $2, $3, $4, $5, $6, $7)",
+            )
+            .bind(Uuid::new_v4())
+            .bind(contact.first_name)
+            .bind(contact.last_name)
+            .bind(contact.email)
+            .bind(contact.phone)
+            .bind(contact.message)
+            .bind(contact.created_at)
+            .execute(&mut *tx)
+            .await?;
+            summary.imported += 1;
+        }
+        tx.commit().await?;
+        Ok(summary)
+    }
+
+    async fn legacy_phones(&self) -> Result<Vec<(Uuid, i64)>> {
+        query_as("SELECT id, legacy_phone FROM contacts WHERE legacy_phone IS NOT NULL")
+            .fetch_all(&self.pool)
+            .await
+    }
+
+    async fn set_phone(&self, id: Uuid, phone: &str) -> Result<()> {
+        query("UPDATE contacts SET phone = $2, legacy_phone = NULL WHERE id = $1")
+            .bind(id)
+            .bind(phone)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn move_legacy_phone_to_notes(&self, id: Uuid, note: &str) -> Result<()> {
+        query(
+            "UPDATE contacts SET legacy_phone = NULL,
+            notes = CASE WHEN notes IS NULL THEN $2 ELSE notes || chr(10) || $2 END
+            WHERE id = $1",
+        )
+        .bind(id)
+        .bind(note)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl ChatRepository for PostgresStorage {
+    async fn write_history(&self, op: &HistoryOp) -> Result<()> {
+        let pool = &self.pool;
+        match op {
+            HistoryOp::Sent {
+                id,
+                room,
+                session,
+                author,
+                text,
+                sent_at,
+                attachment,
+                private,
+            } => {
+                query(
+                    "INSERT INTO chat_messages (id, room, session, author, body, sent_at, private)
+                    VALUES ($1, $2, $3, $4, $5, $6, $7)",
+                )
+                .bind(id)
+                .bind(room)
+                .bind(session)
+                .bind(author)
+                .bind(text)
+                .bind(sent_at)
+                .bind(private)
+                .execute(pool)
+                .await?;
+                if let Some(attachment) = attachment {
+                    query("UPDATE chat_attachments SET message_id = $1 WHERE id = $2")
+                        .bind(id)
+                        .bind(attachment)
+                        .execute(pool)
+                        .await?;
+                }
+            }
+            HistoryOp::Edited {
+                id,
+                text,
+                edited_at,
+            } => {
+                query(
+                    "UPDATE chat_messages SET body = $2, edited_at = $3
+                    WHERE id = $1 AND deleted_at IS NULL",
+                )
+                .bind(id)
+                .bind(text)
+                .bind(edited_at)
+                .execute(pool)
+                .await?;
+            }
+            HistoryOp::Deleted { id, deleted_at } => {
+                query("UPDATE chat_messages SET body = '', deleted_at = $2 WHERE id = $1")
+                    .bind(id)
+                    .bind(deleted_at)
+                    .execute(pool)
+                    .await?;
+                query("DELETE FROM chat_reactions WHERE message_id = $1")
+                    .bind(id)
+                    .execute(pool)
+                    .await?;
+            }
+            HistoryOp::Reacted {
+                id,
+                session,
+                emoji,
+                active: true,
+            } => {
+                query(
+                    "INSERT INTO chat_reactions (message_id, session, emoji) VALUES ($1, $2, $3)
+                    ON CONFLICT DO NOTHING",
+                )
+                .bind(id)
+                .bind(session)
+                .bind(emoji)
+                .execute(pool)
+                .await?;
+            }
+            HistoryOp::Reacted {
+                id,
+                session,
+                emoji,
+                active: false,
+            } => {
+                query(
+                    "DELETE FROM chat_reactions WHERE message_id = $1 AND session = $2 AND emoji = $3",
+                )
+                .bind(id)
+                .bind(session)
+                .bind(emoji)
+                .execute(pool)
+                .await?;
+            }
+            HistoryOp::Mentioned { id, name } => {
+                query(
+                    "INSERT INTO chat_mentions (message_id, name)
+                    SELECT $1, $2 WHERE EXISTS
+                        (SELECT 1 FROM chat_messages WHERE author = $2 AND id != $1)
+                    ON CONFLICT DO NOTHING",
+                )
+                .bind(id)
+                .bind(name)
+                .execute(pool)
+                .await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn take_mentions(&self, name: &str) -> Result<Vec<PendingMention>> {
+        let mut tx = self.pool.begin().await?;
+        let mentions = query_as(
+            r#"SELECT m.id, m.room, m.author AS "from", m.body AS text, m.sent_at
+            FROM chat_mentions n JOIN chat_messages m ON m.id = n.message_id
+            WHERE n.name = $1 AND m.deleted_at IS NULL
+            ORDER BY m.sent_at"#,
+        )
+        .bind(name)
+        .fetch_all(&mut *tx)
+        .await?;
+        query("DELETE FROM chat_mentions WHERE name = $1")
+            .bind(name)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok(mentions)
+    }
+
+    async fn delete_messages_before(&self, before: DateTime<Utc>) -> Result<()> {
+        query("DELETE FROM chat_messages WHERE sent_at < $1")
+            .bind(before)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn load_transcript(
+        &self,
+        room: &str,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+    ) -> Result<Vec<TranscriptLine>> {
+        query_as(
+            "SELECT author, body AS text, sent_at, edited_at
+            FROM chat_messages
+            WHERE room = $1 AND deleted_at IS NULL
+                AND ($2::timestamptz IS NULL OR sent_at >= $2)
+                AND ($3::timestamptz IS NULL OR sent_at < $3)
+            ORDER BY sent_at",
+        )
+        .bind(room)
+        .bind(from)
+        .bind(to)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    async fn is_private_room(&self, room: &str) -> Result<bool> {
+        query_scalar("SELECT EXISTS(SELECT 1 FROM chat_messages WHERE room = $1 AND private)")
+            .bind(room)
+            .fetch_one(&self.pool)
+            .await
+    }
+
+    async fn chat_rooms(&self) -> Result<Vec<String>> {
+        query_scalar("SELECT DISTINCT room FROM chat_messages ORDER BY room")
+            .fetch_all(&self.pool)
+            .await
+    }
+
+    async fn save_attachment(&self, attachment: &AttachmentRecord) -> Result<()> {
+        query(
+            "INSERT INTO chat_attachments (id, name, mime, size, has_thumbnail, uploaded_at)
+            VALUES ($1, $2, $3, $4, $5, $6)",
+        )
+        .bind(attachment.id)
+        .bind(&attachment.name)
+        .bind(&attachment.mime)
+        .bind(attachment.size)
+        .bind(attachment.has_thumbnail)
+        .bind(attachment.uploaded_at)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn find_attachment(&self, id: Uuid) -> Result<Option<AttachmentRecord>> {
+        query_as(
+            "SELECT id, name, mime, size, has_thumbnail, uploaded_at
+            FROM chat_attachments WHERE id = $1",
+        )
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    async fn attachments_of_message(&self, message: Uuid) -> Result<Vec<Uuid>> {
+        query_scalar("SELECT id FROM chat_attachments WHERE message_id = $1")
+            .bind(message)
+            .fetch_all(&self.pool)
+            .await
+    }
+
+    async fn delete_attachments_of_message(&self, message: Uuid) -> Result<()> {
+        query("DELETE FROM chat_attachments WHERE message_id = $1")
+            .bind(message)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn expired_attachments(
+        &self,
+        before: DateTime<Utc>,
+        unshared_before: DateTime<Utc>,
+    ) -> Result<Vec<Uuid>> {
+        query_scalar(
+            "SELECT a.id FROM chat_attachments a
+            LEFT JOIN chat_messages m ON m.id = a.message_id
+            WHERE m.sent_at < $1 OR (a.message_id IS NULL AND a.uploaded_at < $2)",
+        )
+        .bind(before)
+        .bind(unshared_before)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    async fn delete_attachment(&self, id: Uuid) -> Result<()> {
+        query("DELETE FROM chat_attachments WHERE id = $1")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+}
+
+async fn insert_inbox_message(
+    tx: &mut Transaction<'_, Postgres>,
+    conversation: Uuid,
+    from_owner: bool,
+    body: &str,
+    sent_at: DateTime<Utc>,
+) -> Result<()> {
+    query(
+        "INSERT INTO inbox_messages (id, conversation_id, from_owner, body, sent_at)
+        VALUES ($1, $2, $3, $4, $5)",
+    )
+    .bind(Uuid::new_v4())
+    .bind(conversation)
+    .bind(from_owner)
+    .bind(body)
+    .bind(sent_at)
+    .execute(&mut **tx)
+    .await?;
+    Ok(())
+}
+
+#[async_trait]
+impl InboxRepository for PostgresStorage {
+    async fn find_conversation(
+        &self,
+        visitor: Option<Uuid>,
+        session: Uuid,
+    ) -> Result<Option<Uuid>> {
+        // without a visitor the comparison is NULL, which Postgres sorts first
+        query_scalar(
+            "SELECT id FROM inbox_conversations
+            WHERE visitor = $1 OR session = $2
+            ORDER BY (visitor = $1) IS TRUE DESC LIMIT 1",
+        )
+        .bind(visitor)
+        .bind(session)
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    async fn store_visitor_message(
+        &self,
+        visitor: Option<Uuid>,
+        session: Uuid,
+        name: &str,
+        message: &str,
+    ) -> Result<Uuid> {
+        let now = Utc::now();
+        let existing = self.find_conversation(visitor, session).await?;
+        let mut tx = self.pool.begin().await?;
+        let conversation = match existing {
+            Some(id) => {
+                // replies go to where the visitor wrote from last
+                query(
+                    "UPDATE inbox_conversations SET session = $2, name = $3, updated_at = $4
+                    WHERE id = $1",
+                )
+                .bind(id)
+                .bind(session)
+                .bind(name)
+                .bind(now)
+                .execute(&mut *tx)
+                .await?;
+                id
+            }
+            None => {
+                let id = Uuid::new_v4();
+                query(
+                    "INSERT INTO inbox_conversations (id, visitor, session, name, started_at, updated_at)
+                    VALUES ($1, $2, $3, $4, $5, $5)",
+                )
+                .bind(id)
+                .bind(visitor)
+                .bind(session)
+                .bind(name)
+                .bind(now)
+                .execute(&mut *tx)
+                .await?;
+                id
+            }
+        };
+        insert_inbox_message(&mut tx, conversation, false, message, now).await?;
+        tx.commit().await?;
+        Ok(conversation)
+    }
+
+    async fn inbox_messages(&self, conversation: Uuid) -> Result<Vec<InboxMessage>> {
+        query_as(
+            "SELECT from_owner, body, sent_at
+            FROM inbox_messages WHERE conversation_id = $1 ORDER BY sent_at",
+        )
+        .bind(conversation)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    async fn conversations(&self) -> Result<Vec<Conversation>> {
+        query_as(
+            "SELECT c.id, c.name, c.updated_at,
+                (SELECT body FROM inbox_messages WHERE conversation_id = c.id
+                    ORDER BY sent_at DESC LIMIT 1) AS last
+            FROM inbox_conversations c ORDER BY c.updated_at DESC",
+        )
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    async fn conversation_name(&self, id: Uuid) -> Result<Option<String>> {
+        query_scalar("SELECT name FROM inbox_conversations WHERE id = $1")
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await
+    }
+
+    async fn store_reply(
+        &self,
+        conversation: Uuid,
+        message: &str,
+        sent_at: DateTime<Utc>,
+    ) -> Result<Option<Uuid>> {
+        let mut tx = self.pool.begin().await?;
+        let session = query_scalar(
+            "UPDATE inbox_conversations SET updated_at = $2 WHERE id = $1 RETURNING session",
+        )
+        .bind(conversation)
+        .bind(sent_at)
+        .fetch_optional(&mut *tx)
+        .await?;
+        let Some(session) = session else {
+            return Ok(None);
+        };
+        insert_inbox_message(&mut tx, conversation, true, message, sent_at).await?;
+        tx.commit().await?;
+        Ok(Some(session))
+    }
+}
+
+#[async_trait]
+impl QuarantineRepository for PostgresStorage {
+    async fn quarantine(
+        &self,
+        form: &str,
+        ip: &str,
+        payload: &str,
+        score: i64,
+        reasons: &str,
+    ) -> Result<()> {
+        query(
+            "INSERT INTO quarantine (id, form, ip, payload, score, reasons, received_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)",
+        )
+        .bind(Uuid::new_v4())
+        .bind(form)
+        .bind(ip)
+        .bind(payload)
+        .bind(score)
+        .bind(reasons)
+        .bind(Utc::now())
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn quarantined(&self, limit: i64) -> Result<Vec<Quarantined>> {
+        query_as(
+            "SELECT id, form, ip, payload, score, reasons, received_at
+            FROM quarantine ORDER BY received_at DESC LIMIT $1",
+        )
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    async fn find_quarantined(&self, id: Uuid) -> Result<Option<Quarantined>> {
+        query_as(
+            "SELECT id, form, ip, payload, score, reasons, received_at
+            FROM quarantine WHERE id = $1",
+        )
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    async fn delete_quarantined(&self, id: Uuid) -> Result<bool> {
+        let deleted = query("DELETE FROM quarantine WHERE id = $1")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(deleted.rows_affected() > 0)
+    }
+}
+
+async fn queue_email<'c, E>(executor: E, email: &Email) -> Result<Uuid>
+where
+    E: sqlx::Executor<'c, Database = Postgres>,
+{
+    let id = Uuid::new_v4();
+    query(
+        "INSERT INTO email_outbox
+            (id, recipient, subject, body, unsubscribe_url, next_attempt_at, created_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $6)",
+    )
+    .bind(id)
+    .bind(&email.to)
+    .bind(&email.subject)
+    .bind(&email.body)
+    .bind(&email.unsubscribe_url)
+    .bind(Utc::now())
+    .execute(executor)
+    .await?;
+    Ok(id)
+}
+
+#[async_trait]
+impl OutboxRepository for PostgresStorage {
+    async fn queue_email(&self, email: &Email) -> Result<Uuid> {
+        queue_email(&self.pool, email).await
+    }
+
+    async fn claim_due_emails(
+        &self,
+        now: DateTime<Utc>,
+        lease: DateTime<Utc>,
+        limit: i64,
+    ) -> Result<Vec<PendingEmail>> {
+        // emails another worker is claiming are skipped rather than waited for
+        query_as(
+            "UPDATE email_outbox SET next_attempt_at = $1
+            WHERE id IN (
+                SELECT id FROM email_outbox
+                WHERE sent_at IS NULL AND failed_at IS NULL AND next_attempt_at <= $2
+                ORDER BY next_attempt_at LIMIT $3
+                FOR UPDATE SKIP LOCKED
+            )
+            RETURNING id, recipient, subject, body, unsubscribe_url, attempts",
+        )
+        .bind(lease)
+        .bind(now)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    async fn email_sent(&self, id: Uuid, attempts: i64, sent_at: DateTime<Utc>) -> Result<()> {
+        query(
+            "UPDATE email_outbox SET sent_at = $2, attempts = $3, last_error = NULL
+            WHERE id = $1",
+        )
+        .bind(id)
+        .bind(sent_at)
+        .bind(attempts)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn email_failed(
+        &self,
+        id: Uuid,
+        attempts: i64,
+        failed_at: DateTime<Utc>,
+        error: &str,
+    ) -> Result<()> {
+        query(
+            "UPDATE email_outbox SET failed_at = $2, attempts = $3, last_error = $4
+            WHERE id = $1",
+        )
+        .bind(id)
+        .bind(failed_at)
+        .bind(attempts)
+        .bind(error)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn retry_email(
+        &self,
+        id: Uuid,
+        attempts: i64,
+        next_attempt_at: DateTime<Utc>,
+        error: &str,
+    ) -> Result<()> {
+        query(
+            "UPDATE email_outbox SET next_attempt_at = $2, attempts = $3, last_error = $4
+            WHERE id = $1",
+        )
+        .bind(id)
+        .bind(next_attempt_at)
+        .bind(attempts)
+        .bind(error)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn outbox(&self) -> Result<Vec<OutboxEntry>> {
+        query_as(
+            "SELECT id, recipient, subject, attempts, last_error, created_at, sent_at, failed_at
+            FROM email_outbox ORDER BY created_at",
+        )
+        .fetch_all(&self.pool)
+        .await
+    }
+}
+
+#[async_trait]
+impl NewsletterRepository for PostgresStorage {
+    async fn add_pending_subscription(
+        &self,
+        email: &str,
+        token: &str,
+        unsubscribe_token: &str,
+    ) -> Result<bool> {
+        let now = Utc::now();
+        let mut tx = self.pool.begin().await?;
+        let existing: Option<(Uuid, String)> =
+            query_as("SELECT id, status FROM subscriptions WHERE email = $1 FOR UPDATE")
+                .bind(email)
+                .fetch_optional(&mut *tx)
+                .await?;
+        let id = match existing {
+            Some((_, status)) if status == "confirmed" => return Ok(false),
+            Some((id, _)) => {
+                // unsubscribed addresses start over
+                query(
+                    "UPDATE subscriptions SET status = 'pending', unsubscribed_at = NULL WHERE id = $1",
+                )
+                .bind(id)
+                .execute(&mut *tx)
+                .await?;
+                id
+            }
+            None => {
+                let id = Uuid::new_v4();
+                query(
+                    "INSERT INTO subscriptions (id, email, status, unsubscribe_token, created_at)
+                    VALUES ($1, $2, 'pending', $3, $4)",
+                )
+                .bind(id)
+                .bind(email)
+                .bind(unsubscribe_token)
+                .bind(now)
+                .execute(&mut *tx)
+                .await?;
+                id
+            }
+        };
+        query(
+            "INSERT INTO subscription_tokens (token, subscription_id, created_at) VALUES ($1, $2, $3)",
+        )
+        .bind(token)
+        .bind(id)
+        .bind(now)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    async fn confirm_subscription(&self, token: &str, since: DateTime<Utc>) -> Result<bool> {
+        let mut tx = self.pool.begin().await?;
+        let used: Option<(Uuid, DateTime<Utc>)> = query_as(
+            "DELETE FROM subscription_tokens WHERE token = $1
+            RETURNING subscription_id, created_at",
+        )
+        .bind(token)
+        .fetch_optional(&mut *tx)
+        .await?;
+        let Some((subscription, _)) = used.filter(|(_, created_at)| *created_at > since) else {
+            tx.commit().await?;
+            return Ok(false);
+        };
+        query(
+            "UPDATE subscriptions SET status = 'confirmed', confirmed_at = $2
+            WHERE id = $1 AND status = 'pending'",
+        )
+        .bind(subscription)
+        .bind(Utc::now())
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    async fn unsubscribe(&self, unsubscribe_token: &str) -> Result<bool> {
+        let found = query(
+            "UPDATE subscriptions SET status = 'unsubscribed', unsubscribed_at = $2
+            WHERE unsubscribe_token = $1",
+        )
+        .bind(unsubscribe_token)
+        .bind(Utc::now())
+        .execute(&self.pool)
+        .await?;
+        Ok(found.rows_affected() > 0)
+    }
+
+    async fn confirmed_subscribers(&self) -> Result<Vec<Subscriber>> {
+        query_as(
+            "SELECT email, unsubscribe_token FROM subscriptions WHERE status = 'confirmed'
+            ORDER BY confirmed_at",
+        )
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    async fn record_issue(&self, slug: &str, title: &str, emails: &[Email]) -> Result<bool> {
+        let mut tx = self.pool.begin().await?;
+        let sent = query(
+            "INSERT INTO newsletter_issues (slug, title, recipients, sent_at) VALUES ($1, $2, $3, $4)
+            ON CONFLICT (slug) DO NOTHING",
+        )
+        .bind(slug)
+        .bind(title)
+        .bind(emails.len() as i64)
+        .bind(Utc::now())
+        .execute(&mut *tx)
+        .await?;
+        if sent.rows_affected() == 0 {
+            return Ok(false);
+        }
+        for email in emails {
+            queue_email(&mut *tx, email).await?;
+        }
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    async fn subscriptions(&self) -> Result<Vec<Subscription>> {
+        query_as(
+            "SELECT email, status, created_at, confirmed_at, unsubscribed_at
+            FROM subscriptions ORDER BY created_at",
+        )
+        .fetch_all(&self.pool)
+        .await
+    }
+}
@@ -0,0 +1,1002 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use sqlx::{query, query_as, query_scalar, sqlite::SqlitePool, Sqlite, Transaction};
+use uuid::Uuid;
+
+use super::{
+    AnalyticsRepository, ChatRepository, ContactRepository, InboxRepository, LikeRepository,
+    NewsletterRepository, OutboxRepository, QuarantineRepository, Result,
+};
+use crate::{
+    email::{Email, OutboxEntry, PendingEmail},
+    models::{
+        chat::{
+            AttachmentRecord, Conversation, HistoryOp, InboxMessage, PendingMention, TranscriptLine,
+        },
+        contacts::{contacts_sql, ContactChange, ContactQuery, Contacts, Cursor, NewContact},
+        import::{mark_duplicates, ImportRow, ImportSummary, RowStatus},
+        newsletter::{Subscriber, Subscription},
+        quarantine::Quarantined,
+    },
+};
+
+/// The repositories on SQLite, the queries checked against the schema at build time
+pub struct SqliteStorage {
+    pool: SqlitePool,
+}
+
+impl SqliteStorage {
+    pub fn new(pool: SqlitePool) -> Self {
+        SqliteStorage { pool }
+    }
+}
+
+#[async_trait]
+impl LikeRepository for SqliteStorage {
+    async fn add_like(&self) -> Result<Uuid> {
+        let count = query!("SELECT COUNT(*) as count FROM likes")
+            .fetch_one(&self.pool)
+            .await?;
+        let counter = count.count + 1;
+        let id = Uuid::new_v4();
+        query!(
+            "INSERT INTO likes (id, counter) VALUES (?1, ?2)",
+            id,
+            counter
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(id)
+    }
+
+    async fn remove_like(&self, id: Uuid) -> Result<()> {
+        query!("DELETE FROM likes WHERE id = ?1", id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn count_likes(&self) -> Result<i64> {
+        query_scalar!(r#"SELECT COUNT(*) as "count!: i64" FROM likes"#)
+            .fetch_one(&self.pool)
+            .await
+    }
+}
+
+#[async_trait]
+impl AnalyticsRepository for SqliteStorage {
+    async fn record_visitor(&self, id: Uuid) -> Result<()> {
+        let now = Utc::now();
+        query!(
+            "INSERT OR IGNORE INTO visitors (id, first_seen) VALUES (?1, ?2)",
+            id,
+            now
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn count_visitors(&self) -> Result<i64> {
+        query_scalar!(r#"SELECT COUNT(*) as "count!: i64" FROM visitors"#)
+            .fetch_one(&self.pool)
+            .await
+    }
+}
+
+async fn find_contact<'c>(
+    db: impl sqlx::Executor<'c, Database = Sqlite>,
+    id: Uuid,
+) -> Result<Option<Contacts>> {
+    sqlx::query_as::<_, Contacts>(
+        "SELECT id, first_name, last_name, email, phone, message, notes, created_at
+        FROM contacts WHERE id = ?1",
+    )
+    .bind(id)
+    .fetch_optional(db)
+    .await
+}
+
+async fn record_change(
+    tx: &mut Transaction<'_, Sqlite>,
+    changed_by: &str,
+    before: &Contacts,
+    after: Option<&Contacts>,
+) -> Result<()> {
+    let id = Uuid::new_v4();
+    let contact_id = before.id;
+    let action = if after.is_some() { "update" } else { "delete" };
+    let now = Utc::now();
+    let before = serde_json::to_string(before).unwrap_or_default();
+    let after = after.map(|after| serde_json::to_string(after).unwrap_or_default());
+    query!(
+        "INSERT INTO contact_changes (id, contact_id, action, changed_by, changed_at, before, after)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        id,
+        contact_id,
+        action,
+        changed_by,
+        now,
+        before,
+        after
+    )
+    .execute(&mut **tx)
+    .await?;
+    Ok(())
+}
+
+#[async_trait]
+impl ContactRepository for SqliteStorage {
+    async fn save_contact(&self, contact: &NewContact, since: DateTime<Utc>) -> Result<bool> {
+        let id = Uuid::new_v4();
+        let now = Utc::now();
+        // one statement, so a repeat sent meanwhile cannot slip in between the check and the insert
+        let saved = query!(
+            "INSERT INTO contacts (id, first_name, last_name, email, phone, message, created_at)
+            SELECT ?1, ?2, ?3, ?4, ?5, ?6, ?7
+            WHERE NOT EXISTS (SELECT 1 FROM contacts WHERE email = ?4 AND created_at > ?8)",
+            id,
+            contact.first_name,
+            contact.last_name,
+            contact.email,
+            contact.phone,
+            contact.message,
+            now,
+            since
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(saved.rows_affected() == 1)
+    }
+
+    async fn find_contacts(
+        &self,
+        query: &ContactQuery,
+        after: Option<&Cursor>,
+        limit: Option<usize>,
+    ) -> Result<Vec<Contacts>> {
+        // SQLite's LIKE ignores case already
+        let Some(mut sql) = contacts_sql::<Sqlite>(query, after, limit, "LIKE") else {
+            return Ok(Vec::new());
+        };
+        sql.build_query_as::<Contacts>().fetch_all(&self.pool).await
+    }
+
+    async fn find_contact(&self, id: Uuid) -> Result<Option<Contacts>> {
+        find_contact(&self.pool, id).await
+    }
+
+    async fn update_contact(
+        &self,
+        id: Uuid,
+        edit: &NewContact,
+        changed_by: &str,
+    ) -> Result<Option<Contacts>> {
+        let mut tx = self.pool.begin().await?;
+        let Some(before) = find_contact(&mut *tx, id).await? else {
+            return Ok(None);
+        };
+        let after = Contacts {
+            first_name: edit.first_name.clone(),
+            last_name: edit.last_name.clone(),
+            email: edit.email.clone(),
+            phone: edit.phone.clone(),
+            ..before.clone()
+        };
+        query!(
+            "UPDATE contacts SET first_name = ?2, last_name = ?3, email = ?4, phone = ?5
+            WHERE id = ?1",
+            id,
+            after.first_name,
+            after.last_name,
+            after.email,
+            after.phone
+        )
+        .execute(&mut *tx)
+        .await?;
+        record_change(&mut tx, changed_by, &before, Some(&after)).await?;
+        tx.commit().await?;
+        Ok(Some(after))
+    }
+
+    async fn delete_contact(&self, id: Uuid, changed_by: &str) -> Result<Option<Contacts>> {
+        let mut tx = self.pool.begin().await?;
+        let before = sqlx::query_as::<_, Contacts>(
+            "DELETE FROM contacts WHERE id = ?1
+            RETURNING id, first_name, last_name, email, phone, message, notes, created_at",
+        )
+        .bind(id)
+        .fetch_optional(&mut *tx)
+        .await?;
+        let Some(before) = before else {
+            return Ok(None);
+        };
+        record_change(&mut tx, changed_by, &before, None).await?;
+        tx.commit().await?;
+        Ok(Some(before))
+    }
+
+    async fn recent_changes(&self, limit: i64) -> Result<Vec<ContactChange>> {
+        query_as!(
+            ContactChange,
+            r#"SELECT contact_id as "contact_id!: Uuid", action, changed_by,
+                changed_at as "changed_at: DateTime<Utc>", before, after
+            FROM contact_changes ORDER BY changed_at DESC LIMIT ?1"#,
+            limit
+        )
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    async fn contact_emails(&self) -> Result<Vec<String>> {
+        query_scalar!("SELECT email FROM contacts")
+            .fetch_all(&self.pool)
+            .await
+    }
+
+    async fn import_contacts(&self, mut rows: Vec<ImportRow>) -> Result<ImportSummary> {
+        let mut tx = self.pool.begin().await?;
+        let taken = query_scalar!("SELECT email FROM contacts")
+            .fetch_all(&mut *tx)
+            .await?;
+        mark_duplicates(taken, &mut rows);
+        let mut summary = ImportSummary::default();
+        for row in rows {
+            let Some(contact) = row.contact.filter(|_| row.status == RowStatus::New) else {
+                summary.duplicates += 1;
+                continue;
+            };
+            let id = Uuid::new_v4();
+            query!(
+                "INSERT INTO contacts (id, first_name, last_name, email, phone, message, created_at)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                id,
+                contact.first_name,
+                contact.last_name,
+                contact.email,
+                contact.phone,
+                contact.message,
+                contact.created_at
+            )
+            .execute(&mut *tx)
+            .await?;
+            summary.imported += 1;
+        }
+        tx.commit().await?;
+        Ok(summary)
+    }
+
+    // `legacy_phone` only exists while migrating, these are not checked at build time
+    async fn legacy_phones(&self) -> Result<Vec<(Uuid, i64)>> {
+        query_as("SELECT id, legacy_phone FROM contacts WHERE legacy_phone IS NOT NULL")
+            .fetch_all(&self.pool)
+            .await
+    }
+
+    async fn set_phone(&self, id: Uuid, phone: &str) -> Result<()> {
+        query("UPDATE contacts SET phone = ?2, legacy_phone = NULL WHERE id = ?1")
+            .bind(id)
+            .bind(phone)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn move_legacy_phone_to_notes(&self, id: Uuid, note: &str) -> Result<()> {
+        query(
+            "UPDATE contacts SET legacy_phone = NULL,
+            notes = CASE WHEN notes IS NULL THEN ?2 ELSE notes || char(10) || ?2 END
+            WHERE id = ?1",
+        )
+        .bind(id)
+        .bind(note)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl ChatRepository for SqliteStorage {
+    async fn write_history(&self, op: &HistoryOp) -> Result<()> {
+        let pool = &self.pool;
+        match op {
+            HistoryOp::Sent {
+                id,
+                room,
+                session,
+                author,
+                text,
+                sent_at,
+                attachment,
+                private,
+            } => {
+                query!(
+                    "INSERT INTO chat_messages (id, room, session, author, body, sent_at, private)
+                    VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                    id,
+                    room,
+                    session,
+                    author,
+                    text,
+                    sent_at,
+                    private
+                )
+                .execute(pool)
+                .await?;
+                if let Some(attachment) = attachment {
+                    query!(
+                        "UPDATE chat_attachments SET message_id = ?1 WHERE id = ?2",
+                        id,
+                        attachment
+                    )
+                    .execute(pool)
+                    .await?;
+                }
+            }
+            HistoryOp::Edited {
+                id,
+                text,
+                edited_at,
+            } => {
+                query!(
+                    "UPDATE chat_messages SET body = ?2, edited_at = ?3
+                    WHERE id = ?1 AND deleted_at IS NULL",
+                    id,
+                    text,
+                    edited_at
+                )
+                .execute(pool)
+                .await?;
+            }
+            HistoryOp::Deleted { id, deleted_at } => {
+                query!(
+                    "UPDATE chat_messages SET body = '', deleted_at = ?2 WHERE id = ?1",
+                    id,
+                    deleted_at
+                )
+                .execute(pool)
+                .await?;
+                query!("DELETE FROM chat_reactions WHERE message_id = ?1", id)
+                    .execute(pool)
+                    .await?;
+            }
+            HistoryOp::Reacted {
+                id,
+                session,
+                emoji,
+                active: true,
+            } => {
+                query!(
+                    "INSERT OR IGNORE INTO chat_reactions (message_id, session, emoji)
+                    VALUES (?1, ?2, ?3)",
+                    id,
+                    session,
+                    emoji
+                )
+                .execute(pool)
+                .await?;
+            }
+            HistoryOp::Reacted {
+                id,
+                session,
+                emoji,
+                active: false,
+            } => {
+                query!(
+                    "DELETE FROM chat_reactions WHERE message_id = ?1 AND session = ?2 AND emoji = ?3",
+                    id,
+                    session,
+                    emoji
+                )
+                .execute(pool)
+                .await?;
+            }
+            HistoryOp::Mentioned { id, name } => {
+                query!(
+                    "INSERT OR IGNORE INTO chat_mentions (message_id, name)
+                    SELECT ?1, ?2 WHERE EXISTS
+                        (SELECT 1 FROM chat_messages WHERE author = ?2 AND id != ?1)",
+                    id,
+                    name
+                )
+                .execute(pool)
+                .await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn take_mentions(&self, name: &str) -> Result<Vec<PendingMention>> {
+        let mut tx = self.pool.begin().await?;
+        // deleted messages are gone for good, edited ones are handed over as they read now
+        let mentions = query_as!(
+            PendingMention,
+            r#"SELECT m.id as "id!: Uuid", m.room, m.author as "from", m.body as text,
+                m.sent_at as "sent_at: DateTime<Utc>"
+            FROM chat_mentions n JOIN chat_messages m ON m.id = n.message_id
+            WHERE n.name = ?1 AND m.deleted_at IS NULL
+            ORDER BY m.sent_at"#,
+            name
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+        query!("DELETE FROM chat_mentions WHERE name = ?1", name)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok(mentions)
+    }
+
+    async fn delete_messages_before(&self, before: DateTime<Utc>) -> Result<()> {
+        query!("DELETE FROM chat_messages WHERE sent_at < ?1", before)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn load_transcript(
+        &self,
+        room: &str,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+    ) -> Result<Vec<TranscriptLine>> {
+        query_as!(
+            TranscriptLine,
+            r#"SELECT author, body as text, sent_at as "sent_at: DateTime<Utc>",
+                edited_at as "edited_at: DateTime<Utc>"
+            FROM chat_messages
+            WHERE room = ?1 AND deleted_at IS NULL
+                AND (?2 IS NULL OR sent_at >= ?2) AND (?3 IS NULL OR sent_at < ?3)
+            ORDER BY sent_at"#,
+            room,
+            from,
+            to
+        )
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    async fn is_private_room(&self, room: &str) -> Result<bool> {
+        query_scalar!(
+            r#"SELECT EXISTS(SELECT 1 FROM chat_messages WHERE room = ?1 AND private) AS "private!: bool""#,
+            room
+        )
+        .fetch_one(&self.pool)
+        .await
+    }
+
+    async fn chat_rooms(&self) -> Result<Vec<String>> {
+        query_scalar!("SELECT DISTINCT room FROM chat_messages ORDER BY room")
+            .fetch_all(&self.pool)
+            .await
+    }
+
+    async fn save_attachment(&self, attachment: &AttachmentRecord) -> Result<()> {
+        query!(
+            "INSERT INTO chat_attachments (id, name, mime, size, has_thumbnail, uploaded_at)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            attachment.id,
+            attachment.name,
+            attachment.mime,
+            attachment.size,
+            attachment.has_thumbnail,
+            attachment.uploaded_at
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn find_attachment(&self, id: Uuid) -> Result<Option<AttachmentRecord>> {
+        query_as!(
+            AttachmentRecord,
+            r#"SELECT id as "id!: Uuid", name, mime, size, has_thumbnail,
+                uploaded_at as "uploaded_at: DateTime<Utc>"
+            FROM chat_attachments WHERE id = ?1"#,
+            id
+        )
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    async fn attachments_of_message(&self, message: Uuid) -> Result<Vec<Uuid>> {
+        query_scalar!(
+            r#"SELECT id as "id!: Uuid" FROM chat_attachments WHERE message_id = ?1"#,
+            message
+        )
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    async fn delete_attachments_of_message(&self, message: Uuid) -> Result<()> {
+        query!(
+            "DELETE FROM chat_attachments WHERE message_id = ?1",
+            message
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn expired_attachments(
+        &self,
+        before: DateTime<Utc>,
+        unshared_before: DateTime<Utc>,
+    ) -> Result<Vec<Uuid>> {
+        query_scalar!(
+            r#"SELECT a.id as "id!: Uuid" FROM chat_attachments a
+            LEFT JOIN chat_messages m ON m.id = a.message_id
+            WHERE m.sent_at < ?1 OR (a.message_id IS NULL AND a.uploaded_at < ?2)"#,
+            before,
+            unshared_before
+        )
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    async fn delete_attachment(&self, id: Uuid) -> Result<()> {
+        query!("DELETE FROM chat_attachments WHERE id = ?1", id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+}
+
+async fn insert_inbox_message(
+    tx: &mut Transaction<'_, Sqlite>,
+    conversation: Uuid,
+    from_owner: bool,
+    body: &str,
+    sent_at: DateTime<Utc>,
+) -> Result<()> {
+    let id = Uuid::new_v4();
+    query!(
+        "INSERT INTO inbox_messages (id, conversation_id, from_owner, body, sent_at)
+        VALUES (?1, ?2, ?3, ?4, ?5)",
+        id,
+        conversation,
+        from_owner,
+        body,
+        sent_at
+    )
+    .execute(&mut **tx)
+    .await?;
+    Ok(())
+}
+
+#[async_trait]
+impl InboxRepository for SqliteStorage {
+    async fn find_conversation(
+        &self,
+        visitor: Option<Uuid>,
+        session: Uuid,
+    ) -> Result<Option<Uuid>> {
+        query_scalar!(
+            r#"SELECT id as "id!: Uuid" FROM inbox_conversations
+            WHERE visitor = ?1 OR session = ?2
+            ORDER BY visitor = ?1 DESC LIMIT 1"#,
+            visitor,
+            session
+        )
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    async fn store_visitor_message(
+        &self,
+        visitor: Option<Uuid>,
+        session: Uuid,
+        name: &str,
+        message: &str,
+    ) -> Result<Uuid> {
+        let now = Utc::now();
+        let existing = self.find_conversation(visitor, session).await?;
+        let mut tx = self.pool.begin().await?;
+        let conversation = match existing {
+            Some(id) => {
+                // replies go to where the visitor wrote from last
+                query!(
+                    "UPDATE inbox_conversations SET session = ?2, name = ?3, updated_at = ?4
+                    WHERE id = ?1",
+                    id,
+                    session,
+                    name,
+                    now
+                )
+                .execute(&mut *tx)
+                .await?;
+                id
+            }
+            None => {
+                let id = Uuid::new_v4();
+                query!(
+                    "INSERT INTO inbox_conversations (id, visitor, session, name, started_at, updated_at)
+                    VALUES (?1, ?2, ?3, ?4, ?5, ?5)",
+                    id,
+                    visitor,
+                    session,
+                    name,
+                    now
+                )
+                .execute(&mut *tx)
+                .await?;
+                id
+            }
+        };
+        insert_inbox_message(&mut tx, conversation, false, message, now).await?;
+        tx.commit().await?;
+        Ok(conversation)
+    }
+
+    async fn inbox_messages(&self, conversation: Uuid) -> Result<Vec<InboxMessage>> {
+        query_as!(
+            InboxMessage,
+            r#"SELECT from_owner as "from_owner!: bool", body, sent_at as "sent_at: DateTime<Utc>"
+            FROM inbox_messages WHERE conversation_id = ?1 ORDER BY sent_at"#,
+            conversation
+        )
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    async fn conversations(&self) -> Result<Vec<Conversation>> {
+        query_as!(
+            Conversation,
+            r#"SELECT c.id as "id!: Uuid", c.name, c.updated_at as "updated_at: DateTime<Utc>",
+                (SELECT body FROM inbox_messages WHERE conversation_id = c.id
+                    ORDER BY sent_at DESC LIMIT 1) as "last!: String"
+            FROM inbox_conversations c ORDER BY c.updated_at DESC"#
+        )
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    async fn conversation_name(&self, id: Uuid) -> Result<Option<String>> {
+        query_scalar!("SELECT name FROM inbox_conversations WHERE id = ?1", id)
+            .fetch_optional(&self.pool)
+            .await
+    }
+
+    async fn store_reply(
+        &self,
+        conversation: Uuid,
+        message: &str,
+        sent_at: DateTime<Utc>,
+    ) -> Result<Option<Uuid>> {
+        let mut tx = self.pool.begin().await?;
+        let session = query_scalar!(
+            r#"UPDATE inbox_conversations SET updated_at = ?2 WHERE id = ?1
+            RETURNING session as "session!: Uuid""#,
+            conversation,
+            sent_at
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+        let Some(session) = session else {
+            return Ok(None);
+        };
+        insert_inbox_message(&mut tx, conversation, true, message, sent_at).await?;
+        tx.commit().await?;
+        Ok(Some(session))
+    }
+}
+
+#[async_trait]
+impl QuarantineRepository for SqliteStorage {
+    async fn quarantine(
+        &self,
+        form: &str,
+        ip: &str,
+        payload: &str,
+        score: i64,
+        reasons: &str,
+    ) -> Result<()> {
+        let id = Uuid::new_v4();
+        let now = Utc::now();
+        query!(
+            "INSERT INTO quarantine (id, form, ip, payload, score, reasons, received_at)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            id,
+            form,
+            ip,
+            payload,
+            score,
+            reasons,
+            now
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn quarantined(&self, limit: i64) -> Result<Vec<Quarantined>> {
+        query_as!(
+            Quarantined,
+            r#"SELECT id as "id!: Uuid", form, ip, payload, score,
+                reasons, received_at as "received_at: DateTime<Utc>"
+            FROM quarantine ORDER BY received_at DESC LIMIT ?1"#,
+            limit
+        )
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    async fn find_quarantined(&self, id: Uuid) -> Result<Option<Quarantined>> {
+        query_as!(
+            Quarantined,
+            r#"SELECT id as "id!: Uuid", form, ip, payload, score,
+                reasons, received_at as "received_at: DateTime<Utc>"
+            FROM quarantine WHERE id = ?1"#,
+            id
+        )
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    async fn delete_quarantined(&self, id: Uuid) -> Result<bool> {
+        let deleted = query!("DELETE FROM quarantine WHERE id = ?1", id)
+            .execute(&self.pool)
+            .await?;
+        Ok(deleted.rows_affected() > 0)
+    }
+}
+
+async fn queue_email<'c, E>(executor: E, email: &Email) -> Result<Uuid>
+where
+    E: sqlx::Executor<'c, Database = Sqlite>,
+{
+    let id = Uuid::new_v4();
+    let now = Utc::now();
+    query!(
+        "INSERT INTO email_outbox
+            (id, recipient, subject, body, unsubscribe_url, next_attempt_at, created_at)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?6)",
+        id,
+        email.to,
+        email.subject,
+        email.body,
+        email.unsubscribe_url,
+        now
+    )
+    .execute(executor)
+    .await?;
+    Ok(id)
+}
+
+#[async_trait]
+impl OutboxRepository for SqliteStorage {
+    async fn queue_email(&self, email: &Email) -> Result<Uuid> {
+        queue_email(&self.pool, email).await
+    }
+
+    async fn claim_due_emails(
+        &self,
+        now: DateTime<Utc>,
+        lease: DateTime<Utc>,
+        limit: i64,
+    ) -> Result<Vec<PendingEmail>> {
+        query_as!(
+            PendingEmail,
+            r#"UPDATE email_outbox SET next_attempt_at = ?1
+            WHERE id IN (
+                SELECT id FROM email_outbox
+                WHERE sent_at IS NULL AND failed_at IS NULL AND next_attempt_at <= ?2
+                ORDER BY next_attempt_at LIMIT ?3
+            )
+            RETURNING id as "id!: Uuid", recipient, subject, body, unsubscribe_url, attempts"#,
+            lease,
+            now,
+            limit
+        )
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    async fn email_sent(&self, id: Uuid, attempts: i64, sent_at: DateTime<Utc>) -> Result<()> {
+        query!(
+            "UPDATE email_outbox SET sent_at = ?2, attempts = ?3, last_error = NULL
+            WHERE id = ?1",
+            id,
+            sent_at,
+            attempts
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn email_failed(
+        &self,
+        id: Uuid,
+        attempts: i64,
+        failed_at: DateTime<Utc>,
+        error: &str,
+    ) -> Result<()> {
+        query!(
+            "UPDATE email_outbox SET failed_at = ?2, attempts = ?3, last_error = ?4
+            WHERE id = ?1",
+            id,
+            failed_at,
+            attempts,
+            error
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn retry_email(
+        &self,
+        id: Uuid,
+        attempts: i64,
+        next_attempt_at: DateTime<Utc>,
+        error: &str,
+    ) -> Result<()> {
+        query!(
+            "UPDATE email_outbox SET next_attempt_at = ?2, attempts = ?3, last_error = ?4
+            WHERE id = ?1",
+            id,
+            next_attempt_at,
+            attempts,
+            error
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn outbox(&self) -> Result<Vec<OutboxEntry>> {
+        query_as!(
+            OutboxEntry,
+            r#"SELECT id as "id!: Uuid", recipient, subject, attempts, last_error,
+                created_at as "created_at: DateTime<Utc>", sent_at as "sent_at: DateTime<Utc>",
+                failed_at as "failed_at: DateTime<Utc>"
+            FROM email_outbox ORDER BY created_at"#
+        )
+        .fetch_all(&self.pool)
+        .await
+    }
+}
+
+#[async_trait]
+impl NewsletterRepository for SqliteStorage {
+    async fn add_pending_subscription(
+        &self,
+        email: &str,
+        token: &str,
+        unsubscribe_token: &str,
+    ) -> Result<bool> {
+        let now = Utc::now();
+        let mut tx = self.pool.begin().await?;
+        let existing = query!(
+            r#"SELECT id as "id!: Uuid", status FROM subscriptions WHERE email = ?1"#,
+            email
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+        let id = match existing {
+            Some(row) if row.status == "confirmed" => return Ok(false),
+            Some(row) => {
+                // unsubscribed addresses start over
+                query!(
+                    "UPDATE subscriptions SET status = 'pending', unsubscribed_at = NULL WHERE id = ?1",
+                    row.id
+                )
+                .execute(&mut *tx)
+                .await?;
+                row.id
+            }
+            None => {
+                let id = Uuid::new_v4();
+                query!(
+                    "INSERT INTO subscriptions (id, email, status, unsubscribe_token, created_at)
+                    VALUES (?1, ?2, 'pending', ?3, ?4)",
+                    id,
+                    email,
+                    unsubscribe_token,
+                    now
+                )
+                .execute(&mut *tx)
+                .await?;
+                id
+            }
+        };
+        query!(
+            "INSERT INTO subscription_tokens (token, subscription_id, created_at) VALUES (?1, ?2, ?3)",
+            token,
+            id,
+            now
+        )
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    async fn confirm_subscription(&self, token: &str, since: DateTime<Utc>) -> Result<bool> {
+        let mut tx = self.pool.begin().await?;
+        let used = query!(
+            r#"DELETE FROM subscription_tokens WHERE token = ?1
+            RETURNING subscription_id as "subscription_id!: Uuid",
+                created_at as "created_at!: DateTime<Utc>""#,
+            token
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+        let Some(used) = used.filter(|used| used.created_at > since) else {
+            tx.commit().await?;
+            return Ok(false);
+        };
+        let now = Utc::now();
+        query!(
+            "UPDATE subscriptions SET status = 'confirmed', confirmed_at = ?2
+            WHERE id = ?1 AND status = 'pending'",
+            used.subscription_id,
+            now
+        )
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    async fn unsubscribe(&self, unsubscribe_token: &str) -> Result<bool> {
+        let now = Utc::now();
+        let found = query!(
+            "UPDATE subscriptions SET status = 'unsubscribed', unsubscribed_at = ?2
+            WHERE unsubscribe_token = ?1",
+            unsubscribe_token,
+            now
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(found.rows_affected() > 0)
+    }
+
+    async fn confirmed_subscribers(&self) -> Result<Vec<Subscriber>> {
+        query_as!(
+            Subscriber,
+            "SELECT email, unsubscribe_token FROM subscriptions WHERE status = 'confirmed'
+            ORDER BY confirmed_at"
+        )
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    async fn record_issue(&self, slug: &str, title: &str, emails: &[Email]) -> Result<bool> {
+        let now = Utc::now();
+        let recipients = emails.len() as i64;
+        let mut tx = self.pool.begin().await?;
+        let sent = query!(
+            "INSERT INTO newsletter_issues (slug, title, recipients, sent_at) VALUES (?1, ?2, ?3, ?4)
+            ON CONFLICT (slug) DO NOTHING",
+            slug,
+            title,
+            recipients,
+            now
+        )
+        .execute(&mut *tx)
+        .await?;
+        if sent.rows_affected() == 0 {
+            return Ok(false);
+        }
+        for email in emails {
+            queue_email(&mut *tx, email).await?;
+        }
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    async fn subscriptions(&self) -> Result<Vec<Subscription>> {
+        query_as!(
+            Subscription,
+            r#"SELECT email, status, created_at as "created_at: DateTime<Utc>",
+                confirmed_at as "confirmed_at: DateTime<Utc>",
+                unsubscribed_at as "unsubscribed_at: DateTime<Utc>"
+            FROM subscriptions ORDER BY created_at"#
+        )
+        .fetch_all(&self.pool)
+        .await
+    }
+}
@@ -1,13 +1,147 @@
-use crate::helpers::spawn_app;
-#[tokio::test]
-async fn requests_missing_authorization_are_rejected() {
-    let app = spawn_app().await;
+use crate::helpers::{
+    form_token, site_client, spawn_admin_app, spawn_app, spawn_app_with, EventStream, TestApp,
+    ADMIN_PASSWORD,
+};
+use demcru::configuration::Backend;
+use serde_json::Value;
+use uuid::Uuid;
+
+// A visitor connected to the chat, with the resume token of their session
+async fn visitor(app: &TestApp) -> (EventStream, String) {
+    let mut events = EventStream::connect(app, "main").await;
+    let session = events.find(|event| event["type"] == "session").await;
+    let token = session["token"].as_str().unwrap().to_owned();
+    (events, token)
+}
+
+async fn requests_missing_authorization_are_rejected(backend: Backend) {
+    let app = spawn_app(backend).await;
     let response = reqwest::Client::new()
-        .post(&format!("{}/chat-with-me", &app.address))
+        .post(format!("{}/chat-with-me", &app.address))
         .json(&serde_json::json!({
             "message": "Hey whats app",
         }))
         .send()
         .await
         .expect("Filed to execute request");
+
+    assert_eq!(401, response.status().as_u16());
+}
+
+async fn the_inbox_is_for_the_owner_only(backend: Backend) {
+    let app = spawn_admin_app(backend).await;
+    let client = reqwest::Client::new();
+    for path in [
+        "/admin/inbox",
+        "/admin/inbox/00000000-0000-0000-0000-000000000000",
+    ] {
+        let response = client
+            .get(format!("{}{}", &app.address, path))
+            .send()
+            .await
+            .expect("Failed to execute request");
+        assert_eq!(401, response.status().as_u16(), "{path}");
+    }
+    let response = client
+        .get(format!("{}/admin/inbox", &app.address))
+        .basic_auth("admin", Some("wrong"))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(401, response.status().as_u16());
+}
+
+async fn the_owner_reads_and_answers_visitors_live(backend: Backend) {
+    let app = spawn_admin_app(backend).await;
+    let client = reqwest::Client::new();
+    let (mut events, token) = visitor(&app).await;
+    let form_token = form_token(&app, "/chat", "inbox: '").await;
+
+    let response = client
+        .post(format!("{}/chat-with-me", &app.address))
+        .bearer_auth(&token)
+        .json(&serde_json::json!({ "message": "Hey whats app", "form_token": form_token }))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(201, response.status().as_u16());
+    let body: Value = response.json().await.unwrap();
+    let conversation = body["conversation"].as_str().unwrap().to_owned();
+
+    let inbox = client
+        .get(format!("{}/admin/inbox", &app.address))
+        .basic_auth("admin", Some(ADMIN_PASSWORD))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(200, inbox.status().as_u16());
+    assert!(inbox.text().await.unwrap().contains("Hey whats app"));
+
+    // the reply form redirects back to the conversation
+    let response = site_client(&app)
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap()
+        .post(format!(
+            "{}/admin/inbox/{}/reply",
+            &app.address, conversation
+        ))
+        .basic_auth("admin", Some(ADMIN_PASSWORD))
+        .form(&[("message", "Not much")])
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(303, response.status().as_u16());
+
+    let reply = events.find(|event| event["type"] == "owner_reply").await;
+    assert_eq!(reply["text"], "Not much");
+
+    let mine: Value = client
+        .get(format!("{}/chat-with-me", &app.address))
+        .query(&[("session", &token)])
+        .send()
+        .await
+        .expect("Failed to execute request")
+        .json()
+        .await
+        .unwrap();
+    let messages = mine["messages"].as_array().unwrap();
+    assert_eq!(messages.len(), 2);
+    assert_eq!(messages[1]["from_owner"], true);
+}
+
+async fn uploads_over_the_limit_are_not_kept(backend: Backend) {
+    let dir = std::env::temp_dir().join(format!("demcru-uploads-{}", Uuid::new_v4()));
+    let path = dir.to_str().unwrap().to_owned();
+    let app = spawn_app_with(backend, |settings| settings.chat.attachments_path = path).await;
+    let (_events, token) = visitor(&app).await;
+    let client = reqwest::Client::new();
+
+    // more messages than a session may send at once
+    for _ in 0..12 {
+        let response = client
+            .post(format!("{}/chat/send", &app.address))
+            .json(&serde_json::json!({ "session": token, "text": "hi" }))
+            .send()
+            .await
+            .expect("Failed to execute request");
+        assert_eq!(202, response.status().as_u16());
+    }
+    let response = client
+        .post(format!("{}/chat/attachments", &app.address))
+        .query(&[("session", token.as_str()), ("name", "notes.txt")])
+        .body("hello")
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    assert_eq!(429, response.status().as_u16());
+    assert!(!dir.exists(), "the upload was stored");
 }
+
+crate::on_both_backends!(
+    requests_missing_authorization_are_rejected,
+    the_inbox_is_for_the_owner_only,
+    the_owner_reads_and_answers_visitors_live,
+    uploads_over_the_limit_are_not_kept,
+);
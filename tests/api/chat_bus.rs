@@ -0,0 +1,129 @@
+use demcru::configuration::{Backend, BusSettings, Settings};
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::helpers::{
+    form_token, postgres_server, spawn_app_with, EventStream, TestApp, CHAT_TOKEN,
+};
+
+// Two apps sharing a channel of their own
+async fn spawn_instances() -> (TestApp, TestApp) {
+    let channel = format!("chat_test_{}", Uuid::new_v4().simple());
+    // any database of the server carries the notifications
+    let url = format!("{}/postgres", postgres_server());
+    let bus = |channel: &str| {
+        let url = url.clone();
+        let channel = channel.to_owned();
+        move |settings: &mut Settings| {
+            settings.chat.bus = BusSettings::Postgres { channel, url };
+            // shared, as the settings require with a postgres bus
+            settings.chat.resume_secret = Some("r".repeat(32));
+            settings.protection.secret = Some("p".repeat(32));
+        }
+    };
+    let first = spawn_app_with(Backend::Sqlite, bus(&channel)).await;
+    let second = spawn_app_with(Backend::Sqlite, bus(&channel)).await;
+    (first, second)
+}
+
+// Sends a line as the session of an event stream
+async fn say(app: &TestApp, session: &Value, text: &str) {
+    let response = reqwest::Client::new()
+        .post(format!("{}/chat/send", app.address))
+        .json(&serde_json::json!({
+            "session": session["token"],
+            "text": text,
+        }))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status().as_u16(), 202);
+}
+
+// Needs a running Postgres
+#[actix_web::test]
+async fn rooms_are_shared_between_instances() {
+    let (first, second) = spawn_instances().await;
+
+    let mut alice = EventStream::connect(&first, "main").await;
+    let mut bob = EventStream::connect(&second, "main").await;
+    let session = bob.find(|event| event["type"] == "session").await;
+
+    // joins are relayed: alice sees bob in the member list of her instance
+    alice
+        .find(|event| event["type"] == "members" && event["members"].as_array().unwrap().len() == 2)
+        .await;
+
+    say(&second, &session, "hello from the other side").await;
+
+    let message = alice.find(|event| event["type"] == "message").await;
+    assert_eq!(message["text"], "hello from the other side");
+    assert_eq!(message["mine"], false);
+}
+
+#[actix_web::test]
+async fn private_rooms_stay_private_between_instances() {
+    let (first, second) = spawn_instances().await;
+
+    let mut alice = EventStream::connect(&first, "main").await;
+    let mut bob = EventStream::connect(&second, "main").await;
+    let alice_session = alice.find(|event| event["type"] == "session").await;
+    let bob_session = bob.find(|event| event["type"] == "session").await;
+
+    say(&first, &alice_session, "/join secret-club hunter2").await;
+    alice.find(|event| event["type"] == "notice").await;
+    // the other instance learns of the room through the bus
+    tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+
+    say(&second, &bob_session, "/join secret-club").await;
+    let refused = bob.find(|event| event["type"] == "error").await;
+    assert!(
+        refused["text"].as_str().unwrap().contains("private"),
+        "{refused}"
+    );
+    say(&first, &alice_session, "the secret plans").await;
+
+    say(&second, &bob_session, "/join secret-club hunter2").await;
+    bob.find(|event| {
+        assert_ne!(event["text"], "the secret plans");
+        event["type"] == "notice"
+    })
+    .await;
+    say(&first, &alice_session, "welcome").await;
+    bob.find(|event| {
+        assert_ne!(event["text"], "the secret plans");
+        event["type"] == "message" && event["text"] == "welcome"
+    })
+    .await;
+}
+
+// Needs a running Postgres
+#[actix_web::test]
+async fn either_instance_takes_requests_for_a_session() {
+    let (first, second) = spawn_instances().await;
+
+    let mut alice = EventStream::connect(&first, "main").await;
+    let mut bob = EventStream::connect(&second, "main").await;
+    let bob_session = bob.find(|event| event["type"] == "session").await;
+    alice
+        .find(|event| event["type"] == "members" && event["members"].as_array().unwrap().len() == 2)
+        .await;
+
+    // bob's stream is on the second instance, his post goes to the first
+    say(&first, &bob_session, "hello from behind the balancer").await;
+
+    let message = alice.find(|event| event["type"] == "message").await;
+    assert_eq!(message["text"], "hello from behind the balancer");
+    let message = bob.find(|event| event["type"] == "message").await;
+    assert_eq!(message["mine"], true);
+
+    // a page served by one instance opens its stream on the other
+    let token = form_token(&first, "/chat", CHAT_TOKEN).await;
+    let response = reqwest::Client::new()
+        .get(format!("{}/chat/rooms/main/events", second.address))
+        .query(&[("form_token", token)])
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status().as_u16(), 200);
+}
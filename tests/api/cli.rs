@@ -0,0 +1,156 @@
+use serde_json::Value;
+use std::{
+    net::TcpListener,
+    path::PathBuf,
+    process::{Command, Output, Stdio},
+    time::Duration,
+};
+use uuid::Uuid;
+
+// The binary, run from the crate like the server is, with `APP_` variables on top
+fn demcru(args: &[&str], env: &[(&str, &str)]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_demcru"))
+        .args(args)
+        .envs(env.iter().copied())
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .output()
+        .expect("Failed to run demcru")
+}
+
+fn stdout(output: &Output) -> String {
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+fn stderr(output: &Output) -> String {
+    assert!(!output.status.success(), "it should have failed");
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("demcru-cli-{}-{name}", Uuid::new_v4()))
+}
+
+// A copy of the blog to change
+fn blog_copy() -> String {
+    let path = temp_path("blog.yml");
+    std::fs::copy(
+        concat!(env!("CARGO_MANIFEST_DIR"), "/config/blog.yml"),
+        &path,
+    )
+    .unwrap();
+    path.display().to_string()
+}
+
+fn sqlite_url() -> String {
+    format!("sqlite:{}", temp_path("data.db").display())
+}
+
+#[test]
+fn check_config_fails_on_bad_settings_or_posts() {
+    assert!(stdout(&demcru(&["check-config"], &[])).contains("are fine"));
+
+    let output = demcru(&["check-config"], &[("APP_APPLICATION__PORT", "0")]);
+    assert!(stderr(&output).contains("application.port"));
+
+    let dir = temp_path("configuration");
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(
+        dir.join("base.yaml"),
+        "database: { url: \"mysql://blog\" }\n",
+    )
+    .unwrap();
+    std::fs::write(dir.join("local.yaml"), "").unwrap();
+    let output = demcru(&["check-config", "--config", dir.to_str().unwrap()], &[]);
+    assert!(stderr(&output).contains("database.url"));
+
+    let blog = blog_copy();
+    let posts = std::fs::read_to_string(&blog).unwrap();
+    std::fs::write(
+        &blog,
+        posts.replace("slug: threads-rust", "slug: light-web-stack"),
+    )
+    .unwrap();
+    let output = demcru(&["check-config"], &[("APP_PATHS__BLOG", &blog)]);
+    assert!(stderr(&output).contains("\"light-web-stack\": the slug is taken"));
+}
+
+#[test]
+fn posts_are_listed_and_started() {
+    let blog = blog_copy();
+    let env = [("APP_PATHS__BLOG", blog.as_str())];
+
+    let list = stdout(&demcru(&["posts", "list"], &env));
+    assert_eq!(list.lines().count(), 2);
+    assert!(list.starts_with("2022-10-23 threads-rust Low level concurrency\n"));
+
+    let added = stdout(&demcru(&["posts", "new", "Testing: the CLI"], &env));
+    assert!(added.contains("testing-the-cli"));
+    let list = stdout(&demcru(&["posts", "list"], &env));
+    let last = list.lines().last().unwrap();
+    assert!(
+        last.ends_with(" testing-the-cli Testing: the CLI"),
+        "{last}"
+    );
+    assert!(stdout(&demcru(&["check-config"], &env)).contains("3 posts"));
+
+    let again = demcru(&["posts", "new", "Testing the CLI"], &env);
+    assert!(stderr(&again).contains("There is a post testing-the-cli already"));
+}
+
+#[test]
+fn databases_are_migrated_and_exported() {
+    let url = sqlite_url();
+    let env = [("APP_DATABASE__URL", url.as_str())];
+
+    let status = stdout(&demcru(&["migrate"], &env));
+    assert!(status.lines().all(|line| line.contains(" pending ")));
+    let applied = stdout(&demcru(&["migrate", "apply"], &env));
+    assert!(applied.lines().all(|line| line.contains(" applied ")));
+    stdout(&demcru(&["import-contacts", "scripts/contacts.csv"], &env));
+
+    let export: Value =
+        serde_json::from_str(&stdout(&demcru(&["export"], &env))).expect("The export is not JSON");
+    assert_eq!(export["likes"], 0);
+    assert!(!export["contacts"].as_array().unwrap().is_empty());
+    assert!(export["subscriptions"].as_array().unwrap().is_empty());
+}
+
+#[actix_web::test]
+async fn serve_migrates_and_listens_on_the_port_asked_for() {
+    let url = sqlite_url();
+    let port = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let mut server = Command::new(env!("CARGO_BIN_EXE_demcru"))
+        .args(["serve", "--port", &port.to_string()])
+        .env("APP_DATABASE__URL", &url)
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .stdout(Stdio::null())
+        .spawn()
+        .expect("Failed to start demcru");
+
+    let health = format!("http://127.0.0.1:{port}/health-check");
+    let up = async {
+        loop {
+            match reqwest::get(&health).await {
+                Ok(response) if response.status().is_success() => return,
+                _ => tokio::time::sleep(Duration::from_millis(100)).await,
+            }
+        }
+    };
+    let up = tokio::time::timeout(Duration::from_secs(30), up).await;
+    server.kill().unwrap();
+    server.wait().unwrap();
+    up.expect("The server never answered");
+
+    // it started from an empty database
+    let status = stdout(&demcru(&["migrate"], &[("APP_DATABASE__URL", &url)]));
+    assert!(status.lines().all(|line| line.contains(" applied ")));
+}
@@ -0,0 +1,542 @@
+use crate::helpers::{
+    form_token, site_client, spawn_admin_app, spawn_app, TestApp, ADMIN_PASSWORD, CONTACT_TOKEN,
+};
+use chrono::Utc;
+use demcru::configuration::Backend;
+use demcru::{
+    models::contacts::{ContactQuery, Direction, NewContact, SortColumn},
+    storage::Database,
+};
+use sqlx::query;
+use uuid::Uuid;
+
+pub async fn submit(app: &TestApp, form: &[(&str, &str)]) -> reqwest::Response {
+    let token = form_token(app, "/contacts", CONTACT_TOKEN).await;
+    let mut form = form.to_vec();
+    form.push(("form_token", &token));
+    reqwest::Client::new()
+        .post(format!("{}/contacts", &app.address))
+        .header("HX-Request", "true")
+        .form(&form)
+        .send()
+        .await
+        .expect("Failed to execute request")
+}
+
+pub async fn saved_emails(app: &TestApp) -> Vec<String> {
+    app.storage
+        .contacts
+        .contact_emails()
+        .await
+        .expect("Failed to fetch saved contacts")
+}
+
+async fn valid_submissions_are_saved_and_thanked_for(backend: Backend) {
+    let app = spawn_app(backend).await;
+
+    let response = submit(
+        &app,
+        &[
+            ("first_name", "Joelie"),
+            ("last_name", "Sayburn"),
+            ("email", "JSayburn0@tamu.edu"),
+            ("message", "Hi there"),
+        ],
+    )
+    .await;
+
+    assert_eq!(200, response.status().as_u16());
+    let body = response.text().await.unwrap();
+    assert!(body.contains("Thank you, Joelie!"));
+    assert!(!body.contains("<html"), "htmx only gets the fragment");
+    assert_eq!(saved_emails(&app).await, ["jsayburn0@tamu.edu"]);
+}
+
+async fn invalid_fields_come_back_with_their_errors(backend: Backend) {
+    let app = spawn_app(backend).await;
+
+    let response = submit(
+        &app,
+        &[
+            ("first_name", ""),
+            ("last_name", "Dwane"),
+            ("email", "edwane1"),
+        ],
+    )
+    .await;
+
+    assert_eq!(422, response.status().as_u16());
+    let body = response.text().await.unwrap();
+    assert!(body.contains("Please tell us your name"));
+    assert!(body.contains("That does not look like an email address"));
+    // what was typed is kept
+    assert!(body.contains(r#"value="Dwane""#));
+    assert!(saved_emails(&app).await.is_empty());
+}
+
+async fn repeated_submissions_are_stored_once(backend: Backend) {
+    let app = spawn_app(backend).await;
+    let form = [
+        ("first_name", "Cristen"),
+        ("last_name", "Keys"),
+        ("email", "ckeys2@sogou.com"),
+    ];
+
+    for email in ["ckeys2@sogou.com", "CKeys2@Sogou.com"] {
+        let mut form = form;
+        form[2].1 = email;
+        let response = submit(&app, &form).await;
+        assert_eq!(200, response.status().as_u16());
+    }
+
+    assert_eq!(saved_emails(&app).await.len(), 1);
+}
+
+async fn simultaneous_repeats_are_stored_once(backend: Backend) {
+    let app = spawn_app(backend).await;
+    let contact = NewContact {
+        first_name: "Cordelia".to_owned(),
+        last_name: "Keys".to_owned(),
+        email: "ckeys2@sogou.com".to_owned(),
+        phone: None,
+        message: None,
+    };
+    let since = Utc::now() - chrono::Duration::minutes(5);
+
+    let saves = (0..8).map(|_| app.storage.contacts.save_contact(&contact, since));
+    let saved = futures_util::future::try_join_all(saves)
+        .await
+        .expect("Failed to save contact");
+
+    assert_eq!(saved.iter().filter(|&&saved| saved).count(), 1);
+    assert_eq!(saved_emails(&app).await.len(), 1);
+}
+
+async fn add_contact(app: &TestApp, first_name: &str, last_name: &str, email: &str) -> Uuid {
+    let contact = NewContact {
+        first_name: first_name.to_owned(),
+        last_name: last_name.to_owned(),
+        email: email.to_owned(),
+        phone: None,
+        message: None,
+    };
+    let contacts = &app.storage.contacts;
+    contacts
+        .save_contact(&contact, Utc::now())
+        .await
+        .expect("Failed to add contact");
+    let query = ContactQuery {
+        q: email.to_owned(),
+        ..ContactQuery::default()
+    };
+    let found = contacts
+        .find_contacts(&query, None, None)
+        .await
+        .expect("Failed to find contact");
+    found[0].id
+}
+
+// Rows of the admin table, as htmx gets them
+async fn admin_rows(app: &TestApp, path_and_query: &str) -> String {
+    let response = reqwest::Client::new()
+        .get(format!("{}{}", &app.address, path_and_query))
+        .header("HX-Request", "true")
+        .basic_auth("admin", Some(ADMIN_PASSWORD))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(200, response.status().as_u16());
+    response.text().await.unwrap()
+}
+
+fn row_ids(rows: &str) -> Vec<String> {
+    rows.split(r#"<tr id="contact-"#)
+        .skip(1)
+        .map(|row| row[..36].to_owned())
+        .collect()
+}
+
+// The URL of the next page, from the row that loads it
+fn next_page(rows: &str) -> Option<String> {
+    let (_, rest) = rows.split_once(r#"<tr hx-get=""#)?;
+    let (url, _) = rest.split_once('"')?;
+    Some(url.replace("&amp;", "&").replace("&#x3D;", "="))
+}
+
+async fn admin_contacts_need_a_login(backend: Backend) {
+    let app = spawn_admin_app(backend).await;
+    let id = add_contact(&app, "Joelie", "Sayburn", "jsayburn0@tamu.edu").await;
+    let client = reqwest::Client::new();
+    let url = format!("{}/admin/contacts/{}", &app.address, id);
+
+    let requests = [
+        client.get(format!("{}/admin/contacts", &app.address)),
+        client.get(format!("{url}/edit")),
+        client.put(&url).form(&[("first_name", "Joe")]),
+        client.delete(&url),
+    ];
+    for request in requests {
+        let response = request.send().await.expect("Failed to execute request");
+        assert_eq!(401, response.status().as_u16());
+    }
+    let page = client
+        .get(format!("{}/admin/contacts", &app.address))
+        .basic_auth("admin", Some(ADMIN_PASSWORD))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(200, page.status().as_u16());
+    let page = page.text().await.unwrap();
+    assert!(page.contains("Recent changes"));
+    assert_eq!(row_ids(&page).len(), 1);
+}
+
+async fn admin_contacts_are_searched_by_name_and_email(backend: Backend) {
+    let app = spawn_admin_app(backend).await;
+    let joelie = add_contact(&app, "Joelie", "Sayburn", "jsayburn0@tamu.edu").await;
+    let eal = add_contact(&app, "Eal", "Dwane", "edwane1@diigo.com").await;
+    add_contact(&app, "Cristen", "Keys", "ckeys2@sogou.com").await;
+
+    let rows = admin_rows(&app, "/admin/contacts?q=SAYB").await;
+    assert_eq!(row_ids(&rows), [joelie.to_string()]);
+    let rows = admin_rows(&app, "/admin/contacts?q=eal%20dwane").await;
+    assert_eq!(row_ids(&rows), [eal.to_string()]);
+    let rows = admin_rows(&app, "/admin/contacts?q=diigo").await;
+    assert_eq!(row_ids(&rows), [eal.to_string()]);
+    // wildcards are taken literally
+    let rows = admin_rows(&app, "/admin/contacts?q=%25").await;
+    assert!(row_ids(&rows).is_empty());
+
+    let rows = admin_rows(&app, "/admin/contacts?sort=first_name&dir=asc").await;
+    assert!(rows.find("Cristen").unwrap() < rows.find("Eal").unwrap());
+    assert!(rows.find("Eal").unwrap() < rows.find("Joelie").unwrap());
+}
+
+async fn admin_contacts_are_paged_with_a_cursor(backend: Backend) {
+    let app = spawn_admin_app(backend).await;
+    for n in 0..30 {
+        // the same name on every contact, pages still neither skip nor repeat
+        add_contact(&app, "Same", "Name", &format!("same{n}@example.com")).await;
+    }
+
+    let mut seen = Vec::new();
+    let mut url = Some("/admin/contacts?sort=last_name&dir=asc".to_owned());
+    let mut pages = 0;
+    while let Some(next) = url {
+        let rows = admin_rows(&app, &next).await;
+        seen.extend(row_ids(&rows));
+        url = next_page(&rows);
+        pages += 1;
+    }
+
+    assert_eq!(pages, 2);
+    let count = seen.len();
+    seen.sort();
+    seen.dedup();
+    assert_eq!((count, seen.len()), (30, 30));
+}
+
+async fn admin_edits_and_deletions_are_recorded(backend: Backend) {
+    let app = spawn_admin_app(backend).await;
+    let id = add_contact(&app, "Eal", "Dwane", "edwane1@diigo.com").await;
+    let client = site_client(&app).build().unwrap();
+    let url = format!("{}/admin/contacts/{}", &app.address, id);
+
+    let response = client
+        .put(&url)
+        .basic_auth("admin", Some(ADMIN_PASSWORD))
+        .form(&[("first_name", ""), ("last_name", "Dwane"), ("email", "x")])
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(422, response.status().as_u16());
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains("Please tell us your name"));
+
+    let response = client
+        .put(&url)
+        .basic_auth("admin", Some(ADMIN_PASSWORD))
+        .form(&[
+            ("first_name", "Earl"),
+            ("last_name", "Dwane"),
+            ("email", "EDwane1@diigo.com"),
+            ("phone", "201 555 0123"),
+        ])
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(200, response.status().as_u16());
+    let row = response.text().await.unwrap();
+    assert!(row.contains("Earl"));
+    // stored as E.164, shown the way people write it
+    assert!(row.contains("+1 201-555-0123"));
+
+    let response = client
+        .delete(&url)
+        .basic_auth("admin", Some(ADMIN_PASSWORD))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(200, response.status().as_u16());
+    assert!(saved_emails(&app).await.is_empty());
+
+    let mut changes = app
+        .storage
+        .contacts
+        .recent_changes(10)
+        .await
+        .expect("Failed to fetch changes");
+    // the most recent come first
+    changes.reverse();
+    assert_eq!(changes.len(), 2);
+    let update = &changes[0];
+    assert_eq!(
+        (update.action.as_str(), update.changed_by.as_str()),
+        ("update", "admin")
+    );
+    assert!(update.before.contains("\"Eal\""));
+    let after = update.after.as_deref().unwrap();
+    assert!(after.contains("\"edwane1@diigo.com\""));
+    assert!(after.contains("\"+12015550123\""));
+    let delete = &changes[1];
+    assert_eq!(delete.action, "delete");
+    assert!(delete.before.contains("\"Earl\""));
+    assert!(delete.after.is_none());
+}
+
+async fn admin_changes_only_come_from_the_site(backend: Backend) {
+    let app = spawn_admin_app(backend).await;
+    let id = add_contact(&app, "Eal", "Dwane", "edwane1@diigo.com").await;
+    let url = format!("{}/admin/contacts/{}", &app.address, id);
+
+    // a form on another site, and a page that hides where it is
+    let elsewhere = [
+        Some((reqwest::header::ORIGIN, "https://evil.example")),
+        Some((reqwest::header::REFERER, "https://evil.example/contacts")),
+        None,
+    ];
+    for header in elsewhere {
+        let mut request = reqwest::Client::new()
+            .delete(&url)
+            .basic_auth("admin", Some(ADMIN_PASSWORD));
+        if let Some((name, value)) = header {
+            request = request.header(name, value);
+        }
+        let response = request.send().await.expect("Failed to execute request");
+        assert_eq!(403, response.status().as_u16());
+    }
+    assert_eq!(saved_emails(&app).await, ["edwane1@diigo.com"]);
+
+    let response = reqwest::Client::new()
+        .delete(&url)
+        .basic_auth("admin", Some(ADMIN_PASSWORD))
+        .header(
+            reqwest::header::REFERER,
+            format!("{}/admin/contacts", &app.address),
+        )
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(200, response.status().as_u16());
+    assert!(saved_emails(&app).await.is_empty());
+}
+
+async fn admin_post(app: &TestApp, path: &str, csv: &str) -> reqwest::Response {
+    site_client(app)
+        .build()
+        .unwrap()
+        .post(format!("{}{}", &app.address, path))
+        .basic_auth("admin", Some(ADMIN_PASSWORD))
+        .form(&[("csv", csv)])
+        .send()
+        .await
+        .expect("Failed to execute request")
+}
+
+async fn csv_imports_are_previewed_then_committed(backend: Backend) {
+    let app = spawn_admin_app(backend).await;
+    add_contact(&app, "Eal", "Dwane", "edwane1@diigo.com").await;
+    let csv = "first_name,last_name,email\n\
+        Joelie,Sayburn,jsayburn0@tamu.edu\n\
+        Eal,Dwane,EDwane1@diigo.com\n\
+        Joelie,Sayburn,jsayburn0@tamu.edu\n";
+
+    let response = admin_post(&app, "/admin/contacts/import/preview", csv).await;
+    assert_eq!(200, response.status().as_u16());
+    let preview = response.text().await.unwrap();
+    assert!(
+        preview.contains("1 new, 2 duplicates, 0 invalid"),
+        "{preview}"
+    );
+    // previewing imports nothing
+    assert_eq!(saved_emails(&app).await.len(), 1);
+
+    let response = admin_post(&app, "/admin/contacts/import", csv).await;
+    assert_eq!(200, response.status().as_u16());
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains("Imported 1 contacts, skipped 2 duplicates"));
+    assert_eq!(saved_emails(&app).await.len(), 2);
+}
+
+async fn csv_imports_with_an_invalid_row_import_nothing(backend: Backend) {
+    let app = spawn_admin_app(backend).await;
+    let csv = "first_name,last_name,email\n\
+        Joelie,Sayburn,jsayburn0@tamu.edu\n\
+        Eal,Dwane,not an email\n";
+
+    let response = admin_post(&app, "/admin/contacts/import", csv).await;
+    assert_eq!(422, response.status().as_u16());
+    assert!(response.text().await.unwrap().contains("invalid"));
+    assert!(saved_emails(&app).await.is_empty());
+
+    let response = admin_post(&app, "/admin/contacts/import/preview", "name\nJoelie\n").await;
+    assert_eq!(422, response.status().as_u16());
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains("The first_name column is missing"));
+}
+
+async fn the_fixture_imports_and_exports_filtered(backend: Backend) {
+    let app = spawn_admin_app(backend).await;
+    let fixture = std::fs::read_to_string("scripts/contacts.csv").unwrap();
+    let response = admin_post(&app, "/admin/contacts/import", &fixture).await;
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains("Imported 50 contacts"));
+
+    let client = reqwest::Client::new();
+    let csv = client
+        .get(format!(
+            "{}/admin/contacts/export.csv?q=tamu.edu&sort=email&dir=asc",
+            &app.address
+        ))
+        .basic_auth("admin", Some(ADMIN_PASSWORD))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(200, csv.status().as_u16());
+    assert!(csv.headers()["content-disposition"]
+        .to_str()
+        .unwrap()
+        .contains("contacts.csv"));
+    let csv = csv.text().await.unwrap();
+    let lines: Vec<_> = csv.lines().collect();
+    assert_eq!(lines.len(), 3);
+    assert!(lines[1].starts_with("Joelie,Sayburn,jsayburn0@tamu.edu,+12015550100,,2022-11-05"));
+    assert!(lines[2].starts_with("Rakel,Watt,rwatt1c@tamu.edu,+12015550148,,"));
+
+    let vcard = client
+        .get(format!(
+            "{}/admin/contacts/export.vcf?q=O%27Crevy",
+            &app.address
+        ))
+        .basic_auth("admin", Some(ADMIN_PASSWORD))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(200, vcard.status().as_u16());
+    let vcard = vcard.text().await.unwrap();
+    assert_eq!(vcard.matches("BEGIN:VCARD").count(), 1);
+    assert!(vcard.contains("FN:Almire O'Crevy\r\n"));
+}
+
+async fn phones_from_before_e164_are_converted_or_kept_in_the_notes(backend: Backend) {
+    let app = spawn_app(backend).await;
+    // back to when the old numbers waited in `legacy_phone`
+    app.storage
+        .revert(Some(20261019170000))
+        .await
+        .expect("Failed to revert");
+    let joelie = add_contact(&app, "Joelie", "Sayburn", "jsayburn0@tamu.edu").await;
+    let eal = add_contact(&app, "Eal", "Dwane", "edwane1@diigo.com").await;
+    // numbers as they were stored before, no repository writes them anymore
+    let set_legacy = "UPDATE contacts SET legacy_phone = $2 WHERE id = $1";
+    for (id, legacy) in [(joelie, 59_i64), (eal, 2015550123)] {
+        match app.storage.database() {
+            Database::Sqlite(pool) => {
+                let query = query(set_legacy).bind(id).bind(legacy);
+                query.execute(pool).await.map(|_| ())
+            }
+            Database::Postgres(pool) => {
+                let query = query(set_legacy).bind(id).bind(legacy);
+                query.execute(pool).await.map(|_| ())
+            }
+        }
+        .unwrap();
+    }
+
+    // migrated without the app, the numbers are not dropped unconverted
+    let (mut sqlite, mut postgres) = (
+        sqlx::migrate!("./migrations/sqlite"),
+        sqlx::migrate!("./migrations/postgres"),
+    );
+    // a failed run would keep the Postgres lock on a connection of the pool
+    sqlite.set_locking(false);
+    postgres.set_locking(false);
+    let plain = match app.storage.database() {
+        Database::Sqlite(pool) => sqlite.run(pool).await,
+        Database::Postgres(pool) => postgres.run(pool).await,
+    };
+    assert!(plain.is_err());
+    assert_eq!(app.storage.contacts.legacy_phones().await.unwrap().len(), 2);
+
+    let region = demcru::models::phone::region("US");
+    app.storage
+        .migrate(region)
+        .await
+        .expect("Failed to migrate");
+    // and the column is gone with them
+    assert!(app.storage.contacts.legacy_phones().await.is_err());
+
+    let query = ContactQuery {
+        sort: SortColumn::FirstName,
+        dir: Direction::Asc,
+        ..ContactQuery::default()
+    };
+    let phones: Vec<_> = app
+        .storage
+        .contacts
+        .find_contacts(&query, None, None)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|contact| (contact.first_name, contact.phone, contact.notes))
+        .collect();
+    assert_eq!(
+        phones,
+        [
+            ("Eal".to_owned(), Some("+12015550123".to_owned()), None),
+            (
+                "Joelie".to_owned(),
+                None,
+                Some("Phone before E.164: 59".to_owned())
+            ),
+        ]
+    );
+}
+
+crate::on_both_backends!(
+    valid_submissions_are_saved_and_thanked_for,
+    invalid_fields_come_back_with_their_errors,
+    repeated_submissions_are_stored_once,
+    simultaneous_repeats_are_stored_once,
+    admin_contacts_need_a_login,
+    admin_contacts_are_searched_by_name_and_email,
+    admin_contacts_are_paged_with_a_cursor,
+    admin_edits_and_deletions_are_recorded,
+    admin_changes_only_come_from_the_site,
+    csv_imports_are_previewed_then_committed,
+    csv_imports_with_an_invalid_row_import_nothing,
+    the_fixture_imports_and_exports_filtered,
+    phones_from_before_e164_are_converted_or_kept_in_the_notes,
+);
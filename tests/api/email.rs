@@ -0,0 +1,95 @@
+use crate::contacts::submit;
+use crate::helpers::{spawn_app_with_smtp, FakeSmtp, TestApp, OWNER_EMAIL};
+use demcru::configuration::Backend;
+use std::time::Duration;
+
+const FORM: [(&str, &str); 5] = [
+    ("first_name", "Joelie"),
+    ("last_name", "Sayburn"),
+    ("email", "jsayburn0@tamu.edu"),
+    ("phone", "(201) 555-0123"),
+    ("message", "Are you free for a project in May?"),
+];
+
+// (attempts, sent, failed) of the only email of the outbox, once `done` says so
+async fn outbox_state(
+    app: &TestApp,
+    done: impl Fn(&(i64, bool, bool)) -> bool,
+) -> (i64, bool, bool) {
+    let wait = async {
+        loop {
+            let outbox = app
+                .storage
+                .outbox
+                .outbox()
+                .await
+                .expect("Failed to fetch the outbox");
+            let email = &outbox[0];
+            let state = (
+                email.attempts,
+                email.sent_at.is_some(),
+                email.failed_at.is_some(),
+            );
+            if done(&state) {
+                return state;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    };
+    tokio::time::timeout(Duration::from_secs(10), wait)
+        .await
+        .expect("The outbox never got there")
+}
+
+async fn the_owner_hears_about_new_contacts(backend: Backend) {
+    let smtp = FakeSmtp::start().await;
+    let app = spawn_app_with_smtp(backend, &smtp, |_| ()).await;
+
+    assert_eq!(200, submit(&app, &FORM).await.status().as_u16());
+
+    let messages = smtp.wait_for(1).await;
+    let message = &messages[0];
+    assert!(message.contains(&format!("To: {OWNER_EMAIL}")));
+    assert!(message.contains("From: demcru <noreply@localhost>"));
+    assert!(message.contains("Subject: New contact: Joelie Sayburn"));
+    assert!(message.contains("Email: jsayburn0@tamu.edu"));
+    assert!(message.contains("Phone: +1 201-555-0123"));
+    assert!(message.contains("Are you free for a project in May?"));
+    assert_eq!(outbox_state(&app, |state| state.1).await, (1, true, false));
+
+    // repeats are not stored, nor told about
+    submit(&app, &FORM).await;
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    assert_eq!(smtp.wait_for(1).await.len(), 1);
+}
+
+async fn failed_deliveries_are_retried(backend: Backend) {
+    let smtp = FakeSmtp::start().await;
+    smtp.fail_next(2);
+    let app = spawn_app_with_smtp(backend, &smtp, |_| ()).await;
+
+    submit(&app, &FORM).await;
+
+    let messages = smtp.wait_for(1).await;
+    assert!(messages[0].contains("Subject: New contact: Joelie Sayburn"));
+    assert_eq!(outbox_state(&app, |state| state.1).await, (3, true, false));
+}
+
+async fn emails_are_given_up_on_after_the_last_attempt(backend: Backend) {
+    let smtp = FakeSmtp::start().await;
+    smtp.fail_next(usize::MAX);
+    let app = spawn_app_with_smtp(backend, &smtp, |config| config.email.max_attempts = 2).await;
+
+    submit(&app, &FORM).await;
+
+    assert_eq!(outbox_state(&app, |state| state.2).await, (2, false, true));
+    let outbox = app.storage.outbox.outbox().await.unwrap();
+    let error = outbox[0].last_error.clone().unwrap();
+    assert!(error.contains("Try again later"), "{error}");
+}
+
+crate::on_both_backends!(
+    the_owner_hears_about_new_contacts,
+    failed_deliveries_are_retried,
+    emails_are_given_up_on_after_the_last_attempt,
+);
@@ -1,3 +1,10 @@
 mod chat;
+mod chat_bus;
 mod check;
+mod cli;
+mod contacts;
+mod email;
 mod helpers;
+mod migrations;
+mod newsletter;
+mod protection;
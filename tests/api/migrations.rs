@@ -0,0 +1,88 @@
+use demcru::configuration::Backend;
+use demcru::storage::{Database, MigrationState, Storage};
+use sqlx::{migrate::MigrateError, query};
+
+use crate::helpers::test_database;
+
+async fn states(storage: &Storage) -> Vec<MigrationState> {
+    let migrations = storage
+        .migrations()
+        .await
+        .expect("Failed to read the migrations");
+    migrations.iter().map(|migration| migration.state).collect()
+}
+
+async fn migrations_are_applied_and_reverted(backend: Backend) {
+    let storage = test_database(backend).await;
+    let pending = states(&storage).await;
+    assert!(pending
+        .iter()
+        .all(|&state| state == MigrationState::Pending));
+
+    assert_eq!(
+        storage.migrate_on_start(true, None).await.unwrap(),
+        pending.len()
+    );
+    assert!(states(&storage)
+        .await
+        .iter()
+        .all(|&state| state == MigrationState::Applied));
+    // nothing left to do
+    assert_eq!(storage.migrate_on_start(true, None).await.unwrap(), 0);
+
+    let migrations = storage.migrations().await.unwrap();
+    let latest = migrations.last().unwrap().version;
+    assert_eq!(storage.revert(None).await.unwrap(), [latest]);
+    assert_eq!(
+        states(&storage).await.last(),
+        Some(&MigrationState::Pending)
+    );
+    assert_eq!(storage.migrate_on_start(true, None).await.unwrap(), 1);
+
+    // every down migration undoes its up one
+    let reverted = storage.revert(Some(0)).await.unwrap();
+    assert_eq!(reverted.len(), migrations.len());
+    assert_eq!(states(&storage).await, pending);
+    storage
+        .migrate(None)
+        .await
+        .expect("Failed to migrate again");
+}
+
+async fn schemas_of_newer_binaries_are_left_alone_or_refused(backend: Backend) {
+    let storage = test_database(backend).await;
+    storage.migrate(None).await.unwrap();
+    // as a newer binary would record its migration
+    let newer = "INSERT INTO _sqlx_migrations
+        (version, description, success, checksum, execution_time)
+        VALUES ($1, 'from the future', TRUE, $2, 0)";
+    let (version, checksum) = (99990101000000_i64, vec![0_u8; 48]);
+    match storage.database() {
+        Database::Sqlite(pool) => {
+            let query = query(newer).bind(version).bind(checksum);
+            query.execute(pool).await.map(|_| ())
+        }
+        Database::Postgres(pool) => {
+            let query = query(newer).bind(version).bind(checksum);
+            query.execute(pool).await.map(|_| ())
+        }
+    }
+    .unwrap();
+
+    let migrations = storage.migrations().await.unwrap();
+    let last = migrations.last().unwrap();
+    assert_eq!(
+        (last.version, last.state),
+        (version, MigrationState::Unknown)
+    );
+    assert_eq!(storage.migrate_on_start(false, None).await.unwrap(), 0);
+    assert!(matches!(
+        storage.migrate_on_start(true, None).await,
+        Err(MigrateError::VersionMissing(v)) if v == version
+    ));
+}
+
+crate::on_both_backends!(
+    migrations_are_applied_and_reverted,
+    schemas_of_newer_binaries_are_left_alone_or_refused,
+);
@@ -0,0 +1,151 @@
+use crate::helpers::{form_token, spawn_app_with_smtp, FakeSmtp, TestApp, CONTACT_TOKEN};
+use demcru::configuration::Backend;
+use demcru::{
+    configuration::{Config, NewsletterSettings, PathSettings},
+    routes::{send_post, NewsletterError},
+    startup::templates,
+};
+
+// The path of the first link of `message` to the site
+fn link_path(message: &str, path: &str) -> String {
+    let base = NewsletterSettings::default().base_url;
+    let start = message
+        .find(&format!("{base}{path}"))
+        .expect("The email has no such link")
+        + base.len();
+    message[start..]
+        .split(|c: char| c.is_whitespace() || c == '>')
+        .next()
+        .unwrap()
+        .to_owned()
+}
+
+async fn status(app: &TestApp, email: &str) -> String {
+    let subscriptions = app
+        .storage
+        .newsletter
+        .subscriptions()
+        .await
+        .expect("Failed to fetch the subscriptions");
+    subscriptions
+        .into_iter()
+        .find(|subscription| subscription.email == email)
+        .expect("No such subscription")
+        .status
+}
+
+fn blog() -> Config {
+    Config::load(&PathSettings::default().blog).expect("Failed to read the blog")
+}
+
+async fn send(app: &TestApp, post: usize) -> Result<usize, NewsletterError> {
+    let config = blog();
+    send_post(
+        app.storage.newsletter.as_ref(),
+        &templates(&PathSettings::default().templates),
+        &NewsletterSettings::default(),
+        &config.posts[post],
+    )
+    .await
+}
+
+async fn subscribers_confirm_receive_posts_and_unsubscribe(backend: Backend) {
+    let smtp = FakeSmtp::start().await;
+    let app = spawn_app_with_smtp(backend, &smtp, |_| ()).await;
+    let client = reqwest::Client::new();
+
+    // subscribe from the blog
+    let token = form_token(&app, "/blog", CONTACT_TOKEN).await;
+    let response = client
+        .post(format!("{}/subscriptions", &app.address))
+        .header("HX-Request", "true")
+        .form(&[("email", "Reader@Example.com"), ("form_token", &token)])
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(200, response.status().as_u16());
+    assert!(response.text().await.unwrap().contains("please confirm"));
+    assert_eq!(status(&app, "reader@example.com").await, "pending");
+    // nothing goes to subscribers who did not confirm
+    assert_eq!(send(&app, 1).await.unwrap(), 0);
+
+    // confirm, the link works once
+    let confirmation = &smtp.wait_for(1).await[0];
+    assert!(confirmation.contains("To: reader@example.com"));
+    assert!(confirmation.contains("Subject: Please confirm your subscription"));
+    let confirm = link_path(confirmation, "/subscriptions/confirm");
+    let response = client
+        .get(format!("{}{}", &app.address, confirm))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(200, response.status().as_u16());
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains("You are subscribed"));
+    assert_eq!(status(&app, "reader@example.com").await, "confirmed");
+    let again = client
+        .get(format!("{}{}", &app.address, confirm))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(400, again.status().as_u16());
+
+    // a new post goes out once
+    let post = &blog().posts[0];
+    assert_eq!(send(&app, 0).await.unwrap(), 1);
+    assert!(matches!(
+        send(&app, 0).await,
+        Err(NewsletterError::AlreadySent(_))
+    ));
+    let issue = &smtp.wait_for(2).await[1];
+    assert!(issue.contains("To: reader@example.com"));
+    assert!(issue.contains(&format!("Subject: New post: {}", post.title)));
+    assert!(issue.contains(&format!("/blog/{}", post.slug)));
+    assert!(issue.contains("List-Unsubscribe-Post: List-Unsubscribe=One-Click"));
+
+    // one-click unsubscribe, as a mail client does it
+    let unsubscribe = link_path(issue, "/subscriptions/unsubscribe");
+    assert!(issue.contains(&format!(
+        "List-Unsubscribe: <http://localhost:8080{unsubscribe}>"
+    )));
+    let response = client
+        .post(format!("{}{}", &app.address, unsubscribe))
+        .form(&[("List-Unsubscribe", "One-Click")])
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(status(&app, "reader@example.com").await, "unsubscribed");
+    assert_eq!(send(&app, 1).await.unwrap(), 0);
+}
+
+async fn invalid_addresses_are_not_subscribed(backend: Backend) {
+    let smtp = FakeSmtp::start().await;
+    let app = spawn_app_with_smtp(backend, &smtp, |_| ()).await;
+
+    let token = form_token(&app, "/blog", CONTACT_TOKEN).await;
+    let response = reqwest::Client::new()
+        .post(format!("{}/subscriptions", &app.address))
+        .header("HX-Request", "true")
+        .form(&[("email", "not an address"), ("form_token", &token)])
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    assert_eq!(422, response.status().as_u16());
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains("That does not look like an email address"));
+    let subscriptions = app.storage.newsletter.subscriptions().await.unwrap();
+    assert!(subscriptions.is_empty());
+}
+
+crate::on_both_backends!(
+    subscribers_confirm_receive_posts_and_unsubscribe,
+    invalid_addresses_are_not_subscribed,
+);
@@ -0,0 +1,243 @@
+use crate::contacts::{saved_emails, submit};
+use crate::helpers::{
+    form_token, site_client, spawn_admin_app, spawn_app, spawn_app_with, EventStream, TestApp,
+    ADMIN_PASSWORD, CHAT_TOKEN, CONTACT_TOKEN,
+};
+use demcru::{configuration::Backend, models::quarantine::Quarantined};
+use futures_util::SinkExt;
+use std::time::Duration;
+use tokio_tungstenite::{
+    connect_async,
+    tungstenite::{self, Message},
+};
+
+const FORM: [(&str, &str); 3] = [
+    ("first_name", "Ivette"),
+    ("last_name", "Gammidge"),
+    ("email", "igammidge3@example.com"),
+];
+
+// Posts the contact form as a bot would, the fields as they are
+async fn post_contact(app: &TestApp, form: &[(&str, &str)]) -> String {
+    let response = reqwest::Client::new()
+        .post(format!("{}/contacts", &app.address))
+        .header("HX-Request", "true")
+        .form(form)
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(200, response.status().as_u16());
+    response.text().await.unwrap()
+}
+
+async fn quarantined(app: &TestApp) -> Vec<Quarantined> {
+    app.storage
+        .quarantine
+        .quarantined(100)
+        .await
+        .expect("Failed to fetch the quarantine")
+}
+
+// Opens the chat websocket with `form_token` in its query
+async fn chat_socket(
+    app: &TestApp,
+    form_token: &str,
+) -> Result<
+    tokio_tungstenite::WebSocketStream<tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>>,
+    tungstenite::Error,
+> {
+    let address = app.address.replacen("http", "ws", 1);
+    let url = format!("{address}/ws?form_token={form_token}");
+    connect_async(url).await.map(|(socket, _)| socket)
+}
+
+async fn bots_are_thanked_and_forgotten(backend: Backend) {
+    let app = spawn_app(backend).await;
+    let token = form_token(&app, "/contacts", CONTACT_TOKEN).await;
+
+    let mut honeypot = FORM.to_vec();
+    honeypot.push(("form_token", &token));
+    honeypot.push(("website", "http://spam.example"));
+    let without_token = FORM.to_vec();
+    let mut forged = FORM.to_vec();
+    forged.push(("form_token", "1.00"));
+
+    for form in [honeypot, without_token, forged] {
+        let body = post_contact(&app, &form).await;
+        assert!(body.contains("Thank you, Ivette!"));
+    }
+    assert!(saved_emails(&app).await.is_empty());
+}
+
+async fn forms_sent_right_after_loading_are_dropped(backend: Backend) {
+    let app = spawn_app_with(backend, |config| config.protection.min_submit_secs = 60).await;
+
+    let response = submit(&app, &FORM).await;
+
+    assert_eq!(200, response.status().as_u16());
+    assert!(response.text().await.unwrap().contains("Thank you"));
+    assert!(saved_emails(&app).await.is_empty());
+}
+
+async fn addresses_over_the_rate_limit_are_dropped(backend: Backend) {
+    let app = spawn_app_with(backend, |config| config.protection.rate_limits.contact = 2).await;
+
+    for email in ["a@example.com", "b@example.com", "c@example.com"] {
+        let mut form = FORM;
+        form[2].1 = email;
+        assert_eq!(200, submit(&app, &form).await.status().as_u16());
+    }
+
+    assert_eq!(saved_emails(&app).await, ["a@example.com", "b@example.com"]);
+}
+
+async fn spam_is_quarantined_until_the_admin_releases_it(backend: Backend) {
+    let app = spawn_admin_app(backend).await;
+    let client = site_client(&app).build().unwrap();
+    let mut form = FORM.to_vec();
+    form.push((
+        "message",
+        "Cheap backlinks! https://a.example https://b.example",
+    ));
+
+    let response = submit(&app, &form).await;
+    assert!(response.text().await.unwrap().contains("Thank you"));
+    assert!(saved_emails(&app).await.is_empty());
+    let entries = quarantined(&app).await;
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].form, "contact");
+
+    let review = client
+        .get(format!("{}/admin/quarantine", &app.address))
+        .basic_auth("admin", Some(ADMIN_PASSWORD))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(200, review.status().as_u16());
+    assert!(review.text().await.unwrap().contains("Cheap backlinks!"));
+
+    let id = entries[0].id;
+    let release = format!("{}/admin/quarantine/{}/release", &app.address, id);
+    let response = client.post(&release).send().await.unwrap();
+    assert_eq!(401, response.status().as_u16());
+    let response = client
+        .post(&release)
+        .basic_auth("admin", Some(ADMIN_PASSWORD))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(200, response.status().as_u16());
+
+    assert_eq!(saved_emails(&app).await, ["igammidge3@example.com"]);
+    assert!(quarantined(&app).await.is_empty());
+}
+
+async fn likes_without_a_token_are_not_counted(backend: Backend) {
+    let app = spawn_app(backend).await;
+    let client = reqwest::Client::new();
+    let likes = || async { app.storage.likes.count_likes().await.unwrap() };
+
+    let response = client
+        .post(format!("{}/like", &app.address))
+        .form(&[("form_token", "")])
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(200, response.status().as_u16());
+    // the heart shows, as if it had worked
+    assert!(response.text().await.unwrap().contains("heart.svg"));
+    assert_eq!(likes().await, 0);
+
+    let token = form_token(&app, "/", r#""form_token": ""#).await;
+    let response = client
+        .post(format!("{}/like", &app.address))
+        .form(&[("form_token", &token)])
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(likes().await, 1);
+}
+
+async fn chat_sockets_need_a_chat_token(backend: Backend) {
+    let app = spawn_app(backend).await;
+
+    for token in ["", "1.00"] {
+        match chat_socket(&app, token).await {
+            Err(tungstenite::Error::Http(response)) => assert_eq!(403, response.status().as_u16()),
+            other => panic!("the socket was not refused: {other:?}"),
+        }
+    }
+
+    let token = form_token(&app, "/chat", CHAT_TOKEN).await;
+    chat_socket(&app, &token)
+        .await
+        .expect("Failed to connect with the chat token");
+
+    // event streams, too
+    let response = reqwest::get(format!("{}/chat/rooms/main/events", &app.address))
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(403, response.status().as_u16());
+}
+
+async fn typing_and_fast_messages_get_through(backend: Backend) {
+    let app = spawn_app_with(backend, |settings| {
+        settings.protection.min_submit_secs = 60;
+    })
+    .await;
+    let mut bystander = EventStream::connect(&app, "main").await;
+    let token = form_token(&app, "/chat", CHAT_TOKEN).await;
+    let mut socket = chat_socket(&app, &token).await.unwrap();
+
+    // more typing pings than any session may send messages
+    for _ in 0..30 {
+        let typing = Message::Text("/typing".to_owned());
+        socket.send(typing).await.unwrap();
+    }
+    socket
+        .send(Message::Text("hello".to_owned()))
+        .await
+        .unwrap();
+
+    let message = bystander.find(|event| event["type"] == "message").await;
+    assert_eq!(message["text"], "hello");
+}
+
+async fn spammy_chat_frames_are_quarantined(backend: Backend) {
+    let app = spawn_app(backend).await;
+    let mut bystander = EventStream::connect(&app, "main").await;
+    let token = form_token(&app, "/chat", CHAT_TOKEN).await;
+    let mut socket = chat_socket(&app, &token).await.unwrap();
+
+    let spam = "Cheap backlinks! https://a.example https://b.example";
+    for text in [spam, "hello"] {
+        socket.send(Message::Text(text.to_owned())).await.unwrap();
+    }
+
+    let message = bystander.find(|event| event["type"] == "message").await;
+    assert_eq!(message["text"], "hello");
+    // kept while the next frame went on
+    let mut entries = quarantined(&app).await;
+    for _ in 0..20 {
+        if !entries.is_empty() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        entries = quarantined(&app).await;
+    }
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].form, "chat");
+    assert!(entries[0].payload.contains("Cheap backlinks!"));
+}
+
+crate::on_both_backends!(
+    bots_are_thanked_and_forgotten,
+    forms_sent_right_after_loading_are_dropped,
+    addresses_over_the_rate_limit_are_dropped,
+    spam_is_quarantined_until_the_admin_releases_it,
+    likes_without_a_token_are_not_counted,
+    chat_sockets_need_a_chat_token,
+    spammy_chat_frames_are_quarantined,
+    typing_and_fast_messages_get_through,
+);